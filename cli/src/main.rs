@@ -0,0 +1,422 @@
+//! 出力プラグインのエンコード処理を使って連番PNGや動画ストリームからアニメーション画像を出力するツール
+
+mod png_sequence;
+mod progress;
+
+use aviutl::convert::YuvMatrix;
+use aviutl::source::FrameSource;
+use aviutl::source::stream::StreamFrameSource;
+use ini::Ini;
+use png_sequence::PngSequenceSource;
+use progress::Progress;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+
+const USAGE: &str = "\
+使い方: animated-image-cli <入力> <出力ファイル> [オプション]
+
+入力には次のいずれかを指定します。
+  - 連番PNGのディレクトリ（ファイル名の末尾の数字の順に読み込む）
+  - YUV4MPEG2（.y4m）のファイル
+  - `RGB24 W640 H480 F30:1`（RGBAは`RGBA32`）のヘッダー行に続けて生のフレームを並べたファイル
+  - `-`（上記の動画ストリームを標準入力から読み込む）
+
+オプション:
+  --fps <フレームレート>   フレームレート（例: 30, 29.97, 30000/1001）
+                           [既定: 連番PNGは30、動画ストリームはヘッダーの値]
+  --matrix <行列>          Y4MのYUVからRGBへの変換行列 bt601 / bt709 [既定: bt601]
+  --format <形式>          出力形式 gif / apng / webp / avif（省略時は出力ファイルの拡張子から判断）
+  --config <ファイル>      プラグインの設定ファイル（ini）を読み込む
+  --<キー> <値>            設定ファイルと同じキーで設定を指定する（例: --repeat 0 --quality 80）
+  -h, --help               このヘルプを表示する
+";
+
+/// 出力形式
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Format {
+    Gif,
+    Apng,
+    Webp,
+    Avif,
+}
+
+impl Format {
+    /// 出力ファイルの拡張子から判断する
+    fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "png" => Some(Format::Apng),
+            ext => ext.parse().ok(),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gif" => Ok(Format::Gif),
+            "apng" => Ok(Format::Apng),
+            "webp" => Ok(Format::Webp),
+            "avif" => Ok(Format::Avif),
+            _ => Err(format!("不明な出力形式です: {}", s)),
+        }
+    }
+}
+
+struct Args {
+    input: PathBuf,
+    output: PathBuf,
+    format: Option<Format>,
+    /// (rate, scale)
+    fps: Option<(i32, i32)>,
+    matrix: YuvMatrix,
+    config: Option<PathBuf>,
+    /// 設定ファイルのキーと値
+    settings: Vec<(String, String)>,
+}
+
+/// コマンドライン引数を解析する
+/// - 戻り値: ヘルプを表示する場合はNone
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut paths = Vec::new();
+    let mut format = None;
+    let mut fps = None;
+    let mut matrix = YuvMatrix::default();
+    let mut config = None;
+    let mut settings = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(None);
+        }
+
+        let Some(option) = arg.strip_prefix("--") else {
+            // `-`は標準入力
+            if arg.len() > 1 && arg.starts_with('-') {
+                return Err(format!("不明なオプションです: {}", arg));
+            }
+            paths.push(PathBuf::from(arg));
+            continue;
+        };
+
+        let (key, value) = match option.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("--{}に値がありません", option))?;
+                (option.to_string(), value)
+            }
+        };
+
+        match key.as_str() {
+            "fps" => fps = Some(parse_fps(&value)?),
+            "matrix" => matrix = parse_matrix(&value)?,
+            "format" => format = Some(value.parse()?),
+            "config" => config = Some(PathBuf::from(value)),
+            _ => settings.push((key.replace('-', "_"), value)),
+        }
+    }
+
+    let [input, output] = <[PathBuf; 2]>::try_from(paths)
+        .map_err(|_| "入力と出力ファイルを指定してください".to_string())?;
+
+    Ok(Some(Args {
+        input,
+        output,
+        format,
+        fps,
+        matrix,
+        config,
+        settings,
+    }))
+}
+
+impl Args {
+    /// 出力形式（指定が無い場合は出力ファイルの拡張子から判断する）
+    fn output_format(&self) -> Result<Format, String> {
+        self.format
+            .or_else(|| Format::from_path(&self.output))
+            .ok_or_else(|| "出力形式を判断できません。--formatで指定してください".to_string())
+    }
+}
+
+/// フレームレートを解析する
+/// - 戻り値: (rate, scale)
+fn parse_fps(s: &str) -> Result<(i32, i32), String> {
+    let invalid = || format!("フレームレートが無効です: {}", s);
+
+    let (rate, scale) = if let Some((rate, scale)) = s.split_once('/') {
+        (rate.parse().ok(), scale.parse().ok())
+    } else if let Some((int, frac)) = s.split_once('.') {
+        // 29.97 → 2997/100
+        let scale = u32::try_from(frac.len())
+            .ok()
+            .and_then(|len| 10i32.checked_pow(len));
+        (format!("{}{}", int, frac).parse().ok(), scale)
+    } else {
+        (s.parse().ok(), Some(1))
+    };
+
+    match (rate, scale) {
+        (Some(rate), Some(scale)) if rate > 0 && scale > 0 => Ok((rate, scale)),
+        _ => Err(invalid()),
+    }
+}
+
+/// YUVからRGBへの変換行列を解析する
+fn parse_matrix(s: &str) -> Result<YuvMatrix, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "bt601" => Ok(YuvMatrix::Bt601),
+        "bt709" => Ok(YuvMatrix::Bt709),
+        _ => Err(format!("不明な変換行列です: {}", s)),
+    }
+}
+
+/// 指定した設定が読み込み後の値と一致するか確認する
+/// - applied: 読み込んだ設定を書き出したini
+fn check_settings(settings: &[(String, String)], applied: &Ini) -> Result<(), String> {
+    let section = applied.section(Some("Config"));
+    for (key, value) in settings {
+        match section.and_then(|s| s.get(key)) {
+            Some(applied) if applied != value => {
+                eprintln!("警告: {}={}は{}として扱われます", key, value, applied);
+            }
+            Some(_) => {}
+            None => return Err(format!("不明な設定です: {}", key)),
+        }
+    }
+    Ok(())
+}
+
+/// Y4Mまたは生のRGB/RGBAの動画ストリームを読み込む（`-`なら標準入力）
+fn read_stream(path: &Path, matrix: YuvMatrix) -> Result<StreamFrameSource, String> {
+    let source = if path == Path::new("-") {
+        StreamFrameSource::read(std::io::stdin().lock(), matrix)
+    } else {
+        File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| StreamFrameSource::read(BufReader::new(file), matrix))
+    };
+    source.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn encode(
+    format: Format,
+    ini: &Ini,
+    settings: &[(String, String)],
+    source: &dyn FrameSource,
+    output: &Path,
+) -> Result<(), String> {
+    if source.frame_count() == 0 {
+        return Err("入力にフレームがありません".to_string());
+    }
+    let source = Progress::new(source);
+
+    let result = match format {
+        Format::Gif => {
+            let config = gif_output::config::Config::from_ini(ini);
+            check_settings(settings, &config.to_ini())?;
+            eprintln!("{}", config.summary());
+            gif_output::create_gif_from_video(&source, output, &config)
+        }
+        Format::Apng => {
+            let config = png_output::config::Config::from_ini(ini);
+            check_settings(settings, &config.to_ini())?;
+            eprintln!("{}", config.summary());
+            png_output::create_apng_from_video(&source, output, &config).map(|()| None)
+        }
+        Format::Webp => {
+            let config = webp_output::config::Config::from_ini(ini);
+            check_settings(settings, &config.to_ini())?;
+            eprintln!("{}", config.summary());
+            webp_output::create_webp_from_video(&source, output, &config)
+        }
+        Format::Avif => {
+            let config = avif_output::config::Config::from_ini(ini);
+            check_settings(settings, &config.to_ini())?;
+            eprintln!("{}", config.summary());
+            avif_output::create_avif_from_video(&source, output, &config)
+        }
+    };
+    // 進捗表示の行を終える
+    eprintln!();
+
+    // ファイルサイズの上限を指定した場合は選んだ設定を表示する
+    if let Some(report) = result? {
+        eprintln!("{}", report);
+    }
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let format = args.output_format()?;
+
+    let mut ini = match &args.config {
+        Some(path) => {
+            Ini::load_from_file(path).map_err(|e| format!("設定ファイル読み込みエラー: {}", e))?
+        }
+        None => Ini::new(),
+    };
+    for (key, value) in &args.settings {
+        ini.with_section(Some("Config")).set(key, value);
+    }
+
+    if args.input.is_dir() {
+        let (rate, scale) = args.fps.unwrap_or((30, 1));
+        let source = PngSequenceSource::open(&args.input, rate, scale)?;
+        let result = encode(format, &ini, &args.settings, &source, &args.output);
+
+        // デコードに失敗した場合はエンコーダーの中断エラーより元のエラーを優先する
+        match source.take_error() {
+            Some(e) => Err(e),
+            None => result,
+        }
+    } else {
+        let mut source = read_stream(&args.input, args.matrix)?;
+        if let Some((rate, scale)) = args.fps {
+            source = source.with_frame_rate(rate, scale);
+        }
+        encode(format, &ini, &args.settings, &source, &args.output)
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("エラー: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("エラー: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn fps_forms() {
+        assert_eq!(parse_fps("30"), Ok((30, 1)));
+        assert_eq!(parse_fps("30000/1001"), Ok((30000, 1001)));
+        assert_eq!(parse_fps("29.97"), Ok((2997, 100)));
+        assert_eq!(parse_fps("23.976"), Ok((23976, 1000)));
+        for invalid in [
+            "",
+            "0",
+            "-30",
+            "30/0",
+            "30/",
+            "abc",
+            "29.97.1",
+            "1.00000000000",
+        ] {
+            assert!(parse_fps(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn options_and_settings() {
+        let args = parse(&[
+            "frames",
+            "--fps",
+            "29.97",
+            "--matrix=BT709",
+            "out.gif",
+            "--config",
+            "gif.ini",
+            "--max-file-size",
+            "512",
+            "--repeat=0",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(args.input, PathBuf::from("frames"));
+        assert_eq!(args.output, PathBuf::from("out.gif"));
+        assert_eq!(args.fps, Some((2997, 100)));
+        assert_eq!(args.matrix, YuvMatrix::Bt709);
+        assert_eq!(args.config, Some(PathBuf::from("gif.ini")));
+        // キーの`-`は設定ファイルと同じ`_`にする
+        assert_eq!(
+            args.settings,
+            [
+                ("max_file_size".to_string(), "512".to_string()),
+                ("repeat".to_string(), "0".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn stdin_input_and_help() {
+        let args = parse(&["-", "out.webp"]).unwrap().unwrap();
+        assert_eq!(args.input, PathBuf::from("-"));
+        assert!(parse(&["-", "out.webp", "--help"]).unwrap().is_none());
+        assert!(parse(&["-h"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn missing_value_is_an_error() {
+        assert!(parse(&["in.y4m", "out.gif", "--fps"]).is_err());
+        assert!(parse(&["in.y4m", "out.gif", "--quality"]).is_err());
+        assert!(parse(&["in.y4m", "out.gif", "--fps", "fast"]).is_err());
+        assert!(parse(&["in.y4m", "out.gif", "--matrix", "bt2020"]).is_err());
+        // 入力と出力の両方が必要
+        assert!(parse(&["in.y4m"]).is_err());
+        assert!(parse(&["a", "b", "c"]).is_err());
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        assert!(parse(&["in.y4m", "out.gif", "-x"]).is_err());
+        assert!(parse(&["in.y4m", "out.gif", "--format", "bmp"]).is_err());
+
+        // 設定のキーは出力時に書き出した設定と照合する
+        let args = parse(&["in.y4m", "out.gif", "--no-such-key", "1"])
+            .unwrap()
+            .unwrap();
+        let ini = gif_output::config::Config::default().to_ini();
+        assert!(check_settings(&args.settings, &ini).is_err());
+        let args = parse(&["in.y4m", "out.gif", "--repeat", "3"])
+            .unwrap()
+            .unwrap();
+        assert!(check_settings(&args.settings, &ini).is_ok());
+    }
+
+    #[test]
+    fn format_from_output_extension() {
+        let format = |args: &[&str]| parse(args).unwrap().unwrap().output_format();
+        assert_eq!(format(&["in", "out.gif"]), Ok(Format::Gif));
+        assert_eq!(format(&["in", "out.PNG"]), Ok(Format::Apng));
+        assert_eq!(format(&["in", "out.apng"]), Ok(Format::Apng));
+        assert_eq!(format(&["in", "dir/out.webp"]), Ok(Format::Webp));
+        assert_eq!(format(&["in", "out.avif"]), Ok(Format::Avif));
+        assert!(format(&["in", "out.mp4"]).is_err());
+        assert!(format(&["in", "out"]).is_err());
+        // --formatの指定は拡張子より優先する
+        assert_eq!(
+            format(&["in", "out.png", "--format", "webp"]),
+            Ok(Format::Webp)
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use aviutl::convert;
+use aviutl::frame::{FrameBuffer, FrameView, PixelLayout};
+use aviutl::source::FrameSource;
+use png::{BitDepth, ColorType, Decoder, Transformations};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// 連番PNGのフレームを供給するソース
+///
+/// フレームは読み出すたびにデコードしてPA64（乗算済みα）にし、プラグインと同じ変換処理を通す
+pub struct PngSequenceSource {
+    files: Vec<PathBuf>,
+    width: i32,
+    height: i32,
+    rate: i32,
+    scale: i32,
+    /// デコードに失敗したときのエラー（以降は中断扱いにする）
+    error: RefCell<Option<String>>,
+}
+
+impl PngSequenceSource {
+    /// ディレクトリ内の連番PNGを開く
+    ///
+    /// ファイル名の末尾の数字の順に並べ、数字を含まないファイルは無視する
+    pub fn open(dir: &Path, rate: i32, scale: i32) -> Result<Self, String> {
+        let entries =
+            std::fs::read_dir(dir).map_err(|e| format!("ディレクトリ読み込みエラー: {}", e))?;
+
+        let mut frames = Vec::new();
+        for entry in entries {
+            let path = entry
+                .map_err(|e| format!("ディレクトリ読み込みエラー: {}", e))?
+                .path();
+            let is_png = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+            if let Some(number) = frame_number(&path).filter(|_| is_png) {
+                frames.push((number, path));
+            }
+        }
+        frames.sort();
+
+        let files: Vec<PathBuf> = frames.into_iter().map(|(_, path)| path).collect();
+        let first = files
+            .first()
+            .ok_or_else(|| format!("連番PNGが見つかりません: {}", dir.display()))?;
+
+        let (width, height) = read_size(first)?;
+        for path in &files[1..] {
+            if read_size(path)? != (width, height) {
+                return Err(format!(
+                    "フレームサイズが一致しません: {} ({}x{}である必要があります)",
+                    path.display(),
+                    width,
+                    height
+                ));
+            }
+        }
+
+        Ok(Self {
+            files,
+            width: width as i32,
+            height: height as i32,
+            rate,
+            scale,
+            error: RefCell::new(None),
+        })
+    }
+
+    /// デコードで発生したエラーを取り出す
+    pub fn take_error(&self) -> Option<String> {
+        self.error.borrow_mut().take()
+    }
+
+    /// フレームをPA64にデコードする（失敗したらエラーを記録する）
+    fn decode(&self, frame: i32) -> Option<Vec<u16>> {
+        let path = usize::try_from(frame)
+            .ok()
+            .and_then(|i| self.files.get(i))?;
+        match decode_pa64(path) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                *self.error.borrow_mut() = Some(format!("{}: {}", path.display(), e));
+                None
+            }
+        }
+    }
+
+    /// 書き込み先のバッファをこのソースの大きさに合わせる
+    fn prepare<'b, T: Copy + Default>(
+        &self,
+        buffer: &'b mut FrameBuffer<T>,
+        layout: PixelLayout,
+    ) -> &'b mut [T] {
+        buffer.prepare(self.width as usize, self.height as usize, layout)
+    }
+}
+
+impl FrameSource for PngSequenceSource {
+    fn width(&self) -> i32 {
+        self.width
+    }
+
+    fn height(&self) -> i32 {
+        self.height
+    }
+
+    fn rate(&self) -> i32 {
+        self.rate
+    }
+
+    fn scale(&self) -> i32 {
+        self.scale
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.files.len() as i32
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let data = self.decode(frame)?;
+        convert::parallel::pa64_to_rgb_into(&data, self.prepare(buffer, PixelLayout::Rgb));
+        Some(buffer.view())
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: convert::AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let data = self.decode(frame)?;
+        convert::parallel::pa64_to_rgba_into(&data, alpha, self.prepare(buffer, PixelLayout::Rgba));
+        Some(buffer.view())
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        let data = self.decode(frame)?;
+        convert::parallel::pa64_to_rgb16_into(&data, self.prepare(buffer, PixelLayout::Rgb));
+        Some(buffer.view())
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: convert::AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        let data = self.decode(frame)?;
+        convert::parallel::pa64_to_rgba16_into(
+            &data,
+            alpha,
+            self.prepare(buffer, PixelLayout::Rgba),
+        );
+        Some(buffer.view())
+    }
+
+    fn is_abort(&self) -> bool {
+        self.error.borrow().is_some()
+    }
+}
+
+/// ファイル名の末尾にある数字
+fn frame_number(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    let end = stem.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = stem[..end]
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .len();
+    stem[start..end].parse().ok()
+}
+
+/// PNGの大きさをヘッダーから読み取る
+fn read_size(path: &Path) -> Result<(u32, u32), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let reader = Decoder::new(BufReader::new(file))
+        .read_info()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let info = reader.info();
+    Ok((info.width, info.height))
+}
+
+/// PNGをデコードしてPA64にする
+fn decode_pa64(path: &Path) -> Result<Vec<u16>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = Decoder::new(BufReader::new(file));
+    // パレットや1/2/4bitのグレースケール、tRNSを8bit以上のRGB(A)/グレースケールに展開する
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+    let data = &data[..info.buffer_size()];
+
+    // 8bitのサンプルは16bitに引き伸ばし、16bitはビッグエンディアンから読み取る
+    let samples: Vec<u16> = match info.bit_depth {
+        BitDepth::Eight => data.iter().map(|&v| v as u16 * 257).collect(),
+        BitDepth::Sixteen => data
+            .chunks_exact(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect(),
+        bit_depth => return Err(format!("未対応のビット深度です: {:?}", bit_depth)),
+    };
+
+    let rgba: Vec<u16> = match info.color_type {
+        ColorType::Rgba => samples,
+        ColorType::Rgb => samples
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], u16::MAX])
+            .collect(),
+        ColorType::GrayscaleAlpha => samples
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        ColorType::Grayscale => samples.iter().flat_map(|&v| [v, v, v, u16::MAX]).collect(),
+        color_type => return Err(format!("未対応のカラータイプです: {:?}", color_type)),
+    };
+
+    Ok(convert::rgba16_to_pa64(&rgba))
+}
@@ -0,0 +1,82 @@
+use aviutl::convert::AlphaMode;
+use aviutl::frame::{FrameBuffer, FrameView};
+use aviutl::source::FrameSource;
+
+/// 進捗を標準エラー出力に表示するソース
+pub struct Progress<'a> {
+    source: &'a dyn FrameSource,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(source: &'a dyn FrameSource) -> Self {
+        Self { source }
+    }
+}
+
+impl FrameSource for Progress<'_> {
+    fn width(&self) -> i32 {
+        self.source.width()
+    }
+
+    fn height(&self) -> i32 {
+        self.source.height()
+    }
+
+    fn rate(&self) -> i32 {
+        self.source.rate()
+    }
+
+    fn scale(&self) -> i32 {
+        self.source.scale()
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.source.frame_count()
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.source.read_rgb(frame, buffer)
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.source.read_rgba(frame, alpha, buffer)
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.source.read_rgb16(frame, buffer)
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.source.read_rgba16(frame, alpha, buffer)
+    }
+
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        self.source.read_pa64(frame, buffer)
+    }
+
+    fn is_abort(&self) -> bool {
+        self.source.is_abort()
+    }
+
+    fn rest_time_disp(&self, now: i32, total: i32) {
+        eprint!("\r{}/{}", now + 1, total);
+    }
+}
@@ -0,0 +1,59 @@
+//! 中断されたときの出力の扱い
+//!
+//! 既定では中断すると出力しないが、指定した場合はそれまでのフレームで再生できるファイルを出力する。
+
+use crate::settings;
+use ini::{Properties, SectionSetter};
+
+/// 中断された場合のエラーメッセージ
+pub const ABORTED: &str = "処理が中断されました";
+
+/// 中断されたときの出力の扱い
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OnAbort {
+    /// 中断した場合も、それまでのフレームで再生できるファイルを出力する
+    ///
+    /// 1フレームも出力していない場合は出力しない。ファイルサイズの上限を指定した場合は、
+    /// 中断までに上限に収まった中で最も大きい設定の出力を残す
+    pub finish: bool,
+}
+
+impl OnAbort {
+    /// iniのセクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn load_ini(section: Option<&Properties>) -> Self {
+        let default = Self::default();
+        Self {
+            finish: settings::get_bool(section, "finish_on_abort").unwrap_or(default.finish),
+        }
+    }
+
+    /// iniのセクションに書き出す
+    pub fn save_ini(&self, section: &mut SectionSetter) {
+        section.set("finish_on_abort", settings::bool_value(self.finish));
+    }
+
+    /// 出力ダイアログに表示する設定の概要（中断時に保存しない場合は空、それ以外は先頭に空白を付ける）
+    pub fn summary(&self) -> String {
+        match self.finish {
+            true => " 中断時保存".to_string(),
+            false => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip_through_ini() {
+        let on_abort = OnAbort { finish: true };
+        let mut ini = ini::Ini::new();
+        on_abort.save_ini(&mut ini.with_section(Some("Config")));
+        assert_eq!(OnAbort::load_ini(ini.section(Some("Config"))), on_abort);
+        assert_eq!(OnAbort::load_ini(None), OnAbort::default());
+
+        assert_eq!(on_abort.summary(), " 中断時保存");
+        assert_eq!(OnAbort::default().summary(), "");
+    }
+}
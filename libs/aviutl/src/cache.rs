@@ -0,0 +1,405 @@
+//! 読み込んだフレームの一時ファイルへの保存
+//!
+//! 入力を先頭から順に一度だけ読み込み、PA64のまま一時ファイルに保存して何度でも読み直せるようにする。
+//! 逆順に読む場合や、同じフレームで何度も出力し直す場合に使う。
+//! 読み込めなかったフレームは直前のフレームで埋めるので、1フレームの失敗で以降のフレームが欠けることはない。
+
+use crate::convert::{self, AlphaMode};
+use crate::frame::{FrameBuffer, FrameView, PixelLayout};
+use crate::source::FrameSource;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// フレームを保存する一時ファイル
+///
+/// フレームは先頭から順に追加し、削除はドロップ時に行う
+pub(crate) struct FrameCache {
+    file: File,
+    path: PathBuf,
+    /// 1フレームのバイト数
+    frame_bytes: usize,
+    /// 保存済みのフレーム数
+    len: usize,
+    bytes: Vec<u8>,
+}
+
+impl FrameCache {
+    /// - frame_bytes: 1フレームのバイト数
+    pub(crate) fn new(frame_bytes: usize) -> std::io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "aviutl-frame-cache-{}-{}.tmp",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self {
+            file,
+            path,
+            frame_bytes,
+            len: 0,
+            bytes: Vec::new(),
+        })
+    }
+
+    /// 保存済みのフレーム数
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// フレームを末尾に追加する
+    pub(crate) fn push_bytes(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start((self.len * self.frame_bytes) as u64))?;
+        self.file.write_all(data)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// 保存済みのフレームを読み込む
+    pub(crate) fn read_bytes(&mut self, frame: usize, data: &mut [u8]) -> std::io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start((frame * self.frame_bytes) as u64))?;
+        self.file.read_exact(data)
+    }
+
+    fn push(&mut self, data: &[u16]) -> std::io::Result<()> {
+        let mut bytes = std::mem::take(&mut self.bytes);
+        bytes.clear();
+        bytes.extend(data.iter().flat_map(|v| v.to_le_bytes()));
+        let result = self.push_bytes(&bytes);
+        self.bytes = bytes;
+        result
+    }
+
+    fn read(&mut self, frame: usize, data: &mut [u16]) -> std::io::Result<()> {
+        let mut bytes = std::mem::take(&mut self.bytes);
+        bytes.resize(self.frame_bytes, 0);
+        let result = self.read_bytes(frame, &mut bytes);
+        for (v, bytes) in data.iter_mut().zip(bytes.chunks_exact(2)) {
+            *v = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        self.bytes = bytes;
+        result
+    }
+}
+
+impl Drop for FrameCache {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// 作業領域
+struct Scratch {
+    cache: FrameCache,
+    frame: FrameBuffer<u16>,
+}
+
+/// 別のソースのフレームを一時ファイルに保存して供給するソース
+///
+/// 入力は先頭から順に一度だけ読み込み、読み込んだフレームはどの順番でも読み直せる
+pub struct CachedSource<'a> {
+    source: &'a dyn FrameSource,
+    scratch: RefCell<Scratch>,
+}
+
+impl<'a> CachedSource<'a> {
+    /// 入力の全フレームを読み込んで一時ファイルに保存する
+    ///
+    /// 読み込む間は進捗を表示する（中断された場合はそこまでのフレームを保存する）
+    /// - 戻り値: 一時ファイルを作れない場合や書き込めない場合はエラー
+    pub fn new(source: &'a dyn FrameSource) -> Result<Self, String> {
+        let (width, height) = (source.width() as usize, source.height() as usize);
+        let mut cache = FrameCache::new(width * height * 4 * 2)
+            .map_err(|e| format!("一時ファイル作成エラー: {}", e))?;
+
+        let mut buffer = FrameBuffer::new();
+        let mut previous = FrameBuffer::new();
+        let frame_count = source.frame_count();
+        for frame in 0..frame_count {
+            if source.is_abort() {
+                break;
+            }
+            let data = match source.read_pa64(frame, &mut buffer) {
+                Some(data) => data,
+                // 読み込めなかったフレームは直前のフレーム（先頭の場合は透明）で埋める
+                None => {
+                    let data = previous.prepare(width, height, PixelLayout::Rgba);
+                    match cache.len().checked_sub(1) {
+                        Some(last) => cache
+                            .read(last, data)
+                            .map_err(|e| format!("一時ファイル読み込みエラー: {}", e))?,
+                        None => data.fill(0),
+                    }
+                    data
+                }
+            };
+            cache
+                .push(data)
+                .map_err(|e| format!("一時ファイル書き込みエラー: {}", e))?;
+            source.rest_time_disp(frame, frame_count);
+        }
+
+        Ok(Self {
+            source,
+            scratch: RefCell::new(Scratch {
+                cache,
+                frame: FrameBuffer::new(),
+            }),
+        })
+    }
+
+    /// フレームを一時ファイルから読み込み、変換して出力する
+    ///
+    /// - write: (PA64, 出力先)を受け取る処理
+    /// - 戻り値: 保存していないフレームの場合はNone
+    fn read_cached<'b, T: Copy + Default>(
+        &self,
+        frame: i32,
+        layout: PixelLayout,
+        buffer: &'b mut FrameBuffer<T>,
+        write: impl FnOnce(&[u16], &mut [T]),
+    ) -> Option<FrameView<'b, T>> {
+        let mut scratch = self.scratch.borrow_mut();
+        let Scratch {
+            cache,
+            frame: cached,
+        } = &mut *scratch;
+        let frame = usize::try_from(frame)
+            .ok()
+            .filter(|&frame| frame < cache.len())?;
+
+        let (width, height) = (self.source.width() as usize, self.source.height() as usize);
+        let data = cached.prepare(width, height, PixelLayout::Rgba);
+        cache.read(frame, data).ok()?;
+        write(cached.as_slice(), buffer.prepare(width, height, layout));
+        Some(buffer.view())
+    }
+}
+
+impl FrameSource for CachedSource<'_> {
+    fn width(&self) -> i32 {
+        self.source.width()
+    }
+
+    fn height(&self) -> i32 {
+        self.source.height()
+    }
+
+    fn rate(&self) -> i32 {
+        self.source.rate()
+    }
+
+    fn scale(&self) -> i32 {
+        self.source.scale()
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.source.frame_count()
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.read_cached(frame, PixelLayout::Rgb, buffer, |data, dst| {
+            convert::parallel::pa64_to_rgb_into(data, dst)
+        })
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.read_cached(frame, PixelLayout::Rgba, buffer, |data, dst| {
+            convert::parallel::pa64_to_rgba_into(data, alpha, dst)
+        })
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.read_cached(frame, PixelLayout::Rgb, buffer, |data, dst| {
+            convert::parallel::pa64_to_rgb16_into(data, dst)
+        })
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.read_cached(frame, PixelLayout::Rgba, buffer, |data, dst| {
+            convert::parallel::pa64_to_rgba16_into(data, alpha, dst)
+        })
+    }
+
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        self.read_cached(frame, PixelLayout::Rgba, buffer, |data, dst| {
+            dst.copy_from_slice(data)
+        })
+        .map(|view| view.data())
+    }
+
+    fn is_cached(&self) -> bool {
+        true
+    }
+
+    fn is_abort(&self) -> bool {
+        self.source.is_abort()
+    }
+
+    fn rest_time_disp(&self, now: i32, total: i32) {
+        self.source.rest_time_disp(now, total)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::source::MemoryFrameSource;
+
+    /// 読み込んだフレームの順番を記録するソース
+    pub(crate) struct RecordingSource {
+        source: MemoryFrameSource,
+        pub(crate) reads: RefCell<Vec<i32>>,
+        /// 表示した進捗のフレーム番号
+        pub(crate) progress: RefCell<Vec<i32>>,
+        /// 読み込めないことにするフレーム番号
+        pub(crate) unreadable: Vec<i32>,
+    }
+
+    impl FrameSource for RecordingSource {
+        fn width(&self) -> i32 {
+            self.source.width()
+        }
+
+        fn height(&self) -> i32 {
+            self.source.height()
+        }
+
+        fn rate(&self) -> i32 {
+            self.source.rate()
+        }
+
+        fn scale(&self) -> i32 {
+            self.source.scale()
+        }
+
+        fn frame_count(&self) -> i32 {
+            self.source.frame_count()
+        }
+
+        fn read_rgb<'b>(
+            &self,
+            frame: i32,
+            buffer: &'b mut FrameBuffer<u8>,
+        ) -> Option<FrameView<'b, u8>> {
+            self.source.read_rgb(frame, buffer)
+        }
+
+        fn read_rgba<'b>(
+            &self,
+            frame: i32,
+            alpha: AlphaMode,
+            buffer: &'b mut FrameBuffer<u8>,
+        ) -> Option<FrameView<'b, u8>> {
+            self.source.read_rgba(frame, alpha, buffer)
+        }
+
+        fn read_rgb16<'b>(
+            &self,
+            frame: i32,
+            buffer: &'b mut FrameBuffer<u16>,
+        ) -> Option<FrameView<'b, u16>> {
+            self.source.read_rgb16(frame, buffer)
+        }
+
+        fn read_rgba16<'b>(
+            &self,
+            frame: i32,
+            alpha: AlphaMode,
+            buffer: &'b mut FrameBuffer<u16>,
+        ) -> Option<FrameView<'b, u16>> {
+            self.source.read_rgba16(frame, alpha, buffer)
+        }
+
+        fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+            self.reads.borrow_mut().push(frame);
+            if self.unreadable.contains(&frame) {
+                return None;
+            }
+            self.source.read_pa64(frame, buffer)
+        }
+
+        fn rest_time_disp(&self, now: i32, _total: i32) {
+            self.progress.borrow_mut().push(now);
+        }
+    }
+
+    /// フレーム番号を赤のチャンネルに書いた1x1のソース
+    pub(crate) fn numbered(frames: u8) -> RecordingSource {
+        let mut source = MemoryFrameSource::new(1, 1, 30, 1);
+        for frame in 0..frames {
+            source.push_rgb(&[frame, 0, 0]).unwrap();
+        }
+        RecordingSource {
+            source,
+            reads: RefCell::new(Vec::new()),
+            progress: RefCell::new(Vec::new()),
+            unreadable: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn source_is_read_once_in_order() {
+        let source = numbered(3);
+        let cached = CachedSource::new(&source).unwrap();
+        let read = |frame| cached.frame_rgb(frame).unwrap()[0];
+        assert_eq!([read(1), read(0), read(1), read(2)], [1, 0, 1, 2]);
+        assert_eq!(*source.reads.borrow(), [0, 1, 2]);
+        assert!(cached.frame_rgb(3).is_none());
+        assert!(cached.frame_rgb(-1).is_none());
+    }
+
+    #[test]
+    fn progress_is_shown_while_filling_the_cache() {
+        let source = numbered(3);
+        let cached = CachedSource::new(&source).unwrap();
+        assert_eq!(*source.progress.borrow(), [0, 1, 2]);
+
+        // 保存済みのフレームを読み直す間は表示しない
+        assert_eq!(cached.frame_rgb(2).unwrap()[0], 2);
+        assert_eq!(source.progress.borrow().len(), 3);
+    }
+
+    #[test]
+    fn unreadable_frames_repeat_the_previous_frame() {
+        let mut source = numbered(4);
+        source.unreadable = vec![0, 2];
+        let cached = CachedSource::new(&source).unwrap();
+
+        // 先頭は透明、それ以外は直前のフレームで埋めて、後のフレームも読み込める
+        let mut buffer = FrameBuffer::new();
+        let frames: Vec<_> = (0..4)
+            .map(|frame| cached.read_pa64(frame, &mut buffer).unwrap().to_vec())
+            .collect();
+        assert_eq!(frames[0], [0, 0, 0, 0]);
+        assert_eq!(frames[2], frames[1]);
+        assert_ne!(frames[3], frames[1]);
+    }
+}
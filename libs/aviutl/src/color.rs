@@ -0,0 +1,280 @@
+//! 出力ファイルの色空間の指定
+//!
+//! 入力のRGBはsRGBとして扱い、各形式の方法で色空間を書き込む。
+//! PNGはsRGB・gAMA・cHRMチャンク、WebPはICCプロファイル、AVIFはCICP（nclx）で指定する。
+
+use std::str::FromStr;
+
+/// 埋め込む色空間の情報（PNG・WebP）
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorProfile {
+    /// 書き込まない（表示するソフトウェアの扱いに任せる）
+    None,
+    /// sRGB
+    #[default]
+    Srgb,
+    /// 指定したICCプロファイル
+    Icc,
+}
+
+impl From<ColorProfile> for &'static str {
+    fn from(profile: ColorProfile) -> Self {
+        match profile {
+            ColorProfile::None => "指定しない",
+            ColorProfile::Srgb => "sRGB",
+            ColorProfile::Icc => "ICCプロファイル",
+        }
+    }
+}
+
+impl FromStr for ColorProfile {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(ColorProfile::None),
+            Ok(1) => Ok(ColorProfile::Srgb),
+            Ok(2) => Ok(ColorProfile::Icc),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ColorProfile {
+    pub fn to_index(&self) -> u32 {
+        match self {
+            ColorProfile::None => 0,
+            ColorProfile::Srgb => 1,
+            ColorProfile::Icc => 2,
+        }
+    }
+}
+
+/// CICP（ITU-T H.273）の色空間の指定
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cicp {
+    /// 色域（ColourPrimaries）
+    pub primaries: u16,
+    /// 伝達特性（TransferCharacteristics）
+    pub transfer: u16,
+    /// RGBとYUVの変換行列（MatrixCoefficients）
+    pub matrix: u16,
+}
+
+/// YUVで出力する場合の色空間（AVIF）
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// sRGB（色域・変換行列はBT.709・BT.601、伝達特性はsRGB）
+    #[default]
+    Srgb,
+    /// BT.709
+    Bt709,
+    /// BT.601（SMPTE 170M）
+    Bt601,
+}
+
+impl From<ColorSpace> for &'static str {
+    fn from(space: ColorSpace) -> Self {
+        match space {
+            ColorSpace::Srgb => "sRGB",
+            ColorSpace::Bt709 => "BT.709",
+            ColorSpace::Bt601 => "BT.601",
+        }
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(ColorSpace::Srgb),
+            Ok(1) => Ok(ColorSpace::Bt709),
+            Ok(2) => Ok(ColorSpace::Bt601),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ColorSpace {
+    pub fn to_index(&self) -> u32 {
+        match self {
+            ColorSpace::Srgb => 0,
+            ColorSpace::Bt709 => 1,
+            ColorSpace::Bt601 => 2,
+        }
+    }
+
+    /// 書き込むCICPの値（RGBからYUVへの変換にも同じ変換行列を使う）
+    pub const fn cicp(self) -> Cicp {
+        match self {
+            ColorSpace::Srgb => Cicp {
+                primaries: 1,
+                transfer: 13,
+                matrix: 6,
+            },
+            ColorSpace::Bt709 => Cicp {
+                primaries: 1,
+                transfer: 1,
+                matrix: 1,
+            },
+            ColorSpace::Bt601 => Cicp {
+                primaries: 6,
+                transfer: 6,
+                matrix: 6,
+            },
+        }
+    }
+}
+
+/// ICCプロファイルのファイルを読み込む
+pub fn load_icc(path: &str) -> Result<Vec<u8>, String> {
+    let path = path.trim();
+    if path.is_empty() {
+        return Err("ICCプロファイルのファイルが指定されていません".into());
+    }
+    let data = std::fs::read(path).map_err(|e| format!("ICCプロファイル読み込みエラー: {}", e))?;
+    // ヘッダー（128バイト）の先頭にプロファイルの大きさ、36バイト目に"acsp"がある
+    let valid = data.len() >= 128
+        && &data[36..40] == b"acsp"
+        && u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize == data.len();
+    if !valid {
+        return Err("ICCプロファイル読み込みエラー: ICCプロファイルではありません".into());
+    }
+    Ok(data)
+}
+
+/// トーンカーブの点の数
+const SRGB_CURVE_POINTS: usize = 64;
+
+/// sRGBのICCプロファイル（v2のディスプレイプロファイル）を作る
+///
+/// ICCプロファイルでしか色空間を指定できない形式に埋め込むための、小さいプロファイル
+pub fn srgb_icc_profile() -> Vec<u8> {
+    // sRGBの原色と白色点をD50に順応させたXYZ
+    const RED: [f64; 3] = [0.436_074_7, 0.222_504_5, 0.013_932_2];
+    const GREEN: [f64; 3] = [0.385_064_9, 0.716_878_6, 0.097_104_5];
+    const BLUE: [f64; 3] = [0.143_080_4, 0.060_616_9, 0.714_173_3];
+    const D50: [f64; 3] = [0.964_2, 1.0, 0.824_9];
+
+    fn s15_fixed16(value: f64) -> [u8; 4] {
+        ((value * 65536.0).round() as i32).to_be_bytes()
+    }
+    fn xyz(value: [f64; 3]) -> Vec<u8> {
+        let mut data = b"XYZ \0\0\0\0".to_vec();
+        value.iter().for_each(|&v| data.extend(s15_fixed16(v)));
+        data
+    }
+
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    let name = b"sRGB\0";
+    desc.extend((name.len() as u32).to_be_bytes());
+    desc.extend(name);
+    // Unicode・ScriptCodeの説明は空
+    desc.extend([0; 4 + 4 + 2 + 1 + 67]);
+
+    let mut cprt = b"text\0\0\0\0".to_vec();
+    cprt.extend(b"No copyright, use freely\0");
+
+    // sRGBの伝達特性を等間隔の点で表す
+    let mut curve = b"curv\0\0\0\0".to_vec();
+    curve.extend((SRGB_CURVE_POINTS as u32).to_be_bytes());
+    for i in 0..SRGB_CURVE_POINTS {
+        let v = i as f64 / (SRGB_CURVE_POINTS - 1) as f64;
+        let linear = if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+        curve.extend(((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+
+    let elements: [(&[u8; 4], Vec<u8>); 6] = [
+        (b"desc", desc),
+        (b"cprt", cprt),
+        (b"wtpt", xyz(D50)),
+        (b"rXYZ", xyz(RED)),
+        (b"gXYZ", xyz(GREEN)),
+        (b"bXYZ", xyz(BLUE)),
+    ];
+    let tags = elements.len() + 3;
+    let mut table = (tags as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let data_start = 128 + 4 + tags * 12;
+    let mut add = |signature: &[&[u8; 4]], element: &[u8]| {
+        let offset = data_start + data.len();
+        for signature in signature {
+            table.extend(*signature);
+            table.extend((offset as u32).to_be_bytes());
+            table.extend((element.len() as u32).to_be_bytes());
+        }
+        data.extend(element);
+        // タグのデータは4バイト境界に置く
+        data.resize(data.len().next_multiple_of(4), 0);
+    };
+    for (signature, element) in &elements {
+        add(&[signature], element);
+    }
+    // RGBのトーンカーブは同じなので1つのデータを指す
+    add(&[b"rTRC", b"gTRC", b"bTRC"], &curve);
+
+    let size = data_start + data.len();
+    let mut header = Vec::with_capacity(128);
+    header.extend((size as u32).to_be_bytes());
+    header.extend([0; 4]); // CMM
+    header.extend([0x02, 0x10, 0, 0]); // バージョン2.1
+    header.extend(b"mntrRGB XYZ ");
+    header.extend([0; 12]); // 作成日時
+    header.extend(b"acsp");
+    header.extend([0; 4 + 4 + 4 + 4 + 8 + 4]); // プラットフォーム・フラグ・機器・属性・レンダリングインテント
+    D50.iter().for_each(|&v| header.extend(s15_fixed16(v)));
+    header.resize(128, 0);
+
+    [header, table, data].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_profile_has_valid_tags() {
+        let profile = srgb_icc_profile();
+        assert_eq!(
+            u32::from_be_bytes(profile[0..4].try_into().unwrap()) as usize,
+            profile.len()
+        );
+        assert_eq!(&profile[36..40], b"acsp");
+
+        // 全てのタグがプロファイル内の4バイト境界を指し、データの種類が合っている
+        let tags = u32::from_be_bytes(profile[128..132].try_into().unwrap()) as usize;
+        assert_eq!(tags, 9);
+        for tag in profile[132..132 + tags * 12].chunks_exact(12) {
+            let offset = u32::from_be_bytes(tag[4..8].try_into().unwrap()) as usize;
+            let size = u32::from_be_bytes(tag[8..12].try_into().unwrap()) as usize;
+            assert_eq!(offset % 4, 0);
+            assert!(offset + size <= profile.len());
+            let kind: &[u8] = match &tag[0..4] {
+                b"desc" => b"desc",
+                b"cprt" => b"text",
+                b"rTRC" | b"gTRC" | b"bTRC" => b"curv",
+                _ => b"XYZ ",
+            };
+            assert_eq!(&profile[offset..offset + 4], kind);
+        }
+    }
+
+    #[test]
+    fn icc_profile_is_validated() {
+        let path = std::env::temp_dir().join(format!("{}-color.icc", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        std::fs::write(&path, srgb_icc_profile()).unwrap();
+        assert_eq!(load_icc(path_str).unwrap(), srgb_icc_profile());
+
+        std::fs::write(&path, b"not a profile").unwrap();
+        assert!(load_icc(path_str).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(load_icc("").is_err());
+    }
+}
@@ -0,0 +1,519 @@
+//! AviUtl2から受け取る画像データのピクセル変換
+//!
+//! このモジュールの関数は1画素ずつ処理するスカラー実装で、変換結果の基準となる。
+//! 実際のフレーム取得には同じ結果を返す[`parallel`]の実装を使う。
+
+pub mod parallel;
+mod simd;
+
+/// RGB24のDIBデータの1行あたりのバイト数（4バイト境界アライメント）
+#[inline(always)]
+pub fn dib_rgb24_stride(width: usize) -> usize {
+    (width * 3).div_ceil(4) * 4
+}
+
+/// 下から上に格納されたBGR24のDIBデータを上から下のRGB24に変換
+pub fn dib_bgr24_to_rgb(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let input_stride = dib_rgb24_stride(width);
+    let mut image_buffer = Vec::with_capacity(width * height * 3);
+
+    // BMPは下から上に格納されているので反転してBGR→RGB変換
+    for y in (0..height).rev() {
+        let row_start = y * input_stride;
+        let row_end = row_start + width * 3;
+        for bgr_pixel in data[row_start..row_end].chunks_exact(3) {
+            image_buffer.push(bgr_pixel[2]); // R
+            image_buffer.push(bgr_pixel[1]); // G
+            image_buffer.push(bgr_pixel[0]); // B
+        }
+    }
+    image_buffer
+}
+
+/// 乗算済みαからストレートαに戻す際のαチャンネルの扱い
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// 半透明を保ったまま厳密にストレートαに戻す
+    #[default]
+    Straight,
+    /// 8bit換算のαがしきい値未満なら完全透明、それ以外は不透明にする
+    Threshold(u8),
+    /// 指定色の背景に合成して不透明にする
+    Matte([u8; 3]),
+}
+
+/// "RRGGBB"形式の色を解析
+pub fn parse_color(s: &str) -> Option<[u8; 3]> {
+    let s = s.trim().trim_start_matches('#');
+    if s.len() != 6 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let value = u32::from_str_radix(s, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+/// 色を"RRGGBB"形式の文字列にする
+pub fn format_color(color: [u8; 3]) -> String {
+    format!("{:02X}{:02X}{:02X}", color[0], color[1], color[2])
+}
+
+/// 乗算済みの16bit値を背景色に合成
+#[inline(always)]
+fn matte16(c: u32, a: u32, background: u8) -> u32 {
+    let background = background as u32 * 257;
+    (c + (background * (65535 - a) + 32767) / 65535).min(65535)
+}
+
+/// PA64（乗算済みα）の1画素をRGBA32に変換
+#[inline(always)]
+fn pa64_pixel_to_rgba(pixel: &[u16], alpha: AlphaMode) -> [u8; 4] {
+    let r = pixel[0] as u32;
+    let g = pixel[1] as u32;
+    let b = pixel[2] as u32;
+    let a = pixel[3] as u32;
+    let a8 = (a + 128) / 257;
+
+    let unpremultiply = |c: u32| ((c * 255 + a / 2) / a).min(255) as u8;
+
+    match alpha {
+        AlphaMode::Straight if a == 0 => [0, 0, 0, 0],
+        AlphaMode::Straight => [
+            unpremultiply(r),
+            unpremultiply(g),
+            unpremultiply(b),
+            a8 as u8,
+        ],
+        AlphaMode::Threshold(threshold) if a8 < threshold as u32 => [0, 0, 0, 0],
+        AlphaMode::Threshold(_) if a == 0 => [0, 0, 0, 255],
+        AlphaMode::Threshold(_) => [unpremultiply(r), unpremultiply(g), unpremultiply(b), 255],
+        AlphaMode::Matte(background) => [
+            ((matte16(r, a, background[0]) + 128) / 257) as u8,
+            ((matte16(g, a, background[1]) + 128) / 257) as u8,
+            ((matte16(b, a, background[2]) + 128) / 257) as u8,
+            255,
+        ],
+    }
+}
+
+/// PA64（乗算済みα）のデータをRGBA32に変換
+pub fn pa64_to_rgba(data: &[u16], alpha: AlphaMode) -> Vec<u8> {
+    let mut image_buffer = Vec::with_capacity(data.len());
+
+    for chunk in data.chunks_exact(4) {
+        image_buffer.extend_from_slice(&pa64_pixel_to_rgba(chunk, alpha));
+    }
+
+    image_buffer
+}
+
+/// PA64（乗算済みα）のデータを黒背景に合成したRGB24に変換
+pub fn pa64_to_rgb(data: &[u16]) -> Vec<u8> {
+    let mut image_buffer = Vec::with_capacity(data.len() / 4 * 3);
+
+    for chunk in data.chunks_exact(4) {
+        for &c in &chunk[..3] {
+            image_buffer.push(((c as u32 + 128) / 257) as u8);
+        }
+    }
+
+    image_buffer
+}
+
+/// PA64（乗算済みα）の1画素を16bit RGBAに変換
+#[inline(always)]
+fn pa64_pixel_to_rgba16(pixel: &[u16], alpha: AlphaMode) -> [u16; 4] {
+    let r = pixel[0] as u32;
+    let g = pixel[1] as u32;
+    let b = pixel[2] as u32;
+    let a = pixel[3] as u32;
+    let a8 = (a + 128) / 257;
+
+    let unpremultiply = |c: u32| ((c * 65535 + a / 2) / a).min(65535) as u16;
+
+    match alpha {
+        AlphaMode::Straight if a == 0 => [0, 0, 0, 0],
+        AlphaMode::Straight => [
+            unpremultiply(r),
+            unpremultiply(g),
+            unpremultiply(b),
+            a as u16,
+        ],
+        AlphaMode::Threshold(threshold) if a8 < threshold as u32 => [0, 0, 0, 0],
+        AlphaMode::Threshold(_) if a == 0 => [0, 0, 0, 65535],
+        AlphaMode::Threshold(_) => [unpremultiply(r), unpremultiply(g), unpremultiply(b), 65535],
+        AlphaMode::Matte(background) => [
+            matte16(r, a, background[0]) as u16,
+            matte16(g, a, background[1]) as u16,
+            matte16(b, a, background[2]) as u16,
+            65535,
+        ],
+    }
+}
+
+/// PA64（乗算済みα）のデータを16bit RGBAに変換
+pub fn pa64_to_rgba16(data: &[u16], alpha: AlphaMode) -> Vec<u16> {
+    let mut image_buffer = Vec::with_capacity(data.len());
+
+    for chunk in data.chunks_exact(4) {
+        image_buffer.extend_from_slice(&pa64_pixel_to_rgba16(chunk, alpha));
+    }
+
+    image_buffer
+}
+
+/// PA64（乗算済みα）のデータを黒背景に合成した16bit RGBに変換
+pub fn pa64_to_rgb16(data: &[u16]) -> Vec<u16> {
+    let mut image_buffer = Vec::with_capacity(data.len() / 4 * 3);
+
+    for chunk in data.chunks_exact(4) {
+        image_buffer.extend_from_slice(&chunk[..3]);
+    }
+
+    image_buffer
+}
+
+/// ストレートαのRGBA32をPA64（乗算済みα）に変換
+pub fn rgba_to_pa64(data: &[u8]) -> Vec<u16> {
+    let mut image_buffer = Vec::with_capacity(data.len());
+
+    for chunk in data.chunks_exact(4) {
+        let a = chunk[3] as u32;
+        for &c in &chunk[..3] {
+            image_buffer.push(((c as u32 * a * 257 + 127) / 255) as u16);
+        }
+        image_buffer.push((a * 257) as u16);
+    }
+
+    image_buffer
+}
+
+/// ストレートαの16bit RGBAをPA64（乗算済みα）に変換
+pub fn rgba16_to_pa64(data: &[u16]) -> Vec<u16> {
+    let mut image_buffer = Vec::with_capacity(data.len());
+
+    for chunk in data.chunks_exact(4) {
+        let a = chunk[3] as u32;
+        for &c in &chunk[..3] {
+            image_buffer.push(((c as u32 * a + 32767) / 65535) as u16);
+        }
+        image_buffer.push(chunk[3]);
+    }
+
+    image_buffer
+}
+
+/// YUVからRGBへの変換行列
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum YuvMatrix {
+    /// BT.601
+    #[default]
+    Bt601,
+    /// BT.709
+    Bt709,
+}
+
+/// YUVの値の範囲
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum YuvRange {
+    /// リミテッドレンジ（Y: 16〜235、UV: 16〜240）
+    #[default]
+    Limited,
+    /// フルレンジ（Y・UVとも0〜255）
+    Full,
+}
+
+impl YuvMatrix {
+    /// YUVをRGBに変換する係数（16bit固定小数点）
+    /// - 戻り値: (Y, Yの黒の値, Cr→R, Cb→G, Cr→G, Cb→B)
+    const fn coefficients(self, range: YuvRange) -> (i32, i32, i32, i32, i32, i32) {
+        match (self, range) {
+            (YuvMatrix::Bt601, YuvRange::Limited) => (76309, 16, 104597, 25675, 53279, 132201),
+            (YuvMatrix::Bt709, YuvRange::Limited) => (76309, 16, 117489, 13975, 34925, 138438),
+            (YuvMatrix::Bt601, YuvRange::Full) => (65536, 0, 91881, 22553, 46802, 116130),
+            (YuvMatrix::Bt709, YuvRange::Full) => (65536, 0, 103206, 12276, 30679, 121609),
+        }
+    }
+}
+
+/// YUY2の1行あたりのバイト数
+#[inline(always)]
+pub fn yuy2_stride(width: usize) -> usize {
+    width.div_ceil(2) * 4
+}
+
+/// YUY2（パックド4:2:2）のデータをRGB24に変換
+pub fn yuy2_to_rgb(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    matrix: YuvMatrix,
+    range: YuvRange,
+) -> Vec<u8> {
+    let mut image_buffer = vec![0; width * height * 3];
+    yuy2_to_rgb_into(data, width, height, matrix, range, &mut image_buffer);
+    image_buffer
+}
+
+/// YUY2（パックド4:2:2）のデータをRGB24に変換して`image_buffer`に書き込む
+///
+/// 出力先は`width * height * 3`要素以上
+pub fn yuy2_to_rgb_into(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    matrix: YuvMatrix,
+    range: YuvRange,
+    image_buffer: &mut [u8],
+) {
+    let (cy, black, crr, cbg, crg, cbb) = matrix.coefficients(range);
+    let input_stride = yuy2_stride(width);
+
+    for (y, dst_row) in image_buffer
+        .chunks_exact_mut(width * 3)
+        .take(height)
+        .enumerate()
+    {
+        let row = &data[y * input_stride..(y + 1) * input_stride];
+        for (x, yuyv) in row.chunks_exact(4).enumerate() {
+            let u = yuyv[1] as i32 - 128;
+            let v = yuyv[3] as i32 - 128;
+            let r_diff = crr * v;
+            let g_diff = -cbg * u - crg * v;
+            let b_diff = cbb * u;
+
+            for (i, &luma) in [yuyv[0], yuyv[2]].iter().enumerate() {
+                // 奇数幅の場合、最後のペアの2画素目は出力しない
+                if x * 2 + i >= width {
+                    break;
+                }
+                let l = cy * (luma as i32 - black) + (1 << 15);
+                let dst = &mut dst_row[(x * 2 + i) * 3..][..3];
+                dst[0] = ((l + r_diff) >> 16).clamp(0, 255) as u8;
+                dst[1] = ((l + g_diff) >> 16).clamp(0, 255) as u8;
+                dst[2] = ((l + b_diff) >> 16).clamp(0, 255) as u8;
+            }
+        }
+    }
+}
+
+/// 半精度浮動小数点数をf32に変換
+#[inline(always)]
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits as u32) & 0x8000) << 16;
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+
+    let magnitude = match exponent {
+        // ゼロ・非正規化数
+        0 => {
+            let value = mantissa as f32 / (1 << 24) as f32;
+            return if sign != 0 { -value } else { value };
+        }
+        // 無限大・NaN
+        0x1f => 0x7f80_0000 | (mantissa << 13),
+        _ => ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(sign | magnitude)
+}
+
+/// HF64（半精度浮動小数点数・乗算済みα）の値をPA64の値に変換
+///
+/// 0.0〜1.0の範囲外の値は切り詰める
+#[inline(always)]
+fn hf64_value_to_pa64(h: u16) -> u16 {
+    let v = f16_to_f32(h);
+    // NaNは0として扱う
+    let v = if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) };
+    (v * 65535.0 + 0.5) as u16
+}
+
+/// HF64（半精度浮動小数点数・乗算済みα）のデータをPA64に変換
+///
+/// 0.0〜1.0の範囲外の値は切り詰める
+pub fn hf64_to_pa64(data: &[u16]) -> Vec<u16> {
+    data.iter().map(|&h| hf64_value_to_pa64(h)).collect()
+}
+
+/// HF64（半精度浮動小数点数・乗算済みα）のデータをPA64に変換して`image_buffer`に書き込む
+///
+/// 出力先は`data`と同じ要素数以上
+pub fn hf64_to_pa64_into(data: &[u16], image_buffer: &mut [u16]) {
+    for (dst, &h) in image_buffer.iter_mut().zip(data) {
+        *dst = hf64_value_to_pa64(h);
+    }
+}
+
+/// HF64（半精度浮動小数点数・乗算済みα）のデータをRGBA32に変換
+pub fn hf64_to_rgba(data: &[u16], alpha: AlphaMode) -> Vec<u8> {
+    pa64_to_rgba(&hf64_to_pa64(data), alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_round_trip_as_hex() {
+        assert_eq!(parse_color("#ff8000"), Some([255, 128, 0]));
+        assert_eq!(parse_color(" 0A0B0C "), Some([10, 11, 12]));
+        assert_eq!(parse_color("12345"), None);
+        assert_eq!(parse_color("+12345"), None);
+        assert_eq!(format_color([255, 128, 0]), "FF8000");
+    }
+
+    /// 1画素（Y, U, V）を2画素幅のYUY2にしてRGBに変換する
+    fn decode(yuv: [u8; 3], matrix: YuvMatrix, range: YuvRange) -> [u8; 3] {
+        let [y, u, v] = yuv;
+        let rgb = yuy2_to_rgb(&[y, u, y, v], 2, 1, matrix, range);
+        assert_eq!(rgb[..3], rgb[3..]);
+        [rgb[0], rgb[1], rgb[2]]
+    }
+
+    /// 各規格の100%カラーバーのYUVを変換し、8bitに丸めたYUVの誤差の範囲で元の色に戻ることを確かめる
+    fn assert_color_bars(matrix: YuvMatrix, range: YuvRange, bars: &[([u8; 3], [u8; 3])]) {
+        for &(yuv, expected) in bars {
+            let rgb = decode(yuv, matrix, range);
+            for (c, e) in rgb.iter().zip(expected) {
+                assert!(
+                    c.abs_diff(e) <= 1,
+                    "{matrix:?} {range:?} {yuv:?}: {rgb:?} != {expected:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn yuy2_bt601_limited() {
+        assert_color_bars(
+            YuvMatrix::Bt601,
+            YuvRange::Limited,
+            &[
+                ([210, 16, 146], [255, 255, 0]),
+                ([170, 166, 16], [0, 255, 255]),
+                ([145, 54, 34], [0, 255, 0]),
+                ([106, 202, 222], [255, 0, 255]),
+                ([81, 90, 240], [255, 0, 0]),
+                ([41, 240, 110], [0, 0, 255]),
+            ],
+        );
+    }
+
+    #[test]
+    fn yuy2_bt709_limited() {
+        assert_color_bars(
+            YuvMatrix::Bt709,
+            YuvRange::Limited,
+            &[
+                ([219, 16, 138], [255, 255, 0]),
+                ([188, 154, 16], [0, 255, 255]),
+                ([173, 42, 26], [0, 255, 0]),
+                ([78, 214, 230], [255, 0, 255]),
+                ([63, 102, 240], [255, 0, 0]),
+                ([32, 240, 118], [0, 0, 255]),
+            ],
+        );
+    }
+
+    #[test]
+    fn yuy2_full_range() {
+        assert_color_bars(
+            YuvMatrix::Bt601,
+            YuvRange::Full,
+            &[
+                ([76, 85, 255], [255, 0, 0]),
+                ([150, 44, 21], [0, 255, 0]),
+                ([29, 255, 107], [0, 0, 255]),
+            ],
+        );
+        assert_color_bars(
+            YuvMatrix::Bt709,
+            YuvRange::Full,
+            &[
+                ([54, 99, 255], [255, 0, 0]),
+                ([182, 30, 12], [0, 255, 0]),
+                ([18, 255, 116], [0, 0, 255]),
+            ],
+        );
+    }
+
+    #[test]
+    fn yuy2_gray_levels_are_exact() {
+        for matrix in [YuvMatrix::Bt601, YuvMatrix::Bt709] {
+            // 黒・白と、範囲外の値の切り詰め
+            for (luma, expected) in [(16, 0), (235, 255), (126, 128), (0, 0), (255, 255)] {
+                assert_eq!(
+                    decode([luma, 128, 128], matrix, YuvRange::Limited),
+                    [expected; 3]
+                );
+            }
+            for luma in [0, 1, 128, 254, 255] {
+                assert_eq!(decode([luma, 128, 128], matrix, YuvRange::Full), [luma; 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn yuy2_odd_width() {
+        // 幅3は1行2ペア（8バイト）で、最後のペアの2画素目は出力しない
+        let data = [
+            16, 128, 235, 128, 126, 128, 0, 128, //
+            235, 128, 16, 128, 16, 128, 235, 128,
+        ];
+        let rgb = yuy2_to_rgb(&data, 3, 2, YuvMatrix::Bt601, YuvRange::Limited);
+        assert_eq!(
+            rgb,
+            [[0; 3], [255; 3], [128; 3], [255; 3], [0; 3], [0; 3]].concat()
+        );
+    }
+
+    #[test]
+    fn f16_special_values() {
+        assert_eq!(f16_to_f32(0x0000).to_bits(), 0.0f32.to_bits());
+        assert_eq!(f16_to_f32(0x8000).to_bits(), (-0.0f32).to_bits());
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0x3800), 0.5);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        // 最大の正規化数と最小の正規化数
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        assert_eq!(f16_to_f32(0x0400), 2.0f32.powi(-14));
+        // 非正規化数
+        assert_eq!(f16_to_f32(0x0001), 2.0f32.powi(-24));
+        assert_eq!(f16_to_f32(0x03ff), 1023.0 * 2.0f32.powi(-24));
+        assert_eq!(f16_to_f32(0x8001), -(2.0f32.powi(-24)));
+        // 無限大・NaN
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+        assert!(f16_to_f32(0xfc01).is_nan());
+    }
+
+    #[test]
+    fn hf64_is_clamped_to_pa64() {
+        let data = [
+            0x0000, // 0.0
+            0x3c00, // 1.0
+            0x3800, // 0.5
+            0x0001, // 最小の非正規化数は0に丸める
+            0xbc00, // -1.0
+            0x8000, // -0.0
+            0x4000, // 2.0
+            0x7c00, // +∞
+            0xfc00, // -∞
+            0x7e00, // NaN
+        ];
+        assert_eq!(
+            hf64_to_pa64(&data),
+            [0, 65535, 32768, 0, 0, 0, 65535, 65535, 0, 0]
+        );
+    }
+
+    #[test]
+    fn hf64_to_rgba_unpremultiplies() {
+        // 乗算済みの半透明の赤と、αより大きい色
+        let data = [0x3800, 0, 0, 0x3800, 0x3c00, 0x3c00, 0x3c00, 0x3400];
+        assert_eq!(
+            hf64_to_rgba(&data, AlphaMode::Straight),
+            [255, 0, 0, 128, 255, 255, 255, 64]
+        );
+    }
+}
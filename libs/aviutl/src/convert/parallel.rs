@@ -0,0 +1,461 @@
+//! 行単位で並列化したピクセル変換
+//!
+//! 親モジュールのスカラー実装と同じ結果を返す。出力バッファを事前に確保して
+//! スライスに直接書き込み、並べ替えや不透明な区間の丸めは[`simd`]の命令でまとめて処理する。
+//! 大きな画像では使い回しのワーカースレッドで帯状に分割して処理する。
+
+use super::simd;
+use super::{AlphaMode, dib_rgb24_stride, pa64_pixel_to_rgba, pa64_pixel_to_rgba16};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+
+/// 1スレッドあたりに割り当てる最小要素数
+const MIN_ELEMENTS_PER_THREAD: usize = 1 << 16;
+
+/// ワーカースレッドで実行する処理
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// 変換に使うワーカースレッド
+///
+/// フレームごとにスレッドを作らないよう、最初に使うときに作ったスレッドを使い回す
+struct WorkerPool {
+    sender: Sender<Job>,
+    /// 呼び出し元のスレッドを含めた並列数
+    threads: usize,
+}
+
+impl WorkerPool {
+    fn get() -> &'static WorkerPool {
+        static POOL: OnceLock<WorkerPool> = OnceLock::new();
+        POOL.get_or_init(|| {
+            let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+            let (sender, receiver) = mpsc::channel::<Job>();
+            let receiver = Arc::new(Mutex::new(receiver));
+            // 呼び出し元のスレッドも1つの帯を処理する
+            for _ in 1..threads {
+                let receiver = Arc::clone(&receiver);
+                std::thread::spawn(move || Self::work(&receiver));
+            }
+            WorkerPool { sender, threads }
+        })
+    }
+
+    fn work(receiver: &Mutex<Receiver<Job>>) {
+        loop {
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+            match job {
+                Ok(job) => job(),
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+/// 並列に処理している帯の完了待ち
+struct Latch {
+    remaining: Mutex<usize>,
+    done: Condvar,
+    panicked: AtomicBool,
+}
+
+impl Latch {
+    fn new(count: usize) -> Self {
+        Self {
+            remaining: Mutex::new(count),
+            done: Condvar::new(),
+            panicked: AtomicBool::new(false),
+        }
+    }
+
+    fn count_down(&self) {
+        let mut remaining = self.remaining.lock().unwrap_or_else(|e| e.into_inner());
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.done.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let mut remaining = self.remaining.lock().unwrap_or_else(|e| e.into_inner());
+        while *remaining > 0 {
+            remaining = self.done.wait(remaining).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// 呼び出し元で処理が失敗した場合も、ワーカーに渡した帯の処理が終わるまで待つ
+struct WaitOnDrop<'a>(&'a Latch);
+
+impl Drop for WaitOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.wait();
+    }
+}
+
+/// 出力の要素数から使用するスレッド数を決める
+fn thread_count(len: usize) -> usize {
+    WorkerPool::get()
+        .threads
+        .min(len / MIN_ELEMENTS_PER_THREAD)
+        .max(1)
+}
+
+/// 出力バッファを`unit_len`単位の帯に分割して並列に処理
+/// - f: (帯の先頭の単位番号, 帯のバッファ)を受け取る処理
+pub(crate) fn par_bands<T: Send>(
+    dst: &mut [T],
+    unit_len: usize,
+    f: impl Fn(usize, &mut [T]) + Sync,
+) {
+    par_bands_with(thread_count(dst.len()), dst, unit_len, f);
+}
+
+/// スレッド数を指定して`par_bands`を行う
+fn par_bands_with<T: Send>(
+    threads: usize,
+    dst: &mut [T],
+    unit_len: usize,
+    f: impl Fn(usize, &mut [T]) + Sync,
+) {
+    if dst.is_empty() || unit_len == 0 {
+        return;
+    }
+
+    if threads <= 1 {
+        f(0, dst);
+        return;
+    }
+
+    let units_per_band = (dst.len() / unit_len).div_ceil(threads);
+    let mut bands = dst.chunks_mut(units_per_band * unit_len).enumerate();
+    let Some((_, first)) = bands.next() else {
+        return;
+    };
+
+    let latch = Latch::new(bands.len());
+    let wait = WaitOnDrop(&latch);
+    let f = &f;
+    for (i, band) in bands {
+        let latch = &latch;
+        let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+            if catch_unwind(AssertUnwindSafe(|| f(i * units_per_band, band))).is_err() {
+                latch.panicked.store(true, Ordering::Relaxed);
+            }
+            latch.count_down();
+        });
+        // SAFETY: 借用している`f`、`band`、`latch`は、この関数を抜ける前に
+        // `WaitOnDrop`がすべての処理の完了を待つので、処理中に無効にならない
+        let job: Job = unsafe { std::mem::transmute(job) };
+        if let Err(mpsc::SendError(job)) = WorkerPool::get().sender.send(job) {
+            // ワーカーが終了している場合はこのスレッドで処理する
+            job();
+        }
+    }
+
+    f(0, first);
+    drop(wait);
+    if latch.panicked.load(Ordering::Relaxed) {
+        panic!("ピクセル変換のスレッドでパニックが発生しました");
+    }
+}
+
+/// 不透明かどうかをまとめて判定する画素数
+const BLOCK_PIXELS: usize = 8;
+
+/// PA64のブロックがすべて完全に不透明か
+///
+/// 不透明な画素はどのαの扱いでも色が`(c + 128) / 257`（16bitではそのまま）になり、
+/// 画素ごとの除算や分岐が不要になる
+#[inline(always)]
+fn is_opaque(block: &[u16]) -> bool {
+    block.chunks_exact(4).all(|pixel| pixel[3] == u16::MAX)
+}
+
+/// 下から上に格納されたBGR24のDIBデータを上から下のRGB24に変換
+pub fn dib_bgr24_to_rgb(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut image_buffer = vec![0; width * 3 * height];
+    dib_bgr24_to_rgb_into(data, width, height, &mut image_buffer);
+    image_buffer
+}
+
+/// 下から上に格納されたBGR24のDIBデータを上から下のRGB24に変換して`image_buffer`に書き込む
+///
+/// 出力先は`width * height * 3`要素以上
+pub fn dib_bgr24_to_rgb_into(data: &[u8], width: usize, height: usize, image_buffer: &mut [u8]) {
+    let input_stride = dib_rgb24_stride(width);
+    let row_len = width * 3;
+    let image_buffer = &mut image_buffer[..row_len * height];
+
+    par_bands(image_buffer, row_len, |first_row, band| {
+        for (i, dst) in band.chunks_exact_mut(row_len).enumerate() {
+            let row_start = (height - 1 - (first_row + i)) * input_stride;
+            simd::bgr_to_rgb(&data[row_start..row_start + row_len], dst);
+        }
+    });
+}
+
+/// PA64（乗算済みα）のデータをRGBA32に変換
+pub fn pa64_to_rgba(data: &[u16], alpha: AlphaMode) -> Vec<u8> {
+    let mut image_buffer = vec![0; data.len() / 4 * 4];
+    pa64_to_rgba_into(data, alpha, &mut image_buffer);
+    image_buffer
+}
+
+/// PA64（乗算済みα）のデータをRGBA32に変換して`image_buffer`に書き込む
+///
+/// 出力先は`data`と同じ要素数以上
+pub fn pa64_to_rgba_into(data: &[u16], alpha: AlphaMode, image_buffer: &mut [u8]) {
+    let image_buffer = &mut image_buffer[..data.len() / 4 * 4];
+
+    par_bands(image_buffer, 4, |first, band| {
+        let src = &data[first * 4..first * 4 + band.len()];
+        let mut dst_blocks = band.chunks_exact_mut(BLOCK_PIXELS * 4);
+        let mut src_blocks = src.chunks_exact(BLOCK_PIXELS * 4);
+
+        for (d, s) in (&mut dst_blocks).zip(&mut src_blocks) {
+            if is_opaque(s) {
+                simd::u16_to_u8(s, d);
+            } else {
+                for (d, s) in d.chunks_exact_mut(4).zip(s.chunks_exact(4)) {
+                    d.copy_from_slice(&pa64_pixel_to_rgba(s, alpha));
+                }
+            }
+        }
+
+        let dst = dst_blocks.into_remainder().chunks_exact_mut(4);
+        for (d, s) in dst.zip(src_blocks.remainder().chunks_exact(4)) {
+            d.copy_from_slice(&pa64_pixel_to_rgba(s, alpha));
+        }
+    });
+}
+
+/// PA64（乗算済みα）のデータを黒背景に合成したRGB24に変換
+pub fn pa64_to_rgb(data: &[u16]) -> Vec<u8> {
+    let mut image_buffer = vec![0; data.len() / 4 * 3];
+    pa64_to_rgb_into(data, &mut image_buffer);
+    image_buffer
+}
+
+/// PA64（乗算済みα）のデータを黒背景に合成したRGB24に変換して`image_buffer`に書き込む
+///
+/// 出力先は画素数の3倍の要素数以上
+pub fn pa64_to_rgb_into(data: &[u16], image_buffer: &mut [u8]) {
+    let image_buffer = &mut image_buffer[..data.len() / 4 * 3];
+
+    par_bands(image_buffer, 3, |first, band| {
+        let src = &data[first * 4..first * 4 + band.len() / 3 * 4];
+        simd::pa64_to_rgb(src, band);
+    });
+}
+
+/// PA64（乗算済みα）のデータを16bit RGBAに変換
+pub fn pa64_to_rgba16(data: &[u16], alpha: AlphaMode) -> Vec<u16> {
+    let mut image_buffer = vec![0; data.len() / 4 * 4];
+    pa64_to_rgba16_into(data, alpha, &mut image_buffer);
+    image_buffer
+}
+
+/// PA64（乗算済みα）のデータを16bit RGBAに変換して`image_buffer`に書き込む
+///
+/// 出力先は`data`と同じ要素数以上
+pub fn pa64_to_rgba16_into(data: &[u16], alpha: AlphaMode, image_buffer: &mut [u16]) {
+    let image_buffer = &mut image_buffer[..data.len() / 4 * 4];
+
+    par_bands(image_buffer, 4, |first, band| {
+        let src = &data[first * 4..first * 4 + band.len()];
+        let mut dst_blocks = band.chunks_exact_mut(BLOCK_PIXELS * 4);
+        let mut src_blocks = src.chunks_exact(BLOCK_PIXELS * 4);
+
+        for (d, s) in (&mut dst_blocks).zip(&mut src_blocks) {
+            if is_opaque(s) {
+                d.copy_from_slice(s);
+            } else {
+                for (d, s) in d.chunks_exact_mut(4).zip(s.chunks_exact(4)) {
+                    d.copy_from_slice(&pa64_pixel_to_rgba16(s, alpha));
+                }
+            }
+        }
+
+        let dst = dst_blocks.into_remainder().chunks_exact_mut(4);
+        for (d, s) in dst.zip(src_blocks.remainder().chunks_exact(4)) {
+            d.copy_from_slice(&pa64_pixel_to_rgba16(s, alpha));
+        }
+    });
+}
+
+/// PA64（乗算済みα）のデータを黒背景に合成した16bit RGBに変換
+pub fn pa64_to_rgb16(data: &[u16]) -> Vec<u16> {
+    let mut image_buffer = vec![0; data.len() / 4 * 3];
+    pa64_to_rgb16_into(data, &mut image_buffer);
+    image_buffer
+}
+
+/// PA64（乗算済みα）のデータを黒背景に合成した16bit RGBに変換して`image_buffer`に書き込む
+///
+/// 出力先は画素数の3倍の要素数以上
+pub fn pa64_to_rgb16_into(data: &[u16], image_buffer: &mut [u16]) {
+    let image_buffer = &mut image_buffer[..data.len() / 4 * 3];
+
+    par_bands(image_buffer, 3, |first, band| {
+        let src = &data[first * 4..first * 4 + band.len() / 3 * 4];
+        for (d, s) in band.chunks_exact_mut(3).zip(src.chunks_exact(4)) {
+            d.copy_from_slice(&s[..3]);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert;
+
+    /// テスト用の決定的な疑似乱数列 (xorshift32)
+    fn random_u16(len: usize, mut seed: u32) -> Vec<u16> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u16
+            })
+            .collect()
+    }
+
+    /// ランダムな値を乗算済みαとして正しい範囲（色 <= α）に収める
+    fn premultiplied(mut data: Vec<u16>) -> Vec<u16> {
+        for pixel in data.chunks_exact_mut(4) {
+            let a = pixel[3] as u32;
+            for c in &mut pixel[..3] {
+                *c = (*c as u32 * a / 65535) as u16;
+            }
+        }
+        data
+    }
+
+    /// 不透明な区間と半透明な区間が混ざったPA64データ
+    fn opaque_runs(len: usize, seed: u32) -> Vec<u16> {
+        let mut data = premultiplied(random_u16(len, seed));
+        for (i, pixel) in data.chunks_exact_mut(4).enumerate() {
+            // ブロック境界をまたぐように長さの異なる区間を作る
+            if (i / 13) % 3 != 0 {
+                pixel[3] = u16::MAX;
+            }
+        }
+        data
+    }
+
+    /// αの全値と代表的な色の値を網羅したPA64データ
+    fn all_alpha_values() -> Vec<u16> {
+        let colors = [0, 1, 127, 128, 255, 256, 32767, 32768, 65534, 65535];
+        let mut data = Vec::new();
+        for a in 0..=u16::MAX {
+            for &c in &colors {
+                // 乗算済みαとして不正な色 > α の値も含める
+                data.extend_from_slice(&[c, a, c.min(a), a]);
+            }
+        }
+        data
+    }
+
+    fn alpha_modes() -> [AlphaMode; 6] {
+        [
+            AlphaMode::Straight,
+            AlphaMode::Threshold(0),
+            AlphaMode::Threshold(128),
+            AlphaMode::Threshold(255),
+            AlphaMode::Matte([0, 0, 0]),
+            AlphaMode::Matte([255, 128, 7]),
+        ]
+    }
+
+    #[test]
+    fn dib_bgr24_to_rgb_matches_scalar() {
+        // 奇数幅（行末のパディングあり）や並列化される大きさを含める
+        for (width, height) in [(0, 0), (1, 1), (3, 2), (7, 5), (641, 480), (1920, 1080)] {
+            let len = dib_rgb24_stride(width) * height;
+            let data: Vec<u8> = random_u16(len, 0x1234_5678 ^ width as u32)
+                .into_iter()
+                .map(|v| v as u8)
+                .collect();
+            assert_eq!(
+                dib_bgr24_to_rgb(&data, width, height),
+                convert::dib_bgr24_to_rgb(&data, width, height),
+                "{width}x{height}"
+            );
+        }
+    }
+
+    #[test]
+    fn pa64_to_rgba_matches_scalar() {
+        let inputs = [
+            all_alpha_values(),
+            random_u16(641 * 480 * 4, 0xdead_beef),
+            premultiplied(random_u16(641 * 480 * 4, 0xcafe_babe)),
+            opaque_runs(641 * 480 * 4 + 12, 0x2468_ace0),
+        ];
+        for data in &inputs {
+            for alpha in alpha_modes() {
+                assert_eq!(
+                    pa64_to_rgba(data, alpha),
+                    convert::pa64_to_rgba(data, alpha),
+                    "{alpha:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pa64_to_rgba16_matches_scalar() {
+        let inputs = [
+            all_alpha_values(),
+            random_u16(641 * 480 * 4, 0x0bad_f00d),
+            premultiplied(random_u16(641 * 480 * 4, 0x1357_9bdf)),
+            opaque_runs(641 * 480 * 4 + 12, 0x0f1e_2d3c),
+        ];
+        for data in &inputs {
+            for alpha in alpha_modes() {
+                assert_eq!(
+                    pa64_to_rgba16(data, alpha),
+                    convert::pa64_to_rgba16(data, alpha),
+                    "{alpha:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pa64_to_rgb_matches_scalar() {
+        for data in [all_alpha_values(), random_u16(641 * 480 * 4, 42)] {
+            assert_eq!(pa64_to_rgb(&data), convert::pa64_to_rgb(&data));
+            assert_eq!(pa64_to_rgb16(&data), convert::pa64_to_rgb16(&data));
+        }
+    }
+
+    #[test]
+    fn par_bands_covers_every_unit() {
+        // 単位数がスレッド数で割り切れない場合も含める
+        for threads in [1, 2, 3, 8, 100] {
+            let mut dst = vec![usize::MAX; 7 * 3];
+            par_bands_with(threads, &mut dst, 3, |first, band| {
+                for (i, unit) in band.chunks_exact_mut(3).enumerate() {
+                    unit.fill(first + i);
+                }
+            });
+            let expected: Vec<usize> = (0..7).flat_map(|i| [i; 3]).collect();
+            assert_eq!(dst, expected, "threads = {threads}");
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        assert!(pa64_to_rgba(&[], AlphaMode::Straight).is_empty());
+        assert!(pa64_to_rgba16(&[], AlphaMode::Straight).is_empty());
+        assert!(pa64_to_rgb(&[]).is_empty());
+        assert!(pa64_to_rgb16(&[]).is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+//! SIMD命令を使ったピクセル変換の内側のループ
+//!
+//! x86_64ではSSE2（常に使える）とSSSE3（実行時に判定）の命令で複数画素をまとめて処理し、
+//! それ以外の環境や端数の画素はスカラーの処理で変換する。どちらも親モジュールのスカラー実装と同じ結果を返す。
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// 16bitの値を8bitに丸める（`(c + 128) / 257`）
+#[inline(always)]
+fn to_u8(c: u16) -> u8 {
+    ((c as u32 + 128) / 257) as u8
+}
+
+/// BGR24の1行をRGB24に並べ替える
+///
+/// `src`と`dst`は同じ長さ
+pub(crate) fn bgr_to_rgb(src: &[u8], dst: &mut [u8]) {
+    #[cfg(target_arch = "x86_64")]
+    let done = if is_x86_feature_detected!("ssse3") {
+        // SAFETY: SSSE3が使えることを確認済み
+        unsafe { bgr_to_rgb_ssse3(src, dst) }
+    } else {
+        0
+    };
+    #[cfg(not(target_arch = "x86_64"))]
+    let done = 0;
+
+    for (d, s) in dst[done..]
+        .chunks_exact_mut(3)
+        .zip(src[done..].chunks_exact(3))
+    {
+        d[0] = s[2];
+        d[1] = s[1];
+        d[2] = s[0];
+    }
+}
+
+/// 16バイトずつ読み込み、先頭の5画素を並べ替える
+/// - 戻り値: 処理したバイト数（3の倍数）
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "ssse3")]
+unsafe fn bgr_to_rgb_ssse3(src: &[u8], dst: &mut [u8]) -> usize {
+    let len = src.len().min(dst.len());
+    // 16バイト目は次の読み込みで上書きされるので、そのまま残す
+    let shuffle = _mm_setr_epi8(2, 1, 0, 5, 4, 3, 8, 7, 6, 11, 10, 9, 14, 13, 12, 15);
+    let mut i = 0;
+    while i + 16 <= len {
+        // SAFETY: i + 16 <= lenなので16バイトの読み書きは範囲内
+        unsafe {
+            let pixels = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+            let pixels = _mm_shuffle_epi8(pixels, shuffle);
+            _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, pixels);
+        }
+        i += 15;
+    }
+    i
+}
+
+/// 16bitの値をすべて8bitに丸める（不透明なPA64をRGBA32にする場合など）
+///
+/// `dst`は`src`と同じ長さ
+pub(crate) fn u16_to_u8(src: &[u16], dst: &mut [u8]) {
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: SSE2はx86_64で常に使える
+    let done = unsafe { u16_to_u8_sse2(src, dst) };
+    #[cfg(not(target_arch = "x86_64"))]
+    let done = 0;
+
+    for (d, &c) in dst[done..].iter_mut().zip(&src[done..]) {
+        *d = to_u8(c);
+    }
+}
+
+/// 8要素の16bitの値を8bitに丸めた値（16bitの各要素の下位に入る）
+///
+/// `c + 128`を飽和加算し（65408以上はどちらでも255になる）、257での除算を
+/// `65281 / 2^24`の乗算で行う。65535以下の値では切り捨ての結果が除算と一致する。
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+fn round_to_u8_sse2(c: __m128i) -> __m128i {
+    let biased = _mm_adds_epu16(c, _mm_set1_epi16(128));
+    _mm_srli_epi16(_mm_mulhi_epu16(biased, _mm_set1_epi16(0xFF01u16 as i16)), 8)
+}
+
+/// 16要素ずつ8bitに丸める
+/// - 戻り値: 処理した要素数
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn u16_to_u8_sse2(src: &[u16], dst: &mut [u8]) -> usize {
+    let len = src.len().min(dst.len());
+    let mut i = 0;
+    while i + 16 <= len {
+        // SAFETY: i + 16 <= lenなので16要素の読み書きは範囲内
+        unsafe {
+            let low = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+            let high = _mm_loadu_si128(src.as_ptr().add(i + 8) as *const __m128i);
+            let packed = _mm_packus_epi16(round_to_u8_sse2(low), round_to_u8_sse2(high));
+            _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, packed);
+        }
+        i += 16;
+    }
+    i
+}
+
+/// PA64の色を8bitに丸めてRGB24にする（αは捨てる）
+///
+/// `dst`は`src`の画素数の3倍の長さ
+pub(crate) fn pa64_to_rgb(src: &[u16], dst: &mut [u8]) {
+    #[cfg(target_arch = "x86_64")]
+    let pixels = if is_x86_feature_detected!("ssse3") {
+        // SAFETY: SSSE3が使えることを確認済み
+        unsafe { pa64_to_rgb_ssse3(src, dst) }
+    } else {
+        0
+    };
+    #[cfg(not(target_arch = "x86_64"))]
+    let pixels = 0;
+
+    for (d, s) in dst[pixels * 3..]
+        .chunks_exact_mut(3)
+        .zip(src[pixels * 4..].chunks_exact(4))
+    {
+        for (d, &c) in d.iter_mut().zip(&s[..3]) {
+            *d = to_u8(c);
+        }
+    }
+}
+
+/// 4画素ずつ8bitに丸めてαを取り除く
+/// - 戻り値: 処理した画素数
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "ssse3")]
+unsafe fn pa64_to_rgb_ssse3(src: &[u16], dst: &mut [u8]) -> usize {
+    let pixels = (src.len() / 4).min(dst.len() / 3);
+    // RGBA×4の16バイトから12バイトのRGBを取り出す（残りの4バイトは次の書き込みで上書きされる）
+    let shuffle = _mm_setr_epi8(0, 1, 2, 4, 5, 6, 8, 9, 10, 12, 13, 14, -1, -1, -1, -1);
+    let mut i = 0;
+    // 書き込みは16バイトなので、出力に4バイト以上の余裕がある間だけ処理する
+    while (i + 4) * 3 + 4 <= pixels * 3 {
+        // SAFETY: 読み込みは(i + 4) * 4 <= src.len()、書き込みはi * 3 + 16 <= dst.len()の範囲内
+        unsafe {
+            let low = _mm_loadu_si128(src.as_ptr().add(i * 4) as *const __m128i);
+            let high = _mm_loadu_si128(src.as_ptr().add(i * 4 + 8) as *const __m128i);
+            let rgba = _mm_packus_epi16(round_to_u8_sse2(low), round_to_u8_sse2(high));
+            let rgb = _mm_shuffle_epi8(rgba, shuffle);
+            _mm_storeu_si128(dst.as_mut_ptr().add(i * 3) as *mut __m128i, rgb);
+        }
+        i += 4;
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u16_to_u8_rounds_every_value() {
+        let src: Vec<u16> = (0..=u16::MAX).collect();
+        let mut dst = vec![0; src.len()];
+        u16_to_u8(&src, &mut dst);
+        for (&c, &d) in src.iter().zip(&dst) {
+            assert_eq!(d, to_u8(c), "{c}");
+        }
+    }
+
+    #[test]
+    fn bgr_to_rgb_every_length() {
+        // SIMDで処理する区間と端数の境目をすべて含める
+        for pixels in 0..40 {
+            let src: Vec<u8> = (0..pixels * 3).map(|i| (i * 7 + 3) as u8).collect();
+            let mut dst = vec![0; src.len()];
+            bgr_to_rgb(&src, &mut dst);
+            let expected: Vec<u8> = src
+                .chunks_exact(3)
+                .flat_map(|p| [p[2], p[1], p[0]])
+                .collect();
+            assert_eq!(dst, expected, "{pixels}");
+        }
+    }
+
+    #[test]
+    fn pa64_to_rgb_every_length() {
+        for pixels in 0..40 {
+            let src: Vec<u16> = (0..pixels * 4).map(|i| (i * 4099 + 17) as u16).collect();
+            let mut dst = vec![0; pixels * 3];
+            pa64_to_rgb(&src, &mut dst);
+            let expected: Vec<u8> = src
+                .chunks_exact(4)
+                .flat_map(|p| [to_u8(p[0]), to_u8(p[1]), to_u8(p[2])])
+                .collect();
+            assert_eq!(dst, expected, "{pixels}");
+        }
+    }
+}
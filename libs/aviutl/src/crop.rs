@@ -0,0 +1,525 @@
+//! フレームの一部分の切り取り
+//!
+//! 範囲を指定するほか、アニメーション全体で変化の無い単色または透明の余白を自動で取り除ける。
+//! 自動の場合も全フレームで同じ範囲を使うので、出力の大きさはフレームごとに変わらない。
+
+use crate::cache::CachedSource;
+use crate::convert::AlphaMode;
+use crate::frame::{FrameBuffer, FrameView};
+use crate::settings;
+use crate::source::FrameSource;
+use ini::{Properties, SectionSetter};
+use std::str::FromStr;
+
+/// 切り取りの方法
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CropMode {
+    /// 切り取らない
+    #[default]
+    None,
+    /// 指定した範囲を切り取る
+    Manual,
+    /// 単色または透明の余白を取り除く
+    Auto,
+}
+
+impl From<CropMode> for &'static str {
+    fn from(mode: CropMode) -> Self {
+        match mode {
+            CropMode::None => "切り取らない",
+            CropMode::Manual => "範囲を指定",
+            CropMode::Auto => "余白を自動で取り除く",
+        }
+    }
+}
+
+impl FromStr for CropMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(CropMode::None),
+            Ok(1) => Ok(CropMode::Manual),
+            Ok(2) => Ok(CropMode::Auto),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CropMode {
+    pub fn to_index(&self) -> u32 {
+        match self {
+            CropMode::None => 0,
+            CropMode::Manual => 1,
+            CropMode::Auto => 2,
+        }
+    }
+}
+
+/// 切り取りの指定
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Crop {
+    pub mode: CropMode,
+    /// 範囲の左端（範囲を指定する場合）
+    pub x: u32,
+    /// 範囲の上端（範囲を指定する場合）
+    pub y: u32,
+    /// 範囲の幅（0 = 右端まで）
+    pub width: u32,
+    /// 範囲の高さ（0 = 下端まで）
+    pub height: u32,
+}
+
+/// 切り取る範囲
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CropRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Crop {
+    /// iniのセクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn load_ini(section: Option<&Properties>) -> Self {
+        let default = Self::default();
+        Self {
+            mode: settings::get(section, "crop_mode").unwrap_or(default.mode),
+            x: settings::get(section, "crop_x").unwrap_or(default.x),
+            y: settings::get(section, "crop_y").unwrap_or(default.y),
+            width: settings::get(section, "crop_width").unwrap_or(default.width),
+            height: settings::get(section, "crop_height").unwrap_or(default.height),
+        }
+    }
+
+    /// iniのセクションに書き出す
+    pub fn save_ini(&self, section: &mut SectionSetter) {
+        section
+            .set("crop_mode", self.mode.to_index().to_string())
+            .set("crop_x", self.x.to_string())
+            .set("crop_y", self.y.to_string())
+            .set("crop_width", self.width.to_string())
+            .set("crop_height", self.height.to_string());
+    }
+
+    /// 出力ダイアログに表示する設定の概要（切り取らない場合は空、それ以外は先頭に空白を付ける）
+    pub fn summary(&self) -> String {
+        match self.mode {
+            CropMode::None => String::new(),
+            CropMode::Manual => format!(" 切り取り({},{})", self.x, self.y),
+            CropMode::Auto => " 余白除去".to_string(),
+        }
+    }
+
+    /// 指定した範囲を画像の大きさで制限する
+    /// - 戻り値: 範囲が画像の外にある場合はエラー
+    pub fn manual_rect(&self, width: usize, height: usize) -> Result<CropRect, String> {
+        let (x, y) = (self.x as usize, self.y as usize);
+        if x >= width || y >= height {
+            return Err(format!(
+                "切り取り範囲の位置({}, {})が画像({}x{})の外です",
+                x, y, width, height
+            ));
+        }
+        let length = |len: u32, rest: usize| match len {
+            0 => rest,
+            len => (len as usize).min(rest),
+        };
+        Ok(CropRect {
+            x,
+            y,
+            width: length(self.width, width - x),
+            height: length(self.height, height - y),
+        })
+    }
+
+    /// 切り取る範囲を決める
+    ///
+    /// 余白を自動で取り除く場合は全フレームを読み込んで調べ、進捗を表示する（中断された場合はそこまでの範囲）
+    /// - 戻り値: 切り取らない場合はNone
+    pub fn rect(&self, source: &dyn FrameSource) -> Result<Option<CropRect>, String> {
+        let (width, height) = (source.width() as usize, source.height() as usize);
+        let rect = match self.mode {
+            CropMode::None => return Ok(None),
+            CropMode::Manual => self.manual_rect(width, height)?,
+            CropMode::Auto => {
+                let mut trim = TrimDetector::new(width, height);
+                let mut buffer = FrameBuffer::new();
+                let frame_count = source.frame_count();
+                for frame in 0..frame_count {
+                    if source.is_abort() {
+                        break;
+                    }
+                    let data = source
+                        .read_pa64(frame, &mut buffer)
+                        .ok_or("フレームの取得に失敗しました")?;
+                    trim.push(data);
+                    // 一時ファイルに保存済みの場合は、保存する間に進捗を表示している
+                    if !source.is_cached() {
+                        source.rest_time_disp(frame, frame_count);
+                    }
+                }
+                trim.rect()
+            }
+        };
+
+        let full = CropRect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        Ok((rect != full).then_some(rect))
+    }
+}
+
+/// 全フレームで余白の色と一致しない画素を囲む範囲を求める
+///
+/// 余白の色は最初のフレームの左上の画素とし、完全透明の画素は色が違っても余白とみなす
+pub struct TrimDetector {
+    width: usize,
+    height: usize,
+    border: Option<[u16; 4]>,
+    /// これまでのフレームで余白でない画素を囲む範囲 (左, 上, 右, 下)
+    bounds: Option<(usize, usize, usize, usize)>,
+}
+
+impl TrimDetector {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            border: None,
+            bounds: None,
+        }
+    }
+
+    fn is_border(border: &[u16; 4], pixel: &[u16]) -> bool {
+        pixel == border || (border[3] == 0 && pixel[3] == 0)
+    }
+
+    /// PA64のフレームを調べる
+    pub fn push(&mut self, data: &[u16]) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+        let border = *self
+            .border
+            .get_or_insert_with(|| [data[0], data[1], data[2], data[3]]);
+
+        for (y, row) in data.chunks_exact(self.width * 4).enumerate() {
+            let mut pixels = row.chunks_exact(4);
+            let Some(left) = pixels.position(|p| !Self::is_border(&border, p)) else {
+                continue;
+            };
+            let right = row
+                .chunks_exact(4)
+                .rposition(|p| !Self::is_border(&border, p))
+                .unwrap();
+            self.bounds = Some(match self.bounds {
+                None => (left, y, right + 1, y + 1),
+                Some((l, t, r, b)) => (l.min(left), t.min(y), r.max(right + 1), b.max(y + 1)),
+            });
+        }
+    }
+
+    /// 余白を除いた範囲（すべて余白の場合は画像全体）
+    pub fn rect(&self) -> CropRect {
+        match self.bounds {
+            Some((left, top, right, bottom)) => CropRect {
+                x: left,
+                y: top,
+                width: right - left,
+                height: bottom - top,
+            },
+            None => CropRect {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            },
+        }
+    }
+}
+
+/// 別のソースのフレームの一部分を切り取って供給するソース
+pub struct CroppedSource<'a> {
+    source: &'a dyn FrameSource,
+    /// 余白を調べるために読み込んだフレーム（余白を自動で取り除く場合）
+    cache: Option<CachedSource<'a>>,
+    rect: Option<CropRect>,
+}
+
+impl<'a> CroppedSource<'a> {
+    /// 切り取る範囲を決めてソースを作る
+    ///
+    /// 余白を自動で取り除く場合は全フレームを読み込み、読み込んだフレームを一時ファイルに保存して出力に使う
+    pub fn new(source: &'a dyn FrameSource, crop: Crop) -> Result<Self, String> {
+        let cache = match crop.mode {
+            CropMode::Auto => Some(CachedSource::new(source)?),
+            _ => None,
+        };
+        let rect = match &cache {
+            Some(cache) => crop.rect(cache)?,
+            None => crop.rect(source)?,
+        };
+        Ok(Self {
+            source,
+            cache,
+            rect,
+        })
+    }
+
+    /// フレームを読み込む元のソース
+    fn input(&self) -> &dyn FrameSource {
+        match &self.cache {
+            Some(cache) => cache,
+            None => self.source,
+        }
+    }
+
+    /// 切り取る範囲（切り取らない場合はNone）
+    pub fn rect(&self) -> Option<CropRect> {
+        self.rect
+    }
+
+    /// 元のソースから読み込んだフレームを切り取る
+    fn crop<'b, T: Copy + Default>(
+        &self,
+        buffer: &'b mut FrameBuffer<T>,
+        read: impl FnOnce(&mut FrameBuffer<T>) -> bool,
+    ) -> Option<FrameView<'b, T>> {
+        if !read(buffer) {
+            return None;
+        }
+        if let Some(rect) = self.rect {
+            buffer.crop(rect.x, rect.y, rect.width, rect.height);
+        }
+        Some(buffer.view())
+    }
+}
+
+impl FrameSource for CroppedSource<'_> {
+    fn width(&self) -> i32 {
+        match self.rect {
+            Some(rect) => rect.width as i32,
+            None => self.source.width(),
+        }
+    }
+
+    fn height(&self) -> i32 {
+        match self.rect {
+            Some(rect) => rect.height as i32,
+            None => self.source.height(),
+        }
+    }
+
+    fn rate(&self) -> i32 {
+        self.source.rate()
+    }
+
+    fn scale(&self) -> i32 {
+        self.source.scale()
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.source.frame_count()
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.crop(buffer, |buffer| {
+            self.input().read_rgb(frame, buffer).is_some()
+        })
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.crop(buffer, |buffer| {
+            self.input().read_rgba(frame, alpha, buffer).is_some()
+        })
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.crop(buffer, |buffer| {
+            self.input().read_rgb16(frame, buffer).is_some()
+        })
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.crop(buffer, |buffer| {
+            self.input().read_rgba16(frame, alpha, buffer).is_some()
+        })
+    }
+
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        self.crop(buffer, |buffer| {
+            self.input().read_pa64(frame, buffer).is_some()
+        })
+        .map(|view| view.data())
+    }
+
+    fn is_cached(&self) -> bool {
+        self.cache.is_some() || self.source.is_cached()
+    }
+
+    fn is_abort(&self) -> bool {
+        self.source.is_abort()
+    }
+
+    fn rest_time_disp(&self, now: i32, total: i32) {
+        self.source.rest_time_disp(now, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemoryFrameSource;
+
+    /// 背景の上の指定した位置に1画素ずつ点を置いたフレームを持つ4x3のソース
+    fn dots(background: [u8; 4], dots: &[(usize, usize)]) -> MemoryFrameSource {
+        let mut source = MemoryFrameSource::new(4, 3, 30, 1);
+        for &(x, y) in dots {
+            let mut rgba = background.repeat(12);
+            let i = (y * 4 + x) * 4;
+            rgba[i..i + 4].copy_from_slice(&[255, 255, 255, 255]);
+            source.push_rgba(&rgba).unwrap();
+        }
+        source
+    }
+
+    #[test]
+    fn manual_rect_is_clamped_to_the_image() {
+        let crop = Crop {
+            mode: CropMode::Manual,
+            x: 1,
+            y: 2,
+            width: 100,
+            height: 0,
+        };
+        assert_eq!(
+            crop.manual_rect(4, 3).unwrap(),
+            CropRect {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 1
+            }
+        );
+        assert!(Crop { x: 4, ..crop }.manual_rect(4, 3).is_err());
+    }
+
+    #[test]
+    fn auto_trim_covers_the_whole_animation() {
+        let source = dots([0, 0, 0, 0], &[(1, 0), (2, 1)]);
+        let crop = Crop {
+            mode: CropMode::Auto,
+            ..Default::default()
+        };
+        let cropped = CroppedSource::new(&source, crop).unwrap();
+        assert_eq!(
+            cropped.rect(),
+            Some(CropRect {
+                x: 1,
+                y: 0,
+                width: 2,
+                height: 2
+            })
+        );
+
+        // 各フレームが同じ範囲で切り取られる
+        let frame = cropped.frame_rgba(1, AlphaMode::Straight).unwrap();
+        assert_eq!(
+            frame,
+            [[0; 4], [0; 4], [0; 4], [255; 4]].concat(),
+            "2フレーム目の点は切り取った範囲の右下"
+        );
+    }
+
+    #[test]
+    fn auto_trim_uses_the_corner_color_as_border() {
+        let source = dots([10, 20, 30, 255], &[(3, 2)]);
+        let rect = Crop {
+            mode: CropMode::Auto,
+            ..Default::default()
+        }
+        .rect(&source)
+        .unwrap();
+        assert_eq!(
+            rect,
+            Some(CropRect {
+                x: 3,
+                y: 2,
+                width: 1,
+                height: 1
+            })
+        );
+    }
+
+    #[test]
+    fn auto_trim_reads_the_source_once() {
+        let source = crate::cache::tests::numbered(3);
+        let crop = Crop {
+            mode: CropMode::Auto,
+            ..Default::default()
+        };
+        let cropped = CroppedSource::new(&source, crop).unwrap();
+        for frame in 0..3 {
+            assert_eq!(cropped.frame_rgb(frame).unwrap(), [frame as u8, 0, 0]);
+        }
+        assert_eq!(*source.reads.borrow(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn uniform_frames_are_not_cropped() {
+        let mut source = MemoryFrameSource::new(4, 3, 30, 1);
+        source.push_rgba(&[0; 4 * 12]).unwrap();
+        let rect = Crop {
+            mode: CropMode::Auto,
+            ..Default::default()
+        }
+        .rect(&source)
+        .unwrap();
+        assert_eq!(rect, None);
+    }
+
+    #[test]
+    fn settings_round_trip_through_ini() {
+        let crop = Crop {
+            mode: CropMode::Manual,
+            x: 8,
+            y: 4,
+            width: 100,
+            height: 0,
+        };
+        let mut ini = ini::Ini::new();
+        crop.save_ini(&mut ini.with_section(Some("Config")));
+        assert_eq!(Crop::load_ini(ini.section(Some("Config"))), crop);
+        assert_eq!(Crop::load_ini(None), Crop::default());
+
+        assert_eq!(crop.summary(), " 切り取り(8,4)");
+        let auto = Crop {
+            mode: CropMode::Auto,
+            ..crop
+        };
+        assert_eq!(auto.summary(), " 余白除去");
+        assert_eq!(Crop::default().summary(), "");
+    }
+}
@@ -0,0 +1,382 @@
+//! フレームレートを下げるためのフレームの間引き
+//!
+//! 出力の各フレームが始まる時刻に表示されている入力のフレームを、有理数の時刻から正確に選ぶ。
+//! 合成する場合は、出力の1フレームの間に表示される入力のフレームをPA64（乗算済みα）のまま平均する。
+
+use crate::convert::{self, AlphaMode};
+use crate::frame::{FrameBuffer, FrameView, PixelLayout};
+use crate::settings;
+use crate::source::FrameSource;
+use ini::{Properties, SectionSetter};
+use std::cell::RefCell;
+use std::ops::Range;
+
+/// フレームレートの指定
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Decimate {
+    /// 出力のフレームレート（0 = 入力のまま）
+    pub fps: u32,
+    /// 間引いたフレームを残すフレームに合成する
+    pub blend: bool,
+}
+
+impl Decimate {
+    /// iniのセクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn load_ini(section: Option<&Properties>) -> Self {
+        let default = Self::default();
+        Self {
+            fps: settings::get(section, "target_fps").unwrap_or(default.fps),
+            blend: settings::get_bool(section, "blend_frames").unwrap_or(default.blend),
+        }
+    }
+
+    /// iniのセクションに書き出す
+    pub fn save_ini(&self, section: &mut SectionSetter) {
+        section
+            .set("target_fps", self.fps.to_string())
+            .set("blend_frames", settings::bool_value(self.blend));
+    }
+
+    /// 出力ダイアログに表示する設定の概要（フレームレートを変えない場合は空、それ以外は先頭に空白を付ける）
+    pub fn summary(&self) -> String {
+        match (self.fps, self.blend) {
+            (0, _) => String::new(),
+            (fps, false) => format!(" {}fps", fps),
+            (fps, true) => format!(" {}fps(合成)", fps),
+        }
+    }
+}
+
+/// 出力の各フレームに使う入力のフレームの範囲
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    /// 入力のフレームレート
+    rate: u64,
+    scale: u64,
+    /// 出力のフレームレート
+    fps: u64,
+    /// 入力のフレーム数
+    frames: u64,
+}
+
+impl Schedule {
+    /// 入力より低いフレームレートが指定された場合だけ作る
+    pub fn new(rate: i32, scale: i32, frames: i32, fps: u32) -> Option<Self> {
+        let (rate, scale) = (rate.max(1) as u64, scale.max(1) as u64);
+        // fps < rate / scale
+        (fps > 0 && (fps as u64) * scale < rate).then_some(Self {
+            rate,
+            scale,
+            fps: fps as u64,
+            frames: frames.max(0) as u64,
+        })
+    }
+
+    /// 出力のフレームの開始時刻に表示されている入力のフレーム
+    ///
+    /// 出力のk番目のフレームは時刻k / fpsに始まるので、入力のフレームはfloor(k × rate / (scale × fps))
+    fn start(&self, frame: u64) -> u64 {
+        (frame as u128 * self.rate as u128 / (self.scale * self.fps) as u128) as u64
+    }
+
+    /// 出力のフレーム数（開始時刻に入力のフレームがあるもの）
+    pub fn frame_count(&self) -> u64 {
+        // k × rate / (scale × fps) < frames となるkの数
+        let numerator = self.frames as u128 * (self.scale * self.fps) as u128;
+        numerator.div_ceil(self.rate as u128) as u64
+    }
+
+    /// 出力のフレームの間に表示される入力のフレームの範囲
+    pub fn range(&self, frame: u64) -> Range<u64> {
+        self.start(frame)..self.start(frame + 1).min(self.frames)
+    }
+}
+
+/// 作業領域
+#[derive(Default)]
+struct Scratch {
+    source: FrameBuffer<u16>,
+    sum: Vec<u32>,
+    blended: FrameBuffer<u16>,
+}
+
+/// 別のソースのフレームを間引いて、指定したフレームレートで供給するソース
+///
+/// 出力するフレームに必要な入力のフレームだけを読み込む
+pub struct DecimatedSource<'a> {
+    source: &'a dyn FrameSource,
+    schedule: Option<Schedule>,
+    blend: bool,
+    scratch: RefCell<Scratch>,
+}
+
+impl<'a> DecimatedSource<'a> {
+    pub fn new(source: &'a dyn FrameSource, decimate: Decimate) -> Self {
+        let schedule = Schedule::new(
+            source.rate(),
+            source.scale(),
+            source.frame_count(),
+            decimate.fps,
+        );
+        Self {
+            source,
+            schedule,
+            blend: decimate.blend,
+            scratch: RefCell::new(Scratch::default()),
+        }
+    }
+
+    /// 出力のフレームに使う入力のフレームの範囲（間引かない場合はそのフレームだけ）
+    fn range(&self, frame: i32) -> Range<i32> {
+        match &self.schedule {
+            Some(schedule) if frame >= 0 => {
+                let range = schedule.range(frame as u64);
+                range.start as i32..range.end as i32
+            }
+            _ => frame..frame + 1,
+        }
+    }
+
+    /// 合成する入力のフレームの範囲（合成しない場合や1フレームだけの場合はNone）
+    fn blended_range(&self, frame: i32) -> Option<Range<i32>> {
+        let range = self.range(frame);
+        (self.blend && range.len() > 1).then_some(range)
+    }
+
+    /// 範囲内の入力のフレームを平均したPA64を作業領域に書き込み、変換して出力する
+    /// - write: (平均したPA64, 出力先)を受け取る処理
+    fn read_blended<'b, T: Copy + Default>(
+        &self,
+        range: Range<i32>,
+        layout: PixelLayout,
+        buffer: &'b mut FrameBuffer<T>,
+        write: impl FnOnce(&[u16], &mut [T]),
+    ) -> Option<FrameView<'b, T>> {
+        let mut scratch = self.scratch.borrow_mut();
+        let Scratch {
+            source,
+            sum,
+            blended,
+        } = &mut *scratch;
+
+        let count = range.len() as u32;
+        sum.clear();
+        for frame in range {
+            let data = self.source.read_pa64(frame, source)?;
+            if sum.is_empty() {
+                sum.resize(data.len(), 0);
+            }
+            for (s, &v) in sum.iter_mut().zip(data) {
+                *s += v as u32;
+            }
+        }
+
+        let (width, height) = (source.width(), source.height());
+        let dst = blended.prepare(width, height, PixelLayout::Rgba);
+        for (d, &s) in dst.iter_mut().zip(sum.iter()) {
+            *d = ((s + count / 2) / count) as u16;
+        }
+
+        write(blended.as_slice(), buffer.prepare(width, height, layout));
+        Some(buffer.view())
+    }
+}
+
+impl FrameSource for DecimatedSource<'_> {
+    fn width(&self) -> i32 {
+        self.source.width()
+    }
+
+    fn height(&self) -> i32 {
+        self.source.height()
+    }
+
+    fn rate(&self) -> i32 {
+        match &self.schedule {
+            Some(schedule) => schedule.fps as i32,
+            None => self.source.rate(),
+        }
+    }
+
+    fn scale(&self) -> i32 {
+        match &self.schedule {
+            Some(_) => 1,
+            None => self.source.scale(),
+        }
+    }
+
+    fn frame_count(&self) -> i32 {
+        match &self.schedule {
+            Some(schedule) => schedule.frame_count() as i32,
+            None => self.source.frame_count(),
+        }
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        match self.blended_range(frame) {
+            Some(range) => self.read_blended(range, PixelLayout::Rgb, buffer, |data, dst| {
+                convert::parallel::pa64_to_rgb_into(data, dst)
+            }),
+            None => self.source.read_rgb(self.range(frame).start, buffer),
+        }
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        match self.blended_range(frame) {
+            Some(range) => self.read_blended(range, PixelLayout::Rgba, buffer, |data, dst| {
+                convert::parallel::pa64_to_rgba_into(data, alpha, dst)
+            }),
+            None => self
+                .source
+                .read_rgba(self.range(frame).start, alpha, buffer),
+        }
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        match self.blended_range(frame) {
+            Some(range) => self.read_blended(range, PixelLayout::Rgb, buffer, |data, dst| {
+                convert::parallel::pa64_to_rgb16_into(data, dst)
+            }),
+            None => self.source.read_rgb16(self.range(frame).start, buffer),
+        }
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        match self.blended_range(frame) {
+            Some(range) => self.read_blended(range, PixelLayout::Rgba, buffer, |data, dst| {
+                convert::parallel::pa64_to_rgba16_into(data, alpha, dst)
+            }),
+            None => self
+                .source
+                .read_rgba16(self.range(frame).start, alpha, buffer),
+        }
+    }
+
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        match self.blended_range(frame) {
+            Some(range) => self
+                .read_blended(range, PixelLayout::Rgba, buffer, |data, dst| {
+                    dst.copy_from_slice(data)
+                })
+                .map(|view| view.data()),
+            None => self.source.read_pa64(self.range(frame).start, buffer),
+        }
+    }
+
+    fn is_cached(&self) -> bool {
+        self.source.is_cached()
+    }
+
+    fn is_abort(&self) -> bool {
+        self.source.is_abort()
+    }
+
+    fn rest_time_disp(&self, now: i32, total: i32) {
+        self.source.rest_time_disp(now, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemoryFrameSource;
+
+    fn ranges(schedule: &Schedule) -> Vec<Range<u64>> {
+        (0..schedule.frame_count())
+            .map(|frame| schedule.range(frame))
+            .collect()
+    }
+
+    #[test]
+    fn integer_ratio_picks_every_nth_frame() {
+        let schedule = Schedule::new(30, 1, 8, 10).unwrap();
+        assert_eq!(ranges(&schedule), [0..3, 3..6, 6..8]);
+    }
+
+    #[test]
+    fn ntsc_rate_follows_exact_times() {
+        // 29.97fpsから15fps: 出力の1フレームは入力の約1.998フレーム
+        let schedule = Schedule::new(30000, 1001, 6, 15).unwrap();
+        assert_eq!(ranges(&schedule), [0..1, 1..3, 3..5, 5..6]);
+    }
+
+    #[test]
+    fn higher_rate_is_not_decimated() {
+        assert_eq!(Schedule::new(30, 1, 10, 30), None);
+        assert_eq!(Schedule::new(30, 1, 10, 60), None);
+        assert_eq!(Schedule::new(30, 1, 10, 0), None);
+    }
+
+    #[test]
+    fn blends_skipped_frames() {
+        let mut source = MemoryFrameSource::new(1, 1, 30, 1);
+        for value in [0u16, 300, 600, 900] {
+            source.push_pa64(vec![value, value, value, 65535]).unwrap();
+        }
+
+        let decimated = DecimatedSource::new(
+            &source,
+            Decimate {
+                fps: 15,
+                blend: true,
+            },
+        );
+        assert_eq!((decimated.rate(), decimated.scale()), (15, 1));
+        assert_eq!(decimated.frame_count(), 2);
+        let mut buffer = FrameBuffer::new();
+        assert_eq!(
+            decimated.read_pa64(1, &mut buffer).unwrap(),
+            [750, 750, 750, 65535]
+        );
+
+        let picked = DecimatedSource::new(
+            &source,
+            Decimate {
+                fps: 15,
+                blend: false,
+            },
+        );
+        assert_eq!(
+            picked.read_pa64(1, &mut buffer).unwrap(),
+            [600, 600, 600, 65535]
+        );
+    }
+
+    #[test]
+    fn settings_round_trip_through_ini() {
+        let decimate = Decimate {
+            fps: 15,
+            blend: true,
+        };
+        let mut ini = ini::Ini::new();
+        decimate.save_ini(&mut ini.with_section(Some("Config")));
+        assert_eq!(Decimate::load_ini(ini.section(Some("Config"))), decimate);
+        assert_eq!(Decimate::load_ini(None), Decimate::default());
+
+        assert_eq!(decimate.summary(), " 15fps(合成)");
+        let fps = Decimate {
+            blend: false,
+            ..decimate
+        };
+        assert_eq!(fps.summary(), " 15fps");
+        assert_eq!(Decimate::default().summary(), "");
+    }
+}
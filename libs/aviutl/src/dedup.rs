@@ -0,0 +1,160 @@
+//! 直前のフレームと同じ画像の検出
+//!
+//! 静止している区間のフレームを1枚にまとめ、前のフレームの表示時間を延ばすために使う。
+
+use crate::settings;
+use ini::{Properties, SectionSetter};
+
+/// 重複フレームをまとめる指定
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dedup {
+    /// 直前と同じフレームをまとめて表示時間を延ばす
+    pub merge: bool,
+    /// 同じフレームとみなす各チャンネルの差（8bit換算）
+    pub tolerance: u8,
+}
+
+impl Dedup {
+    /// iniのセクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn load_ini(section: Option<&Properties>) -> Self {
+        let default = Self::default();
+        Self {
+            merge: settings::get_bool(section, "merge_duplicates").unwrap_or(default.merge),
+            tolerance: settings::get(section, "duplicate_tolerance").unwrap_or(default.tolerance),
+        }
+    }
+
+    /// iniのセクションに書き出す
+    pub fn save_ini(&self, section: &mut SectionSetter) {
+        section
+            .set("merge_duplicates", settings::bool_value(self.merge))
+            .set("duplicate_tolerance", self.tolerance.to_string());
+    }
+
+    /// 出力ダイアログに表示する設定の概要（まとめない場合は空、それ以外は先頭に空白を付ける）
+    pub fn summary(&self) -> String {
+        match (self.merge, self.tolerance) {
+            (false, _) => String::new(),
+            (true, 0) => " 重複まとめ".to_string(),
+            (true, n) => format!(" 重複まとめ(誤差{})", n),
+        }
+    }
+
+    /// まとめる場合は、指定の許容誤差で重複を検出する検出器
+    pub fn detector<T: Sample>(&self) -> Option<DuplicateDetector<T>> {
+        self.merge.then(|| DuplicateDetector::new(self.tolerance))
+    }
+}
+
+/// 比較するサンプルの型
+pub trait Sample: Copy {
+    /// 8bit換算の許容誤差以内か
+    fn within(self, other: Self, tolerance: u8) -> bool;
+}
+
+impl Sample for u8 {
+    #[inline(always)]
+    fn within(self, other: Self, tolerance: u8) -> bool {
+        self.abs_diff(other) <= tolerance
+    }
+}
+
+impl Sample for u16 {
+    #[inline(always)]
+    fn within(self, other: Self, tolerance: u8) -> bool {
+        self.abs_diff(other) <= tolerance as u16 * 257
+    }
+}
+
+/// 直前に出力したフレームと同じとみなせるフレームを検出する
+///
+/// 比較の基準は最後に同じでないと判定したフレームなので、
+/// 許容誤差より小さな変化が続いても差は蓄積され、いずれ別のフレームとして出力される
+#[derive(Clone, Debug)]
+pub struct DuplicateDetector<T> {
+    tolerance: u8,
+    previous: Option<Vec<T>>,
+}
+
+impl<T: Sample> DuplicateDetector<T> {
+    /// - tolerance: 各チャンネルの差（8bit換算）がこの値以下なら同じとみなす
+    pub fn new(tolerance: u8) -> Self {
+        Self {
+            tolerance,
+            previous: None,
+        }
+    }
+
+    /// 直前に出力したフレームと同じとみなせるか判定する
+    ///
+    /// 同じでなければ、このフレームを次の比較の基準にする
+    pub fn is_duplicate(&mut self, frame: &[T]) -> bool {
+        match &mut self.previous {
+            Some(previous) if previous.len() == frame.len() => {
+                let tolerance = self.tolerance;
+                if previous
+                    .iter()
+                    .zip(frame)
+                    .all(|(&a, &b)| a.within(b, tolerance))
+                {
+                    return true;
+                }
+                previous.copy_from_slice(frame);
+            }
+            _ => self.previous = Some(frame.to_vec()),
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_frame_is_not_duplicate() {
+        let mut detector = DuplicateDetector::<u8>::new(0);
+        assert!(!detector.is_duplicate(&[1, 2, 3]));
+        assert!(detector.is_duplicate(&[1, 2, 3]));
+        assert!(!detector.is_duplicate(&[1, 2, 4]));
+    }
+
+    #[test]
+    fn tolerance_is_in_8bit_units() {
+        let mut detector = DuplicateDetector::<u16>::new(1);
+        assert!(!detector.is_duplicate(&[1000]));
+        assert!(detector.is_duplicate(&[1257]));
+        assert!(!detector.is_duplicate(&[1258]));
+    }
+
+    #[test]
+    fn small_changes_do_not_accumulate() {
+        let mut detector = DuplicateDetector::<u8>::new(2);
+        assert!(!detector.is_duplicate(&[10]));
+        assert!(detector.is_duplicate(&[11]));
+        assert!(detector.is_duplicate(&[12]));
+        // 基準は最初のフレームのままなので、3つ目の変化で別のフレームになる
+        assert!(!detector.is_duplicate(&[13]));
+        assert!(detector.is_duplicate(&[14]));
+    }
+
+    #[test]
+    fn settings_round_trip_through_ini() {
+        let dedup = Dedup {
+            merge: true,
+            tolerance: 3,
+        };
+        let mut ini = ini::Ini::new();
+        dedup.save_ini(&mut ini.with_section(Some("Config")));
+        assert_eq!(Dedup::load_ini(ini.section(Some("Config"))), dedup);
+        assert_eq!(Dedup::load_ini(None), Dedup::default());
+
+        assert_eq!(dedup.summary(), " 重複まとめ(誤差3)");
+        let exact = Dedup {
+            tolerance: 0,
+            ..dedup
+        };
+        assert_eq!(exact.summary(), " 重複まとめ");
+        assert_eq!(Dedup::default().summary(), "");
+    }
+}
@@ -0,0 +1,444 @@
+//! 出力プラグインの設定ダイアログで共通の入力欄
+//!
+//! 各プラグインのダイアログは共通の設定ごとに入力欄をまとめた型を作ってレイアウトに追加し、
+//! OKボタンが押されたときに`value`で入力された設定を取り出す。
+
+use crate::abort::OnAbort;
+use crate::convert::{format_color, parse_color};
+use crate::crop::{Crop, CropMode};
+use crate::decimate::Decimate;
+use crate::dedup::Dedup;
+use crate::metadata::{MetadataFiles, MetadataSettings};
+use crate::playback::{Playback, PlaybackOrder};
+use crate::resize::{Resize, ResizeFilter, ResizeMode};
+use crate::size_limit::MaxFileSize;
+use std::str::FromStr;
+use win32_dialog::layout::FlexLayout;
+use win32_dialog::widget::{CheckBox, CheckBoxEvent, ComboBox, Label, Number, TextBox, Widget};
+
+/// ラベルと入力欄を縦に並べる
+fn labeled<W: Widget + 'static>(label: &str, widget: W) -> FlexLayout {
+    FlexLayout::column()
+        .with_gap(5.0)
+        .with_widget(Label::new(label))
+        .with_widget(widget)
+}
+
+/// 数値の入力欄の値
+/// - 戻り値: 不正な値の場合はエラーメッセージ
+fn number<T: FromStr>(input: &Number, message: &str) -> Result<T, String> {
+    input.get_value::<T>().map_err(|_| message.to_string())
+}
+
+/// 半透明を合成する背景色の入力欄
+#[derive(Clone)]
+pub struct MatteSection {
+    color: TextBox,
+}
+
+impl MatteSection {
+    pub fn new(color: [u8; 3]) -> Self {
+        Self {
+            color: TextBox::new().text(&format_color(color)),
+        }
+    }
+
+    /// 入力欄をレイアウトに追加する
+    pub fn add_to(&self, layout: FlexLayout) -> FlexLayout {
+        layout.with_layout(labeled("背景色 (RRGGBB)", self.color.clone()))
+    }
+
+    /// 入力された背景色
+    /// - 戻り値: 不正な値の場合はエラーメッセージ
+    pub fn value(&self) -> Result<[u8; 3], String> {
+        parse_color(&self.color.get_text())
+            .ok_or_else(|| "背景色の値が無効です。RRGGBB形式で入力してください。".to_string())
+    }
+}
+
+/// 重複フレームをまとめる指定の入力欄
+#[derive(Clone)]
+pub struct DedupSection {
+    merge: CheckBox,
+    tolerance: Number,
+}
+
+impl DedupSection {
+    pub fn new(dedup: &Dedup) -> Self {
+        let tolerance = Number::new()
+            .value(dedup.tolerance as i32)
+            .range(0, 255)
+            .enabled(dedup.merge);
+        // 誤差はまとめる場合だけ入力できる
+        let merge = CheckBox::new("重複フレームをまとめる")
+            .checked(dedup.merge)
+            .add_event_handler({
+                let tolerance = tolerance.clone();
+                move |event: CheckBoxEvent| match event {
+                    CheckBoxEvent::Changed(checked) => tolerance.set_enabled(checked),
+                }
+            });
+        Self { merge, tolerance }
+    }
+
+    /// 入力欄をレイアウトに追加する
+    pub fn add_to(&self, layout: FlexLayout) -> FlexLayout {
+        layout
+            .with_widget(self.merge.clone())
+            .with_layout(labeled("同じとみなす誤差 (0-255)", self.tolerance.clone()))
+    }
+
+    /// 入力された重複フレームの指定
+    /// - 戻り値: 不正な値の場合はエラーメッセージ
+    pub fn value(&self) -> Result<Dedup, String> {
+        Ok(Dedup {
+            merge: self.merge.is_checked(),
+            tolerance: number(
+                &self.tolerance,
+                "同じとみなす誤差の値が無効です。0-255の値を入力してください。",
+            )?,
+        })
+    }
+}
+
+/// 出力フレームレートの入力欄
+#[derive(Clone)]
+pub struct DecimateSection {
+    fps: Number,
+    blend: CheckBox,
+}
+
+impl DecimateSection {
+    pub fn new(decimate: &Decimate) -> Self {
+        Self {
+            fps: Number::new().value(decimate.fps as i32).range(0, 240),
+            blend: CheckBox::new("間引いたフレームを合成する").checked(decimate.blend),
+        }
+    }
+
+    /// 入力欄をレイアウトに追加する
+    pub fn add_to(&self, layout: FlexLayout) -> FlexLayout {
+        layout
+            .with_layout(labeled("出力フレームレート (0=そのまま)", self.fps.clone()))
+            .with_widget(self.blend.clone())
+    }
+
+    /// 入力されたフレームレートの指定
+    /// - 戻り値: 不正な値の場合はエラーメッセージ
+    pub fn value(&self) -> Result<Decimate, String> {
+        Ok(Decimate {
+            fps: number(
+                &self.fps,
+                "出力フレームレートの値が無効です。0-240の値を入力してください。",
+            )?,
+            blend: self.blend.is_checked(),
+        })
+    }
+}
+
+/// 出力サイズの入力欄
+#[derive(Clone)]
+pub struct ResizeSection {
+    width: Number,
+    height: Number,
+    keep_aspect: CheckBox,
+    mode: ComboBox,
+    filter: ComboBox,
+}
+
+impl ResizeSection {
+    pub fn new(resize: &Resize) -> Self {
+        Self {
+            width: Number::new().value(resize.width as i32).range(0, 16384),
+            height: Number::new().value(resize.height as i32).range(0, 16384),
+            keep_aspect: CheckBox::new("縦横比を保持").checked(resize.keep_aspect),
+            mode: ComboBox::new(vec![ResizeMode::Fit.into(), ResizeMode::Fill.into()])
+                .selected(resize.mode.to_index() as i32),
+            filter: ComboBox::new(vec![
+                ResizeFilter::Lanczos3.into(),
+                ResizeFilter::Bicubic.into(),
+                ResizeFilter::Box.into(),
+            ])
+            .selected(resize.filter.to_index() as i32),
+        }
+    }
+
+    /// 入力欄をレイアウトに追加する
+    pub fn add_to(&self, layout: FlexLayout) -> FlexLayout {
+        layout
+            .with_layout(labeled("幅 (0=自動)", self.width.clone()))
+            .with_layout(labeled("高さ (0=自動)", self.height.clone()))
+            .with_widget(self.keep_aspect.clone())
+            .with_layout(labeled(
+                "合わせ方 (縦横比を保持する場合)",
+                self.mode.clone(),
+            ))
+            .with_layout(labeled("フィルター", self.filter.clone()))
+    }
+
+    /// 入力された出力サイズの指定
+    /// - 戻り値: 不正な値の場合はエラーメッセージ
+    pub fn value(&self) -> Result<Resize, String> {
+        Ok(Resize {
+            width: number(
+                &self.width,
+                "幅の値が無効です。0-16384の値を入力してください。",
+            )?,
+            height: number(
+                &self.height,
+                "高さの値が無効です。0-16384の値を入力してください。",
+            )?,
+            keep_aspect: self.keep_aspect.is_checked(),
+            mode: match self.mode.get_selected_index() {
+                0 => ResizeMode::Fit,
+                1 => ResizeMode::Fill,
+                _ => Default::default(),
+            },
+            filter: match self.filter.get_selected_index() {
+                0 => ResizeFilter::Lanczos3,
+                1 => ResizeFilter::Bicubic,
+                2 => ResizeFilter::Box,
+                _ => Default::default(),
+            },
+        })
+    }
+}
+
+/// 切り取りの入力欄
+#[derive(Clone)]
+pub struct CropSection {
+    mode: ComboBox,
+    x: Number,
+    y: Number,
+    width: Number,
+    height: Number,
+}
+
+impl CropSection {
+    pub fn new(crop: &Crop) -> Self {
+        let length = |value: u32| Number::new().value(value as i32).range(0, 16384);
+        Self {
+            mode: ComboBox::new(vec![
+                CropMode::None.into(),
+                CropMode::Manual.into(),
+                CropMode::Auto.into(),
+            ])
+            .selected(crop.mode.to_index() as i32),
+            x: length(crop.x),
+            y: length(crop.y),
+            width: length(crop.width),
+            height: length(crop.height),
+        }
+    }
+
+    /// 入力欄をレイアウトに追加する
+    pub fn add_to(&self, layout: FlexLayout) -> FlexLayout {
+        layout
+            .with_layout(labeled("切り取り", self.mode.clone()))
+            .with_layout(labeled("切り取り範囲 X", self.x.clone()))
+            .with_layout(labeled("切り取り範囲 Y", self.y.clone()))
+            .with_layout(labeled("切り取り範囲 幅 (0=右端まで)", self.width.clone()))
+            .with_layout(labeled(
+                "切り取り範囲 高さ (0=下端まで)",
+                self.height.clone(),
+            ))
+    }
+
+    /// 入力された切り取りの指定
+    /// - 戻り値: 不正な値の場合はエラーメッセージ
+    pub fn value(&self) -> Result<Crop, String> {
+        Ok(Crop {
+            mode: match self.mode.get_selected_index() {
+                0 => CropMode::None,
+                1 => CropMode::Manual,
+                2 => CropMode::Auto,
+                _ => Default::default(),
+            },
+            x: number(
+                &self.x,
+                "切り取り範囲 Xの値が無効です。0-16384の値を入力してください。",
+            )?,
+            y: number(
+                &self.y,
+                "切り取り範囲 Yの値が無効です。0-16384の値を入力してください。",
+            )?,
+            width: number(
+                &self.width,
+                "切り取り範囲の幅の値が無効です。0-16384の値を入力してください。",
+            )?,
+            height: number(
+                &self.height,
+                "切り取り範囲の高さの値が無効です。0-16384の値を入力してください。",
+            )?,
+        })
+    }
+}
+
+/// メタデータの入力欄
+#[derive(Clone)]
+pub struct MetadataSection {
+    files: MetadataFiles,
+    title: TextBox,
+    author: TextBox,
+    copyright: TextBox,
+    comment: TextBox,
+    xmp_path: TextBox,
+    exif_path: TextBox,
+}
+
+impl MetadataSection {
+    pub fn new(metadata: &MetadataSettings, files: MetadataFiles) -> Self {
+        Self {
+            files,
+            title: TextBox::new().text(&metadata.title),
+            author: TextBox::new().text(&metadata.author),
+            copyright: TextBox::new().text(&metadata.copyright),
+            comment: TextBox::new().text(&metadata.comment),
+            xmp_path: TextBox::new().text(&metadata.xmp_path),
+            exif_path: TextBox::new().text(&metadata.exif_path),
+        }
+    }
+
+    /// 入力欄をレイアウトに追加する
+    pub fn add_to(&self, layout: FlexLayout) -> FlexLayout {
+        let layout = layout
+            .with_layout(labeled("タイトル", self.title.clone()))
+            .with_layout(labeled("作者", self.author.clone()))
+            .with_layout(labeled("著作権表示", self.copyright.clone()))
+            .with_layout(labeled("コメント", self.comment.clone()));
+        let xmp_label = match self.files {
+            MetadataFiles::None => return layout,
+            MetadataFiles::Given => "XMPファイル (空欄=埋め込まない)",
+            MetadataFiles::GivenOrGenerated => "XMPファイル (空欄=上の項目から作成)",
+        };
+        layout
+            .with_layout(labeled(xmp_label, self.xmp_path.clone()))
+            .with_layout(labeled(
+                "EXIFファイル (空欄=埋め込まない)",
+                self.exif_path.clone(),
+            ))
+    }
+
+    /// 入力されたメタデータの項目
+    pub fn value(&self) -> MetadataSettings {
+        let path = |input: &TextBox| match self.files {
+            MetadataFiles::None => String::new(),
+            _ => input.get_text(),
+        };
+        MetadataSettings {
+            title: self.title.get_text(),
+            author: self.author.get_text(),
+            copyright: self.copyright.get_text(),
+            comment: self.comment.get_text(),
+            xmp_path: path(&self.xmp_path),
+            exif_path: path(&self.exif_path),
+        }
+    }
+}
+
+/// ファイルサイズの上限の入力欄
+#[derive(Clone)]
+pub struct MaxFileSizeSection {
+    kilobytes: Number,
+}
+
+impl MaxFileSizeSection {
+    pub fn new(max_file_size: &MaxFileSize) -> Self {
+        Self {
+            kilobytes: Number::new()
+                .value(max_file_size.kilobytes as i32)
+                .range(0, i32::MAX),
+        }
+    }
+
+    /// 入力欄をレイアウトに追加する
+    pub fn add_to(&self, layout: FlexLayout) -> FlexLayout {
+        layout.with_layout(labeled(
+            "ファイルサイズの上限 (KB, 0=上限なし)",
+            self.kilobytes.clone(),
+        ))
+    }
+
+    /// 入力されたファイルサイズの上限
+    /// - 戻り値: 不正な値の場合はエラーメッセージ
+    pub fn value(&self) -> Result<MaxFileSize, String> {
+        Ok(MaxFileSize {
+            kilobytes: number(
+                &self.kilobytes,
+                "ファイルサイズの上限の値が無効です。0以上の値を入力してください。",
+            )?,
+        })
+    }
+}
+
+/// 中断時の扱いの入力欄
+#[derive(Clone)]
+pub struct OnAbortSection {
+    finish: CheckBox,
+}
+
+impl OnAbortSection {
+    pub fn new(on_abort: &OnAbort) -> Self {
+        Self {
+            finish: CheckBox::new("中断時もそれまでのフレームを保存する").checked(on_abort.finish),
+        }
+    }
+
+    /// 入力欄をレイアウトに追加する
+    pub fn add_to(&self, layout: FlexLayout) -> FlexLayout {
+        layout.with_widget(self.finish.clone())
+    }
+
+    /// 入力された中断時の扱い
+    pub fn value(&self) -> OnAbort {
+        OnAbort {
+            finish: self.finish.is_checked(),
+        }
+    }
+}
+
+/// 再生速度と再生順の入力欄
+#[derive(Clone)]
+pub struct PlaybackSection {
+    speed: Number,
+    order: ComboBox,
+}
+
+impl PlaybackSection {
+    pub fn new(playback: &Playback) -> Self {
+        Self {
+            speed: Number::new().value(playback.speed as i32).range(1, 1000),
+            order: ComboBox::new(vec![
+                PlaybackOrder::Forward.into(),
+                PlaybackOrder::Reverse.into(),
+                PlaybackOrder::PingPong.into(),
+            ])
+            .selected(playback.order.to_index() as i32),
+        }
+    }
+
+    /// 入力欄をレイアウトに追加する
+    pub fn add_to(&self, layout: FlexLayout) -> FlexLayout {
+        layout
+            .with_layout(labeled("再生速度 (%)", self.speed.clone()))
+            .with_layout(labeled("再生順", self.order.clone()))
+    }
+
+    /// 入力された再生の指定
+    /// - 戻り値: 不正な値の場合はエラーメッセージ
+    pub fn value(&self) -> Result<Playback, String> {
+        let message = "再生速度の値が無効です。1-1000の値を入力してください。";
+        Ok(Playback {
+            speed: match number(&self.speed, message)? {
+                0 => return Err(message.to_string()),
+                speed => speed,
+            },
+            order: match self.order.get_selected_index() {
+                0 => PlaybackOrder::Forward,
+                1 => PlaybackOrder::Reverse,
+                2 => PlaybackOrder::PingPong,
+                _ => Default::default(),
+            },
+        })
+    }
+}
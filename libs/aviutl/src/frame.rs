@@ -0,0 +1,226 @@
+//! 変換済みフレームの受け渡し
+
+/// 画素のチャンネル構成
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelLayout {
+    /// R, G, B
+    Rgb,
+    /// R, G, B, A（ストレートα）
+    Rgba,
+}
+
+impl PixelLayout {
+    /// 1画素あたりのチャンネル数
+    pub const fn channels(self) -> usize {
+        match self {
+            PixelLayout::Rgb => 3,
+            PixelLayout::Rgba => 4,
+        }
+    }
+
+    /// αチャンネルを持つか
+    pub const fn has_alpha(self) -> bool {
+        matches!(self, PixelLayout::Rgba)
+    }
+}
+
+/// 借用したフレームデータ
+///
+/// 行の間に余白があってもよく、`stride`は行の先頭同士の間隔を要素数で表す
+#[derive(Copy, Clone, Debug)]
+pub struct FrameView<'a, T = u8> {
+    data: &'a [T],
+    width: usize,
+    height: usize,
+    stride: usize,
+    layout: PixelLayout,
+}
+
+impl<'a, T> FrameView<'a, T> {
+    /// フレームデータを借用したビューを作成
+    /// - stride: 1行あたりの要素数（`width * layout.channels()`以上）
+    /// - 戻り値: `data`の長さが足りない場合は`None`
+    pub fn new(
+        data: &'a [T],
+        width: usize,
+        height: usize,
+        stride: usize,
+        layout: PixelLayout,
+    ) -> Option<Self> {
+        let row_len = width * layout.channels();
+        let required = match height {
+            0 => 0,
+            _ => (height - 1) * stride + row_len,
+        };
+        if stride < row_len || data.len() < required {
+            return None;
+        }
+        Some(Self {
+            data,
+            width,
+            height,
+            stride,
+            layout,
+        })
+    }
+
+    /// 隙間なく並んだフレームデータを借用したビューを作成
+    pub fn packed(data: &'a [T], width: usize, height: usize, layout: PixelLayout) -> Option<Self> {
+        Self::new(data, width, height, width * layout.channels(), layout)
+    }
+
+    /// 幅
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// 高さ
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// 1行あたりの要素数
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// 画素のチャンネル構成
+    pub fn layout(&self) -> PixelLayout {
+        self.layout
+    }
+
+    /// 先頭行の先頭からのデータ
+    pub fn data(&self) -> &'a [T] {
+        self.data
+    }
+
+    /// 行の間に余白がないか
+    pub fn is_packed(&self) -> bool {
+        self.stride == self.width * self.layout.channels()
+    }
+
+    /// 指定した行の画素データ
+    pub fn row(&self, y: usize) -> &'a [T] {
+        let start = y * self.stride;
+        &self.data[start..start + self.width * self.layout.channels()]
+    }
+
+    /// 上から順に各行の画素データを返すイテレーター
+    pub fn rows(&self) -> impl Iterator<Item = &'a [T]> + 'a {
+        let (data, stride) = (self.data, self.stride);
+        let row_len = self.width * self.layout.channels();
+        (0..self.height).map(move |y| &data[y * stride..y * stride + row_len])
+    }
+}
+
+impl<T: Copy> FrameView<'_, T> {
+    /// 隙間なく並べ直したデータをコピーして取得
+    pub fn to_vec(&self) -> Vec<T> {
+        if self.is_packed() {
+            return self.data[..self.height * self.stride].to_vec();
+        }
+        let mut data = Vec::with_capacity(self.width * self.height * self.layout.channels());
+        for row in self.rows() {
+            data.extend_from_slice(row);
+        }
+        data
+    }
+}
+
+/// フレームごとに再利用できるフレームバッファ
+///
+/// 同じ大きさのフレームを続けて書き込む場合は確保済みの領域をそのまま使う
+#[derive(Clone, Debug)]
+pub struct FrameBuffer<T = u8> {
+    data: Vec<T>,
+    width: usize,
+    height: usize,
+    layout: PixelLayout,
+}
+
+impl<T> Default for FrameBuffer<T> {
+    fn default() -> Self {
+        Self {
+            data: Vec::new(),
+            width: 0,
+            height: 0,
+            layout: PixelLayout::Rgb,
+        }
+    }
+}
+
+impl<T: Copy + Default> FrameBuffer<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 大きさとチャンネル構成を設定して書き込み先の領域を取得
+    pub fn prepare(&mut self, width: usize, height: usize, layout: PixelLayout) -> &mut [T] {
+        self.width = width;
+        self.height = height;
+        self.layout = layout;
+        self.data
+            .resize(width * height * layout.channels(), T::default());
+        &mut self.data
+    }
+
+    /// 書き込まれたフレームから指定した範囲を切り出し、隙間なく詰め直す
+    ///
+    /// 範囲は書き込まれたフレームに収まっている必要がある
+    pub fn crop(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        assert!(x + width <= self.width && y + height <= self.height);
+        let channels = self.layout.channels();
+        let (stride, row_len) = (self.stride(), width * channels);
+        // 切り出した各行は元の位置より前に移るので、上から順に移せば上書きされない
+        for row in 0..height {
+            let start = (y + row) * stride + x * channels;
+            self.data.copy_within(start..start + row_len, row * row_len);
+        }
+        self.prepare(width, height, self.layout);
+    }
+}
+
+impl<T> FrameBuffer<T> {
+    /// 幅
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// 高さ
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// 1行あたりの要素数
+    pub fn stride(&self) -> usize {
+        self.width * self.layout.channels()
+    }
+
+    /// 画素のチャンネル構成
+    pub fn layout(&self) -> PixelLayout {
+        self.layout
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+
+    /// バッファ全体のビュー
+    pub fn view(&self) -> FrameView<'_, T> {
+        FrameView {
+            data: &self.data,
+            width: self.width,
+            height: self.height,
+            stride: self.stride(),
+            layout: self.layout,
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.data
+    }
+}
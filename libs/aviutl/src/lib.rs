@@ -1,2 +1,22 @@
+pub mod abort;
+pub mod cache;
+pub mod color;
+pub mod convert;
+pub mod crop;
+pub mod decimate;
+pub mod dedup;
+#[cfg(feature = "dialog")]
+pub mod dialog;
+pub mod frame;
+pub mod metadata;
 pub mod output2;
+pub mod output_file;
+pub mod playback;
+#[cfg(windows)]
+pub mod plugin;
+pub mod resize;
+mod settings;
+pub mod size_limit;
+pub mod source;
+pub mod timing;
 pub mod types;
@@ -0,0 +1,353 @@
+//! 出力ファイルに埋め込むメタデータ
+//!
+//! タイトルや作者などの文字列と、ファイルから読み込んだXMP・EXIFをまとめて扱う。
+//! 各形式への書き込み（GIFのコメント拡張、PNGのテキストチャンクなど）は各プラグインで行う。
+
+use crate::settings;
+use ini::{Properties, SectionSetter};
+use std::path::Path;
+
+/// 出力ファイルに埋め込むメタデータ
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// タイトル
+    pub title: String,
+    /// 作者
+    pub author: String,
+    /// 著作権表示
+    pub copyright: String,
+    /// コメント
+    pub comment: String,
+    /// 出力したソフトウェア（プラグイン名とバージョン）
+    pub software: String,
+    /// そのまま埋め込むXMPパケット（指定しない場合は上の項目から作る）
+    pub xmp: Option<Vec<u8>>,
+    /// そのまま埋め込むEXIF（TIFFヘッダーから始まるデータ）
+    pub exif: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// 空でないテキストの項目（PNGのテキストチャンクのキーワードと値）
+    pub fn text_entries(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Copyright", &self.copyright),
+            ("Comment", &self.comment),
+            ("Software", &self.software),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(keyword, value)| (keyword, value.as_str()))
+        .collect()
+    }
+
+    /// 空でないテキストの項目を1行ずつ並べたコメント（GIFのコメント拡張に使う）
+    pub fn comment_text(&self) -> String {
+        self.text_entries()
+            .iter()
+            .map(|(keyword, value)| format!("{}: {}", keyword, value))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// 埋め込むXMPパケット
+    ///
+    /// ファイルから読み込んだXMPがあればそのまま、無ければテキストの項目から作る
+    pub fn xmp_packet(&self) -> Vec<u8> {
+        if let Some(xmp) = &self.xmp {
+            return xmp.clone();
+        }
+
+        let alt = |value: &str| {
+            format!(
+                "<rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt>",
+                escape_xml(value)
+            )
+        };
+        let mut properties = String::new();
+        if !self.title.is_empty() {
+            properties.push_str(&format!("   <dc:title>{}</dc:title>\n", alt(&self.title)));
+        }
+        if !self.author.is_empty() {
+            properties.push_str(&format!(
+                "   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+                escape_xml(&self.author)
+            ));
+        }
+        if !self.copyright.is_empty() {
+            properties.push_str(&format!(
+                "   <dc:rights>{}</dc:rights>\n",
+                alt(&self.copyright)
+            ));
+        }
+        if !self.comment.is_empty() {
+            properties.push_str(&format!(
+                "   <dc:description>{}</dc:description>\n",
+                alt(&self.comment)
+            ));
+        }
+        if !self.software.is_empty() {
+            properties.push_str(&format!(
+                "   <xmp:CreatorTool>{}</xmp:CreatorTool>\n",
+                escape_xml(&self.software)
+            ));
+        }
+
+        format!(
+            concat!(
+                "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+                "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+                " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+                "  <rdf:Description rdf:about=\"\"",
+                " xmlns:dc=\"http://purl.org/dc/elements/1.1/\"",
+                " xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\n",
+                "{}",
+                "  </rdf:Description>\n",
+                " </rdf:RDF>\n",
+                "</x:xmpmeta>\n",
+                "<?xpacket end=\"w\"?>",
+            ),
+            properties
+        )
+        .into_bytes()
+    }
+
+    /// XMPファイルを読み込む（パスが空の場合はNone）
+    pub fn load_xmp(path: &str) -> Result<Option<Vec<u8>>, String> {
+        read_file(path).map_err(|e| format!("XMPファイル読み込みエラー: {}", e))
+    }
+
+    /// EXIFファイルを読み込む（パスが空の場合はNone）
+    ///
+    /// JPEGのAPP1と同じ`Exif\0\0`から始まる場合は取り除き、TIFFヘッダーから始まるデータにする
+    pub fn load_exif(path: &str) -> Result<Option<Vec<u8>>, String> {
+        let Some(data) =
+            read_file(path).map_err(|e| format!("EXIFファイル読み込みエラー: {}", e))?
+        else {
+            return Ok(None);
+        };
+        let data = match data.strip_prefix(b"Exif\0\0") {
+            Some(tiff) => tiff.to_vec(),
+            None => data,
+        };
+        if !data.starts_with(b"II*\0") && !data.starts_with(b"MM\0*") {
+            return Err("EXIFファイル読み込みエラー: TIFFヘッダーがありません".into());
+        }
+        Ok(Some(data))
+    }
+}
+
+/// 形式が埋め込めるファイルの種類（設定ダイアログの入力欄と概要に使う）
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MetadataFiles {
+    /// XMP・EXIFを埋め込まない形式
+    None,
+    /// XMP・EXIFファイルを指定した場合だけ埋め込む
+    Given,
+    /// XMPファイルを指定しない場合は上の項目から作る
+    GivenOrGenerated,
+}
+
+/// 設定ダイアログで入力し、設定ファイルに保存するメタデータの項目
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetadataSettings {
+    /// タイトル
+    pub title: String,
+    /// 作者
+    pub author: String,
+    /// 著作権表示
+    pub copyright: String,
+    /// コメント
+    pub comment: String,
+    /// 埋め込むXMPファイルのパス（空 = 指定しない）
+    pub xmp_path: String,
+    /// 埋め込むEXIFファイルのパス（空 = 指定しない）
+    pub exif_path: String,
+}
+
+impl MetadataSettings {
+    /// iniのセクションから読み込む（無い項目は空にする）
+    pub fn load_ini(section: Option<&Properties>) -> Self {
+        let text = |key: &str| settings::get(section, key).unwrap_or_default();
+        Self {
+            title: text("title"),
+            author: text("author"),
+            copyright: text("copyright"),
+            comment: text("comment"),
+            xmp_path: text("xmp_path"),
+            exif_path: text("exif_path"),
+        }
+    }
+
+    /// iniのセクションに書き出す
+    pub fn save_ini(&self, section: &mut SectionSetter) {
+        section
+            .set("title", self.title.as_str())
+            .set("author", self.author.as_str())
+            .set("copyright", self.copyright.as_str())
+            .set("comment", self.comment.as_str())
+            .set("xmp_path", self.xmp_path.as_str())
+            .set("exif_path", self.exif_path.as_str());
+    }
+
+    /// 出力ダイアログに表示する設定の概要（埋め込む項目が無い場合は空、それ以外は先頭に空白を付ける）
+    /// - files: 形式が埋め込めるファイル（埋め込まない形式ではファイルのパスを無視する）
+    pub fn summary(&self, files: MetadataFiles) -> String {
+        let texts = [&self.title, &self.author, &self.copyright, &self.comment];
+        let paths = [&self.xmp_path, &self.exif_path];
+        let specified = texts.iter().any(|s| !s.is_empty())
+            || (files != MetadataFiles::None && paths.iter().any(|s| !s.is_empty()));
+        match specified {
+            true => " メタデータ".to_string(),
+            false => String::new(),
+        }
+    }
+
+    /// テキストの項目だけのメタデータ
+    pub fn to_text_metadata(&self, software: &str) -> Metadata {
+        Metadata {
+            title: self.title.clone(),
+            author: self.author.clone(),
+            copyright: self.copyright.clone(),
+            comment: self.comment.clone(),
+            software: software.to_string(),
+            xmp: None,
+            exif: None,
+        }
+    }
+
+    /// XMP・EXIFファイルを読み込んだメタデータ
+    pub fn to_metadata(&self, software: &str) -> Result<Metadata, String> {
+        Ok(Metadata {
+            xmp: Metadata::load_xmp(&self.xmp_path)?,
+            exif: Metadata::load_exif(&self.exif_path)?,
+            ..self.to_text_metadata(software)
+        })
+    }
+}
+
+/// ファイルを読み込む（パスが空の場合はNone）
+fn read_file(path: &str) -> std::io::Result<Option<Vec<u8>>> {
+    let path = path.trim();
+    if path.is_empty() {
+        return Ok(None);
+    }
+    std::fs::read(Path::new(path)).map(Some)
+}
+
+/// XMLの文字列として書けるように記号を置き換える
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            title: "Title <1>".into(),
+            author: "yu7400ki".into(),
+            software: "Test v1.0".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn empty_entries_are_skipped() {
+        let metadata = metadata();
+        assert_eq!(
+            metadata.text_entries(),
+            vec![
+                ("Title", "Title <1>"),
+                ("Author", "yu7400ki"),
+                ("Software", "Test v1.0")
+            ]
+        );
+        assert_eq!(
+            metadata.comment_text(),
+            "Title: Title <1>\nAuthor: yu7400ki\nSoftware: Test v1.0"
+        );
+    }
+
+    #[test]
+    fn xmp_is_generated_from_the_entries() {
+        let xmp = String::from_utf8(metadata().xmp_packet()).unwrap();
+        assert!(xmp.contains("<rdf:li xml:lang=\"x-default\">Title &lt;1&gt;</rdf:li>"));
+        assert!(xmp.contains("<rdf:li>yu7400ki</rdf:li>"));
+        assert!(xmp.contains("<xmp:CreatorTool>Test v1.0</xmp:CreatorTool>"));
+        assert!(!xmp.contains("dc:rights"));
+
+        // 読み込んだXMPがあればそのまま使う
+        let raw = Metadata {
+            xmp: Some(b"<x:xmpmeta/>".to_vec()),
+            ..metadata()
+        };
+        assert_eq!(raw.xmp_packet(), b"<x:xmpmeta/>");
+    }
+
+    #[test]
+    fn exif_prefix_is_removed() {
+        let path = std::env::temp_dir().join(format!("{}-metadata.exif", std::process::id()));
+        std::fs::write(&path, b"Exif\0\0MM\0*\0\0\0\x08").unwrap();
+        let path_str = path.to_str().unwrap();
+        assert_eq!(
+            Metadata::load_exif(path_str).unwrap(),
+            Some(b"MM\0*\0\0\0\x08".to_vec())
+        );
+
+        std::fs::write(&path, b"not exif").unwrap();
+        assert!(Metadata::load_exif(path_str).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(Metadata::load_exif("").unwrap(), None);
+    }
+
+    #[test]
+    fn settings_round_trip_through_ini() {
+        let settings = MetadataSettings {
+            title: "タイトル".into(),
+            comment: "a=b".into(),
+            exif_path: "C:\\exif.bin".into(),
+            ..Default::default()
+        };
+        let mut ini = ini::Ini::new();
+        settings.save_ini(&mut ini.with_section(Some("Config")));
+        let section = ini.section(Some("Config"));
+        assert_eq!(MetadataSettings::load_ini(section), settings);
+        assert_eq!(
+            MetadataSettings::load_ini(None),
+            MetadataSettings::default()
+        );
+
+        assert_eq!(settings.summary(MetadataFiles::Given), " メタデータ");
+        assert_eq!(
+            MetadataSettings::default().summary(MetadataFiles::Given),
+            ""
+        );
+        // ファイルを埋め込まない形式では、ファイルのパスだけなら何も埋め込まない
+        let files_only = MetadataSettings {
+            exif_path: "C:\\exif.bin".into(),
+            ..Default::default()
+        };
+        assert_eq!(files_only.summary(MetadataFiles::None), "");
+        assert_eq!(files_only.summary(MetadataFiles::Given), " メタデータ");
+        let metadata = settings.to_text_metadata("Test v1.0");
+        assert_eq!(
+            metadata.comment_text(),
+            "Title: タイトル\nComment: a=b\nSoftware: Test v1.0"
+        );
+    }
+}
@@ -27,8 +27,13 @@ THE SOFTWARE.
  */
 #![allow(dead_code)]
 
+use crate::convert::{self, AlphaMode, YuvMatrix, YuvRange};
+use crate::frame::{FrameBuffer, FrameView, PixelLayout};
 use crate::types::{DWORD, LPCWSTR};
 use std::os::raw::{c_int, c_void};
+use std::path::PathBuf;
+use widestring::U16CStr;
+#[cfg(windows)]
 use windows::Win32::Foundation::{HINSTANCE, HWND};
 
 /// 出力情報構造体
@@ -98,8 +103,14 @@ impl OutputInfo {
     pub const FLAG_AUDIO: c_int = 2;
 
     /// 画像データを取得
+    /// - 戻り値: フレーム番号が範囲外の場合や取得できなかった場合は`None`
     pub fn get_video(&self, frame: i32, format: u32) -> Option<*mut c_void> {
-        self.func_get_video.map(|f| f(frame, format))
+        if !(0..self.n).contains(&frame) {
+            return None;
+        }
+        self.func_get_video
+            .map(|f| f(frame, format))
+            .filter(|data_ptr| !data_ptr.is_null())
     }
 
     /// 音声データを取得
@@ -130,67 +141,200 @@ impl OutputInfo {
         }
     }
 
+    /// セーブファイル名をパスとして取得
+    ///
+    /// Windowsでは対になっていないサロゲートを含む名前も置き換えずにそのまま保持する
+    pub fn save_path(&self) -> PathBuf {
+        if self.savefile.is_null() {
+            return PathBuf::new();
+        }
+        let savefile = unsafe { U16CStr::from_ptr_str(self.savefile) };
+        path_from_wide(savefile.as_slice())
+    }
+
+    /// BGRフォーマットのフレームデータをRGBに変換してバッファに書き込む
+    pub fn read_video_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let data_ptr = self.get_video(frame, video_format::BI_RGB)?;
+
+        let (w, h) = (self.w as usize, self.h as usize);
+        let data_slice = unsafe {
+            std::slice::from_raw_parts(data_ptr as *const u8, convert::dib_rgb24_stride(w) * h)
+        };
+
+        let image_buffer = buffer.prepare(w, h, PixelLayout::Rgb);
+        convert::parallel::dib_bgr24_to_rgb_into(data_slice, w, h, image_buffer);
+        Some(buffer.view())
+    }
+
+    /// PA64フォーマットのフレームデータをRGBAに変換してバッファに書き込む（アルファチャンネル付き）
+    pub fn read_video_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let data_slice = self.get_video_pa64(frame)?;
+
+        let image_buffer = buffer.prepare(self.w as usize, self.h as usize, PixelLayout::Rgba);
+        convert::parallel::pa64_to_rgba_into(data_slice, alpha, image_buffer);
+        Some(buffer.view())
+    }
+
+    /// PA64フォーマットのフレームデータを16bit RGBAに変換してバッファに書き込む（アルファチャンネル付き）
+    pub fn read_video_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        let data_slice = self.get_video_pa64(frame)?;
+
+        let image_buffer = buffer.prepare(self.w as usize, self.h as usize, PixelLayout::Rgba);
+        convert::parallel::pa64_to_rgba16_into(data_slice, alpha, image_buffer);
+        Some(buffer.view())
+    }
+
+    /// PA64フォーマットのフレームデータを16bit RGBに変換してバッファに書き込む
+    pub fn read_video_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        let data_slice = self.get_video_pa64(frame)?;
+
+        let image_buffer = buffer.prepare(self.w as usize, self.h as usize, PixelLayout::Rgb);
+        convert::parallel::pa64_to_rgb16_into(data_slice, image_buffer);
+        Some(buffer.view())
+    }
+
+    /// PA64フォーマットのフレームデータをそのままバッファにコピーする
+    pub fn read_video_pa64<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<&'b [u16]> {
+        let data_slice = self.get_video_pa64(frame)?;
+
+        let image_buffer = buffer.prepare(self.w as usize, self.h as usize, PixelLayout::Rgba);
+        image_buffer.copy_from_slice(data_slice);
+        Some(image_buffer)
+    }
+
+    /// PA64フォーマットのフレームデータを取得
+    fn get_video_pa64(&self, frame: i32) -> Option<&[u16]> {
+        let data_ptr = self.get_video(frame, video_format::PA64)?;
+
+        Some(unsafe {
+            std::slice::from_raw_parts(data_ptr as *const u16, (self.w * self.h * 4) as usize)
+        })
+    }
+
     /// BGRフォーマットのフレームデータをRGBに変換して取得
     #[inline(always)]
     pub fn get_video_rgb(&self, frame: i32) -> Option<Vec<u8>> {
-        let data_ptr = self.get_video(frame, video_format::BI_RGB)?;
-
-        unsafe {
-            let input_stride = ((self.w * 3 + 3) / 4) * 4; // RGB24のストライド（4バイト境界アライメント）
-            let data_slice =
-                std::slice::from_raw_parts(data_ptr as *const u8, (input_stride * self.h) as usize);
-
-            let mut image_buffer = Vec::with_capacity((self.w * self.h * 3) as usize);
-
-            // BMPは下から上に格納されているので反転してBGR→RGB変換
-            for y in (0..self.h).rev() {
-                let row_start = (y * input_stride) as usize;
-                let row_end = row_start + (self.w * 3) as usize;
-                for bgr_pixel in data_slice[row_start..row_end].chunks_exact(3) {
-                    image_buffer.push(bgr_pixel[2]); // R
-                    image_buffer.push(bgr_pixel[1]); // G
-                    image_buffer.push(bgr_pixel[0]); // B
-                }
-            }
-            Some(image_buffer)
-        }
+        let mut buffer = FrameBuffer::new();
+        self.read_video_rgb(frame, &mut buffer)?;
+        Some(buffer.into_vec())
     }
 
     /// PA64フォーマットのフレームデータをRGBAに変換して取得（アルファチャンネル付き）
     #[inline(always)]
-    pub fn get_video_rgba(&self, frame: i32) -> Option<Vec<u8>> {
-        let data_ptr = self.get_video(frame, video_format::PA64)?;
+    pub fn get_video_rgba(&self, frame: i32, alpha: AlphaMode) -> Option<Vec<u8>> {
+        let mut buffer = FrameBuffer::new();
+        self.read_video_rgba(frame, alpha, &mut buffer)?;
+        Some(buffer.into_vec())
+    }
 
+    /// PA64フォーマットのフレームデータを16bit RGBAに変換して取得（アルファチャンネル付き）
+    #[inline(always)]
+    pub fn get_video_rgba16(&self, frame: i32, alpha: AlphaMode) -> Option<Vec<u16>> {
+        let mut buffer = FrameBuffer::new();
+        self.read_video_rgba16(frame, alpha, &mut buffer)?;
+        Some(buffer.into_vec())
+    }
+
+    /// PA64フォーマットのフレームデータを16bit RGBに変換して取得
+    #[inline(always)]
+    pub fn get_video_rgb16(&self, frame: i32) -> Option<Vec<u16>> {
+        let mut buffer = FrameBuffer::new();
+        self.read_video_rgb16(frame, &mut buffer)?;
+        Some(buffer.into_vec())
+    }
+
+    /// YUY2フォーマットのフレームデータをRGBに変換してバッファに書き込む
+    ///
+    /// AviUtl2のYUY2はリミテッドレンジとして扱う
+    pub fn read_video_rgb_from_yuy2<'b>(
+        &self,
+        frame: i32,
+        matrix: YuvMatrix,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let data_ptr = self.get_video(frame, video_format::YUY2)?;
+
+        let (w, h) = (self.w as usize, self.h as usize);
         let data_slice = unsafe {
-            std::slice::from_raw_parts(data_ptr as *const u16, (self.w * self.h * 4) as usize)
+            std::slice::from_raw_parts(data_ptr as *const u8, convert::yuy2_stride(w) * h)
         };
 
-        let mut image_buffer = Vec::with_capacity((self.w * self.h * 4) as usize);
-
-        for chunk in data_slice.chunks_exact(4) {
-            let r = chunk[0] as u32;
-            let g = chunk[1] as u32;
-            let b = chunk[2] as u32;
-            let a = chunk[3] as u32;
-
-            let (r8, g8, b8, a8) = if a < 128 {
-                (0, 0, 0, 0)
-            } else {
-                (
-                    ((r * 255 + a / 2) / a) as u8,
-                    ((g * 255 + a / 2) / a) as u8,
-                    ((b * 255 + a / 2) / a) as u8,
-                    ((a + 128) / 257) as u8,
-                )
-            };
-            image_buffer.extend_from_slice(&[r8, g8, b8, a8]);
-        }
+        let image_buffer = buffer.prepare(w, h, PixelLayout::Rgb);
+        convert::yuy2_to_rgb_into(data_slice, w, h, matrix, YuvRange::Limited, image_buffer);
+        Some(buffer.view())
+    }
+
+    /// HF64フォーマットのフレームデータをPA64に変換してバッファに書き込む
+    pub fn read_video_pa64_from_hf64<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<&'b [u16]> {
+        let data_ptr = self.get_video(frame, video_format::HF64)?;
+
+        let data_slice = unsafe {
+            std::slice::from_raw_parts(data_ptr as *const u16, (self.w * self.h * 4) as usize)
+        };
 
+        let image_buffer = buffer.prepare(self.w as usize, self.h as usize, PixelLayout::Rgba);
+        convert::hf64_to_pa64_into(data_slice, image_buffer);
         Some(image_buffer)
     }
+
+    /// YUY2フォーマットのフレームデータをRGBに変換して取得
+    #[inline(always)]
+    pub fn get_video_rgb_from_yuy2(&self, frame: i32, matrix: YuvMatrix) -> Option<Vec<u8>> {
+        let mut buffer = FrameBuffer::new();
+        self.read_video_rgb_from_yuy2(frame, matrix, &mut buffer)?;
+        Some(buffer.into_vec())
+    }
+
+    /// HF64フォーマットのフレームデータをRGBAに変換して取得（アルファチャンネル付き）
+    #[inline(always)]
+    pub fn get_video_rgba_from_hf64(&self, frame: i32, alpha: AlphaMode) -> Option<Vec<u8>> {
+        let mut buffer = FrameBuffer::new();
+        let data = self.read_video_pa64_from_hf64(frame, &mut buffer)?;
+        Some(convert::pa64_to_rgba(data, alpha))
+    }
+}
+
+/// UTF-16のパスをOSの文字列のまま変換する
+#[cfg(windows)]
+fn path_from_wide(wide: &[u16]) -> PathBuf {
+    use std::os::windows::ffi::OsStringExt;
+    std::ffi::OsString::from_wide(wide).into()
+}
+
+/// UTF-16のパスを変換する（Windows以外では不正な並びを置換文字にする）
+#[cfg(not(windows))]
+fn path_from_wide(wide: &[u16]) -> PathBuf {
+    String::from_utf16_lossy(wide).into()
 }
 
 /// 出力プラグイン構造体
+#[cfg(windows)]
 #[repr(C)]
 pub struct OutputPluginTable {
     /// フラグ (未使用)
@@ -212,6 +356,7 @@ pub struct OutputPluginTable {
     pub func_get_config_text: Option<extern "C" fn() -> LPCWSTR>,
 }
 
+#[cfg(windows)]
 impl OutputPluginTable {
     /// フラグ定数: 画像をサポートする
     pub const FLAG_VIDEO: c_int = 1;
@@ -244,3 +389,89 @@ pub mod audio_format {
     /// PCM (float) 32bit
     pub const WAVE_FORMAT_IEEE_FLOAT: DWORD = 3;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// セーブファイル名だけを設定した出力情報
+    fn output_info(savefile: LPCWSTR) -> OutputInfo {
+        OutputInfo {
+            flag: OutputInfo::FLAG_VIDEO,
+            w: 0,
+            h: 0,
+            rate: 30,
+            scale: 1,
+            n: 0,
+            audio_rate: 0,
+            audio_ch: 0,
+            audio_n: 0,
+            savefile,
+            func_get_video: None,
+            func_get_audio: None,
+            func_is_abort: None,
+            func_rest_time_disp: None,
+            func_set_buffer_size: None,
+        }
+    }
+
+    /// NUL終端したUTF-16の列
+    fn nul_terminated(wide: &[u16]) -> Vec<u16> {
+        wide.iter().copied().chain(Some(0)).collect()
+    }
+
+    #[test]
+    fn save_path_of_null_is_empty() {
+        assert_eq!(output_info(std::ptr::null()).save_path(), PathBuf::new());
+    }
+
+    #[test]
+    fn save_path_keeps_valid_utf16() {
+        // サロゲートペア（U+1F3AC）を含む
+        let path = "C:\\出力\\🎬アニメ.webp";
+        let wide = nul_terminated(&path.encode_utf16().collect::<Vec<_>>());
+        assert_eq!(output_info(wide.as_ptr()).save_path(), PathBuf::from(path));
+    }
+
+    #[test]
+    fn save_path_stops_at_nul() {
+        let wide: Vec<u16> = "a.gif\0b.gif\0".encode_utf16().collect();
+        assert_eq!(
+            output_info(wide.as_ptr()).save_path(),
+            PathBuf::from("a.gif")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn save_path_keeps_unpaired_surrogates() {
+        use std::os::windows::ffi::OsStrExt;
+
+        let sequences: [&[u16]; 4] = [
+            // 上位サロゲートのみ
+            &[0x43, 0x3A, 0x5C, 0xD800, 0x2E, 0x67, 0x69, 0x66],
+            // 下位サロゲートのみ
+            &[0x43, 0x3A, 0x5C, 0xDC00, 0x2E, 0x67, 0x69, 0x66],
+            // 順序が逆のサロゲート
+            &[0x61, 0xDC00, 0xD800, 0x2E, 0x70, 0x6E, 0x67],
+            // 末尾の上位サロゲート
+            &[0x61, 0x2E, 0x77, 0x65, 0x62, 0x70, 0xDBFF],
+        ];
+        for sequence in sequences {
+            let wide = nul_terminated(sequence);
+            let path = output_info(wide.as_ptr()).save_path();
+            let encoded: Vec<u16> = path.as_os_str().encode_wide().collect();
+            assert_eq!(encoded, sequence);
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn save_path_replaces_unpaired_surrogates() {
+        let wide = nul_terminated(&[0x61, 0xD800, 0x2E, 0x67, 0x69, 0x66]);
+        assert_eq!(
+            output_info(wide.as_ptr()).save_path(),
+            PathBuf::from("a\u{FFFD}.gif")
+        );
+    }
+}
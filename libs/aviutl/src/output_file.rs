@@ -0,0 +1,192 @@
+//! 出力ファイルの書き込み
+//!
+//! 出力先と同じフォルダーの一時ファイルにバッファーを通して書き込み、最後まで書けた場合だけ出力先に置き換える。
+//! エラーや中断で書き終えなかった場合は一時ファイルを削除するので、途中までのファイルは残らず、既存のファイルも上書きされない。
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// 書き終えたら出力先に置き換える一時ファイル
+///
+/// [`commit`](Self::commit)せずにドロップした場合や、置き換えに失敗した場合は一時ファイルを削除する
+pub struct OutputFile {
+    /// 置き換える前やドロップ時に閉じるのでOption
+    writer: Option<BufWriter<File>>,
+    temp_path: PathBuf,
+    output_path: PathBuf,
+    /// 出力先に置き換えたか
+    committed: bool,
+}
+
+impl OutputFile {
+    /// 出力先と同じフォルダーに一時ファイルを作る
+    pub fn create(output_path: &Path) -> Result<Self, String> {
+        let mut name = output_path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.partial", std::process::id()));
+        let temp_path = output_path.with_file_name(name);
+
+        // 書き込んだヘッダーを後から直せるように読み書きできるように開く
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp_path)
+            .map_err(|e| format!("ファイル作成エラー: {}", e))?;
+        Ok(Self {
+            writer: Some(BufWriter::new(file)),
+            temp_path,
+            output_path: output_path.to_path_buf(),
+            committed: false,
+        })
+    }
+
+    /// 書き込んだ内容をディスクに書き出し、出力先に置き換える
+    pub fn commit(mut self) -> Result<(), String> {
+        let writer = self
+            .writer
+            .take()
+            .expect("書き込み先は置き換えるまで残っている");
+        let file = writer
+            .into_inner()
+            .map_err(|e| format!("ファイル保存エラー: {}", e.error()))?;
+        file.sync_all()
+            .map_err(|e| format!("ファイル保存エラー: {}", e))?;
+        // 置き換える前にファイルを閉じる
+        drop(file);
+
+        std::fs::rename(&self.temp_path, &self.output_path)
+            .map_err(|e| format!("ファイル保存エラー: {}", e))?;
+        self.committed = true;
+        Ok(())
+    }
+
+    /// 書き込んだ内容の一部を読み込む
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        let writer = self.writer();
+        writer.flush()?;
+        let file = writer.get_mut();
+        file.seek(SeekFrom::Start(offset))?;
+        let read = file.read_exact(buf);
+        file.seek(SeekFrom::End(0))?;
+        read
+    }
+
+    /// 書き込んだ内容の一部を書き換える（宣言した値を後から直す場合に使う）
+    pub fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        let writer = self.writer();
+        writer.flush()?;
+        let file = writer.get_mut();
+        file.seek(SeekFrom::Start(offset))?;
+        let written = file.write_all(data);
+        file.seek(SeekFrom::End(0))?;
+        written
+    }
+
+    fn writer(&mut self) -> &mut BufWriter<File> {
+        self.writer
+            .as_mut()
+            .expect("書き込み先は置き換えるまで残っている")
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.writer().write_all(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        // 置き換えていなければ、閉じてから一時ファイルを削除する
+        drop(self.writer.take());
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn commit_replaces_the_output() {
+        let path = output_path("output-file-commit.bin");
+        std::fs::write(&path, b"old").unwrap();
+
+        let mut file = OutputFile::create(&path).unwrap();
+        file.write_all(b"new").unwrap();
+        // 置き換えるまでは元のファイルのまま
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        let temp_path = file.temp_path.clone();
+        file.commit().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!temp_path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn written_bytes_can_be_patched() {
+        let path = output_path("output-file-patch.bin");
+        let mut file = OutputFile::create(&path).unwrap();
+        file.write_all(b"header:0;body").unwrap();
+
+        let mut value = [0u8; 1];
+        file.read_at(7, &mut value).unwrap();
+        assert_eq!(&value, b"0");
+        file.write_at(7, b"4").unwrap();
+        // 書き換えた後も末尾に追記される
+        file.write_all(b";end").unwrap();
+        file.commit().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"header:4;body;end");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dropping_keeps_the_previous_output() {
+        let path = output_path("output-file-drop.bin");
+        std::fs::write(&path, b"old").unwrap();
+
+        let mut file = OutputFile::create(&path).unwrap();
+        file.write_all(b"partial").unwrap();
+        let temp_path = file.temp_path.clone();
+        drop(file);
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        assert!(!temp_path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_commit_removes_the_temporary_file() {
+        // 空でないフォルダーは置き換えられない
+        let path = output_path("output-file-failed-commit");
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("keep.bin"), b"old").unwrap();
+
+        let mut file = OutputFile::create(&path).unwrap();
+        file.write_all(b"new").unwrap();
+        let temp_path = file.temp_path.clone();
+        assert!(file.commit().is_err());
+
+        assert!(!temp_path.exists());
+        assert_eq!(std::fs::read(path.join("keep.bin")).unwrap(), b"old");
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,355 @@
+//! 再生速度と再生順の変更
+//!
+//! 速度はフレームレートを変えて表示時間だけを伸縮する。
+//! 逆再生と往復では入力を先頭から順に一度だけ読み込み、読み込んだフレームを一時ファイル（[`CachedSource`]）に保存して使い回す。
+
+use crate::cache::CachedSource;
+use crate::convert::AlphaMode;
+use crate::frame::{FrameBuffer, FrameView};
+use crate::settings;
+use crate::source::FrameSource;
+use crate::timing::gcd;
+use ini::{Properties, SectionSetter};
+use std::str::FromStr;
+
+/// 再生順
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PlaybackOrder {
+    #[default]
+    Forward,
+    Reverse,
+    /// 順再生の後に逆再生する（折り返しの両端のフレームは重複させない）
+    PingPong,
+}
+
+impl From<PlaybackOrder> for &'static str {
+    fn from(order: PlaybackOrder) -> Self {
+        match order {
+            PlaybackOrder::Forward => "順再生",
+            PlaybackOrder::Reverse => "逆再生",
+            PlaybackOrder::PingPong => "往復",
+        }
+    }
+}
+
+impl FromStr for PlaybackOrder {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(PlaybackOrder::Forward),
+            Ok(1) => Ok(PlaybackOrder::Reverse),
+            Ok(2) => Ok(PlaybackOrder::PingPong),
+            _ => Err(()),
+        }
+    }
+}
+
+impl PlaybackOrder {
+    pub fn to_index(&self) -> u32 {
+        match self {
+            PlaybackOrder::Forward => 0,
+            PlaybackOrder::Reverse => 1,
+            PlaybackOrder::PingPong => 2,
+        }
+    }
+
+    /// 入力のフレーム数から出力のフレーム数を求める
+    pub fn frame_count(self, frames: i32) -> i32 {
+        match self {
+            PlaybackOrder::PingPong if frames > 2 => frames * 2 - 2,
+            _ => frames,
+        }
+    }
+
+    /// 出力のフレームに対応する入力のフレーム
+    pub fn source_frame(self, frame: i32, frames: i32) -> i32 {
+        match self {
+            PlaybackOrder::Forward => frame,
+            PlaybackOrder::Reverse => frames - 1 - frame,
+            PlaybackOrder::PingPong if frame < frames => frame,
+            PlaybackOrder::PingPong => frames * 2 - 2 - frame,
+        }
+    }
+}
+
+/// 再生の指定
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Playback {
+    /// 再生速度（%、100 = 等速）
+    pub speed: u32,
+    pub order: PlaybackOrder,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            speed: 100,
+            order: PlaybackOrder::Forward,
+        }
+    }
+}
+
+impl Playback {
+    /// iniのセクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn load_ini(section: Option<&Properties>) -> Self {
+        let default = Self::default();
+        Self {
+            speed: settings::get(section, "playback_speed")
+                .unwrap_or(default.speed)
+                .max(1),
+            order: settings::get(section, "playback_order").unwrap_or(default.order),
+        }
+    }
+
+    /// iniのセクションに書き出す
+    pub fn save_ini(&self, section: &mut SectionSetter) {
+        section
+            .set("playback_speed", self.speed.to_string())
+            .set("playback_order", self.order.to_index().to_string());
+    }
+
+    /// 出力ダイアログに表示する設定の概要（等速の順再生の場合は空、それ以外は先頭に空白を付ける）
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+        if self.speed != 100 {
+            summary.push_str(&format!(" 速度{}%", self.speed));
+        }
+        if self.order != PlaybackOrder::Forward {
+            summary.push(' ');
+            summary.push_str(self.order.into());
+        }
+        summary
+    }
+}
+
+/// 別のソースのフレームを、再生速度と再生順を変えて供給するソース
+pub struct PlaybackSource<'a> {
+    source: &'a dyn FrameSource,
+    /// 順再生以外で入力を読み直すためのキャッシュ（入力が保存済みの場合は作らない）
+    cached: Option<CachedSource<'a>>,
+    order: PlaybackOrder,
+    rate: i32,
+    scale: i32,
+}
+
+impl<'a> PlaybackSource<'a> {
+    /// 順再生以外の場合は、入力の全フレームを読み込んで一時ファイルに保存する
+    pub fn new(source: &'a dyn FrameSource, playback: Playback) -> Result<Self, String> {
+        // rate / scale × speed / 100
+        let rate = source.rate().max(1) as u64 * playback.speed.max(1) as u64;
+        let scale = source.scale().max(1) as u64 * 100;
+        let divisor = gcd(rate, scale);
+        let (rate, scale) = (rate / divisor, scale / divisor);
+
+        let cached = match playback.order != PlaybackOrder::Forward && !source.is_cached() {
+            true => Some(CachedSource::new(source)?),
+            false => None,
+        };
+        Ok(Self {
+            source,
+            cached,
+            order: playback.order,
+            rate: rate.min(i32::MAX as u64) as i32,
+            scale: scale.min(i32::MAX as u64) as i32,
+        })
+    }
+
+    /// 出力のフレームを読み込むソースと、そのソースでのフレーム番号
+    fn locate(&self, frame: i32) -> (&dyn FrameSource, i32) {
+        let frames = self.source.frame_count();
+        let cached: &dyn FrameSource = match &self.cached {
+            Some(cached) => cached,
+            None => self.source,
+        };
+        match self.order {
+            PlaybackOrder::Forward => (self.source, frame),
+            _ if !(0..self.order.frame_count(frames)).contains(&frame) => (cached, -1),
+            order => (cached, order.source_frame(frame, frames)),
+        }
+    }
+}
+
+impl FrameSource for PlaybackSource<'_> {
+    fn width(&self) -> i32 {
+        self.source.width()
+    }
+
+    fn height(&self) -> i32 {
+        self.source.height()
+    }
+
+    fn rate(&self) -> i32 {
+        self.rate
+    }
+
+    fn scale(&self) -> i32 {
+        self.scale
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.order.frame_count(self.source.frame_count())
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let (source, frame) = self.locate(frame);
+        source.read_rgb(frame, buffer)
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let (source, frame) = self.locate(frame);
+        source.read_rgba(frame, alpha, buffer)
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        let (source, frame) = self.locate(frame);
+        source.read_rgb16(frame, buffer)
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        let (source, frame) = self.locate(frame);
+        source.read_rgba16(frame, alpha, buffer)
+    }
+
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        let (source, frame) = self.locate(frame);
+        source.read_pa64(frame, buffer)
+    }
+
+    fn is_cached(&self) -> bool {
+        self.order != PlaybackOrder::Forward || self.source.is_cached()
+    }
+
+    fn is_abort(&self) -> bool {
+        self.source.is_abort()
+    }
+
+    fn rest_time_disp(&self, now: i32, total: i32) {
+        self.source.rest_time_disp(now, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::tests::{RecordingSource, numbered};
+    use crate::source::MemoryFrameSource;
+
+    fn play(source: &RecordingSource, order: PlaybackOrder) -> Vec<u8> {
+        let playback = PlaybackSource::new(
+            source,
+            Playback {
+                order,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        (0..playback.frame_count())
+            .map(|frame| playback.frame_rgb(frame).unwrap()[0])
+            .collect()
+    }
+
+    #[test]
+    fn reverse_reads_the_source_in_order_once() {
+        let source = numbered(4);
+        assert_eq!(play(&source, PlaybackOrder::Reverse), [3, 2, 1, 0]);
+        assert_eq!(*source.reads.borrow(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn ping_pong_does_not_repeat_the_end_frames() {
+        let source = numbered(4);
+        assert_eq!(play(&source, PlaybackOrder::PingPong), [0, 1, 2, 3, 2, 1]);
+        assert_eq!(*source.reads.borrow(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn only_reordered_playback_is_cached() {
+        let source = numbered(2);
+        let playback = |order| {
+            PlaybackSource::new(
+                &source,
+                Playback {
+                    order,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .is_cached()
+        };
+        assert!(!playback(PlaybackOrder::Forward));
+        assert!(playback(PlaybackOrder::Reverse));
+        assert!(playback(PlaybackOrder::PingPong));
+    }
+
+    #[test]
+    fn cached_input_is_read_directly() {
+        let source = numbered(3);
+        let cached = CachedSource::new(&source).unwrap();
+        let playback = PlaybackSource::new(
+            &cached,
+            Playback {
+                order: PlaybackOrder::Reverse,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // 保存済みの入力をもう一度一時ファイルに保存しない
+        assert!(playback.cached.is_none());
+        let frames: Vec<_> = (0..playback.frame_count())
+            .map(|frame| playback.frame_rgb(frame).unwrap()[0])
+            .collect();
+        assert_eq!(frames, [2, 1, 0]);
+        assert_eq!(*source.reads.borrow(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn speed_scales_the_frame_rate() {
+        let source = MemoryFrameSource::new(1, 1, 30000, 1001);
+        let playback = PlaybackSource::new(
+            &source,
+            Playback {
+                speed: 50,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // 29.97fpsの半分
+        assert_eq!((playback.rate(), playback.scale()), (15000, 1001));
+    }
+
+    #[test]
+    fn settings_round_trip_through_ini() {
+        let playback = Playback {
+            speed: 200,
+            order: PlaybackOrder::PingPong,
+        };
+        let mut ini = ini::Ini::new();
+        playback.save_ini(&mut ini.with_section(Some("Config")));
+        assert_eq!(Playback::load_ini(ini.section(Some("Config"))), playback);
+        assert_eq!(Playback::load_ini(None), Playback::default());
+        let zero = ini::Ini::load_from_str("[Config]\nplayback_speed=0\n").unwrap();
+        assert_eq!(Playback::load_ini(zero.section(Some("Config"))).speed, 1);
+
+        assert_eq!(playback.summary(), " 速度200% 往復");
+        assert_eq!(Playback::default().summary(), "");
+    }
+}
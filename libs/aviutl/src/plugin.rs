@@ -0,0 +1,139 @@
+//! 出力プラグインの定義とエクスポート
+
+use crate::output2::OutputInfo;
+use crate::source::FrameSource;
+use crate::types::LPCWSTR;
+use std::path::Path;
+use std::sync::Mutex;
+use widestring::Utf16Str;
+use windows::Win32::Foundation::HWND;
+
+/// 出力プラグインの実装
+///
+/// [`export_output_plugin!`](crate::export_output_plugin)に渡すと、
+/// `OutputPluginTable`とDLLのエクスポート関数が生成される
+pub trait OutputPlugin {
+    /// プラグインの名前（NUL終端）
+    const NAME: &'static Utf16Str;
+    /// ファイルのフィルタ（`説明\0パターン\0`の並びの最後にさらにNUL）
+    const FILE_FILTER: &'static Utf16Str;
+    /// プラグインの情報（NUL終端）
+    const INFORMATION: &'static Utf16Str;
+
+    /// 出力する
+    /// - source: 出力するフレームのソース
+    /// - path: 保存先のパス
+    /// - 戻り値: 成功したらtrue
+    fn output(source: &dyn FrameSource, path: &Path) -> bool;
+
+    /// 出力設定のダイアログを表示する
+    /// - 戻り値: 設定が決定されたらtrue
+    fn configure(hwnd: HWND) -> bool;
+
+    /// 出力設定のテキスト情報
+    fn config_text() -> Option<String> {
+        None
+    }
+}
+
+/// 文字列をNUL終端のUTF-16にして保持し、そのポインタを返す
+///
+/// AviUtl2が以前に受け取ったポインタを参照し続けても安全なように、一度返した文字列は解放しない。
+/// 同じ内容の文字列には同じポインタを返すので、保持する量は設定の組み合わせの数までに収まる。
+fn intern_wide(text: &str) -> LPCWSTR {
+    static TEXTS: Mutex<Vec<Box<[u16]>>> = Mutex::new(Vec::new());
+
+    let wide: Vec<u16> = text.encode_utf16().chain(Some(0)).collect();
+    let mut texts = TEXTS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(interned) = texts.iter().find(|interned| interned[..] == wide[..]) {
+        return interned.as_ptr();
+    }
+
+    let interned = wide.into_boxed_slice();
+    let ptr = interned.as_ptr();
+    texts.push(interned);
+    ptr
+}
+
+/// `export_output_plugin!`の展開先から使う関数
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+
+    pub use windows::Win32::Foundation::{BOOL, HINSTANCE, HWND, TRUE};
+
+    /// # Safety
+    /// `oip`はnullか、AviUtl2から渡された有効な`OutputInfo`を指していること
+    pub unsafe fn output<P: OutputPlugin>(oip: *mut OutputInfo) -> bool {
+        let info = match unsafe { oip.as_ref() } {
+            Some(info) => info,
+            None => return false,
+        };
+
+        P::output(info, &info.save_path())
+    }
+
+    pub fn config_text<P: OutputPlugin>() -> LPCWSTR {
+        intern_wide(&P::config_text().unwrap_or_default())
+    }
+}
+
+/// `OutputPlugin`を実装した型から出力プラグインのDLLのエクスポート関数を生成する
+///
+/// ```ignore
+/// struct GifOutput;
+///
+/// impl OutputPlugin for GifOutput {
+///     // ...
+/// }
+///
+/// aviutl::export_output_plugin!(GifOutput);
+/// ```
+#[macro_export]
+macro_rules! export_output_plugin {
+    ($plugin:ty) => {
+        const _: () = {
+            use $crate::output2::{OutputInfo, OutputPluginTable};
+            use $crate::plugin::{__private, OutputPlugin};
+
+            extern "C" fn output_func(oip: *mut OutputInfo) -> bool {
+                unsafe { __private::output::<$plugin>(oip) }
+            }
+
+            extern "C" fn config_func(
+                hwnd: __private::HWND,
+                _dll_hinst: __private::HINSTANCE,
+            ) -> bool {
+                <$plugin as OutputPlugin>::configure(hwnd)
+            }
+
+            extern "C" fn get_config_text_func() -> $crate::types::LPCWSTR {
+                __private::config_text::<$plugin>()
+            }
+
+            const OUTPUT_PLUGIN_TABLE: OutputPluginTable = OutputPluginTable {
+                flag: OutputPluginTable::FLAG_VIDEO,
+                name: <$plugin as OutputPlugin>::NAME.as_ptr(),
+                filefilter: <$plugin as OutputPlugin>::FILE_FILTER.as_ptr(),
+                information: <$plugin as OutputPlugin>::INFORMATION.as_ptr(),
+                func_output: Some(output_func),
+                func_config: Some(config_func),
+                func_get_config_text: Some(get_config_text_func),
+            };
+
+            #[unsafe(no_mangle)]
+            pub unsafe extern "C" fn DllMain(
+                _hinst: __private::HINSTANCE,
+                _reason: u32,
+                _reserved: *mut ::std::ffi::c_void,
+            ) -> __private::BOOL {
+                __private::TRUE
+            }
+
+            #[unsafe(no_mangle)]
+            pub unsafe extern "C" fn GetOutputPluginTable() -> *mut OutputPluginTable {
+                &OUTPUT_PLUGIN_TABLE as *const OutputPluginTable as *mut OutputPluginTable
+            }
+        };
+    };
+}
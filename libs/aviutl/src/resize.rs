@@ -0,0 +1,723 @@
+//! 出力サイズへの拡大・縮小
+//!
+//! PA64（乗算済みα）のまま重み付き平均を取るので、透明な画素の色が縁ににじまない。
+//! 横方向と縦方向に分けて、出力画素ごとに前計算した重みで畳み込む。
+
+use crate::convert::parallel::par_bands;
+use crate::convert::{self, AlphaMode};
+use crate::frame::{FrameBuffer, FrameView, PixelLayout};
+use crate::settings;
+use crate::source::FrameSource;
+use ini::{Properties, SectionSetter};
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// リサンプリングのフィルター
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ResizeFilter {
+    /// 半径3のLanczos（最も鮮明）
+    #[default]
+    Lanczos3,
+    /// Catmull-Romのバイキュービック
+    Bicubic,
+    /// 範囲内の画素の単純平均
+    Box,
+}
+
+impl From<ResizeFilter> for &'static str {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Lanczos3 => "Lanczos3",
+            ResizeFilter::Bicubic => "バイキュービック",
+            ResizeFilter::Box => "平均（Box）",
+        }
+    }
+}
+
+impl FromStr for ResizeFilter {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(ResizeFilter::Lanczos3),
+            Ok(1) => Ok(ResizeFilter::Bicubic),
+            Ok(2) => Ok(ResizeFilter::Box),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ResizeFilter {
+    pub fn to_index(&self) -> u32 {
+        match self {
+            ResizeFilter::Lanczos3 => 0,
+            ResizeFilter::Bicubic => 1,
+            ResizeFilter::Box => 2,
+        }
+    }
+
+    /// 重みが0でない範囲の半径（入力画素単位）
+    fn support(self) -> f64 {
+        match self {
+            ResizeFilter::Lanczos3 => 3.0,
+            ResizeFilter::Bicubic => 2.0,
+            ResizeFilter::Box => 0.5,
+        }
+    }
+
+    /// 中心からの距離に対する重み
+    fn weight(self, x: f64) -> f64 {
+        let sinc = |x: f64| {
+            if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            }
+        };
+
+        let x = x.abs();
+        match self {
+            ResizeFilter::Lanczos3 if x < 3.0 => sinc(x) * sinc(x / 3.0),
+            ResizeFilter::Bicubic if x < 1.0 => 1.5 * x * x * x - 2.5 * x * x + 1.0,
+            ResizeFilter::Bicubic if x < 2.0 => -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0,
+            ResizeFilter::Box if x <= 0.5 => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
+/// 縦横比を保つ場合の合わせ方
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ResizeMode {
+    /// 指定した大きさに収まるように拡大・縮小する（出力は指定より小さくなることがある）
+    #[default]
+    Fit,
+    /// 指定した大きさを覆うように拡大・縮小し、はみ出した部分を切り取る
+    Fill,
+}
+
+impl From<ResizeMode> for &'static str {
+    fn from(mode: ResizeMode) -> Self {
+        match mode {
+            ResizeMode::Fit => "枠内に収める",
+            ResizeMode::Fill => "枠を埋める（はみ出しを切り取り）",
+        }
+    }
+}
+
+impl FromStr for ResizeMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(ResizeMode::Fit),
+            Ok(1) => Ok(ResizeMode::Fill),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ResizeMode {
+    pub fn to_index(&self) -> u32 {
+        match self {
+            ResizeMode::Fit => 0,
+            ResizeMode::Fill => 1,
+        }
+    }
+}
+
+/// 出力サイズの指定
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Resize {
+    /// 幅（0 = 高さに合わせて縦横比を保つ）
+    pub width: u32,
+    /// 高さ（0 = 幅に合わせて縦横比を保つ）
+    pub height: u32,
+    /// 幅と高さを両方指定した場合に縦横比を保つか
+    pub keep_aspect: bool,
+    pub mode: ResizeMode,
+    pub filter: ResizeFilter,
+}
+
+/// 入力のどの範囲をどの大きさで出力するか
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ResizeGeometry {
+    /// 出力の幅
+    pub width: usize,
+    /// 出力の高さ
+    pub height: usize,
+    /// 使用する入力の範囲 (x, y, 幅, 高さ)
+    pub source: (f64, f64, f64, f64),
+}
+
+impl Default for Resize {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            keep_aspect: true,
+            mode: ResizeMode::default(),
+            filter: ResizeFilter::default(),
+        }
+    }
+}
+
+impl Resize {
+    /// iniのセクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn load_ini(section: Option<&Properties>) -> Self {
+        let default = Self::default();
+        Self {
+            width: settings::get(section, "resize_width").unwrap_or(default.width),
+            height: settings::get(section, "resize_height").unwrap_or(default.height),
+            keep_aspect: settings::get_bool(section, "keep_aspect").unwrap_or(default.keep_aspect),
+            mode: settings::get(section, "resize_mode").unwrap_or(default.mode),
+            filter: settings::get(section, "resize_filter").unwrap_or(default.filter),
+        }
+    }
+
+    /// iniのセクションに書き出す
+    pub fn save_ini(&self, section: &mut SectionSetter) {
+        section
+            .set("resize_width", self.width.to_string())
+            .set("resize_height", self.height.to_string())
+            .set("keep_aspect", settings::bool_value(self.keep_aspect))
+            .set("resize_mode", self.mode.to_index().to_string())
+            .set("resize_filter", self.filter.to_index().to_string());
+    }
+
+    /// 出力ダイアログに表示する設定の概要（リサイズしない場合は空、それ以外は先頭に空白を付ける）
+    pub fn summary(&self) -> String {
+        if !self.is_enabled() {
+            return String::new();
+        }
+        let length = |n: u32| match n {
+            0 => "自動".to_string(),
+            n => n.to_string(),
+        };
+        format!(" リサイズ{}x{}", length(self.width), length(self.height))
+    }
+
+    /// 幅か高さが指定されているか
+    pub fn is_enabled(&self) -> bool {
+        self.width > 0 || self.height > 0
+    }
+
+    /// 入力の大きさから、出力の大きさと使用する入力の範囲を求める
+    pub fn geometry(&self, width: usize, height: usize) -> ResizeGeometry {
+        let (src_w, src_h) = (width.max(1) as f64, height.max(1) as f64);
+        let (dst_w, dst_h) = (self.width as f64, self.height as f64);
+        let full = (0.0, 0.0, width as f64, height as f64);
+        let scaled = |len: f64| (len.round() as usize).max(1);
+
+        let (width, height, source) = match (self.width, self.height) {
+            (0, 0) => (width, height, full),
+            (_, 0) => (self.width as usize, scaled(src_h * dst_w / src_w), full),
+            (0, _) => (scaled(src_w * dst_h / src_h), self.height as usize, full),
+            _ if !self.keep_aspect => (self.width as usize, self.height as usize, full),
+            _ => match self.mode {
+                ResizeMode::Fit => {
+                    let scale = (dst_w / src_w).min(dst_h / src_h);
+                    (scaled(src_w * scale), scaled(src_h * scale), full)
+                }
+                ResizeMode::Fill => {
+                    // 拡大率の大きい方に合わせ、はみ出す方向の中央を使う
+                    let scale = (dst_w / src_w).max(dst_h / src_h);
+                    let (crop_w, crop_h) = (dst_w / scale, dst_h / scale);
+                    let source = (
+                        (src_w - crop_w) / 2.0,
+                        (src_h - crop_h) / 2.0,
+                        crop_w,
+                        crop_h,
+                    );
+                    (self.width as usize, self.height as usize, source)
+                }
+            },
+        };
+
+        ResizeGeometry {
+            width,
+            height,
+            source,
+        }
+    }
+}
+
+/// 1つの出力画素に寄与する入力画素と重み
+struct Contribution {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+/// 1次元方向の重みを前計算する
+/// - src_len: 入力の画素数
+/// - offset / span: 使用する入力の範囲
+/// - dst_len: 出力の画素数
+fn contributions(
+    src_len: usize,
+    offset: f64,
+    span: f64,
+    dst_len: usize,
+    filter: ResizeFilter,
+) -> Vec<Contribution> {
+    let scale = span / dst_len as f64;
+    // 縮小時はフィルターを広げて、範囲内の画素をすべて平均に含める
+    let filter_scale = scale.max(1.0);
+    let support = filter.support() * filter_scale;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = offset + (i as f64 + 0.5) * scale;
+            let start = ((center - support).floor().max(0.0) as usize).min(src_len - 1);
+            let end = ((center + support).ceil() as usize).clamp(start + 1, src_len);
+
+            let mut weights: Vec<f64> = (start..end)
+                .map(|j| filter.weight((j as f64 + 0.5 - center) / filter_scale))
+                .collect();
+            let sum: f64 = weights.iter().sum();
+            if sum.abs() < f64::EPSILON {
+                // 重みがすべて0になる場合は最も近い画素を使う
+                let nearest = (center.floor() as usize).clamp(start, end - 1);
+                weights.iter_mut().for_each(|w| *w = 0.0);
+                weights[nearest - start] = 1.0;
+            } else {
+                weights.iter_mut().for_each(|w| *w /= sum);
+            }
+
+            Contribution {
+                start,
+                weights: weights.into_iter().map(|w| w as f32).collect(),
+            }
+        })
+        .collect()
+}
+
+/// PA64のフレームを拡大・縮小する
+///
+/// 重みが負になるフィルターで色がαを超えた場合は、乗算済みαとして正しい範囲に収める
+pub struct Resampler {
+    src_width: usize,
+    src_height: usize,
+    width: usize,
+    height: usize,
+    horizontal: Vec<Contribution>,
+    vertical: Vec<Contribution>,
+}
+
+impl Resampler {
+    pub fn new(
+        src_width: usize,
+        src_height: usize,
+        geometry: &ResizeGeometry,
+        filter: ResizeFilter,
+    ) -> Self {
+        let (x, y, w, h) = geometry.source;
+        Self {
+            src_width,
+            src_height,
+            width: geometry.width,
+            height: geometry.height,
+            horizontal: contributions(src_width, x, w, geometry.width, filter),
+            vertical: contributions(src_height, y, h, geometry.height, filter),
+        }
+    }
+
+    /// 出力の幅
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// 出力の高さ
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// `src`を拡大・縮小して`dst`に書き込む
+    /// - temp: 横方向に処理した途中結果の作業領域
+    pub fn resample(&self, src: &[u16], temp: &mut Vec<f32>, dst: &mut [u16]) {
+        let src_row_len = self.src_width * 4;
+        let row_len = self.width * 4;
+
+        // 横方向: 入力の各行を出力の幅にする
+        temp.resize(row_len * self.src_height, 0.0);
+        par_bands(temp, row_len, |first, band| {
+            for (y, row) in band.chunks_exact_mut(row_len).enumerate() {
+                let src_row = &src[(first + y) * src_row_len..(first + y + 1) * src_row_len];
+                for (pixel, contribution) in row.chunks_exact_mut(4).zip(&self.horizontal) {
+                    let mut sum = [0.0f32; 4];
+                    for (i, &w) in contribution.weights.iter().enumerate() {
+                        let s = &src_row[(contribution.start + i) * 4..][..4];
+                        for c in 0..4 {
+                            sum[c] += s[c] as f32 * w;
+                        }
+                    }
+                    pixel.copy_from_slice(&sum);
+                }
+            }
+        });
+
+        // 縦方向: 出力の各行を途中結果の行から求める
+        let temp = &*temp;
+        let dst = &mut dst[..row_len * self.height];
+        par_bands(dst, row_len, |first, band| {
+            // 行ごとの合計はバンド内で使い回す
+            let mut sum = vec![0.0f32; row_len];
+            for (y, row) in band.chunks_exact_mut(row_len).enumerate() {
+                let contribution = &self.vertical[first + y];
+                sum.fill(0.0);
+                for (i, &w) in contribution.weights.iter().enumerate() {
+                    let temp_row = &temp[(contribution.start + i) * row_len..][..row_len];
+                    for (s, &t) in sum.iter_mut().zip(temp_row) {
+                        *s += t * w;
+                    }
+                }
+
+                for (pixel, sum) in row.chunks_exact_mut(4).zip(sum.chunks_exact(4)) {
+                    let a = sum[3].round().clamp(0.0, 65535.0);
+                    pixel[3] = a as u16;
+                    for c in 0..3 {
+                        pixel[c] = sum[c].round().clamp(0.0, a) as u16;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// 作業領域
+#[derive(Default)]
+struct Scratch {
+    source: FrameBuffer<u16>,
+    temp: Vec<f32>,
+    resized: FrameBuffer<u16>,
+}
+
+/// 別のソースのフレームを拡大・縮小して供給するソース
+///
+/// 大きさが変わらない場合は元のソースをそのまま読み込む
+pub struct ResizedSource<'a> {
+    source: &'a dyn FrameSource,
+    resampler: Option<Resampler>,
+    scratch: RefCell<Scratch>,
+}
+
+impl<'a> ResizedSource<'a> {
+    pub fn new(source: &'a dyn FrameSource, resize: Resize) -> Self {
+        let (width, height) = (source.width() as usize, source.height() as usize);
+        let geometry = resize.geometry(width, height);
+        let unchanged = ResizeGeometry {
+            width,
+            height,
+            source: (0.0, 0.0, width as f64, height as f64),
+        };
+        let resampler = (geometry != unchanged && width > 0 && height > 0)
+            .then(|| Resampler::new(width, height, &geometry, resize.filter));
+
+        Self {
+            source,
+            resampler,
+            scratch: RefCell::new(Scratch::default()),
+        }
+    }
+
+    /// 拡大・縮小したPA64のフレームを作業領域に書き込み、変換して出力する
+    /// - write: (拡大・縮小したPA64, 出力先)を受け取る処理
+    fn read_resized<'b, T: Copy + Default>(
+        &self,
+        resampler: &Resampler,
+        frame: i32,
+        layout: PixelLayout,
+        buffer: &'b mut FrameBuffer<T>,
+        write: impl FnOnce(&[u16], &mut [T]),
+    ) -> Option<FrameView<'b, T>> {
+        let mut scratch = self.scratch.borrow_mut();
+        let Scratch {
+            source,
+            temp,
+            resized,
+        } = &mut *scratch;
+
+        let data = self.source.read_pa64(frame, source)?;
+        let dst = resized.prepare(resampler.width, resampler.height, PixelLayout::Rgba);
+        resampler.resample(data, temp, dst);
+
+        write(
+            resized.as_slice(),
+            buffer.prepare(resampler.width, resampler.height, layout),
+        );
+        Some(buffer.view())
+    }
+}
+
+impl FrameSource for ResizedSource<'_> {
+    fn width(&self) -> i32 {
+        match &self.resampler {
+            Some(resampler) => resampler.width as i32,
+            None => self.source.width(),
+        }
+    }
+
+    fn height(&self) -> i32 {
+        match &self.resampler {
+            Some(resampler) => resampler.height as i32,
+            None => self.source.height(),
+        }
+    }
+
+    fn rate(&self) -> i32 {
+        self.source.rate()
+    }
+
+    fn scale(&self) -> i32 {
+        self.source.scale()
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.source.frame_count()
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        match &self.resampler {
+            Some(resampler) => {
+                self.read_resized(resampler, frame, PixelLayout::Rgb, buffer, |data, dst| {
+                    convert::parallel::pa64_to_rgb_into(data, dst)
+                })
+            }
+            None => self.source.read_rgb(frame, buffer),
+        }
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        match &self.resampler {
+            Some(resampler) => {
+                self.read_resized(resampler, frame, PixelLayout::Rgba, buffer, |data, dst| {
+                    convert::parallel::pa64_to_rgba_into(data, alpha, dst)
+                })
+            }
+            None => self.source.read_rgba(frame, alpha, buffer),
+        }
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        match &self.resampler {
+            Some(resampler) => {
+                self.read_resized(resampler, frame, PixelLayout::Rgb, buffer, |data, dst| {
+                    convert::parallel::pa64_to_rgb16_into(data, dst)
+                })
+            }
+            None => self.source.read_rgb16(frame, buffer),
+        }
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        match &self.resampler {
+            Some(resampler) => {
+                self.read_resized(resampler, frame, PixelLayout::Rgba, buffer, |data, dst| {
+                    convert::parallel::pa64_to_rgba16_into(data, alpha, dst)
+                })
+            }
+            None => self.source.read_rgba16(frame, alpha, buffer),
+        }
+    }
+
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        match &self.resampler {
+            Some(resampler) => self
+                .read_resized(resampler, frame, PixelLayout::Rgba, buffer, |data, dst| {
+                    dst.copy_from_slice(data)
+                })
+                .map(|view| view.data()),
+            None => self.source.read_pa64(frame, buffer),
+        }
+    }
+
+    fn is_cached(&self) -> bool {
+        self.source.is_cached()
+    }
+
+    fn is_abort(&self) -> bool {
+        self.source.is_abort()
+    }
+
+    fn rest_time_disp(&self, now: i32, total: i32) {
+        self.source.rest_time_disp(now, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemoryFrameSource;
+
+    fn resize(width: u32, height: u32, keep_aspect: bool, mode: ResizeMode) -> Resize {
+        Resize {
+            width,
+            height,
+            keep_aspect,
+            mode,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn geometry_follows_the_given_side() {
+        let full = (0.0, 0.0, 1920.0, 1080.0);
+        let geometry = resize(480, 0, false, ResizeMode::Fit).geometry(1920, 1080);
+        assert_eq!(
+            (geometry.width, geometry.height, geometry.source),
+            (480, 270, full)
+        );
+        let geometry = resize(0, 360, false, ResizeMode::Fit).geometry(1920, 1080);
+        assert_eq!((geometry.width, geometry.height), (640, 360));
+        let geometry = resize(300, 300, false, ResizeMode::Fit).geometry(1920, 1080);
+        assert_eq!((geometry.width, geometry.height), (300, 300));
+    }
+
+    #[test]
+    fn geometry_keeps_aspect() {
+        let geometry = resize(300, 300, true, ResizeMode::Fit).geometry(1920, 1080);
+        assert_eq!((geometry.width, geometry.height), (300, 169));
+        assert_eq!(geometry.source, (0.0, 0.0, 1920.0, 1080.0));
+
+        // 高さに合わせて拡大率を決め、左右をはみ出した分だけ切り取る
+        let geometry = resize(300, 300, true, ResizeMode::Fill).geometry(1920, 1080);
+        assert_eq!((geometry.width, geometry.height), (300, 300));
+        assert_eq!(geometry.source, (420.0, 0.0, 1080.0, 1080.0));
+    }
+
+    /// ストレートαのRGBA32の1フレームのソース
+    fn single_frame(width: i32, height: i32, rgba: &[u8]) -> MemoryFrameSource {
+        let mut source = MemoryFrameSource::new(width, height, 30, 1);
+        source.push_rgba(rgba).unwrap();
+        source
+    }
+
+    #[test]
+    fn solid_color_stays_solid() {
+        let rgba = [200u8, 100, 50, 255].repeat(8 * 6);
+        let source = single_frame(8, 6, &rgba);
+        for filter in [
+            ResizeFilter::Lanczos3,
+            ResizeFilter::Bicubic,
+            ResizeFilter::Box,
+        ] {
+            for (width, height) in [(3, 2), (13, 11)] {
+                let resized = ResizedSource::new(
+                    &source,
+                    Resize {
+                        width,
+                        height,
+                        keep_aspect: false,
+                        filter,
+                        ..Default::default()
+                    },
+                );
+                let frame = resized.frame_rgba(0, AlphaMode::Straight).unwrap();
+                assert_eq!(frame.len(), (width * height * 4) as usize);
+                assert!(frame.chunks_exact(4).all(|p| p == [200, 100, 50, 255]));
+            }
+        }
+    }
+
+    #[test]
+    fn box_filter_averages_pixels() {
+        // 黒と白が交互に並んだ行を半分にすると灰色になる
+        let rgba: Vec<u8> = (0..4)
+            .flat_map(|x| if x % 2 == 0 { [0, 0, 0, 255] } else { [255; 4] })
+            .collect();
+        let source = single_frame(4, 1, &rgba);
+        let resized = ResizedSource::new(
+            &source,
+            Resize {
+                width: 2,
+                height: 1,
+                keep_aspect: false,
+                filter: ResizeFilter::Box,
+                ..Default::default()
+            },
+        );
+        let frame = resized.frame_rgb(0).unwrap();
+        assert_eq!(frame, [128, 128, 128, 128, 128, 128]);
+    }
+
+    #[test]
+    fn transparent_pixels_do_not_bleed_color() {
+        // 完全透明な画素の色（緑）は、縮小後の半透明な画素に混ざらない
+        let rgba: Vec<u8> = (0..4)
+            .flat_map(|x| {
+                if x < 2 {
+                    [255, 0, 0, 255]
+                } else {
+                    [0, 255, 0, 0]
+                }
+            })
+            .collect();
+        let source = single_frame(4, 1, &rgba);
+        for filter in [
+            ResizeFilter::Lanczos3,
+            ResizeFilter::Bicubic,
+            ResizeFilter::Box,
+        ] {
+            let resized = ResizedSource::new(
+                &source,
+                Resize {
+                    width: 3,
+                    height: 1,
+                    keep_aspect: false,
+                    filter,
+                    ..Default::default()
+                },
+            );
+            let frame = resized.frame_rgba(0, AlphaMode::Straight).unwrap();
+            for pixel in frame.chunks_exact(4).filter(|p| p[3] > 0) {
+                assert_eq!(&pixel[..3], [255, 0, 0], "{:?}", filter);
+            }
+        }
+    }
+
+    #[test]
+    fn unchanged_size_reads_source_directly() {
+        let rgba = [1u8, 2, 3, 4].repeat(4);
+        let source = single_frame(2, 2, &rgba);
+        let resized = ResizedSource::new(&source, resize(2, 0, false, ResizeMode::Fit));
+        assert!(resized.resampler.is_none());
+        let mut buffer = FrameBuffer::new();
+        assert_eq!(resized.read_pa64(0, &mut buffer), source.frame_pa64(0));
+    }
+
+    #[test]
+    fn settings_round_trip_through_ini() {
+        let resize = Resize {
+            width: 480,
+            height: 0,
+            keep_aspect: false,
+            mode: ResizeMode::Fill,
+            filter: ResizeFilter::Box,
+        };
+        let mut ini = ini::Ini::new();
+        resize.save_ini(&mut ini.with_section(Some("Config")));
+        assert_eq!(Resize::load_ini(ini.section(Some("Config"))), resize);
+        assert_eq!(Resize::load_ini(None), Resize::default());
+
+        assert_eq!(resize.summary(), " リサイズ480x自動");
+        assert_eq!(Resize::default().summary(), "");
+    }
+}
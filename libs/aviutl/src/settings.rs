@@ -0,0 +1,50 @@
+//! 出力プラグインで共通の設定のiniへの読み書き
+//!
+//! リサイズや切り取りなどの共通の設定は、それぞれの型の`load_ini`・`save_ini`で
+//! 各プラグインの設定ファイルの`[Config]`セクションに同じキーで読み書きする。
+
+use ini::Properties;
+use std::str::FromStr;
+
+/// 項目の値（無い項目や解析できない値はNone）
+pub(crate) fn get<T: FromStr>(section: Option<&Properties>, key: &str) -> Option<T> {
+    section
+        .and_then(|s| s.get(key))
+        .and_then(|s| s.parse::<T>().ok())
+}
+
+/// 真偽値の項目（`0`/`1`のほか、`false`/`true`も読める）
+pub(crate) fn get_bool(section: Option<&Properties>, key: &str) -> Option<bool> {
+    match section.and_then(|s| s.get(key))? {
+        "true" => Some(true),
+        "false" => Some(false),
+        value => value.parse::<u32>().ok().map(|v| v != 0),
+    }
+}
+
+/// 真偽値を`0`/`1`で書き出す
+pub(crate) fn bool_value(value: bool) -> String {
+    (value as u32).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ini::Ini;
+
+    #[test]
+    fn bools_are_read_in_both_forms() {
+        let ini = Ini::load_from_str("[Config]\na=1\nb=0\nc=true\nd=false\ne=yes\n").unwrap();
+        let section = ini.section(Some("Config"));
+        let values: Vec<_> = ["a", "b", "c", "d", "e", "f"]
+            .iter()
+            .map(|key| get_bool(section, key))
+            .collect();
+        assert_eq!(
+            values,
+            [Some(true), Some(false), Some(true), Some(false), None, None]
+        );
+        assert_eq!(get::<u32>(section, "a"), Some(1));
+        assert_eq!(get::<u32>(section, "c"), None);
+    }
+}
@@ -0,0 +1,556 @@
+//! ファイルサイズの上限に収まる設定の探索
+//!
+//! まず一部のフレームだけを出力した大きさから全体の大きさを予想して最初に試す設定を選び、
+//! 全フレームの出力を限られた回数だけ繰り返す二分探索で、上限に収まる最も大きい設定を探す。
+//! 入力は一時ファイルに保存するので、出力を繰り返しても入力を読み込むのは一度だけ。
+//! 上限に収まった出力は一時ファイルとして残し、探索が終わってから一度だけ出力先に移す。
+
+use crate::abort::{self, OnAbort};
+use crate::cache::CachedSource;
+use crate::convert::AlphaMode;
+use crate::frame::{FrameBuffer, FrameView};
+use crate::settings;
+use crate::source::FrameSource;
+use ini::{Properties, SectionSetter};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// 予想に使うフレーム数
+pub const SAMPLE_FRAMES: i32 = 8;
+/// 予想する設定の数
+pub const PREDICTION_STEPS: u32 = 5;
+/// 全フレームを出力する回数の上限
+pub const MAX_ENCODES: u32 = 6;
+
+/// ファイルサイズの上限の指定
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MaxFileSize {
+    /// 上限（KB、0 = 上限なし）
+    pub kilobytes: u32,
+}
+
+impl MaxFileSize {
+    /// iniのセクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn load_ini(section: Option<&Properties>) -> Self {
+        let default = Self::default();
+        Self {
+            kilobytes: settings::get(section, "max_file_size").unwrap_or(default.kilobytes),
+        }
+    }
+
+    /// iniのセクションに書き出す
+    pub fn save_ini(&self, section: &mut SectionSetter) {
+        section.set("max_file_size", self.kilobytes.to_string());
+    }
+
+    /// 出力ダイアログに表示する設定の概要（上限なしの場合は空、それ以外は先頭に空白を付ける）
+    pub fn summary(&self) -> String {
+        match self.kilobytes {
+            0 => String::new(),
+            kilobytes => format!(" 上限{}KB", kilobytes),
+        }
+    }
+
+    /// 上限と、収まるように調整する設定の範囲（上限なしの場合はNone）
+    /// - name, min, max: 探索する設定の名前と範囲
+    pub fn limit(&self, name: &'static str, min: u32, max: u32) -> Option<SizeLimit> {
+        (self.kilobytes > 0).then_some(SizeLimit {
+            max_bytes: self.kilobytes as u64 * 1024,
+            name,
+            min,
+            max,
+        })
+    }
+}
+
+/// ファイルサイズの上限と、探索する設定
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SizeLimit {
+    /// 上限のバイト数
+    pub max_bytes: u64,
+    /// 探索する設定の名前（結果の表示に使う）
+    pub name: &'static str,
+    /// 設定の最小値（ファイルサイズが最も小さくなる値）
+    pub min: u32,
+    /// 設定の最大値
+    pub max: u32,
+}
+
+/// 探索の結果
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SizeLimitReport {
+    /// 選んだ設定の名前
+    pub name: &'static str,
+    /// 選んだ設定の値
+    pub value: u32,
+    /// 出力したファイルのバイト数
+    pub size: u64,
+    /// 全フレームを出力した回数
+    pub encodes: u32,
+}
+
+impl fmt::Display for SizeLimitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}で出力しました（{:.1}KB、{}回エンコード）",
+            self.name,
+            self.value,
+            self.size as f64 / 1024.0,
+            self.encodes
+        )
+    }
+}
+
+/// 予想に使うフレームの番号（全体から等間隔に選ぶ）
+pub fn sample_frames(frame_count: i32) -> Vec<i32> {
+    if frame_count <= SAMPLE_FRAMES {
+        return (0..frame_count).collect();
+    }
+    (0..SAMPLE_FRAMES)
+        .map(|i| (i as i64 * frame_count as i64 / SAMPLE_FRAMES as i64) as i32)
+        .collect()
+}
+
+/// 予想するときの設定の値（最小値から最大値まで等間隔）
+fn prediction_values(limit: &SizeLimit) -> Vec<u32> {
+    let steps = PREDICTION_STEPS.min(limit.max - limit.min + 1).max(1);
+    if steps == 1 {
+        return vec![limit.min];
+    }
+    (0..steps)
+        .map(|i| {
+            limit.min + ((limit.max - limit.min) as u64 * i as u64 / (steps - 1) as u64) as u32
+        })
+        .collect()
+}
+
+/// 予想したファイルサイズから最初に試す設定を選ぶ
+///
+/// 上限を挟む2つの設定の間は、ファイルサイズが設定に比例するとして補間する
+/// - predictions: 設定の小さい順に並んだ(設定, 予想したバイト数)
+fn first_guess(limit: &SizeLimit, predictions: &[(u32, u64)]) -> u32 {
+    let fits = predictions
+        .iter()
+        .rposition(|&(_, size)| size <= limit.max_bytes);
+    match fits {
+        None => limit.min,
+        Some(i) if i + 1 == predictions.len() => predictions[i].0,
+        Some(i) => {
+            let (v0, s0) = predictions[i];
+            let (v1, s1) = predictions[i + 1];
+            let ratio = (limit.max_bytes - s0) as f64 / s1.saturating_sub(s0).max(1) as f64;
+            v0 + ((v1 - v0) as f64 * ratio) as u32
+        }
+    }
+}
+
+/// 全フレームの出力を繰り返し、上限に収まる最も大きい設定を探す
+///
+/// - encode: 設定を受け取って全フレームを出力し、そのバイト数を返す処理（中断された場合はNone）
+/// - accept: 直前の出力が収まり、これまでで最も大きい設定だった場合に採用する処理
+/// - 戻り値: 最小値でも収まらない場合や、中断されて採用する出力が無い場合はエラー
+fn search(
+    limit: &SizeLimit,
+    guess: u32,
+    on_abort: OnAbort,
+    mut encode: impl FnMut(u32) -> Result<Option<u64>, String>,
+    mut accept: impl FnMut() -> Result<(), String>,
+) -> Result<SizeLimitReport, String> {
+    let (mut low, mut high) = (limit.min, limit.max);
+    let mut value = guess.clamp(low, high);
+    let mut best = None;
+    let mut encodes = 0;
+    let mut smallest = None;
+
+    loop {
+        let Some(size) = encode(value)? else {
+            // 中断時も保存する場合は、それまでに収まった中で最も大きい設定の出力を残す
+            return match best {
+                Some((value, size)) if on_abort.finish => Ok(SizeLimitReport {
+                    name: limit.name,
+                    value,
+                    size,
+                    encodes,
+                }),
+                _ => Err(abort::ABORTED.into()),
+            };
+        };
+        encodes += 1;
+        if size <= limit.max_bytes {
+            accept()?;
+            best = Some((value, size));
+            low = value + 1;
+        } else {
+            if value == limit.min {
+                smallest = Some(size);
+                break;
+            }
+            high = value - 1;
+        }
+
+        if low > high {
+            break;
+        }
+        // 回数が尽きる前に、まだ収まる設定が見つかっていなければ最小値を試す
+        value = if best.is_none() && encodes + 1 >= MAX_ENCODES {
+            limit.min
+        } else if encodes >= MAX_ENCODES {
+            break;
+        } else {
+            low + (high - low) / 2
+        };
+    }
+
+    match best {
+        Some((value, size)) => Ok(SizeLimitReport {
+            name: limit.name,
+            value,
+            size,
+            encodes,
+        }),
+        None => Err(format!(
+            "{}を最小の{}にしても{:.1}KBになり、上限の{:.1}KBを超えます",
+            limit.name,
+            limit.min,
+            smallest.unwrap_or_default() as f64 / 1024.0,
+            limit.max_bytes as f64 / 1024.0
+        )),
+    }
+}
+
+/// 一時的に使うファイルのパス（ドロップ時に削除する）
+struct TempPath(PathBuf);
+
+impl TempPath {
+    /// 出力先と同じフォルダーに作る（出力先に移すときに名前を変えるだけで済むように）
+    ///
+    /// 同じ出力先に同時に出力しても消し合わないように、名前にプロセスIDを含める
+    fn beside(output_path: &Path, suffix: &str) -> Self {
+        let mut name = output_path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.{}.tmp", std::process::id(), suffix));
+        Self(output_path.with_file_name(name))
+    }
+
+    fn len(&self) -> Result<u64, String> {
+        std::fs::metadata(&self.0)
+            .map(|metadata| metadata.len())
+            .map_err(|e| format!("出力ファイルの確認エラー: {}", e))
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// ファイルサイズの上限に収まる設定を探して出力する
+///
+/// 中断された場合は探索をやめる（途中までの出力の大きさを比べても意味がないため）。
+/// 中断時も保存する指定では、それまでに全フレームを出力して上限に収まった中で最も大きい設定の出力を残す
+/// - encode: (ソース, 出力先, 設定の値)を受け取り、ファイルを出力する処理
+/// - 戻り値: 選んだ設定と出力したファイルサイズ
+pub fn encode_within_size(
+    source: &dyn FrameSource,
+    output_path: &Path,
+    limit: &SizeLimit,
+    on_abort: OnAbort,
+    mut encode: impl FnMut(&dyn FrameSource, &Path, u32) -> Result<(), String>,
+) -> Result<SizeLimitReport, String> {
+    // 逆再生などで既に保存されている場合は、そのまま読み直す
+    let cached;
+    let source = if source.is_cached() {
+        source
+    } else {
+        cached = CachedSource::new(source)?;
+        &cached as &dyn FrameSource
+    };
+    let sampled = SampledSource::new(source, sample_frames(source.frame_count()));
+
+    // 一部のフレームの大きさから全体の大きさを予想する
+    let sample_path = TempPath::beside(output_path, "sample");
+    let mut predictions = Vec::new();
+    for value in prediction_values(limit) {
+        let encoded = encode(&sampled, &sample_path.0, value);
+        if source.is_abort() {
+            return Err(abort::ABORTED.into());
+        }
+        encoded?;
+        let size = sample_path.len()? * source.frame_count().max(1) as u64
+            / sampled.frame_count().max(1) as u64;
+        predictions.push((value, size));
+    }
+    drop(sample_path);
+
+    // 上限に収まった中で最も大きい設定の出力は、次の出力で上書きしないように名前を変えて残す
+    let attempt_path = TempPath::beside(output_path, "attempt");
+    let best_path = TempPath::beside(output_path, "best");
+    let report = search(
+        limit,
+        first_guess(limit, &predictions),
+        on_abort,
+        |value| {
+            // 中断された出力は途中までなので、エラーになっていても大きさを比べずに探索をやめる
+            let encoded = encode(source, &attempt_path.0, value);
+            if source.is_abort() {
+                return Ok(None);
+            }
+            encoded?;
+            attempt_path.len().map(Some)
+        },
+        || {
+            std::fs::rename(&attempt_path.0, &best_path.0)
+                .map_err(|e| format!("出力ファイルの置き換えエラー: {}", e))
+        },
+    )?;
+
+    std::fs::rename(&best_path.0, output_path)
+        .map_err(|e| format!("出力ファイルの置き換えエラー: {}", e))?;
+    Ok(report)
+}
+
+/// 別のソースから選んだフレームだけを供給するソース
+pub struct SampledSource<'a> {
+    source: &'a dyn FrameSource,
+    frames: Vec<i32>,
+}
+
+impl<'a> SampledSource<'a> {
+    pub fn new(source: &'a dyn FrameSource, frames: Vec<i32>) -> Self {
+        Self { source, frames }
+    }
+
+    /// 元のソースでのフレーム番号
+    fn source_frame(&self, frame: i32) -> i32 {
+        usize::try_from(frame)
+            .ok()
+            .and_then(|frame| self.frames.get(frame).copied())
+            .unwrap_or(-1)
+    }
+}
+
+impl FrameSource for SampledSource<'_> {
+    fn width(&self) -> i32 {
+        self.source.width()
+    }
+
+    fn height(&self) -> i32 {
+        self.source.height()
+    }
+
+    fn rate(&self) -> i32 {
+        self.source.rate()
+    }
+
+    fn scale(&self) -> i32 {
+        self.source.scale()
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.frames.len() as i32
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.source.read_rgb(self.source_frame(frame), buffer)
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.source
+            .read_rgba(self.source_frame(frame), alpha, buffer)
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.source.read_rgb16(self.source_frame(frame), buffer)
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.source
+            .read_rgba16(self.source_frame(frame), alpha, buffer)
+    }
+
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        self.source.read_pa64(self.source_frame(frame), buffer)
+    }
+
+    fn is_cached(&self) -> bool {
+        self.source.is_cached()
+    }
+
+    fn is_abort(&self) -> bool {
+        self.source.is_abort()
+    }
+
+    fn rest_time_disp(&self, now: i32, total: i32) {
+        self.source.rest_time_disp(now, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: SizeLimit = SizeLimit {
+        max_bytes: 1000,
+        name: "品質",
+        min: 0,
+        max: 100,
+    };
+
+    /// 設定の値に比例した大きさで出力したとして探索する
+    /// - 戻り値: (結果, 採用された値)
+    fn run(
+        limit: &SizeLimit,
+        guess: u32,
+        size: impl Fn(u32) -> u64,
+    ) -> (Result<SizeLimitReport, String>, Vec<u32>) {
+        let last = std::cell::Cell::new(0);
+        let mut accepted = Vec::new();
+        let result = search(
+            limit,
+            guess,
+            OnAbort::default(),
+            |value| {
+                last.set(value);
+                Ok(Some(size(value)))
+            },
+            || {
+                accepted.push(last.get());
+                Ok(())
+            },
+        );
+        (result, accepted)
+    }
+
+    #[test]
+    fn samples_are_spread_over_the_animation() {
+        assert_eq!(sample_frames(3), [0, 1, 2]);
+        assert_eq!(sample_frames(80), [0, 10, 20, 30, 40, 50, 60, 70]);
+    }
+
+    #[test]
+    fn first_guess_interpolates_between_predictions() {
+        let predictions = [(0, 200), (25, 600), (50, 1400), (75, 2000), (100, 3000)];
+        assert_eq!(first_guess(&LIMIT, &predictions), 37);
+        assert_eq!(first_guess(&LIMIT, &[(0, 2000), (100, 3000)]), 0);
+        assert_eq!(first_guess(&LIMIT, &[(0, 200), (100, 300)]), 100);
+    }
+
+    #[test]
+    fn search_finds_the_largest_value_that_fits() {
+        // 収まるのは62まで
+        let (result, accepted) = run(&LIMIT, 60, |value| value as u64 * 16);
+        let report = result.unwrap();
+        assert_eq!((report.value, report.size), (62, 992));
+        assert!(report.encodes <= MAX_ENCODES);
+        assert_eq!(accepted.last(), Some(&62));
+        assert!(accepted.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn output_is_replaced_once_with_the_best_attempt() {
+        let source = crate::source::MemoryFrameSource::new(1, 1, 30, 1);
+        let output_path =
+            std::env::temp_dir().join(format!("aviutl-size-limit-{}.bin", std::process::id()));
+        std::fs::write(&output_path, b"previous").unwrap();
+
+        let report = encode_within_size(
+            &source,
+            &output_path,
+            &LIMIT,
+            OnAbort::default(),
+            |_, path, value| {
+                // 探索が終わるまで出力先は置き換えない
+                assert_eq!(std::fs::read(&output_path).unwrap(), b"previous");
+                std::fs::write(path, vec![value as u8; value as usize * 16])
+                    .map_err(|e| e.to_string())
+            },
+        )
+        .unwrap();
+        let output = std::fs::read(&output_path).unwrap();
+        std::fs::remove_file(&output_path).unwrap();
+
+        assert_eq!(report.value, 62);
+        assert_eq!(output, [62; 992]);
+    }
+
+    #[test]
+    fn search_falls_back_to_the_minimum() {
+        // 予想が外れても回数が尽きる前に最小値を試す
+        let (result, _) = run(&LIMIT, 100, |value| if value == 0 { 900 } else { 5000 });
+        assert_eq!(result.unwrap().value, 0);
+
+        let (result, accepted) = run(&LIMIT, 50, |_| 5000);
+        assert!(result.is_err());
+        assert!(accepted.is_empty());
+    }
+
+    #[test]
+    fn settings_round_trip_through_ini() {
+        let max_file_size = MaxFileSize { kilobytes: 512 };
+        let mut ini = ini::Ini::new();
+        max_file_size.save_ini(&mut ini.with_section(Some("Config")));
+        assert_eq!(
+            MaxFileSize::load_ini(ini.section(Some("Config"))),
+            max_file_size
+        );
+        assert_eq!(MaxFileSize::load_ini(None), MaxFileSize::default());
+
+        assert_eq!(max_file_size.summary(), " 上限512KB");
+        assert_eq!(MaxFileSize::default().summary(), "");
+
+        let limit = max_file_size.limit("品質", 0, 100).unwrap();
+        assert_eq!(limit.max_bytes, 512 * 1024);
+        assert_eq!((limit.name, limit.min, limit.max), ("品質", 0, 100));
+        assert!(MaxFileSize::default().limit("品質", 0, 100).is_none());
+    }
+
+    #[test]
+    fn abort_keeps_the_best_attempt_only_when_finishing() {
+        // 2回目の出力中に中断する
+        let abort_second = |finish| {
+            let mut encodes = 0;
+            search(
+                &LIMIT,
+                50,
+                OnAbort { finish },
+                |value| {
+                    encodes += 1;
+                    Ok((encodes < 2).then_some(value as u64 * 16))
+                },
+                || Ok(()),
+            )
+        };
+        let report = abort_second(true).unwrap();
+        assert_eq!((report.value, report.size, report.encodes), (50, 800, 1));
+        assert_eq!(abort_second(false).unwrap_err(), abort::ABORTED);
+
+        // 収まる出力が無いうちに中断された場合は保存しない
+        let result = search(
+            &LIMIT,
+            100,
+            OnAbort { finish: true },
+            |_| Ok(None),
+            || Ok(()),
+        );
+        assert_eq!(result.unwrap_err(), abort::ABORTED);
+    }
+}
@@ -0,0 +1,521 @@
+use crate::convert::{self, AlphaMode};
+use crate::frame::{FrameBuffer, FrameView, PixelLayout};
+use crate::output2::OutputInfo;
+
+pub mod stream;
+
+/// エンコーダーにフレームを供給するソース
+///
+/// `OutputInfo`を直接扱わずにエンコード処理を書けるようにするための抽象化
+pub trait FrameSource {
+    /// 幅
+    fn width(&self) -> i32;
+    /// 高さ
+    fn height(&self) -> i32;
+    /// フレームレート
+    fn rate(&self) -> i32;
+    /// スケール
+    fn scale(&self) -> i32;
+    /// フレーム数
+    fn frame_count(&self) -> i32;
+
+    /// RGB24のフレームデータをバッファに書き込む
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>>;
+
+    /// RGBA32のフレームデータをバッファに書き込む
+    /// - alpha: 乗算済みαの戻し方
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>>;
+
+    /// 16bit RGBのフレームデータをバッファに書き込む
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>>;
+
+    /// 16bit RGBAのフレームデータをバッファに書き込む
+    /// - alpha: 乗算済みαの戻し方
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>>;
+
+    /// PA64（乗算済みα）のフレームデータをバッファに書き込む
+    ///
+    /// 既定の実装は16bit RGBAとして読み込んでから乗算済みαに戻す
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        self.read_rgba16(frame, AlphaMode::Straight, buffer)?;
+        for pixel in buffer.as_mut_slice().chunks_exact_mut(4) {
+            let a = pixel[3] as u32;
+            for c in &mut pixel[..3] {
+                *c = ((*c as u32 * a + 32767) / 65535) as u16;
+            }
+        }
+        Some(buffer.as_slice())
+    }
+
+    /// RGB24のフレームデータを取得
+    fn frame_rgb(&self, frame: i32) -> Option<Vec<u8>> {
+        let mut buffer = FrameBuffer::new();
+        self.read_rgb(frame, &mut buffer)?;
+        Some(buffer.into_vec())
+    }
+
+    /// RGBA32のフレームデータを取得
+    /// - alpha: 乗算済みαの戻し方
+    fn frame_rgba(&self, frame: i32, alpha: AlphaMode) -> Option<Vec<u8>> {
+        let mut buffer = FrameBuffer::new();
+        self.read_rgba(frame, alpha, &mut buffer)?;
+        Some(buffer.into_vec())
+    }
+
+    /// 16bit RGBのフレームデータを取得
+    fn frame_rgb16(&self, frame: i32) -> Option<Vec<u16>> {
+        let mut buffer = FrameBuffer::new();
+        self.read_rgb16(frame, &mut buffer)?;
+        Some(buffer.into_vec())
+    }
+
+    /// 16bit RGBAのフレームデータを取得
+    /// - alpha: 乗算済みαの戻し方
+    fn frame_rgba16(&self, frame: i32, alpha: AlphaMode) -> Option<Vec<u16>> {
+        let mut buffer = FrameBuffer::new();
+        self.read_rgba16(frame, alpha, &mut buffer)?;
+        Some(buffer.into_vec())
+    }
+
+    /// 読み込んだフレームを保持していて、何度でも読み直せるか
+    ///
+    /// 同じフレームを繰り返し読む処理で、さらに一時ファイルに保存するかの判断に使う
+    fn is_cached(&self) -> bool {
+        false
+    }
+
+    /// 中断チェック
+    fn is_abort(&self) -> bool {
+        false
+    }
+
+    /// 進捗表示
+    fn rest_time_disp(&self, _now: i32, _total: i32) {}
+}
+
+impl FrameSource for OutputInfo {
+    fn width(&self) -> i32 {
+        self.w
+    }
+
+    fn height(&self) -> i32 {
+        self.h
+    }
+
+    fn rate(&self) -> i32 {
+        self.rate
+    }
+
+    fn scale(&self) -> i32 {
+        self.scale
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.n
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.read_video_rgb(frame, buffer)
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.read_video_rgba(frame, alpha, buffer)
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.read_video_rgb16(frame, buffer)
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.read_video_rgba16(frame, alpha, buffer)
+    }
+
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        self.read_video_pa64(frame, buffer)
+    }
+
+    fn is_abort(&self) -> bool {
+        OutputInfo::is_abort(self)
+    }
+
+    fn rest_time_disp(&self, now: i32, total: i32) {
+        OutputInfo::rest_time_disp(self, now, total)
+    }
+}
+
+/// メモリ上のフレームを供給するソース
+///
+/// フレームはAviUtl2と同じPA64（乗算済みα）で保持し、同じ変換処理を通して取り出す
+#[derive(Clone)]
+pub struct MemoryFrameSource {
+    width: i32,
+    height: i32,
+    rate: i32,
+    scale: i32,
+    frames: Vec<Vec<u16>>,
+}
+
+impl MemoryFrameSource {
+    pub fn new(width: i32, height: i32, rate: i32, scale: i32) -> Self {
+        Self {
+            width,
+            height,
+            rate,
+            scale,
+            frames: Vec::new(),
+        }
+    }
+
+    fn frame_len(&self) -> usize {
+        (self.width * self.height * 4) as usize
+    }
+
+    /// PA64のフレームを追加
+    pub fn push_pa64(&mut self, data: Vec<u16>) -> Result<(), String> {
+        if data.len() != self.frame_len() {
+            return Err(format!(
+                "フレームサイズが一致しません: {} != {}",
+                data.len(),
+                self.frame_len()
+            ));
+        }
+        self.frames.push(data);
+        Ok(())
+    }
+
+    /// ストレートαのRGBA32のフレームを追加
+    pub fn push_rgba(&mut self, data: &[u8]) -> Result<(), String> {
+        self.push_pa64(convert::rgba_to_pa64(data))
+    }
+
+    /// RGB24のフレームを不透明として追加
+    pub fn push_rgb(&mut self, data: &[u8]) -> Result<(), String> {
+        let rgba: Vec<u8> = data
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect();
+        self.push_rgba(&rgba)
+    }
+
+    /// 書き込み先のバッファをこのソースの大きさに合わせる
+    fn prepare<'b, T: Copy + Default>(
+        &self,
+        buffer: &'b mut FrameBuffer<T>,
+        layout: PixelLayout,
+    ) -> &'b mut [T] {
+        buffer.prepare(self.width as usize, self.height as usize, layout)
+    }
+
+    /// PA64のフレームデータを取得
+    pub fn frame_pa64(&self, frame: i32) -> Option<&[u16]> {
+        usize::try_from(frame)
+            .ok()
+            .and_then(|i| self.frames.get(i))
+            .map(Vec::as_slice)
+    }
+}
+
+impl FrameSource for MemoryFrameSource {
+    fn width(&self) -> i32 {
+        self.width
+    }
+
+    fn height(&self) -> i32 {
+        self.height
+    }
+
+    fn rate(&self) -> i32 {
+        self.rate
+    }
+
+    fn scale(&self) -> i32 {
+        self.scale
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.frames.len() as i32
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let data = self.frame_pa64(frame)?;
+        convert::parallel::pa64_to_rgb_into(data, self.prepare(buffer, PixelLayout::Rgb));
+        Some(buffer.view())
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let data = self.frame_pa64(frame)?;
+        convert::parallel::pa64_to_rgba_into(data, alpha, self.prepare(buffer, PixelLayout::Rgba));
+        Some(buffer.view())
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        let data = self.frame_pa64(frame)?;
+        convert::parallel::pa64_to_rgb16_into(data, self.prepare(buffer, PixelLayout::Rgb));
+        Some(buffer.view())
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        let data = self.frame_pa64(frame)?;
+        convert::parallel::pa64_to_rgba16_into(
+            data,
+            alpha,
+            self.prepare(buffer, PixelLayout::Rgba),
+        );
+        Some(buffer.view())
+    }
+
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        let data = self.frame_pa64(frame)?;
+        let image_buffer = self.prepare(buffer, PixelLayout::Rgba);
+        image_buffer.copy_from_slice(data);
+        Some(image_buffer)
+    }
+
+    fn is_cached(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::YuvMatrix;
+    use crate::output2::video_format;
+    use crate::types::DWORD;
+    use std::cell::{Cell, RefCell};
+    use std::os::raw::{c_int, c_void};
+
+    thread_local! {
+        /// 偽の`func_get_video`が返すBI_RGBのフレーム
+        static DIB: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+        /// 偽の`func_get_video`が返すPA64のフレーム
+        static PA64: RefCell<Vec<u16>> = const { RefCell::new(Vec::new()) };
+        /// 偽の`func_get_video`が返すYUY2のフレーム
+        static YUY2: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+        /// 偽の`func_get_video`が返すHF64のフレーム
+        static HF64: RefCell<Vec<u16>> = const { RefCell::new(Vec::new()) };
+        /// `func_get_video`が呼ばれた回数
+        static CALLS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// 取得に失敗する（NULLを返す）フレーム
+    const FAILING_FRAME: c_int = 1;
+
+    extern "C" fn get_video(frame: c_int, format: DWORD) -> *mut c_void {
+        CALLS.set(CALLS.get() + 1);
+        if frame == FAILING_FRAME {
+            return std::ptr::null_mut();
+        }
+        match format {
+            video_format::BI_RGB => DIB.with_borrow_mut(|data| data.as_mut_ptr().cast()),
+            video_format::PA64 => PA64.with_borrow_mut(|data| data.as_mut_ptr().cast()),
+            video_format::YUY2 => YUY2.with_borrow_mut(|data| data.as_mut_ptr().cast()),
+            video_format::HF64 => HF64.with_borrow_mut(|data| data.as_mut_ptr().cast()),
+            _ => std::ptr::null_mut(),
+        }
+    }
+
+    /// 3x2の3フレームを返す出力情報
+    ///
+    /// BI_RGBは行末に3バイトの余白がある下から上のBGR、PA64とHF64は左上が半透明の赤、
+    /// YUY2は上の行が白で下の行が黒
+    fn output_info() -> OutputInfo {
+        DIB.set(vec![
+            // 下の行
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 0, 0, //
+            // 上の行
+            10, 11, 12, 13, 14, 15, 16, 17, 18, 0, 0, 0,
+        ]);
+        let mut pa64 = vec![u16::MAX; 3 * 2 * 4];
+        pa64[..4].copy_from_slice(&[32768, 0, 0, 32768]);
+        PA64.set(pa64);
+        // 奇数幅なので最後のペアの2画素目は使われない
+        YUY2.set([[235, 128, 235, 128].repeat(2), [16, 128, 16, 128].repeat(2)].concat());
+        // 0.5と1.0
+        let mut hf64 = vec![0x3c00; 3 * 2 * 4];
+        hf64[..4].copy_from_slice(&[0x3800, 0, 0, 0x3800]);
+        HF64.set(hf64);
+        CALLS.set(0);
+
+        OutputInfo {
+            flag: OutputInfo::FLAG_VIDEO,
+            w: 3,
+            h: 2,
+            rate: 30,
+            scale: 1,
+            n: 3,
+            audio_rate: 0,
+            audio_ch: 0,
+            audio_n: 0,
+            savefile: std::ptr::null(),
+            func_get_video: Some(get_video),
+            func_get_audio: None,
+            func_is_abort: None,
+            func_rest_time_disp: None,
+            func_set_buffer_size: None,
+        }
+    }
+
+    #[test]
+    fn output_info_converts_frames() {
+        let info = output_info();
+
+        // DIBは上下を反転し、行末の余白を除いてRGBに並べ替える
+        assert_eq!(
+            info.frame_rgb(0).unwrap(),
+            [
+                12, 11, 10, 15, 14, 13, 18, 17, 16, 3, 2, 1, 6, 5, 4, 9, 8, 7
+            ]
+        );
+        let rgba = info.frame_rgba(2, AlphaMode::Straight).unwrap();
+        assert_eq!(rgba[..8], [255, 0, 0, 128, 255, 255, 255, 255]);
+
+        let mut buffer = FrameBuffer::new();
+        assert_eq!(
+            info.read_pa64(0, &mut buffer).unwrap()[..4],
+            [32768, 0, 0, 32768]
+        );
+    }
+
+    #[test]
+    fn output_info_rejects_frames_out_of_range() {
+        let info = output_info();
+        let mut buffer = FrameBuffer::new();
+        let mut buffer16 = FrameBuffer::new();
+
+        // 範囲外のフレームはAviUtl2に要求しない
+        for frame in [-1, 3, i32::MAX] {
+            assert!(info.read_rgb(frame, &mut buffer).is_none());
+            assert!(info.read_pa64(frame, &mut buffer16).is_none());
+        }
+        assert_eq!(CALLS.get(), 0);
+
+        // 取得に失敗したフレームはNULLを読まない
+        assert!(info.read_rgb(FAILING_FRAME, &mut buffer).is_none());
+        assert!(
+            info.frame_rgba16(FAILING_FRAME, AlphaMode::Straight)
+                .is_none()
+        );
+        assert_eq!(CALLS.get(), 2);
+    }
+
+    #[test]
+    fn output_info_resizes_reused_buffers() {
+        let info = output_info();
+        let mut memory = MemoryFrameSource::new(5, 4, 30, 1);
+        memory.push_rgb(&[7; 5 * 4 * 3]).unwrap();
+
+        // 別の大きさのフレームを読み込んだバッファを使い回す
+        let mut buffer = FrameBuffer::new();
+        memory
+            .read_rgba(0, AlphaMode::Straight, &mut buffer)
+            .unwrap();
+        let view = info.read_rgb(0, &mut buffer).unwrap();
+        assert_eq!((view.width(), view.height()), (3, 2));
+        assert_eq!(view.data().len(), 3 * 2 * 3);
+
+        let mut buffer16 = FrameBuffer::new();
+        memory.read_rgb16(0, &mut buffer16).unwrap();
+        let view = info
+            .read_rgba16(0, AlphaMode::Straight, &mut buffer16)
+            .unwrap();
+        assert_eq!(view.data().len(), 3 * 2 * 4);
+    }
+
+    #[test]
+    fn memory_source_checks_frame_size_and_range() {
+        let mut source = MemoryFrameSource::new(2, 2, 30, 1);
+        assert!(source.push_pa64(vec![0; 2 * 2 * 4 - 1]).is_err());
+        assert!(source.push_rgb(&[0; 2 * 2 * 3 + 3]).is_err());
+        source.push_rgba(&[255; 2 * 2 * 4]).unwrap();
+
+        assert_eq!(source.frame_count(), 1);
+        assert!(source.frame_rgb(-1).is_none());
+        assert!(source.frame_rgb(1).is_none());
+        assert_eq!(source.frame_rgb(0).unwrap(), [255; 2 * 2 * 3]);
+    }
+
+    #[test]
+    fn output_info_reads_hf64() {
+        let info = output_info();
+
+        let rgba = info
+            .get_video_rgba_from_hf64(0, AlphaMode::Straight)
+            .unwrap();
+        assert_eq!(rgba[..8], [255, 0, 0, 128, 255, 255, 255, 255]);
+        let mut buffer = FrameBuffer::new();
+        assert_eq!(
+            info.read_video_pa64_from_hf64(0, &mut buffer).unwrap()[..8],
+            [32768, 0, 0, 32768, 65535, 65535, 65535, 65535]
+        );
+        assert!(info.read_video_pa64_from_hf64(3, &mut buffer).is_none());
+    }
+
+    #[test]
+    fn output_info_reads_yuy2() {
+        let info = output_info();
+
+        assert_eq!(
+            info.get_video_rgb_from_yuy2(0, YuvMatrix::Bt709).unwrap(),
+            [[255; 9], [0; 9]].concat()
+        );
+        assert!(
+            info.get_video_rgb_from_yuy2(FAILING_FRAME, YuvMatrix::Bt709)
+                .is_none()
+        );
+    }
+}
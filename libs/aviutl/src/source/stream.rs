@@ -0,0 +1,601 @@
+//! YUV4MPEG2（Y4M）と生のRGB/RGBAの動画ストリームの読み込み
+//!
+//! フレームはAviUtl2から受け取るのと同じ形式（YUY2、BI_RGBのDIB）に詰め直して一時ファイルに保存し、
+//! 必要になったときに読み直して`OutputInfo`と同じ変換処理を通して取り出す。
+
+use super::FrameSource;
+use crate::cache::FrameCache;
+use crate::convert::{self, AlphaMode, YuvMatrix, YuvRange};
+use crate::frame::{FrameBuffer, FrameView, PixelLayout};
+use std::cell::{RefCell, RefMut};
+use std::io::{BufRead, Read};
+
+/// 保持しているフレームの形式
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum StreamFormat {
+    /// YUY2（リミテッドレンジのパックド4:2:2）
+    Yuy2(YuvMatrix),
+    /// 下から上に格納されたBGR24のDIB
+    DibRgb24,
+    /// ストレートαのRGBA32
+    Rgba32,
+}
+
+/// Y4Mの色差の間引き方
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Chroma {
+    C420,
+    C422,
+    C444,
+    Mono,
+}
+
+impl Chroma {
+    /// 色差の間引き率
+    /// - 戻り値: (横, 縦)、色差が無い場合はNone
+    fn subsampling(self) -> Option<(usize, usize)> {
+        match self {
+            Chroma::C420 => Some((2, 2)),
+            Chroma::C422 => Some((2, 1)),
+            Chroma::C444 => Some((1, 1)),
+            Chroma::Mono => None,
+        }
+    }
+}
+
+/// 詰め直したフレームの保存先
+struct Frames {
+    cache: FrameCache,
+    /// 読み直したフレーム
+    data: Vec<u8>,
+}
+
+/// 動画ストリームから読み込んだフレームを供給するソース
+///
+/// ストリーム全体をメモリに保持しないよう、フレームは一時ファイルに保存する
+pub struct StreamFrameSource {
+    width: i32,
+    height: i32,
+    rate: i32,
+    scale: i32,
+    format: StreamFormat,
+    frame_count: i32,
+    frames: RefCell<Frames>,
+}
+
+impl StreamFrameSource {
+    /// 先頭の識別子からY4Mか生のRGB/RGBAかを判断して読み込む
+    /// - matrix: Y4MのYUVからRGBへの変換行列
+    pub fn read(mut reader: impl BufRead, matrix: YuvMatrix) -> Result<Self, String> {
+        let header = read_line(&mut reader)?.ok_or("データがありません")?;
+        match header.split(' ').next() {
+            Some("YUV4MPEG2") => Self::read_y4m_frames(reader, &header, matrix),
+            Some("RGB24" | "RGBA32") => Self::read_raw_frames(reader, &header),
+            _ => Err("Y4Mまたは生のRGB/RGBAのデータではありません".to_string()),
+        }
+    }
+
+    /// YUV4MPEG2のストリームを読み込む
+    ///
+    /// 8bitの4:2:0/4:2:2/4:4:4/モノクロに対応し、YUY2に詰め直す。
+    /// `XCOLORRANGE=FULL`の場合はリミテッドレンジに変換する。
+    /// - matrix: YUVからRGBへの変換行列
+    pub fn read_y4m(mut reader: impl BufRead, matrix: YuvMatrix) -> Result<Self, String> {
+        let header = read_line(&mut reader)?.ok_or("データがありません")?;
+        Self::read_y4m_frames(reader, &header, matrix)
+    }
+
+    /// 生のRGB/RGBAのストリームを読み込む
+    ///
+    /// 先頭に`RGB24 W640 H480 F30000:1001`のようなヘッダー行（RGBAは`RGBA32`）があり、
+    /// その後に上から下に詰めたフレームが続く形式
+    pub fn read_raw(mut reader: impl BufRead) -> Result<Self, String> {
+        let header = read_line(&mut reader)?.ok_or("データがありません")?;
+        Self::read_raw_frames(reader, &header)
+    }
+
+    fn read_y4m_frames(
+        mut reader: impl BufRead,
+        header: &str,
+        matrix: YuvMatrix,
+    ) -> Result<Self, String> {
+        let mut params = header.split(' ');
+        if params.next() != Some("YUV4MPEG2") {
+            return Err("YUV4MPEG2のヘッダーがありません".to_string());
+        }
+
+        let mut size = (None, None);
+        let mut frame_rate = None;
+        // Cが無い場合は4:2:0
+        let mut chroma = Chroma::C420;
+        let mut full_range = false;
+        for param in params.filter(|p| !p.is_empty()) {
+            let Some((tag, value)) = param.split_at_checked(1) else {
+                continue;
+            };
+            match tag {
+                "W" => size.0 = value.parse::<usize>().ok(),
+                "H" => size.1 = value.parse::<usize>().ok(),
+                "F" => frame_rate = Some(parse_frame_rate(value)?),
+                "C" => {
+                    chroma = match value {
+                        "420jpeg" | "420paldv" | "420mpeg2" | "420" => Chroma::C420,
+                        "422" => Chroma::C422,
+                        "444" => Chroma::C444,
+                        "mono" => Chroma::Mono,
+                        _ => return Err(format!("未対応の色空間です: {}", value)),
+                    }
+                }
+                "X" if value == "COLORRANGE=FULL" => full_range = true,
+                _ => {}
+            }
+        }
+
+        let (width, height) = match size {
+            (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+            _ => return Err("Y4Mのヘッダーに幅と高さがありません".to_string()),
+        };
+        let (rate, scale) = frame_rate.ok_or("Y4Mのヘッダーにフレームレートがありません")?;
+
+        let chroma_size = chroma
+            .subsampling()
+            .map(|(sx, sy)| (width.div_ceil(sx), height.div_ceil(sy)));
+        let plane_len = width * height + chroma_size.map_or(0, |(cw, ch)| cw * ch * 2);
+
+        let mut planes = vec![0u8; plane_len];
+        let mut cache = new_cache(convert::yuy2_stride(width) * height)?;
+        while let Some(line) = read_line(&mut reader)? {
+            if !line.starts_with("FRAME") {
+                return Err(format!(
+                    "フレーム{}のFRAMEヘッダーがありません",
+                    cache.len()
+                ));
+            }
+            read_frame(&mut reader, &mut planes, cache.len())?;
+            cache
+                .push_bytes(&planar_to_yuy2(&planes, width, height, chroma, full_range))
+                .map_err(|e| e.to_string())?;
+        }
+
+        Self::new(
+            width,
+            height,
+            rate,
+            scale,
+            StreamFormat::Yuy2(matrix),
+            cache,
+        )
+    }
+
+    fn read_raw_frames(mut reader: impl BufRead, header: &str) -> Result<Self, String> {
+        let mut params = header.split(' ');
+        let layout = match params.next() {
+            Some("RGB24") => PixelLayout::Rgb,
+            Some("RGBA32") => PixelLayout::Rgba,
+            _ => return Err("RGB24またはRGBA32のヘッダーがありません".to_string()),
+        };
+
+        let mut size = (None, None);
+        let mut frame_rate = None;
+        for param in params.filter(|p| !p.is_empty()) {
+            let Some((tag, value)) = param.split_at_checked(1) else {
+                continue;
+            };
+            match tag {
+                "W" => size.0 = value.parse::<usize>().ok(),
+                "H" => size.1 = value.parse::<usize>().ok(),
+                "F" => frame_rate = Some(parse_frame_rate(value)?),
+                _ => {}
+            }
+        }
+
+        let (width, height) = match size {
+            (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+            _ => return Err("ヘッダーに幅と高さがありません".to_string()),
+        };
+        let (rate, scale) = frame_rate.ok_or("ヘッダーにフレームレートがありません")?;
+
+        let (format, frame_bytes) = match layout {
+            PixelLayout::Rgb => (
+                StreamFormat::DibRgb24,
+                convert::dib_rgb24_stride(width) * height,
+            ),
+            PixelLayout::Rgba => (StreamFormat::Rgba32, width * height * 4),
+        };
+        let mut data = vec![0u8; width * height * layout.channels()];
+        let mut cache = new_cache(frame_bytes)?;
+        while !reader.fill_buf().map_err(|e| e.to_string())?.is_empty() {
+            read_frame(&mut reader, &mut data, cache.len())?;
+            match layout {
+                PixelLayout::Rgb => cache.push_bytes(&rgb_to_dib_bgr24(&data, width, height)),
+                PixelLayout::Rgba => cache.push_bytes(&data),
+            }
+            .map_err(|e| e.to_string())?;
+        }
+
+        Self::new(width, height, rate, scale, format, cache)
+    }
+
+    fn new(
+        width: usize,
+        height: usize,
+        rate: i32,
+        scale: i32,
+        format: StreamFormat,
+        cache: FrameCache,
+    ) -> Result<Self, String> {
+        let frame_count = i32::try_from(cache.len()).map_err(|_| "フレーム数が多すぎます")?;
+        Ok(Self {
+            width: width as i32,
+            height: height as i32,
+            rate,
+            scale,
+            format,
+            frame_count,
+            frames: RefCell::new(Frames {
+                cache,
+                data: Vec::new(),
+            }),
+        })
+    }
+
+    /// ヘッダーのフレームレートを置き換える
+    pub fn with_frame_rate(mut self, rate: i32, scale: i32) -> Self {
+        self.rate = rate;
+        self.scale = scale;
+        self
+    }
+
+    /// 一時ファイルから読み直したフレーム
+    fn frame_data(&self, frame: i32) -> Option<RefMut<'_, [u8]>> {
+        if !(0..self.frame_count).contains(&frame) {
+            return None;
+        }
+        let mut frames = self.frames.borrow_mut();
+        let Frames { cache, data } = &mut *frames;
+        data.resize(self.frame_bytes(), 0);
+        cache.read_bytes(frame as usize, data).ok()?;
+        Some(RefMut::map(frames, |frames| frames.data.as_mut_slice()))
+    }
+
+    /// 保存している1フレームのバイト数
+    fn frame_bytes(&self) -> usize {
+        let (w, h) = (self.width as usize, self.height as usize);
+        match self.format {
+            StreamFormat::Yuy2(_) => convert::yuy2_stride(w) * h,
+            StreamFormat::DibRgb24 => convert::dib_rgb24_stride(w) * h,
+            StreamFormat::Rgba32 => w * h * 4,
+        }
+    }
+
+    /// フレームをRGB24に変換して書き込む
+    fn decode_rgb(&self, data: &[u8], image_buffer: &mut [u8]) {
+        let (w, h) = (self.width as usize, self.height as usize);
+        match self.format {
+            StreamFormat::Yuy2(matrix) => {
+                convert::yuy2_to_rgb_into(data, w, h, matrix, YuvRange::Limited, image_buffer);
+            }
+            StreamFormat::DibRgb24 => {
+                convert::parallel::dib_bgr24_to_rgb_into(data, w, h, image_buffer);
+            }
+            StreamFormat::Rgba32 => {
+                convert::parallel::pa64_to_rgb_into(&convert::rgba_to_pa64(data), image_buffer);
+            }
+        }
+    }
+
+    /// RGB24に変換したフレーム
+    fn decoded_rgb(&self, data: &[u8]) -> Vec<u8> {
+        let mut rgb = vec![0; (self.width * self.height * 3) as usize];
+        self.decode_rgb(data, &mut rgb);
+        rgb
+    }
+
+    /// 書き込み先のバッファをこのソースの大きさに合わせる
+    fn prepare<'b, T: Copy + Default>(
+        &self,
+        buffer: &'b mut FrameBuffer<T>,
+        layout: PixelLayout,
+    ) -> &'b mut [T] {
+        buffer.prepare(self.width as usize, self.height as usize, layout)
+    }
+}
+
+impl FrameSource for StreamFrameSource {
+    fn width(&self) -> i32 {
+        self.width
+    }
+
+    fn height(&self) -> i32 {
+        self.height
+    }
+
+    fn rate(&self) -> i32 {
+        self.rate
+    }
+
+    fn scale(&self) -> i32 {
+        self.scale
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.frame_count
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let data = self.frame_data(frame)?;
+        self.decode_rgb(&data, self.prepare(buffer, PixelLayout::Rgb));
+        Some(buffer.view())
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        let data = self.frame_data(frame)?;
+        let image_buffer = self.prepare(buffer, PixelLayout::Rgba);
+        if self.format == StreamFormat::Rgba32 {
+            convert::parallel::pa64_to_rgba_into(
+                &convert::rgba_to_pa64(&data),
+                alpha,
+                image_buffer,
+            );
+        } else {
+            // αを持たない形式は不透明として扱う
+            let rgb = self.decoded_rgb(&data);
+            for (dst, src) in image_buffer.chunks_exact_mut(4).zip(rgb.chunks_exact(3)) {
+                dst[..3].copy_from_slice(src);
+                dst[3] = 255;
+            }
+        }
+        Some(buffer.view())
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        let data = self.frame_data(frame)?;
+        let image_buffer = self.prepare(buffer, PixelLayout::Rgb);
+        if self.format == StreamFormat::Rgba32 {
+            convert::parallel::pa64_to_rgb16_into(&convert::rgba_to_pa64(&data), image_buffer);
+        } else {
+            let rgb = self.decoded_rgb(&data);
+            for (dst, &src) in image_buffer.iter_mut().zip(&rgb) {
+                *dst = src as u16 * 257;
+            }
+        }
+        Some(buffer.view())
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        let data = self.frame_data(frame)?;
+        let image_buffer = self.prepare(buffer, PixelLayout::Rgba);
+        if self.format == StreamFormat::Rgba32 {
+            convert::parallel::pa64_to_rgba16_into(
+                &convert::rgba_to_pa64(&data),
+                alpha,
+                image_buffer,
+            );
+        } else {
+            let rgb = self.decoded_rgb(&data);
+            for (dst, src) in image_buffer.chunks_exact_mut(4).zip(rgb.chunks_exact(3)) {
+                for (d, &s) in dst.iter_mut().zip(src) {
+                    *d = s as u16 * 257;
+                }
+                dst[3] = u16::MAX;
+            }
+        }
+        Some(buffer.view())
+    }
+
+    fn is_cached(&self) -> bool {
+        true
+    }
+}
+
+/// 改行までの1行を読む
+/// - 戻り値: データの終端ではNone
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>, String> {
+    let mut line = Vec::new();
+    if reader
+        .read_until(b'\n', &mut line)
+        .map_err(|e| e.to_string())?
+        == 0
+    {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| "ヘッダーが不正です".to_string())
+}
+
+/// フレームを保存する一時ファイルを作る
+fn new_cache(frame_bytes: usize) -> Result<FrameCache, String> {
+    FrameCache::new(frame_bytes).map_err(|e| format!("一時ファイルを作成できません: {}", e))
+}
+
+/// 1フレーム分のデータを読む
+fn read_frame(reader: &mut impl Read, data: &mut [u8], frame: usize) -> Result<(), String> {
+    reader.read_exact(data).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => {
+            format!("フレーム{}のデータが途中で終わっています", frame)
+        }
+        _ => e.to_string(),
+    })
+}
+
+/// `30000:1001`の形式のフレームレート
+/// - 戻り値: (rate, scale)
+fn parse_frame_rate(value: &str) -> Result<(i32, i32), String> {
+    value
+        .split_once(':')
+        .and_then(|(rate, scale)| Some((rate.parse().ok()?, scale.parse().ok()?)))
+        .filter(|&(rate, scale)| rate > 0 && scale > 0)
+        .ok_or_else(|| format!("フレームレートが無効です: {}", value))
+}
+
+/// 上から下のRGB24を下から上に格納されたBGR24のDIBに変換
+fn rgb_to_dib_bgr24(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let stride = convert::dib_rgb24_stride(width);
+    let mut dib = vec![0u8; stride * height];
+    for (y, row) in data.chunks_exact(width * 3).enumerate() {
+        let dst = &mut dib[(height - 1 - y) * stride..][..width * 3];
+        for (d, s) in dst.chunks_exact_mut(3).zip(row.chunks_exact(3)) {
+            d.copy_from_slice(&[s[2], s[1], s[0]]);
+        }
+    }
+    dib
+}
+
+/// Y4MのプレーンをYUY2に詰め直す
+///
+/// 横の色差は2画素ごとにまとめ（4:4:4は平均）、縦に間引かれた色差は同じ行を繰り返す
+fn planar_to_yuy2(
+    planes: &[u8],
+    width: usize,
+    height: usize,
+    chroma: Chroma,
+    full_range: bool,
+) -> Vec<u8> {
+    let stride = convert::yuy2_stride(width);
+    let mut yuy2 = vec![0u8; stride * height];
+    let (luma, chroma_planes) = planes.split_at(width * height);
+
+    // フルレンジの値をリミテッドレンジ（Y: 16〜235、UV: 16〜240）に変換
+    let scale_luma = |v: u8| {
+        if full_range {
+            16 + ((v as u32 * 219 + 127) / 255) as u8
+        } else {
+            v
+        }
+    };
+    let scale_chroma = |v: u8| {
+        if full_range {
+            16 + ((v as u32 * 224 + 127) / 255) as u8
+        } else {
+            v
+        }
+    };
+
+    // (横の間引き率, 縦の間引き率, 色差の幅, U, V)
+    let chroma = chroma.subsampling().map(|(sx, sy)| {
+        let chroma_width = width.div_ceil(sx);
+        let (u, v) = chroma_planes.split_at(chroma_width * height.div_ceil(sy));
+        (sx, sy, chroma_width, u, v)
+    });
+
+    for y in 0..height {
+        let luma_row = &luma[y * width..][..width];
+        let dst_row = &mut yuy2[y * stride..][..stride];
+        for (x, yuyv) in dst_row.chunks_exact_mut(4).enumerate() {
+            let x0 = x * 2;
+            let x1 = (x0 + 1).min(width - 1);
+            let (u, v) = match chroma {
+                Some((sx, sy, chroma_width, u, v)) => {
+                    let row = y / sy * chroma_width;
+                    let sample = |plane: &[u8]| {
+                        if sx == 2 {
+                            plane[row + x]
+                        } else {
+                            (plane[row + x0] as u32 + plane[row + x1] as u32).div_ceil(2) as u8
+                        }
+                    };
+                    (sample(u), sample(v))
+                }
+                None => (128, 128),
+            };
+            yuyv[0] = scale_luma(luma_row[x0]);
+            yuyv[1] = scale_chroma(u);
+            yuyv[2] = scale_luma(luma_row[x1]);
+            yuyv[3] = scale_chroma(v);
+        }
+    }
+
+    yuy2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_y4m_420() {
+        // 4x2の4:2:0が2フレーム
+        let mut data = b"YUV4MPEG2 W4 H2 F30000:1001 Ip A1:1 C420jpeg\n".to_vec();
+        for _ in 0..2 {
+            data.extend_from_slice(b"FRAME\n");
+            data.extend_from_slice(&[16, 16, 235, 235, 16, 16, 235, 235]);
+            data.extend_from_slice(&[128, 128, 128, 128]);
+        }
+
+        let source = StreamFrameSource::read(&data[..], YuvMatrix::Bt601).unwrap();
+        assert_eq!((source.width(), source.height()), (4, 2));
+        assert_eq!((source.rate(), source.scale()), (30000, 1001));
+        assert_eq!(source.frame_count(), 2);
+        assert_eq!(
+            source.frame_rgb(1).unwrap(),
+            [0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255].repeat(2)
+        );
+    }
+
+    #[test]
+    fn reads_y4m_full_range_as_limited() {
+        let mut data = b"YUV4MPEG2 W2 H1 F25:1 C444 XCOLORRANGE=FULL\n".to_vec();
+        data.extend_from_slice(b"FRAME\n");
+        data.extend_from_slice(&[0, 255, 128, 128, 128, 128]);
+
+        let source = StreamFrameSource::read(&data[..], YuvMatrix::Bt709).unwrap();
+        assert_eq!(*source.frame_data(0).unwrap(), [16, 128, 235, 128]);
+    }
+
+    #[test]
+    fn reads_raw_rgb_through_dib() {
+        let mut data = b"RGB24 W3 H2 F30:1\n".to_vec();
+        let frame: Vec<u8> = (0..18).collect();
+        data.extend_from_slice(&frame);
+
+        let source = StreamFrameSource::read(&data[..], YuvMatrix::Bt601).unwrap();
+        assert_eq!(source.frame_count(), 1);
+        assert_eq!(source.frame_rgb(0).unwrap(), frame);
+        assert_eq!(
+            source.frame_rgba(0, AlphaMode::Straight).unwrap()[..4],
+            [0, 1, 2, 255]
+        );
+    }
+
+    #[test]
+    fn reads_raw_rgba() {
+        let mut data = b"RGBA32 W1 H1 F30:1\n".to_vec();
+        data.extend_from_slice(&[200, 100, 50, 128]);
+
+        let source = StreamFrameSource::read(&data[..], YuvMatrix::Bt601).unwrap();
+        assert_eq!(
+            source.frame_rgba(0, AlphaMode::Straight).unwrap(),
+            [200, 100, 50, 128]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_frame() {
+        let mut data = b"RGB24 W2 H2 F30:1\n".to_vec();
+        data.extend_from_slice(&[0; 12 + 5]);
+
+        assert!(StreamFrameSource::read(&data[..], YuvMatrix::Bt601).is_err());
+    }
+}
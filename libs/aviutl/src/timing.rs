@@ -0,0 +1,169 @@
+//! フレームレートから各フォーマットの表示時間への変換
+
+/// 最大公約数
+pub(crate) const fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// フレームごとの表示時間を指定した単位で求める
+///
+/// 各フレームの終了時刻をタイムライン上の正確な時刻から切り捨てで求め、
+/// その差を表示時間とするため、丸め誤差が蓄積せず全体の長さがタイムラインと一致する。
+/// 例えば29.97fpsを1/100秒単位にすると3, 3, 4, 3, 3, 4...となる。
+#[derive(Clone, Debug)]
+pub struct FrameTiming {
+    /// 1フレームの長さの分子（単位数 × scale）
+    numerator: u64,
+    /// 1フレームの長さの分母（rate）
+    denominator: u64,
+    min_delay: u64,
+    frame: u64,
+    elapsed: u64,
+}
+
+impl FrameTiming {
+    /// - rate / scale: フレームレート
+    /// - units_per_second: 1秒あたりの単位数（1/100秒なら100）
+    pub fn new(rate: i32, scale: i32, units_per_second: u32) -> Self {
+        let rate = rate.max(1) as u64;
+        let scale = scale.max(1) as u64;
+        let numerator = scale * units_per_second as u64;
+        let divisor = gcd(numerator, rate);
+        Self {
+            numerator: numerator / divisor,
+            denominator: rate / divisor,
+            min_delay: 0,
+            frame: 0,
+            elapsed: 0,
+        }
+    }
+
+    /// 表示時間の最小値を設定
+    ///
+    /// 最小値まで引き延ばした分は後続のフレームで詰めて、全体の長さを保つ
+    pub fn with_min_delay(mut self, min_delay: u64) -> Self {
+        self.min_delay = min_delay;
+        self
+    }
+
+    /// 先頭から指定したフレーム数が経過した時刻
+    fn time_at(&self, frame: u64) -> u64 {
+        (frame as u128 * self.numerator as u128 / self.denominator as u128) as u64
+    }
+
+    /// 次のフレームの表示時間を取得
+    pub fn next_delay(&mut self) -> u64 {
+        self.next_delay_for(1)
+    }
+
+    /// 次の`frames`フレームをまとめて1枚として表示する時間を取得
+    pub fn next_delay_for(&mut self, frames: u64) -> u64 {
+        self.frame += frames;
+        let delay = self
+            .time_at(self.frame)
+            .saturating_sub(self.elapsed)
+            .max(self.min_delay);
+        self.elapsed += delay;
+        delay
+    }
+
+    /// 次のフレームの開始時刻（これまでの表示時間の合計）
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+}
+
+/// 1フレームの長さを約分した分数（秒）
+/// - 戻り値: (分子, 分母)
+pub fn frame_duration(rate: i32, scale: i32) -> (u64, u64) {
+    let rate = rate.max(1) as u64;
+    let scale = scale.max(1) as u64;
+    let divisor = gcd(scale, rate);
+    (scale / divisor, rate / divisor)
+}
+
+/// 1フレームの長さを16bitの分数で正確に表せる場合はその値を返す
+///
+/// APNGのfcTLのように16bitの分子・分母で表示時間を持つフォーマット向け
+pub fn frame_duration_u16(rate: i32, scale: i32) -> Option<(u16, u16)> {
+    let (numerator, denominator) = frame_duration(rate, scale);
+    Some((
+        u16::try_from(numerator).ok()?,
+        u16::try_from(denominator).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ntsc_rate_in_hundredths() {
+        let mut timing = FrameTiming::new(30000, 1001, 100);
+        let delays: Vec<u64> = (0..10).map(|_| timing.next_delay()).collect();
+        assert_eq!(delays, [3, 3, 4, 3, 3, 4, 3, 3, 4, 3]);
+    }
+
+    #[test]
+    fn total_matches_exact_duration() {
+        for (rate, scale, units) in [
+            (30000, 1001, 100),
+            (24000, 1001, 1000),
+            (60, 1, 100),
+            (7, 3, 1),
+        ] {
+            let mut timing = FrameTiming::new(rate, scale, units);
+            for frames in 1..=1000u64 {
+                timing.next_delay();
+                // 切り捨てた正確な時刻 frames × scale × units / rate
+                let exact = frames * scale as u64 * units as u64 / rate as u64;
+                assert_eq!(timing.elapsed(), exact, "{rate}/{scale} @ {units}");
+            }
+        }
+    }
+
+    #[test]
+    fn merged_frames_use_exact_span() {
+        let mut timing = FrameTiming::new(30000, 1001, 100);
+        // 3フレーム目までの10と、その後の4フレーム分
+        assert_eq!(timing.next_delay_for(3), 10);
+        assert_eq!(timing.next_delay_for(4), 13);
+        assert_eq!(timing.elapsed(), 23);
+    }
+
+    #[test]
+    fn min_delay_is_compensated_by_later_frames() {
+        // 60fpsを1/100秒単位にすると1, 2, 2, 1, 2, 2...
+        let mut timing = FrameTiming::new(60, 1, 100).with_min_delay(2);
+        // 1に足りない分を引き延ばし、まとめた次のフレームで詰める
+        assert_eq!(timing.next_delay(), 2);
+        assert_eq!(timing.next_delay_for(3), 4);
+        assert_eq!(timing.elapsed(), 6);
+        assert_eq!(timing.next_delay_for(6), 10);
+        assert_eq!(timing.elapsed(), 16);
+        // 詰められない場合だけ最小値で延びる
+        assert_eq!(timing.next_delay(), 2);
+        assert_eq!(timing.elapsed(), 18);
+    }
+
+    #[test]
+    fn frame_duration_is_reduced() {
+        assert_eq!(frame_duration(30000, 1001), (1001, 30000));
+        assert_eq!(frame_duration(60, 2), (1, 30));
+        // 0以下の値は1として扱う
+        assert_eq!(frame_duration(0, -5), (1, 1));
+    }
+
+    #[test]
+    fn frame_duration_u16_rejects_overflow() {
+        assert_eq!(frame_duration_u16(30000, 1001), Some((1001, 30000)));
+        assert_eq!(frame_duration_u16(65535, 1), Some((1, 65535)));
+        // 約分しても16bitに収まらない場合は表せない
+        assert_eq!(frame_duration_u16(120000, 1001), None);
+        assert_eq!(frame_duration_u16(1, 65536), None);
+        assert_eq!(frame_duration_u16(131070, 2), Some((1, 65535)));
+    }
+}
@@ -0,0 +1,401 @@
+//! エンコーダーのテスト用の合成アニメーションと、デコード結果の比較
+//!
+//! 各プラグインの`tests`から使い、出力をデコードした結果が入力とどれだけ一致するかを確認する。
+
+use aviutl::convert::AlphaMode;
+use aviutl::frame::{FrameBuffer, FrameView};
+use aviutl::source::{FrameSource, MemoryFrameSource};
+use std::cell::Cell;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// 合成アニメーションの幅
+pub const WIDTH: i32 = 64;
+/// 合成アニメーションの高さ
+pub const HEIGHT: i32 = 48;
+/// 合成アニメーションのフレーム数
+pub const FRAMES: i32 = 6;
+/// フレームレート（29.97fps）
+pub const RATE: i32 = 30000;
+/// スケール
+pub const SCALE: i32 = 1001;
+
+/// 正方形が全フレームで通る範囲 (x, y, 幅, 高さ)（[`moving_square`]の余白を除いた範囲）
+pub const SQUARE_BOUNDS: (i32, i32, i32, i32) = (4, 4, 56, 36);
+
+/// 座標とフレーム番号からストレートαのRGBA32の画素を決めてアニメーションを作る
+fn animation(pixel: impl Fn(i32, i32, i32) -> [u8; 4]) -> MemoryFrameSource {
+    animation_sized(WIDTH, HEIGHT, pixel)
+}
+
+/// 大きさを指定してアニメーションを作る
+fn animation_sized(
+    width: i32,
+    height: i32,
+    pixel: impl Fn(i32, i32, i32) -> [u8; 4],
+) -> MemoryFrameSource {
+    let mut source = MemoryFrameSource::new(width, height, RATE, SCALE);
+    for frame in 0..FRAMES {
+        let rgba: Vec<u8> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| pixel(frame, x, y))
+            .collect();
+        source.push_rgba(&rgba).unwrap();
+    }
+    source
+}
+
+/// フレームごとに横にずれていくグラデーション（不透明）
+pub fn gradient() -> MemoryFrameSource {
+    animation(|frame, x, y| {
+        [
+            ((x * 4 + frame * 16) % 256) as u8,
+            (y * 5) as u8,
+            (255 - x * 2 - y) as u8,
+            255,
+        ]
+    })
+}
+
+/// [`moving_square`]の画素
+/// - shrink: 縮小する倍率（正方形の位置と大きさは2の倍数なので、2までは割り切れる）
+fn square_pixel(frame: i32, x: i32, y: i32, shrink: i32) -> [u8; 4] {
+    let (left, top, size) = (
+        (4 + frame * 8) / shrink,
+        (4 + frame * 4) / shrink,
+        16 / shrink,
+    );
+    if (left..left + size).contains(&x) && (top..top + size).contains(&y) {
+        [240, 200, 40, 255]
+    } else {
+        [20, 40, 120, 255]
+    }
+}
+
+/// 単色の背景の上を斜めに移動する正方形（不透明）
+pub fn moving_square() -> MemoryFrameSource {
+    animation(|frame, x, y| square_pixel(frame, x, y, 1))
+}
+
+/// [`moving_square`]を縦横半分に縮小したアニメーション
+///
+/// 正方形の端が2画素単位でそろっているので、2x2画素の平均で縮小した結果と一致する
+pub fn moving_square_halved() -> MemoryFrameSource {
+    animation_sized(WIDTH / 2, HEIGHT / 2, |frame, x, y| {
+        square_pixel(frame, x, y, 2)
+    })
+}
+
+/// [`moving_square`]から[`SQUARE_BOUNDS`]の範囲を切り取ったアニメーション
+pub fn moving_square_trimmed() -> MemoryFrameSource {
+    let (left, top, width, height) = SQUARE_BOUNDS;
+    animation_sized(width, height, |frame, x, y| {
+        square_pixel(frame, x + left, y + top, 1)
+    })
+}
+
+/// 不透明から完全透明まで徐々に消えていく円（円の外側は完全透明）
+pub fn alpha_fade() -> MemoryFrameSource {
+    animation(|frame, x, y| {
+        let (dx, dy) = (x - WIDTH / 2, y - HEIGHT / 2);
+        if dx * dx + dy * dy < 20 * 20 {
+            let alpha = 255 - frame * 255 / (FRAMES - 1);
+            [200, (60 + x * 2) as u8, 80, alpha as u8]
+        } else {
+            [0, 0, 0, 0]
+        }
+    })
+}
+
+/// 同じ画像が3, 2, 1フレームずつ続くスライドショー（不透明）
+pub fn slideshow() -> MemoryFrameSource {
+    animation(|frame, x, y| {
+        let slide = match frame {
+            0..=2 => 0,
+            3..=4 => 1,
+            _ => 2,
+        };
+        if (x / 8 + y / 8 + slide) % 2 == 0 {
+            [230, 230, 230, 255]
+        } else {
+            [(40 + slide * 80) as u8, 80, 160, 255]
+        }
+    })
+}
+
+/// 指定したフレームだけを取り出す
+pub fn select(frames: &[Vec<u8>], indices: &[usize]) -> Vec<Vec<u8>> {
+    indices.iter().map(|&i| frames[i].clone()).collect()
+}
+
+/// プラグインがRGBA32で受け取るフレーム
+pub fn expected_rgba(source: &dyn FrameSource, alpha: AlphaMode) -> Vec<Vec<u8>> {
+    (0..source.frame_count())
+        .map(|frame| source.frame_rgba(frame, alpha).unwrap())
+        .collect()
+}
+
+/// プラグインがRGB24で受け取るフレーム（比較用に不透明のRGBA32にする）
+pub fn expected_rgb(source: &dyn FrameSource) -> Vec<Vec<u8>> {
+    (0..source.frame_count())
+        .map(|frame| {
+            source
+                .frame_rgb(frame)
+                .unwrap()
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect()
+        })
+        .collect()
+}
+
+/// 画素の誤差
+#[derive(Debug)]
+pub struct PixelError {
+    /// 最大誤差
+    pub max: u8,
+    /// 平均誤差
+    pub mean: f64,
+}
+
+/// ストレートαのRGBA32の画像同士の誤差を求める
+///
+/// 乗算済みαにしてから比較するので、透明な画素の色の違いは誤差にならない
+pub fn pixel_error(expected: &[u8], actual: &[u8]) -> PixelError {
+    assert_eq!(expected.len(), actual.len(), "画像の大きさが一致しません");
+
+    let premultiply = |p: &[u8]| {
+        let a = p[3] as u32;
+        [
+            (p[0] as u32 * a + 127) / 255,
+            (p[1] as u32 * a + 127) / 255,
+            (p[2] as u32 * a + 127) / 255,
+            a,
+        ]
+    };
+
+    let mut max = 0;
+    let mut total = 0u64;
+    for (e, a) in expected.chunks_exact(4).zip(actual.chunks_exact(4)) {
+        for (e, a) in premultiply(e).into_iter().zip(premultiply(a)) {
+            let diff = e.abs_diff(a);
+            max = max.max(diff);
+            total += diff as u64;
+        }
+    }
+
+    PixelError {
+        max: max as u8,
+        mean: total as f64 / expected.len().max(1) as f64,
+    }
+}
+
+/// 全フレームの誤差が範囲内か確認する
+/// - max / mean: 許容する最大誤差と平均誤差
+pub fn assert_frames_close(expected: &[Vec<u8>], actual: &[Vec<u8>], max: u8, mean: f64) {
+    assert_eq!(expected.len(), actual.len(), "フレーム数が一致しません");
+    for (frame, (e, a)) in expected.iter().zip(actual).enumerate() {
+        let error = pixel_error(e, a);
+        assert!(
+            error.max <= max && error.mean <= mean,
+            "フレーム{}の誤差が大きすぎます: {:?}（許容: 最大{} 平均{}）",
+            frame,
+            error,
+            max,
+            mean
+        );
+    }
+}
+
+/// フレームの重ね方
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Blend {
+    /// 領域をそのまま置き換える
+    Source,
+    /// αに応じて下の画像に重ねる
+    Over,
+}
+
+/// 部分的なフレームを重ねて表示される画像を組み立てるキャンバス（ストレートαのRGBA32）
+#[derive(Clone)]
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    /// 完全透明のキャンバスを作る
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        }
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// 領域内の画素のインデックス
+    fn area(&self, left: usize, top: usize, width: usize, height: usize) -> Vec<usize> {
+        assert!(
+            left + width <= self.width && top + height <= self.height,
+            "フレームがキャンバスからはみ出しています"
+        );
+        (top..top + height)
+            .flat_map(|y| (left..left + width).map(move |x| (y * self.width + x) * 4))
+            .collect()
+    }
+
+    /// 指定した位置にフレームを重ねる
+    pub fn draw(
+        &mut self,
+        left: usize,
+        top: usize,
+        width: usize,
+        height: usize,
+        rgba: &[u8],
+        blend: Blend,
+    ) {
+        for (i, src) in self
+            .area(left, top, width, height)
+            .into_iter()
+            .zip(rgba.chunks_exact(4))
+        {
+            let dst = &mut self.pixels[i..i + 4];
+            let sa = src[3] as u32;
+            if blend == Blend::Source || sa == 255 {
+                dst.copy_from_slice(src);
+                continue;
+            }
+
+            let da = dst[3] as u32 * (255 - sa) / 255;
+            let a = sa + da;
+            for c in 0..3 {
+                dst[c] = match a {
+                    0 => 0,
+                    a => ((src[c] as u32 * sa + dst[c] as u32 * da + a / 2) / a) as u8,
+                };
+            }
+            dst[3] = a as u8;
+        }
+    }
+
+    /// 領域を完全透明にする
+    pub fn clear(&mut self, left: usize, top: usize, width: usize, height: usize) {
+        for i in self.area(left, top, width, height) {
+            self.pixels[i..i + 4].fill(0);
+        }
+    }
+}
+
+/// テストの出力先の一時ファイル（破棄するときに削除する）
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    /// - name: テストごとに一意なファイル名
+    pub fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("{}-{}", std::process::id(), name)))
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// 指定した回数だけ進捗を表示した後は中断されたと答えるソース
+pub struct AbortingSource<'a> {
+    source: &'a dyn FrameSource,
+    after: i32,
+    shown: Cell<i32>,
+}
+
+impl<'a> AbortingSource<'a> {
+    pub fn new(source: &'a dyn FrameSource, after: i32) -> Self {
+        Self {
+            source,
+            after,
+            shown: Cell::new(0),
+        }
+    }
+}
+
+impl FrameSource for AbortingSource<'_> {
+    fn width(&self) -> i32 {
+        self.source.width()
+    }
+
+    fn height(&self) -> i32 {
+        self.source.height()
+    }
+
+    fn rate(&self) -> i32 {
+        self.source.rate()
+    }
+
+    fn scale(&self) -> i32 {
+        self.source.scale()
+    }
+
+    fn frame_count(&self) -> i32 {
+        self.source.frame_count()
+    }
+
+    fn read_rgb<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.source.read_rgb(frame, buffer)
+    }
+
+    fn read_rgba<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u8>,
+    ) -> Option<FrameView<'b, u8>> {
+        self.source.read_rgba(frame, alpha, buffer)
+    }
+
+    fn read_rgb16<'b>(
+        &self,
+        frame: i32,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.source.read_rgb16(frame, buffer)
+    }
+
+    fn read_rgba16<'b>(
+        &self,
+        frame: i32,
+        alpha: AlphaMode,
+        buffer: &'b mut FrameBuffer<u16>,
+    ) -> Option<FrameView<'b, u16>> {
+        self.source.read_rgba16(frame, alpha, buffer)
+    }
+
+    fn read_pa64<'b>(&self, frame: i32, buffer: &'b mut FrameBuffer<u16>) -> Option<&'b [u16]> {
+        self.source.read_pa64(frame, buffer)
+    }
+
+    fn is_abort(&self) -> bool {
+        self.shown.get() >= self.after
+    }
+
+    fn rest_time_disp(&self, _now: i32, _total: i32) {
+        self.shown.set(self.shown.get() + 1);
+    }
+}
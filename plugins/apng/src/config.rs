@@ -1,10 +1,23 @@
+use aviutl::abort::OnAbort;
+use aviutl::color::{self, ColorProfile};
+use aviutl::convert::{AlphaMode, format_color, parse_color};
+use aviutl::crop::Crop;
+use aviutl::decimate::Decimate;
+use aviutl::dedup::Dedup;
+use aviutl::metadata::{Metadata, MetadataFiles, MetadataSettings};
+use aviutl::playback::Playback;
+use aviutl::resize::Resize;
 use ini::Ini;
+#[cfg(feature = "plugin")]
 use std::path::PathBuf;
 use std::str::FromStr;
+#[cfg(feature = "plugin")]
 use windows::Win32::Foundation::{HMODULE, MAX_PATH};
+#[cfg(feature = "plugin")]
 use windows::Win32::System::LibraryLoader::{
     GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GetModuleFileNameW, GetModuleHandleExW,
 };
+#[cfg(feature = "plugin")]
 use windows::core::PCWSTR;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -58,6 +71,52 @@ impl ColorFormat {
     }
 }
 
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum BitDepth {
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+impl From<BitDepth> for png::BitDepth {
+    fn from(depth: BitDepth) -> Self {
+        match depth {
+            BitDepth::Eight => png::BitDepth::Eight,
+            BitDepth::Sixteen => png::BitDepth::Sixteen,
+        }
+    }
+}
+
+impl From<BitDepth> for &'static str {
+    fn from(depth: BitDepth) -> Self {
+        match depth {
+            BitDepth::Eight => "8bit",
+            BitDepth::Sixteen => "16bit",
+        }
+    }
+}
+
+impl FromStr for BitDepth {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(BitDepth::Eight),
+            Ok(1) => Ok(BitDepth::Sixteen),
+            _ => Err(()),
+        }
+    }
+}
+
+impl BitDepth {
+    fn to_index(self) -> u32 {
+        match self {
+            BitDepth::Eight => 0,
+            BitDepth::Sixteen => 1,
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum CompressionType {
     Default,
@@ -180,13 +239,71 @@ impl FilterType {
     }
 }
 
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum AlphaHandling {
+    #[default]
+    Straight,
+    Matte,
+}
+
+impl From<AlphaHandling> for &'static str {
+    fn from(handling: AlphaHandling) -> Self {
+        match handling {
+            AlphaHandling::Straight => "そのまま保持",
+            AlphaHandling::Matte => "背景色に合成",
+        }
+    }
+}
+
+impl FromStr for AlphaHandling {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(AlphaHandling::Straight),
+            Ok(1) => Ok(AlphaHandling::Matte),
+            _ => Err(()),
+        }
+    }
+}
+
+impl AlphaHandling {
+    fn to_index(self) -> u32 {
+        match self {
+            AlphaHandling::Straight => 0,
+            AlphaHandling::Matte => 1,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     pub repeat: u32,
     pub color_format: ColorFormat,
+    pub alpha_handling: AlphaHandling,
+    pub matte_color: [u8; 3],
+    pub bit_depth: BitDepth,
     pub compression_type: CompressionType,
     pub filter_type: FilterType,
     pub adaptive_filter: bool,
+    /// 重複フレームをまとめる指定
+    pub dedup: Dedup,
+    /// 出力サイズの指定
+    pub resize: Resize,
+    /// 切り取りの指定
+    pub crop: Crop,
+    /// フレームレートの指定
+    pub decimate: Decimate,
+    /// 再生速度と再生順
+    pub playback: Playback,
+    /// 中断されたときの出力の扱い
+    pub on_abort: OnAbort,
+    /// 埋め込むメタデータの項目
+    pub metadata: MetadataSettings,
+    /// 埋め込む色空間の情報
+    pub color_profile: ColorProfile,
+    /// 埋め込むICCプロファイルのパス（色空間の情報がICCプロファイルの場合）
+    pub icc_path: String,
 }
 
 impl Default for Config {
@@ -194,14 +311,30 @@ impl Default for Config {
         Self {
             repeat: 0,
             color_format: ColorFormat::default(),
+            alpha_handling: AlphaHandling::default(),
+            matte_color: [0, 0, 0],
+            bit_depth: BitDepth::default(),
             compression_type: CompressionType::default(),
             filter_type: FilterType::default(),
             adaptive_filter: true,
+            dedup: Dedup::default(),
+            resize: Resize::default(),
+            crop: Crop::default(),
+            decimate: Decimate::default(),
+            playback: Playback::default(),
+            on_abort: OnAbort::default(),
+            metadata: MetadataSettings::default(),
+            color_profile: ColorProfile::Srgb,
+            icc_path: String::new(),
         }
     }
 }
 
 impl Config {
+    /// 埋め込めるメタデータのファイル
+    pub const METADATA_FILES: MetadataFiles = MetadataFiles::Given;
+
+    #[cfg(feature = "plugin")]
     fn config_file_path() -> Result<PathBuf, String> {
         let (buffer, len) = unsafe {
             let mut hmodule: HMODULE = HMODULE::default();
@@ -230,6 +363,71 @@ impl Config {
         }
     }
 
+    /// 透過付き出力時のαチャンネルの扱い
+    pub fn alpha_mode(&self) -> AlphaMode {
+        match self.alpha_handling {
+            AlphaHandling::Straight => AlphaMode::Straight,
+            AlphaHandling::Matte => AlphaMode::Matte(self.matte_color),
+        }
+    }
+
+    /// 埋め込むICCプロファイル（sRGBはsRGB・gAMA・cHRMチャンクで指定するのでNone）
+    pub fn icc_profile(&self) -> Result<Option<Vec<u8>>, String> {
+        match self.color_profile {
+            ColorProfile::Icc => color::load_icc(&self.icc_path).map(Some),
+            ColorProfile::None | ColorProfile::Srgb => Ok(None),
+        }
+    }
+
+    /// 出力ファイルに埋め込むメタデータ（XMP・EXIFはファイルから読み込む）
+    pub fn metadata(&self) -> Result<Metadata, String> {
+        self.metadata.to_metadata(concat!(
+            "APNG出力プラグイン v",
+            env!("CARGO_PKG_VERSION"),
+            " by yu7400ki"
+        ))
+    }
+
+    /// 出力ダイアログに表示する設定の概要
+    pub fn summary(&self) -> String {
+        let filter: &str = if self.adaptive_filter {
+            "自動"
+        } else {
+            self.filter_type.into()
+        };
+        let repeat = match self.repeat {
+            0 => "ループ∞".to_string(),
+            n => format!("ループ{}回", n),
+        };
+        let color = match (self.color_format, self.alpha_handling) {
+            (ColorFormat::Rgb24, _) => "透過無し".to_string(),
+            (ColorFormat::Rgba32, AlphaHandling::Straight) => "透過付き".to_string(),
+            (ColorFormat::Rgba32, AlphaHandling::Matte) => {
+                format!("透過付き(背景色{})", format_color(self.matte_color))
+            }
+        };
+        let bit_depth: &str = self.bit_depth.into();
+        let compression: &str = self.compression_type.into();
+        let mut summary = format!(
+            "APNG {} 圧縮:{} フィルター:{} {} {}",
+            bit_depth, compression, filter, repeat, color
+        );
+        summary.push_str(&self.dedup.summary());
+        summary.push_str(&self.resize.summary());
+        summary.push_str(&self.crop.summary());
+        summary.push_str(&self.decimate.summary());
+        summary.push_str(&self.playback.summary());
+        summary.push_str(&self.on_abort.summary());
+        summary.push_str(&self.metadata.summary(Self::METADATA_FILES));
+        match self.color_profile {
+            ColorProfile::Srgb => {}
+            ColorProfile::None => summary.push_str(" 色空間なし"),
+            ColorProfile::Icc => summary.push_str(" ICC"),
+        }
+        summary
+    }
+
+    #[cfg(feature = "plugin")]
     pub fn load() -> Self {
         let default = Self::default();
 
@@ -239,62 +437,128 @@ impl Config {
         };
 
         if let Ok(ini) = Ini::load_from_file(&config_path) {
-            if let Some(section) = ini.section(Some("Config")) {
-                let repeat = section
-                    .get("repeat")
-                    .and_then(|s| s.parse().ok())
-                    .unwrap_or(default.repeat);
-
-                let color_format = section
-                    .get("color_format")
-                    .and_then(|s| s.parse().ok())
-                    .unwrap_or(default.color_format);
-
-                let compression_type = section
-                    .get("compression_type")
-                    .and_then(|s| s.parse().ok())
-                    .unwrap_or(default.compression_type);
-
-                let filter_type = section
-                    .get("filter_type")
-                    .and_then(|s| s.parse().ok())
-                    .unwrap_or(default.filter_type);
-
-                let adaptive_filter = section
-                    .get("adaptive_filter")
-                    .and_then(|s| s.parse::<u32>().ok())
-                    .map(|v| v != 0)
-                    .unwrap_or(default.adaptive_filter);
-
-                Config {
-                    repeat,
-                    color_format,
-                    compression_type,
-                    filter_type,
-                    adaptive_filter,
-                }
-            } else {
-                default
+            Self::from_ini(&ini)
+        } else {
+            default
+        }
+    }
+
+    /// iniの`[Config]`セクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn from_ini(ini: &Ini) -> Self {
+        let default = Self::default();
+
+        if let Some(section) = ini.section(Some("Config")) {
+            let repeat = section
+                .get("repeat")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default.repeat);
+
+            let color_format = section
+                .get("color_format")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default.color_format);
+
+            let alpha_handling = section
+                .get("alpha_handling")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default.alpha_handling);
+
+            let matte_color = section
+                .get("matte_color")
+                .and_then(parse_color)
+                .unwrap_or(default.matte_color);
+
+            let bit_depth = section
+                .get("bit_depth")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default.bit_depth);
+
+            let compression_type = section
+                .get("compression_type")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default.compression_type);
+
+            let filter_type = section
+                .get("filter_type")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default.filter_type);
+
+            let adaptive_filter = section
+                .get("adaptive_filter")
+                .and_then(|s| s.parse::<u32>().ok())
+                .map(|v| v != 0)
+                .unwrap_or(default.adaptive_filter);
+
+            let color_profile = section
+                .get("color_profile")
+                .and_then(|s| s.parse::<ColorProfile>().ok())
+                .unwrap_or(default.color_profile);
+
+            let icc_path = section
+                .get("icc_path")
+                .map(|s| s.to_string())
+                .unwrap_or(default.icc_path);
+
+            Config {
+                repeat,
+                color_format,
+                alpha_handling,
+                matte_color,
+                bit_depth,
+                compression_type,
+                filter_type,
+                adaptive_filter,
+                dedup: Dedup::load_ini(Some(section)),
+                resize: Resize::load_ini(Some(section)),
+                crop: Crop::load_ini(Some(section)),
+                decimate: Decimate::load_ini(Some(section)),
+                playback: Playback::load_ini(Some(section)),
+                on_abort: OnAbort::load_ini(Some(section)),
+                metadata: MetadataSettings::load_ini(Some(section)),
+                color_profile,
+                icc_path,
             }
         } else {
             default
         }
     }
 
+    #[cfg(feature = "plugin")]
     pub fn save(&self) -> Result<(), String> {
+        let config_path = Self::config_file_path()?;
+        self.to_ini()
+            .write_to_file(&config_path)
+            .map_err(|e| e.to_string())
+    }
+
+    /// iniの`[Config]`セクションに書き出す
+    pub fn to_ini(&self) -> Ini {
         let mut ini = Ini::new();
 
-        ini.with_section(Some("Config"))
+        let mut section = ini.with_section(Some("Config"));
+        section
             .set("repeat", self.repeat.to_string())
             .set("color_format", self.color_format.to_index().to_string())
+            .set("alpha_handling", self.alpha_handling.to_index().to_string())
+            .set("matte_color", format_color(self.matte_color))
+            .set("bit_depth", self.bit_depth.to_index().to_string())
             .set(
                 "compression_type",
                 self.compression_type.to_index().to_string(),
             )
             .set("filter_type", self.filter_type.to_index().to_string())
             .set("adaptive_filter", (self.adaptive_filter as u32).to_string());
-
-        let config_path = Self::config_file_path()?;
-        ini.write_to_file(&config_path).map_err(|e| e.to_string())
+        self.dedup.save_ini(&mut section);
+        self.resize.save_ini(&mut section);
+        self.crop.save_ini(&mut section);
+        self.decimate.save_ini(&mut section);
+        self.playback.save_ini(&mut section);
+        self.on_abort.save_ini(&mut section);
+        self.metadata.save_ini(&mut section);
+        section
+            .set("color_profile", self.color_profile.to_index().to_string())
+            .set("icc_path", self.icc_path.as_str());
+
+        ini
     }
 }
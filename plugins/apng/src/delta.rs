@@ -0,0 +1,366 @@
+//! 直前のフレームとの差分だけを書き込むための領域と、破棄・合成方法の選択
+
+use png::{BlendOp, DisposeOp};
+
+/// フレームのサンプルの型
+pub trait Sample: Copy + PartialEq {
+    /// 完全透明の画素の値
+    const ZERO: Self;
+    /// 完全不透明のα
+    const OPAQUE: Self;
+
+    /// PNGのバイト列（ビッグエンディアン）として追加する
+    fn extend_bytes(samples: &[Self], bytes: &mut Vec<u8>);
+}
+
+impl Sample for u8 {
+    const ZERO: Self = 0;
+    const OPAQUE: Self = u8::MAX;
+
+    fn extend_bytes(samples: &[Self], bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(samples);
+    }
+}
+
+impl Sample for u16 {
+    const ZERO: Self = 0;
+    const OPAQUE: Self = u16::MAX;
+
+    fn extend_bytes(samples: &[Self], bytes: &mut Vec<u8>) {
+        bytes.extend(samples.iter().flat_map(|v| v.to_be_bytes()));
+    }
+}
+
+/// フレームを書き込む領域
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// 書き込むフレーム
+pub struct DeltaFrame {
+    pub rect: Rect,
+    pub blend_op: BlendOp,
+    /// 領域内の画素（PNGに書き込むバイト列）
+    pub data: Vec<u8>,
+}
+
+/// 直前のフレームの破棄方法と、次のフレームの領域・合成方法の組み合わせから
+/// 圧縮後の大きさが最も小さいものを選ぶ
+pub struct DeltaEncoder<T> {
+    width: usize,
+    height: usize,
+    channels: usize,
+    has_alpha: bool,
+    /// 直前のフレームの画像
+    previous: Vec<T>,
+    /// 直前のフレームを描く前のキャンバス
+    before: Vec<T>,
+    /// 直前のフレームの領域（最初のフレームの前はNone）
+    previous_rect: Option<Rect>,
+    /// 直前のフレームが最初のフレームか
+    previous_is_first: bool,
+}
+
+impl<T: Sample> DeltaEncoder<T> {
+    /// - channels: 1画素のサンプル数（3 = RGB、4 = RGBA）
+    pub fn new(width: usize, height: usize, channels: usize) -> Self {
+        Self {
+            width,
+            height,
+            channels,
+            has_alpha: channels == 4,
+            previous: Vec::new(),
+            before: Vec::new(),
+            previous_rect: None,
+            previous_is_first: false,
+        }
+    }
+
+    /// 次のフレームを追加する
+    /// - compressed_size: 書き込むフレームを圧縮したときの大きさ（候補の比較に使う）
+    /// - 戻り値: 直前のフレームに設定する破棄方法と、このフレームとして書き込む内容
+    pub fn push(
+        &mut self,
+        frame: &[T],
+        mut compressed_size: impl FnMut(&DeltaFrame) -> usize,
+    ) -> (DisposeOp, DeltaFrame) {
+        let Some(previous_rect) = self.previous_rect else {
+            // 最初のフレームは画像全体を書き込む
+            let delta = self.full_frame(frame);
+            self.before = vec![T::ZERO; frame.len()];
+            self.previous = frame.to_vec();
+            self.previous_rect = Some(delta.rect);
+            self.previous_is_first = true;
+            return (DisposeOp::None, delta);
+        };
+
+        // 画像全体をそのまま書き込む場合を基準に、差分の候補と比べる
+        let mut candidates = vec![(DisposeOp::None, self.full_frame(frame))];
+        for dispose_op in [DisposeOp::None, DisposeOp::Background, DisposeOp::Previous] {
+            let Some(canvas) = self.disposed(dispose_op, previous_rect) else {
+                continue;
+            };
+            let rect = self.changed_rect(&canvas, frame);
+            candidates.push((dispose_op, self.crop(frame, rect)));
+            if let Some(over) = self.crop_over(&canvas, frame, rect) {
+                candidates.push((dispose_op, over));
+            }
+        }
+
+        // 大きさが同じなら先の候補を優先する
+        let (_, (dispose_op, delta)) = candidates
+            .into_iter()
+            .map(|candidate| (compressed_size(&candidate.1), candidate))
+            .reduce(|best, candidate| {
+                if candidate.0 < best.0 {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .unwrap();
+
+        self.before = self.disposed(dispose_op, previous_rect).unwrap();
+        self.previous = frame.to_vec();
+        self.previous_rect = Some(delta.rect);
+        self.previous_is_first = false;
+        (dispose_op, delta)
+    }
+
+    /// 直前のフレームを破棄した後のキャンバス（その破棄方法が使えない場合はNone）
+    fn disposed(&self, dispose_op: DisposeOp, rect: Rect) -> Option<Vec<T>> {
+        match dispose_op {
+            DisposeOp::None => Some(self.previous.clone()),
+            // 透過無しでは消した領域をすべて描き直すことになるので使わない
+            DisposeOp::Background if self.has_alpha => {
+                let mut canvas = self.previous.clone();
+                for y in rect.y..rect.y + rect.height {
+                    let start = (y * self.width + rect.x) * self.channels;
+                    canvas[start..start + rect.width * self.channels].fill(T::ZERO);
+                }
+                Some(canvas)
+            }
+            // 最初のフレームのPreviousはBackgroundとして扱われる
+            DisposeOp::Previous if !self.previous_is_first => Some(self.before.clone()),
+            _ => None,
+        }
+    }
+
+    /// 見た目が同じ画素か（完全透明の画素は色が違っても同じとみなす）
+    fn same(&self, a: &[T], b: &[T]) -> bool {
+        a == b || (self.has_alpha && a[3] == T::ZERO && b[3] == T::ZERO)
+    }
+
+    /// キャンバスとフレームで異なる画素を囲む領域
+    ///
+    /// 差が無い場合も空のフレームは書き込めないので、左上の1画素を返す
+    fn changed_rect(&self, canvas: &[T], frame: &[T]) -> Rect {
+        let (mut left, mut top, mut right, mut bottom) = (self.width, self.height, 0, 0);
+        let row_len = self.width * self.channels;
+        for (y, (canvas_row, row)) in canvas
+            .chunks_exact(row_len)
+            .zip(frame.chunks_exact(row_len))
+            .enumerate()
+        {
+            for (x, (a, b)) in canvas_row
+                .chunks_exact(self.channels)
+                .zip(row.chunks_exact(self.channels))
+                .enumerate()
+            {
+                if !self.same(a, b) {
+                    left = left.min(x);
+                    right = right.max(x + 1);
+                    top = top.min(y);
+                    bottom = bottom.max(y + 1);
+                }
+            }
+        }
+
+        if left >= right {
+            return Rect {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+            };
+        }
+        Rect {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    fn full_frame(&self, frame: &[T]) -> DeltaFrame {
+        self.crop(
+            frame,
+            Rect {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            },
+        )
+    }
+
+    /// 領域内の画素で置き換えるフレーム
+    fn crop(&self, frame: &[T], rect: Rect) -> DeltaFrame {
+        let mut data = Vec::new();
+        for y in rect.y..rect.y + rect.height {
+            let start = (y * self.width + rect.x) * self.channels;
+            T::extend_bytes(&frame[start..start + rect.width * self.channels], &mut data);
+        }
+        DeltaFrame {
+            rect,
+            blend_op: BlendOp::Source,
+            data,
+        }
+    }
+
+    /// 変化の無い画素を完全透明にしてキャンバスに重ねるフレーム
+    ///
+    /// 重ねた結果がフレームと一致しない画素（下の画素が見えてしまう半透明の画素など）がある場合はNone
+    fn crop_over(&self, canvas: &[T], frame: &[T], rect: Rect) -> Option<DeltaFrame> {
+        if !self.has_alpha {
+            return None;
+        }
+
+        let zero = [T::ZERO; 4];
+        let mut samples = Vec::with_capacity(rect.width * rect.height * 4);
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                let i = (y * self.width + x) * 4;
+                let (below, pixel) = (&canvas[i..i + 4], &frame[i..i + 4]);
+                if self.same(below, pixel) {
+                    samples.extend_from_slice(&zero);
+                } else if pixel[3] == T::OPAQUE || below[3] == T::ZERO {
+                    samples.extend_from_slice(pixel);
+                } else {
+                    return None;
+                }
+            }
+        }
+
+        let mut data = Vec::new();
+        T::extend_bytes(&samples, &mut data);
+        Some(DeltaFrame {
+            rect,
+            blend_op: BlendOp::Over,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 4x4の単色の画像
+    fn filled(pixel: [u8; 4]) -> Vec<u8> {
+        pixel.repeat(16)
+    }
+
+    fn set(image: &mut [u8], x: usize, y: usize, pixel: [u8; 4]) {
+        let i = (y * 4 + x) * 4;
+        image[i..i + 4].copy_from_slice(&pixel);
+    }
+
+    /// 圧縮の代わりにバイト数で比べる
+    fn push(encoder: &mut DeltaEncoder<u8>, frame: &[u8]) -> (DisposeOp, DeltaFrame) {
+        encoder.push(frame, |delta| delta.data.len())
+    }
+
+    #[test]
+    fn first_frame_is_full_size() {
+        let mut encoder = DeltaEncoder::new(4, 4, 4);
+        let (_, delta) = push(&mut encoder, &filled([10, 20, 30, 255]));
+        assert_eq!(
+            delta.rect,
+            Rect {
+                x: 0,
+                y: 0,
+                width: 4,
+                height: 4
+            }
+        );
+    }
+
+    #[test]
+    fn writes_only_changed_region() {
+        let mut encoder = DeltaEncoder::new(4, 4, 3);
+        let background = [10u8, 20, 30].repeat(16);
+        push(&mut encoder, &background);
+
+        let mut frame = background.clone();
+        frame[(2 * 4 + 1) * 3] = 200;
+        frame[(3 * 4 + 2) * 3] = 200;
+        let (dispose_op, delta) = push(&mut encoder, &frame);
+        assert_eq!(dispose_op, DisposeOp::None);
+        assert_eq!(
+            delta.rect,
+            Rect {
+                x: 1,
+                y: 2,
+                width: 2,
+                height: 2
+            }
+        );
+        assert_eq!(delta.data.len(), 2 * 2 * 3);
+    }
+
+    #[test]
+    fn restores_background_under_a_flicker() {
+        let mut encoder = DeltaEncoder::new(4, 4, 3);
+        let mut background = [10u8, 20, 30].repeat(16);
+        push(&mut encoder, &background);
+
+        // 1フレームだけ現れる点は、次のフレームで元に戻すと差分が無くなる
+        let mut flicker = background.clone();
+        flicker[0] = 200;
+        push(&mut encoder, &flicker);
+        background[15 * 3] = 100;
+        let (dispose_op, delta) = push(&mut encoder, &background);
+        assert_eq!(dispose_op, DisposeOp::Previous);
+        assert_eq!(
+            delta.rect,
+            Rect {
+                x: 3,
+                y: 3,
+                width: 1,
+                height: 1
+            }
+        );
+    }
+
+    #[test]
+    fn semi_transparent_pixels_are_not_blended_over_different_pixels() {
+        let mut encoder = DeltaEncoder::new(4, 4, 4);
+        push(&mut encoder, &filled([255, 0, 0, 255]));
+
+        let mut frame = filled([255, 0, 0, 255]);
+        set(&mut frame, 0, 0, [0, 0, 255, 128]);
+        let (_, delta) = push(&mut encoder, &frame);
+        assert_eq!(delta.blend_op, BlendOp::Source);
+        assert_eq!(&delta.data[..4], &[0, 0, 255, 128]);
+    }
+
+    #[test]
+    fn clears_region_that_becomes_transparent() {
+        let mut encoder = DeltaEncoder::new(4, 4, 4);
+        let mut frame = filled([0, 0, 0, 0]);
+        set(&mut frame, 1, 1, [255, 255, 255, 255]);
+        push(&mut encoder, &frame);
+
+        let mut next = filled([0, 0, 0, 0]);
+        set(&mut next, 3, 3, [255, 255, 255, 255]);
+        let (dispose_op, delta) = push(&mut encoder, &next);
+        // 最初のフレームを消すと、新しい点だけを書き込めばよい
+        assert_eq!(dispose_op, DisposeOp::Background);
+        assert_eq!(delta.data.len(), 4);
+    }
+}
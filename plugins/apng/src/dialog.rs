@@ -1,9 +1,14 @@
-use crate::config::{ColorFormat, CompressionType, Config, FilterType};
+use crate::config::{AlphaHandling, BitDepth, ColorFormat, CompressionType, Config, FilterType};
+use aviutl::color::ColorProfile;
+use aviutl::dialog::{
+    CropSection, DecimateSection, DedupSection, MatteSection, MetadataSection, OnAbortSection,
+    PlaybackSection, ResizeSection,
+};
 use std::sync::{Arc, Mutex};
 use win32_dialog::{
     Dialog, MessageBox,
     layout::{FlexLayout, JustifyContent, SizeValue},
-    widget::{Button, ButtonEvent, CheckBox, CheckBoxEvent, ComboBox, Label, Number},
+    widget::{Button, ButtonEvent, CheckBox, CheckBoxEvent, ComboBox, Label, Number, TextBox},
 };
 use windows::Win32::Foundation::*;
 
@@ -26,6 +31,24 @@ pub fn show_config_dialog(
         ColorFormat::Rgba32 => 1,
     });
 
+    let alpha_label = Label::new("半透明の扱い (透過付きのみ)");
+    let alpha_options = vec![AlphaHandling::Straight.into(), AlphaHandling::Matte.into()];
+    let alpha_combobox =
+        ComboBox::new(alpha_options).selected(match default_config.alpha_handling {
+            AlphaHandling::Straight => 0,
+            AlphaHandling::Matte => 1,
+        });
+
+    let matte_section = MatteSection::new(default_config.matte_color);
+
+    let bit_depth_label = Label::new("ビット深度");
+    let bit_depth_options = vec![BitDepth::Eight.into(), BitDepth::Sixteen.into()];
+    let bit_depth_combobox =
+        ComboBox::new(bit_depth_options).selected(match default_config.bit_depth {
+            BitDepth::Eight => 0,
+            BitDepth::Sixteen => 1,
+        });
+
     let compression_label = Label::new("圧縮");
     let compression_options = vec![
         CompressionType::Default.into(),
@@ -73,15 +96,53 @@ pub fn show_config_dialog(
         }
     });
 
+    let dedup_section = DedupSection::new(&default_config.dedup);
+
+    let resize_section = ResizeSection::new(&default_config.resize);
+
+    let crop_section = CropSection::new(&default_config.crop);
+
+    let decimate_section = DecimateSection::new(&default_config.decimate);
+
+    let playback_section = PlaybackSection::new(&default_config.playback);
+
+    let on_abort_section = OnAbortSection::new(&default_config.on_abort);
+
+    let metadata_section = MetadataSection::new(&default_config.metadata, Config::METADATA_FILES);
+
+    let color_profile_label = Label::new("色空間の情報");
+    let color_profile_options = vec![
+        ColorProfile::None.into(),
+        ColorProfile::Srgb.into(),
+        ColorProfile::Icc.into(),
+    ];
+    let color_profile_combobox = ComboBox::new(color_profile_options)
+        .selected(default_config.color_profile.to_index() as i32);
+
+    let icc_path_label = Label::new("ICCプロファイルのファイル");
+    let icc_path_input = TextBox::new().text(&default_config.icc_path);
+
     let mut dialog = Dialog::new("APNG出力設定");
 
     let ok_button = Button::primary("OK").add_event_handler({
         let result = Arc::clone(&result);
         let repeat_input = repeat_input.clone();
         let color_combobox = color_combobox.clone();
+        let alpha_combobox = alpha_combobox.clone();
+        let matte_section = matte_section.clone();
+        let bit_depth_combobox = bit_depth_combobox.clone();
         let compression_combobox = compression_combobox.clone();
         let filter_combobox = filter_combobox.clone();
         let adaptive_filter_checkbox = adaptive_filter_checkbox.clone();
+        let dedup_section = dedup_section.clone();
+        let resize_section = resize_section.clone();
+        let crop_section = crop_section.clone();
+        let decimate_section = decimate_section.clone();
+        let playback_section = playback_section.clone();
+        let on_abort_section = on_abort_section.clone();
+        let metadata_section = metadata_section.clone();
+        let color_profile_combobox = color_profile_combobox.clone();
+        let icc_path_input = icc_path_input.clone();
         let dialog = dialog.clone();
         move |_: ButtonEvent| {
             let repeat = match repeat_input.get_value::<u32>() {
@@ -100,6 +161,25 @@ pub fn show_config_dialog(
                 1 => ColorFormat::Rgba32,
                 _ => Default::default(),
             };
+
+            let alpha_handling = match alpha_combobox.get_selected_index() {
+                0 => AlphaHandling::Straight,
+                1 => AlphaHandling::Matte,
+                _ => Default::default(),
+            };
+
+            let matte_color = match matte_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+            let bit_depth = match bit_depth_combobox.get_selected_index() {
+                0 => BitDepth::Eight,
+                1 => BitDepth::Sixteen,
+                _ => Default::default(),
+            };
             let compression_type = match compression_combobox.get_selected_index() {
                 0 => CompressionType::Default,
                 1 => CompressionType::Fast,
@@ -117,13 +197,77 @@ pub fn show_config_dialog(
 
             let adaptive_filter = adaptive_filter_checkbox.is_checked();
 
+            let dedup = match dedup_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let resize = match resize_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let crop = match crop_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let decimate = match decimate_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let playback = match playback_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let on_abort = on_abort_section.value();
+
+            let metadata = metadata_section.value();
+
+            let color_profile = match color_profile_combobox.get_selected_index() {
+                0 => ColorProfile::None,
+                1 => ColorProfile::Srgb,
+                2 => ColorProfile::Icc,
+                _ => Default::default(),
+            };
+            let icc_path = icc_path_input.get_text();
+
             if let Ok(mut guard) = result.lock() {
                 *guard = Some(Config {
                     repeat,
                     color_format,
+                    alpha_handling,
+                    matte_color,
+                    bit_depth,
                     compression_type,
                     filter_type,
                     adaptive_filter,
+                    dedup,
+                    resize,
+                    crop,
+                    decimate,
+                    playback,
+                    on_abort,
+                    metadata,
+                    color_profile,
+                    icc_path,
                 });
                 dialog.close();
             } else {
@@ -144,13 +288,16 @@ pub fn show_config_dialog(
     });
 
     // Create layout with sections
-    let mut layout = FlexLayout::column()
-        .with_width(SizeValue::Points(300.0))
-        .with_padding(15.0)
-        .with_gap(10.0);
+    // 1列では画面に収まらないので、基本・エンコード、フレーム・サイズ、メタデータ・中断の3列に分ける
+    let column = || {
+        FlexLayout::column()
+            .with_width(SizeValue::Points(300.0))
+            .with_gap(10.0)
+    };
+    let mut base_column = column();
 
     // Basic Settings Section
-    layout = layout.with_layout(
+    base_column = base_column.with_layout(
         FlexLayout::column()
             .with_gap(5.0)
             .with_widget(repeat_label)
@@ -158,15 +305,32 @@ pub fn show_config_dialog(
     );
 
     // Color Format Section
-    layout = layout.with_layout(
+    base_column = base_column.with_layout(
         FlexLayout::column()
             .with_gap(5.0)
             .with_widget(color_label)
             .with_widget(color_combobox),
     );
 
+    // Alpha Section
+    base_column = base_column.with_layout(
+        FlexLayout::column()
+            .with_gap(5.0)
+            .with_widget(alpha_label)
+            .with_widget(alpha_combobox),
+    );
+    base_column = matte_section.add_to(base_column);
+
+    // Bit Depth Section
+    base_column = base_column.with_layout(
+        FlexLayout::column()
+            .with_gap(5.0)
+            .with_widget(bit_depth_label)
+            .with_widget(bit_depth_combobox),
+    );
+
     // Compression Settings Section
-    layout = layout
+    base_column = base_column
         .with_layout(
             FlexLayout::column()
                 .with_gap(5.0)
@@ -181,6 +345,46 @@ pub fn show_config_dialog(
                 .with_widget(filter_combobox),
         );
 
+    // Color Profile Section
+    base_column = base_column
+        .with_layout(
+            FlexLayout::column()
+                .with_gap(5.0)
+                .with_widget(color_profile_label)
+                .with_widget(color_profile_combobox),
+        )
+        .with_layout(
+            FlexLayout::column()
+                .with_gap(5.0)
+                .with_widget(icc_path_label)
+                .with_widget(icc_path_input),
+        );
+
+    let mut frame_column = column();
+
+    // Duplicate Frames Section
+    frame_column = dedup_section.add_to(frame_column);
+
+    // Frame Rate Section
+    frame_column = decimate_section.add_to(frame_column);
+
+    // Crop Section
+    frame_column = crop_section.add_to(frame_column);
+
+    // Resize Section
+    frame_column = resize_section.add_to(frame_column);
+
+    // Playback Section
+    frame_column = playback_section.add_to(frame_column);
+
+    let mut output_column = column();
+
+    // Metadata Section
+    output_column = metadata_section.add_to(output_column);
+
+    // Abort Section
+    output_column = on_abort_section.add_to(output_column);
+
     // Buttons Section
     let buttons_section = FlexLayout::row()
         .with_gap(10.0)
@@ -189,7 +393,17 @@ pub fn show_config_dialog(
         .with_widget(ok_button)
         .with_widget(cancel_button);
 
-    layout = layout.with_layout(buttons_section);
+    let layout = FlexLayout::column()
+        .with_padding(15.0)
+        .with_gap(10.0)
+        .with_layout(
+            FlexLayout::row()
+                .with_gap(15.0)
+                .with_layout(base_column)
+                .with_layout(frame_column)
+                .with_layout(output_column),
+        )
+        .with_layout(buttons_section);
 
     dialog = dialog.with_layout(layout);
 
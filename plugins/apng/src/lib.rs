@@ -1,22 +1,70 @@
-mod config;
+pub mod config;
+mod delta;
+#[cfg(feature = "plugin")]
 mod dialog;
+#[cfg(feature = "plugin")]
+mod plugin;
 
-use aviutl::output2::{OutputInfo, OutputPluginTable};
-use config::{ColorFormat, Config};
-use dialog::show_config_dialog;
-use png::{BitDepth, ColorType, Encoder};
-use std::ffi::c_void;
-use widestring::{U16CStr, Utf16Str, utf16str};
-use win32_dialog::MessageBox;
-use windows::{Win32::Foundation::*, core::*};
+use aviutl::abort;
+use aviutl::color::ColorProfile;
+use aviutl::crop::CroppedSource;
+use aviutl::decimate::DecimatedSource;
+use aviutl::frame::FrameBuffer;
+use aviutl::metadata::Metadata;
+use aviutl::output_file::OutputFile;
+use aviutl::playback::PlaybackSource;
+use aviutl::resize::ResizedSource;
+use aviutl::source::FrameSource;
+use aviutl::timing::{self, FrameTiming};
+use config::{BitDepth, ColorFormat, Config};
+use delta::{DeltaEncoder, DeltaFrame};
+use png::{
+    ColorType, DisposeOp, Encoder, Info, ScaledFloat, SourceChromaticities, SrgbRenderingIntent,
+    Writer,
+};
+use std::io::Write;
+use std::path::Path;
 
-fn create_apng_from_video(info: &OutputInfo, config: &Config) -> std::result::Result<(), String> {
-    let output_path = unsafe { U16CStr::from_ptr_str(info.savefile).to_string_lossy() };
+/// 出力するビット深度で読み込んだフレーム
+enum Samples<'a> {
+    Eight(&'a [u8]),
+    Sixteen(&'a [u16]),
+}
 
-    let output_file =
-        std::fs::File::create(&output_path).map_err(|e| format!("ファイル作成エラー: {}", e))?;
-    let mut encoder = Encoder::new(output_file, info.w as u32, info.h as u32);
+/// 出力するカラーフォーマットとビット深度でフレームを読み込む
+fn read_samples<'a>(
+    source: &dyn FrameSource,
+    config: &Config,
+    frame: i32,
+    buffer: &'a mut FrameBuffer<u8>,
+    buffer16: &'a mut FrameBuffer<u16>,
+) -> Option<Samples<'a>> {
+    match (config.color_format, config.bit_depth) {
+        (ColorFormat::Rgb24, BitDepth::Eight) => source
+            .read_rgb(frame, buffer)
+            .map(|view| Samples::Eight(view.data())),
+        (ColorFormat::Rgba32, BitDepth::Eight) => source
+            .read_rgba(frame, config.alpha_mode(), buffer)
+            .map(|view| Samples::Eight(view.data())),
+        (ColorFormat::Rgb24, BitDepth::Sixteen) => source
+            .read_rgb16(frame, buffer16)
+            .map(|view| Samples::Sixteen(view.data())),
+        (ColorFormat::Rgba32, BitDepth::Sixteen) => source
+            .read_rgba16(frame, config.alpha_mode(), buffer16)
+            .map(|view| Samples::Sixteen(view.data())),
+    }
+}
+
+/// 読み込んだフレーム
+enum ReadFrame {
+    /// 直前のフレームと同じなので、直前のフレームの表示時間を延ばす
+    Duplicate,
+    /// 直前のフレームの破棄方法と、直前のフレームとの差分
+    Changed(DisposeOp, DeltaFrame),
+}
 
+/// カラーフォーマット・ビット深度・フィルターを設定する
+fn configure<W: Write>(encoder: &mut Encoder<W>, config: &Config) {
     let color_type = if config.color_format == ColorFormat::Rgba32 {
         ColorType::Rgba
     } else {
@@ -24,8 +72,7 @@ fn create_apng_from_video(info: &OutputInfo, config: &Config) -> std::result::Re
     };
 
     encoder.set_color(color_type);
-    encoder.set_depth(BitDepth::Eight);
-    encoder.set_compression(config.compression_type.into());
+    encoder.set_depth(config.bit_depth.into());
 
     if config.adaptive_filter {
         encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
@@ -33,119 +80,290 @@ fn create_apng_from_video(info: &OutputInfo, config: &Config) -> std::result::Re
         encoder.set_filter(config.filter_type.into());
         encoder.set_adaptive_filter(png::AdaptiveFilterType::NonAdaptive);
     }
+}
 
-    // APNG設定
-    encoder
-        .set_animated(info.n as u32, config.repeat)
-        .map_err(|e| format!("APNG設定エラー: {}", e))?;
-
-    encoder
-        .set_frame_delay(info.scale as u16, info.rate as u16)
-        .map_err(|e| format!("フレームレート設定エラー: {}", e))?;
+/// フレームの領域だけを1枚のPNGとして高速に圧縮したときの大きさ
+///
+/// 差分の候補を比べるための目安で、実際の圧縮設定とは異なる
+fn compressed_size(config: &Config, delta: &DeltaFrame) -> usize {
+    let mut output = Vec::new();
+    let mut encoder = Encoder::new(
+        &mut output,
+        delta.rect.width as u32,
+        delta.rect.height as u32,
+    );
+    configure(&mut encoder, config);
+    encoder.set_compression(png::Compression::Fast);
 
-    let mut writer = encoder
+    let written = encoder
         .write_header()
-        .map_err(|e| format!("エンコーダー初期化エラー: {}", e))?;
+        .and_then(|mut writer| writer.write_image_data(&delta.data));
+    match written {
+        Ok(()) => output.len(),
+        Err(_) => usize::MAX,
+    }
+}
 
-    for frame in 0..info.n {
-        if info.is_abort() {
-            return Err("処理が中断されました".into());
+/// 色空間の情報を設定する
+///
+/// sRGBの場合は、sRGBチャンクに対応していないデコーダー向けに同じ値のgAMA・cHRMも書き込む
+fn set_color_profile(info: &mut Info, profile: ColorProfile, icc_profile: Option<Vec<u8>>) {
+    match profile {
+        ColorProfile::None => {}
+        ColorProfile::Srgb => {
+            info.srgb = Some(SrgbRenderingIntent::Perceptual);
+            info.source_gamma = Some(ScaledFloat::from_scaled(45455));
+            info.source_chromaticities = Some(SourceChromaticities {
+                white: (
+                    ScaledFloat::from_scaled(31270),
+                    ScaledFloat::from_scaled(32900),
+                ),
+                red: (
+                    ScaledFloat::from_scaled(64000),
+                    ScaledFloat::from_scaled(33000),
+                ),
+                green: (
+                    ScaledFloat::from_scaled(30000),
+                    ScaledFloat::from_scaled(60000),
+                ),
+                blue: (
+                    ScaledFloat::from_scaled(15000),
+                    ScaledFloat::from_scaled(6000),
+                ),
+            });
         }
-        // カラーフォーマットに応じてフレームデータを取得
-        let frame_data = if config.color_format == ColorFormat::Rgba32 {
-            info.get_video_rgba(frame)
-        } else {
-            info.get_video_rgb(frame)
-        };
+        ColorProfile::Icc => info.icc_profile = icc_profile.map(Into::into),
+    }
+}
 
-        if let Some(data) = frame_data {
-            // フレームデータを書き込み
-            writer
-                .write_image_data(&data)
-                .map_err(|e| format!("フレーム書き込みエラー: {}", e))?;
+/// メタデータをテキストチャンクに設定する
+///
+/// Latin-1で書ける値はtEXt、それ以外とXMPはUTF-8のiTXtに書き込む
+fn add_text_chunks<W: Write>(
+    encoder: &mut Encoder<W>,
+    metadata: &Metadata,
+) -> Result<(), png::EncodingError> {
+    for (keyword, value) in metadata.text_entries() {
+        if value.chars().all(|c| (c as u32) < 0x100) {
+            encoder.add_text_chunk(keyword.to_string(), value.to_string())?;
+        } else {
+            encoder.add_itxt_chunk(keyword.to_string(), value.to_string())?;
         }
-
-        info.rest_time_disp(frame, info.n);
     }
-
-    writer
-        .finish()
-        .map_err(|e| format!("エンコーダー終了エラー: {}", e))?;
+    // テキストチャンクがあるのでXMPは指定された場合だけ書き込む
+    if let Some(xmp) = &metadata.xmp {
+        encoder.add_itxt_chunk(
+            "XML:com.adobe.xmp".to_string(),
+            String::from_utf8_lossy(xmp).into_owned(),
+        )?;
+    }
     Ok(())
 }
 
-extern "C" fn output_func(oip: *mut OutputInfo) -> bool {
-    unsafe {
-        let info = match oip.as_ref() {
-            Some(info) => info,
-            None => return false,
+/// フレームの領域と破棄・合成方法、表示時間を設定して書き込む
+fn write_frame<W: Write>(
+    writer: &mut Writer<W>,
+    delta: &DeltaFrame,
+    dispose_op: DisposeOp,
+    (delay_num, delay_den): (u16, u16),
+) -> std::result::Result<(), String> {
+    // 位置を戻してから大きさを変えないと、前のフレームの位置によっては範囲外になる
+    let set_frame_control =
+        |writer: &mut Writer<W>| -> std::result::Result<(), png::EncodingError> {
+            writer.reset_frame_position()?;
+            writer.set_frame_dimension(delta.rect.width as u32, delta.rect.height as u32)?;
+            writer.set_frame_position(delta.rect.x as u32, delta.rect.y as u32)?;
+            writer.set_blend_op(delta.blend_op)?;
+            writer.set_dispose_op(dispose_op)
         };
+    set_frame_control(writer).map_err(|e| format!("フレーム領域設定エラー: {}", e))?;
 
-        // 設定を読み込み
-        let config = Config::load();
+    writer
+        .set_frame_delay(delay_num, delay_den)
+        .map_err(|e| format!("フレームレート設定エラー: {}", e))?;
 
-        let result = match create_apng_from_video(info, &config) {
-            Ok(_) => true,
-            Err(e) => {
-                let error_msg = format!("APNG出力エラー: {}", e);
-                MessageBox::error(Some(HWND::default()), &error_msg, "エラー");
-                false
-            }
-        };
+    // フレームデータを書き込み
+    writer
+        .write_image_data(&delta.data)
+        .map_err(|e| format!("フレーム書き込みエラー: {}", e))
+}
 
-        result
+/// acTLチャンクのフレーム数を書き換える
+///
+/// acTLはIDATより前にあるので、PNGシグネチャの後のチャンクを順にたどって探す
+fn rewrite_frame_count(file: &mut OutputFile, num_frames: u32) -> std::io::Result<()> {
+    let mut offset = 8;
+    loop {
+        // 長さ(4) + 種類(4)
+        let mut header = [0u8; 8];
+        file.read_at(offset, &mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        match &header[4..] {
+            b"acTL" => {
+                // 種類(4) + フレーム数(4) + ループ回数(4)
+                let mut chunk = [0u8; 12];
+                file.read_at(offset + 4, &mut chunk)?;
+                chunk[4..8].copy_from_slice(&num_frames.to_be_bytes());
+                file.write_at(offset + 4, &chunk)?;
+                return file.write_at(offset + 16, &crc32fast::hash(&chunk).to_be_bytes());
+            }
+            b"IDAT" | b"IEND" => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "acTLチャンクがありません",
+                ));
+            }
+            _ => offset += 12 + length,
+        }
     }
 }
 
-extern "C" fn config_func(hwnd: HWND, _dll_hinst: HINSTANCE) -> bool {
-    let default_config = Config::load();
+/// 動画をAPNGとして出力する
+pub fn create_apng_from_video(
+    source: &dyn FrameSource,
+    output_path: &Path,
+    config: &Config,
+) -> std::result::Result<(), String> {
+    // 以降はフレームを間引き、切り取ってから出力サイズに拡大・縮小し、再生順を変えたフレームを読み込む
+    let decimated = DecimatedSource::new(source, config.decimate);
+    let cropped = CroppedSource::new(&decimated, config.crop)?;
+    let resized = ResizedSource::new(&cropped, config.resize);
+    let played = PlaybackSource::new(&resized, config.playback)?;
+    let source: &dyn FrameSource = &played;
+    let metadata = config.metadata()?;
+    let icc_profile = config.icc_profile()?;
+
+    let num_frames = source.frame_count();
+
+    let mut info = Info::with_size(source.width() as u32, source.height() as u32);
+    set_color_profile(&mut info, config.color_profile, icc_profile);
+
+    let mut output_file = OutputFile::create(output_path)?;
+    let mut encoder = Encoder::with_info(&mut output_file, info)
+        .map_err(|e| format!("エンコーダー初期化エラー: {}", e))?;
+    configure(&mut encoder, config);
+    encoder.set_compression(config.compression_type.into());
+    add_text_chunks(&mut encoder, &metadata).map_err(|e| format!("メタデータ設定エラー: {}", e))?;
+
+    // APNG設定
+    // 重複をまとめたり中断したりして書き込んだフレームが少ない場合は、最後にacTLを書き換える
+    encoder
+        .set_animated(num_frames as u32, config.repeat)
+        .map_err(|e| format!("APNG設定エラー: {}", e))?;
+
+    // フレームの長さが16bitの分数で表せる場合はその値を、そうでなければミリ秒単位で指定する
+    // （ミリ秒単位の時刻はどちらの場合も進めて、タイムラインとのずれを後続のフレームで詰める）
+    let mut ms_timing = FrameTiming::new(source.rate(), source.scale(), 1000).with_min_delay(1);
+    let mut delay_for = |length: u32| -> (u16, u16) {
+        let delay = ms_timing.next_delay_for(length as u64);
+        source
+            .scale()
+            .checked_mul(length as i32)
+            .and_then(|scale| timing::frame_duration_u16(source.rate(), scale))
+            .unwrap_or((delay.min(u16::MAX as u64) as u16, 1000))
+    };
+
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("エンコーダー初期化エラー: {}", e))?;
+
+    // eXIfはIDATより前に書き込む
+    if let Some(exif) = &metadata.exif {
+        writer
+            .write_chunk(png::chunk::eXIf, exif)
+            .map_err(|e| format!("メタデータ書き込みエラー: {}", e))?;
+    }
+
+    let mut buffer = FrameBuffer::<u8>::new();
+    let mut buffer16 = FrameBuffer::<u16>::new();
+    let (width, height) = (source.width() as usize, source.height() as usize);
+    let channels = match config.color_format {
+        ColorFormat::Rgb24 => 3,
+        ColorFormat::Rgba32 => 4,
+    };
+    let mut delta_encoder = DeltaEncoder::<u8>::new(width, height, channels);
+    let mut delta_encoder16 = DeltaEncoder::<u16>::new(width, height, channels);
+    let mut detector = config.dedup.detector();
+    let mut detector16 = config.dedup.detector();
+    // 破棄方法は次のフレームとの差分で決まり、表示時間は重複をまとめると延びるので、
+    // 1フレーム遅らせて（差分, まとめたフレーム数）を書き込む
+    let mut pending: Option<(DeltaFrame, u32)> = None;
+    let mut written = 0;
+    let mut aborted = false;
 
-    if let Ok(result) = show_config_dialog(hwnd, default_config) {
-        match result {
-            Some(config) => {
-                // 設定を保存
-                if let Err(e) = config.save() {
-                    let error_msg = format!("設定保存エラー: {}", e);
-                    MessageBox::warning(Some(hwnd), &error_msg, "警告");
+    for frame in 0..num_frames {
+        if source.is_abort() {
+            // 中断時も保存する場合は、それまでのフレームで出力を完成させる
+            if config.on_abort.finish {
+                aborted = true;
+                break;
+            }
+            return Err(abort::ABORTED.into());
+        }
+        // カラーフォーマットとビット深度に応じてフレームデータを取得し、直前のフレームとの差分を求める
+        let size = |delta: &DeltaFrame| compressed_size(config, delta);
+        let read_frame = match read_samples(source, config, frame, &mut buffer, &mut buffer16) {
+            Some(Samples::Eight(data)) => Some(
+                if detector
+                    .as_mut()
+                    .is_some_and(|detector| detector.is_duplicate(data))
+                {
+                    ReadFrame::Duplicate
+                } else {
+                    let (dispose_op, delta) = delta_encoder.push(data, size);
+                    ReadFrame::Changed(dispose_op, delta)
+                },
+            ),
+            Some(Samples::Sixteen(data)) => Some(
+                if detector16
+                    .as_mut()
+                    .is_some_and(|detector| detector.is_duplicate(data))
+                {
+                    ReadFrame::Duplicate
+                } else {
+                    let (dispose_op, delta) = delta_encoder16.push(data, size);
+                    ReadFrame::Changed(dispose_op, delta)
+                },
+            ),
+            // 読み込めなかったフレームは出力しない
+            None => None,
+        };
+
+        match read_frame {
+            Some(ReadFrame::Duplicate) => {
+                if let Some((_, length)) = &mut pending {
+                    *length += 1;
+                }
+            }
+            Some(ReadFrame::Changed(dispose_op, delta)) => {
+                if let Some((previous, length)) = pending.replace((delta, 1)) {
+                    write_frame(&mut writer, &previous, dispose_op, delay_for(length))?;
+                    written += 1;
                 }
-                true
             }
-            None => false,
+            None => {}
         }
-    } else {
-        MessageBox::error(Some(hwnd), "設定の取得に失敗しました。", "エラー");
-        false
+
+        source.rest_time_disp(frame, num_frames);
     }
-}
 
-const PLUGIN_NAME: &Utf16Str = utf16str!("APNG出力プラグイン\0");
-const FILE_FILTER: &Utf16Str = utf16str!("PNG Files (*.png)\0*.png\0All Files (*)\0*\0\0");
-const PLUGIN_INFO: &Utf16Str = utf16str!(concat!(
-    "APNG出力プラグイン v",
-    env!("CARGO_PKG_VERSION"),
-    " by yu7400ki\0"
-));
-
-const fn init_plugin_table() -> OutputPluginTable {
-    OutputPluginTable {
-        flag: OutputPluginTable::FLAG_VIDEO,
-        name: PLUGIN_NAME.as_ptr(),
-        filefilter: FILE_FILTER.as_ptr(),
-        information: PLUGIN_INFO.as_ptr(),
-        func_output: Some(output_func),
-        func_config: Some(config_func),
-        func_get_config_text: None,
+    if let Some((delta, length)) = pending {
+        write_frame(&mut writer, &delta, DisposeOp::None, delay_for(length))?;
+        written += 1;
+    }
+    if aborted && written == 0 {
+        return Err(abort::ABORTED.into());
     }
-}
 
-const OUTPUT_PLUGIN_TABLE: OutputPluginTable = init_plugin_table();
+    writer
+        .finish()
+        .map_err(|e| format!("エンコーダー終了エラー: {}", e))?;
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn DllMain(_hinst: HINSTANCE, _reason: u32, _reserved: *mut c_void) -> BOOL {
-    TRUE
-}
+    // 中断や読み込めなかったフレームで、acTLで宣言したフレーム数より少ない場合は書き換える
+    if written != num_frames as u32 {
+        rewrite_frame_count(&mut output_file, written)
+            .map_err(|e| format!("フレーム数書き換えエラー: {}", e))?;
+    }
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn GetOutputPluginTable() -> *mut OutputPluginTable {
-    &OUTPUT_PLUGIN_TABLE as *const OutputPluginTable as *mut OutputPluginTable
+    output_file.commit()
 }
@@ -0,0 +1,64 @@
+use crate::config::Config;
+use crate::create_apng_from_video;
+use crate::dialog::show_config_dialog;
+use aviutl::export_output_plugin;
+use aviutl::plugin::OutputPlugin;
+use aviutl::source::FrameSource;
+use std::path::Path;
+use widestring::{Utf16Str, utf16str};
+use win32_dialog::MessageBox;
+use windows::Win32::Foundation::HWND;
+
+struct ApngOutput;
+
+impl OutputPlugin for ApngOutput {
+    const NAME: &'static Utf16Str = utf16str!("APNG出力プラグイン\0");
+    const FILE_FILTER: &'static Utf16Str =
+        utf16str!("PNG Files (*.png)\0*.png\0All Files (*)\0*\0\0");
+    const INFORMATION: &'static Utf16Str = utf16str!(concat!(
+        "APNG出力プラグイン v",
+        env!("CARGO_PKG_VERSION"),
+        " by yu7400ki\0"
+    ));
+
+    fn output(source: &dyn FrameSource, path: &Path) -> bool {
+        // 設定を読み込み
+        let config = Config::load();
+
+        match create_apng_from_video(source, path, &config) {
+            Ok(_) => true,
+            Err(e) => {
+                let error_msg = format!("APNG出力エラー: {}", e);
+                MessageBox::error(Some(HWND::default()), &error_msg, "エラー");
+                false
+            }
+        }
+    }
+
+    fn configure(hwnd: HWND) -> bool {
+        let default_config = Config::load();
+
+        if let Ok(result) = show_config_dialog(hwnd, default_config) {
+            match result {
+                Some(config) => {
+                    // 設定を保存
+                    if let Err(e) = config.save() {
+                        let error_msg = format!("設定保存エラー: {}", e);
+                        MessageBox::warning(Some(hwnd), &error_msg, "警告");
+                    }
+                    true
+                }
+                None => false,
+            }
+        } else {
+            MessageBox::error(Some(hwnd), "設定の取得に失敗しました。", "エラー");
+            false
+        }
+    }
+
+    fn config_text() -> Option<String> {
+        Some(Config::load().summary())
+    }
+}
+
+export_output_plugin!(ApngOutput);
@@ -0,0 +1,480 @@
+//! 合成アニメーションをAPNGに出力し、デコードした結果を入力と比較する
+
+use aviutl::abort::OnAbort;
+use aviutl::color::ColorProfile;
+use aviutl::convert::AlphaMode;
+use aviutl::crop::{Crop, CropMode};
+use aviutl::decimate::Decimate;
+use aviutl::dedup::Dedup;
+use aviutl::metadata::MetadataSettings;
+use aviutl::playback::{Playback, PlaybackOrder};
+use aviutl::resize::{Resize, ResizeFilter};
+use aviutl::source::{FrameSource, MemoryFrameSource};
+use png::{BlendOp, ColorType, Decoder, DisposeOp, Transformations};
+use png_output::config::{BitDepth, ColorFormat, Config};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use test_animations::{Blend, Canvas, TempPath};
+
+struct Decoded {
+    width: u32,
+    height: u32,
+    /// 表示される画像（ストレートαのRGBA32）
+    frames: Vec<Vec<u8>>,
+    /// (分子, 分母)
+    delays: Vec<(u16, u16)>,
+    /// fcTLの(x, y, 幅, 高さ)
+    regions: Vec<(u32, u32, u32, u32)>,
+    num_plays: u32,
+}
+
+/// APNGをデコードし、フレームを合成して表示される画像を求める
+///
+/// 16bitのサンプルは上位8bitに切り詰める
+fn decode(path: &Path) -> Decoded {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path).unwrap()));
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().unwrap();
+
+    let info = reader.info();
+    let (width, height) = (info.width, info.height);
+    let animation = *info.animation_control().expect("acTLがありません");
+    let mut buffer = vec![0; reader.output_buffer_size()];
+
+    // fcTLが無いデフォルト画像はアニメーションに含まれない
+    if reader.info().frame_control().is_none() {
+        reader.next_frame(&mut buffer).unwrap();
+    }
+
+    let mut canvas = Canvas::new(width as usize, height as usize);
+    let mut frames = Vec::new();
+    let mut delays = Vec::new();
+    let mut regions = Vec::new();
+    for _ in 0..animation.num_frames {
+        let output = reader.next_frame(&mut buffer).unwrap();
+        let control = *reader.info().frame_control().unwrap();
+        let data = &buffer[..output.buffer_size()];
+        let rgba: Vec<u8> = match output.color_type {
+            ColorType::Rgba => data.to_vec(),
+            ColorType::Rgb => data
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            color_type => panic!("想定外のカラータイプです: {:?}", color_type),
+        };
+
+        let area = (
+            control.x_offset as usize,
+            control.y_offset as usize,
+            control.width as usize,
+            control.height as usize,
+        );
+        let previous = canvas.clone();
+
+        let blend = match control.blend_op {
+            BlendOp::Source => Blend::Source,
+            BlendOp::Over => Blend::Over,
+        };
+        canvas.draw(area.0, area.1, area.2, area.3, &rgba, blend);
+        frames.push(canvas.pixels().to_vec());
+        // 分母が0の場合は1/100秒単位
+        let delay_den = if control.delay_den == 0 {
+            100
+        } else {
+            control.delay_den
+        };
+        delays.push((control.delay_num, delay_den));
+        regions.push((
+            control.x_offset,
+            control.y_offset,
+            control.width,
+            control.height,
+        ));
+
+        match control.dispose_op {
+            DisposeOp::None => {}
+            DisposeOp::Background => canvas.clear(area.0, area.1, area.2, area.3),
+            DisposeOp::Previous => canvas = previous,
+        }
+    }
+
+    Decoded {
+        width,
+        height,
+        frames,
+        delays,
+        regions,
+        num_plays: animation.num_plays,
+    }
+}
+
+fn encode(source: &MemoryFrameSource, config: &Config, name: &str) -> Decoded {
+    let path = TempPath::new(name);
+    png_output::create_apng_from_video(source, &path, config).unwrap();
+    decode(&path)
+}
+
+#[test]
+fn gradient_without_alpha() {
+    let source = test_animations::gradient();
+    let decoded = encode(&source, &Config::default(), "golden-gradient.png");
+
+    assert_eq!((decoded.width, decoded.height), (64, 48));
+    assert_eq!(decoded.num_plays, 0);
+    // 29.97fpsは1001/30000秒として正確に表せる
+    assert_eq!(decoded.delays, [(1001, 30000); 6]);
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn moving_square_16bit_with_finite_repeat() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        repeat: 2,
+        bit_depth: BitDepth::Sixteen,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square-16bit.png");
+
+    assert_eq!(decoded.num_plays, 2);
+    assert_eq!(decoded.frames.len(), 6);
+    // 8bitへの切り詰めと丸めの違いで1ずれる
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decoded.frames,
+        1,
+        1.0,
+    );
+}
+
+#[test]
+fn moving_square_writes_changed_regions() {
+    let source = test_animations::moving_square();
+    let decoded = encode(&source, &Config::default(), "golden-square-delta.png");
+
+    // 最初のフレームは画像全体、以降は正方形が動いた範囲（移動前後を合わせた24x20）だけ
+    assert_eq!(decoded.regions[0], (0, 0, 64, 48));
+    for (frame, &(x, y, width, height)) in decoded.regions.iter().enumerate().skip(1) {
+        let (left, top) = (4 + (frame as u32 - 1) * 8, 4 + (frame as u32 - 1) * 4);
+        assert_eq!((x, y, width, height), (left, top, 24, 20));
+    }
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn alpha_fade_keeps_alpha() {
+    let source = test_animations::alpha_fade();
+    let config = Config {
+        color_format: ColorFormat::Rgba32,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-fade.png");
+
+    assert_eq!(decoded.frames.len(), 6);
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgba(&source, AlphaMode::Straight),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn alpha_fade_16bit_keeps_alpha() {
+    let source = test_animations::alpha_fade();
+    let config = Config {
+        color_format: ColorFormat::Rgba32,
+        bit_depth: BitDepth::Sixteen,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-fade-16bit.png");
+
+    assert_eq!(decoded.frames.len(), 6);
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgba(&source, AlphaMode::Straight),
+        &decoded.frames,
+        1,
+        1.0,
+    );
+}
+
+#[test]
+fn inexact_frame_rate_falls_back_to_milliseconds() {
+    // 1001/120000秒は分母が16bitに収まらない
+    let gradient = test_animations::gradient();
+    let mut source = MemoryFrameSource::new(gradient.width(), gradient.height(), 120000, 1001);
+    for frame in 0..gradient.frame_count() {
+        source
+            .push_pa64(gradient.frame_pa64(frame).unwrap().to_vec())
+            .unwrap();
+    }
+    let decoded = encode(&source, &Config::default(), "golden-gradient-ms.png");
+
+    let delays: Vec<u16> = decoded.delays.iter().map(|&(num, _)| num).collect();
+    assert_eq!(delays, [8, 8, 9, 8, 8, 9]);
+    assert!(decoded.delays.iter().all(|&(_, den)| den == 1000));
+}
+
+#[test]
+fn slideshow_merges_duplicates() {
+    let source = test_animations::slideshow();
+    let config = Config {
+        dedup: Dedup {
+            merge: true,
+            tolerance: 0,
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-slideshow.png");
+
+    // 3, 2, 1フレーム分の表示時間を約分した分数
+    assert_eq!(
+        decoded.delays,
+        [(1001, 10000), (1001, 15000), (1001, 30000)]
+    );
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 3, 5]),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn moving_square_resized() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        // 平均で半分にすると、正方形の端がそろっているので縮小した画像が計算で求まる
+        resize: Resize {
+            width: 32,
+            filter: ResizeFilter::Box,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square-resized.png");
+
+    // 幅だけ指定すると縦横比を保って高さが決まる
+    assert_eq!((decoded.width, decoded.height), (32, 24));
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&test_animations::moving_square_halved()),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn moving_square_auto_trimmed() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        crop: Crop {
+            mode: CropMode::Auto,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square-trimmed.png");
+
+    // 全フレームで正方形が通った範囲だけが残る
+    let (_, _, width, height) = test_animations::SQUARE_BOUNDS;
+    assert_eq!(
+        (decoded.width as i32, decoded.height as i32),
+        (width, height)
+    );
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&test_animations::moving_square_trimmed()),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn gradient_decimated_to_10fps() {
+    let source = test_animations::gradient();
+    let config = Config {
+        decimate: Decimate {
+            fps: 10,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-gradient-10fps.png");
+
+    // 29.97fpsの0, 0.1, 0.2秒に表示されているフレーム
+    assert_eq!(decoded.delays, [(1, 10); 3]);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 2, 5]),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn gradient_ping_pong() {
+    let source = test_animations::gradient();
+    let config = Config {
+        playback: Playback {
+            speed: 200,
+            order: PlaybackOrder::PingPong,
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-gradient-pingpong.png");
+
+    // 折り返しの両端のフレームは1回だけ表示する
+    // 2倍速なので1フレームは1001/60000秒
+    assert_eq!(decoded.delays, [(1001, 60000); 10]);
+    test_animations::assert_frames_close(
+        &test_animations::select(
+            &test_animations::expected_rgb(&source),
+            &[0, 1, 2, 3, 4, 5, 4, 3, 2, 1],
+        ),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn abort_keeps_the_previous_output() {
+    let source = test_animations::moving_square();
+    let path = TempPath::new("golden-abort.png");
+    std::fs::write(&*path, b"previous").unwrap();
+
+    let aborting = test_animations::AbortingSource::new(&source, 3);
+    assert!(png_output::create_apng_from_video(&aborting, &path, &Config::default()).is_err());
+    // 書きかけのファイルは残らず、前の出力も上書きされない
+    assert_eq!(std::fs::read(&*path).unwrap(), b"previous");
+    let dir = path.parent().unwrap();
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(std::fs::read_dir(dir).unwrap().all(|entry| {
+        let entry = entry.unwrap().file_name();
+        let entry = entry.to_string_lossy();
+        entry == name || !entry.starts_with(name)
+    }));
+}
+
+#[test]
+fn abort_finishes_with_encoded_frames() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        on_abort: OnAbort { finish: true },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-abort-finished.png");
+
+    // 3フレーム出力したところで中断する
+    let aborting = test_animations::AbortingSource::new(&source, 3);
+    png_output::create_apng_from_video(&aborting, &path, &config).unwrap();
+
+    let decoded = decode(&path);
+    assert_eq!(decoded.frames.len(), 3);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 1, 2]),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn metadata_in_text_chunks() {
+    let source = test_animations::gradient();
+    let exif_path = TempPath::new("golden-metadata-apng.exif");
+    std::fs::write(&*exif_path, b"Exif\0\0MM\0*\0\0\0\x08\0\0").unwrap();
+    let config = Config {
+        metadata: MetadataSettings {
+            title: "Gradient".into(),
+            comment: "グラデーション".into(),
+            exif_path: exif_path.to_str().unwrap().into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-metadata.png");
+    png_output::create_apng_from_video(&source, &path, &config).unwrap();
+
+    let decoder = Decoder::new(BufReader::new(File::open(&*path).unwrap()));
+    let reader = decoder.read_info().unwrap();
+    let info = reader.info();
+    // Latin-1で書ける値はtEXt、それ以外はiTXt
+    let latin1: Vec<_> = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.as_str(), chunk.text.as_str()))
+        .collect();
+    assert_eq!(latin1, [("Title", "Gradient")]);
+    let utf8: Vec<_> = info
+        .utf8_text
+        .iter()
+        .map(|chunk| chunk.keyword.as_str())
+        .collect();
+    assert_eq!(utf8, ["Comment", "Software"]);
+    assert_eq!(info.utf8_text[0].get_text().unwrap(), "グラデーション");
+    // eXIfはデコーダーが読まないので、チャンクを順にたどってIDATより前にあることを確かめる
+    let data = std::fs::read(&*path).unwrap();
+    let mut offset = 8;
+    let exif = loop {
+        let length = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        let chunk = &data[offset + 8..offset + 8 + length];
+        match &data[offset + 4..offset + 8] {
+            b"eXIf" => break chunk,
+            b"IDAT" | b"IEND" => panic!("IDATより前にeXIfがありません"),
+            _ => offset += 12 + length,
+        }
+    };
+    // TIFFヘッダーから書き込む
+    assert_eq!(exif, b"MM\0*\0\0\0\x08\0\0");
+
+    assert_eq!(decode(&path).frames.len(), 6);
+}
+
+#[test]
+fn color_profile_chunks() {
+    let source = test_animations::gradient();
+
+    // 既定ではsRGBと、同じ値のgAMA・cHRMを書き込む
+    let path = TempPath::new("golden-color-srgb.png");
+    png_output::create_apng_from_video(&source, &path, &Config::default()).unwrap();
+    let decoder = Decoder::new(BufReader::new(File::open(&*path).unwrap()));
+    let reader = decoder.read_info().unwrap();
+    let info = reader.info();
+    assert_eq!(info.srgb, Some(png::SrgbRenderingIntent::Perceptual));
+    assert_eq!(info.gama_chunk, Some(png::ScaledFloat::from_scaled(45455)));
+    assert!(info.chrm_chunk.is_some());
+    assert!(info.icc_profile.is_none());
+
+    let icc_path = TempPath::new("golden-color-png.icc");
+    std::fs::write(&*icc_path, aviutl::color::srgb_icc_profile()).unwrap();
+    let config = Config {
+        color_profile: ColorProfile::Icc,
+        icc_path: icc_path.to_str().unwrap().into(),
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-color-icc.png");
+    png_output::create_apng_from_video(&source, &path, &config).unwrap();
+    let decoder = Decoder::new(BufReader::new(File::open(&*path).unwrap()));
+    let reader = decoder.read_info().unwrap();
+    let info = reader.info();
+    assert!(info.srgb.is_none());
+    assert_eq!(
+        info.icc_profile.as_deref(),
+        Some(&aviutl::color::srgb_icc_profile()[..])
+    );
+
+    assert_eq!(decode(&path).frames.len(), 6);
+}
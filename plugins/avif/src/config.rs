@@ -1,10 +1,24 @@
+use aviutl::abort::OnAbort;
+use aviutl::color::ColorSpace;
+use aviutl::convert::{AlphaMode, format_color, parse_color};
+use aviutl::crop::Crop;
+use aviutl::decimate::Decimate;
+use aviutl::dedup::Dedup;
+use aviutl::metadata::{Metadata, MetadataFiles, MetadataSettings};
+use aviutl::playback::Playback;
+use aviutl::resize::Resize;
+use aviutl::size_limit::{MaxFileSize, SizeLimit};
 use ini::Ini;
+#[cfg(feature = "plugin")]
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+#[cfg(feature = "plugin")]
 use windows::Win32::Foundation::{HMODULE, MAX_PATH};
+#[cfg(feature = "plugin")]
 use windows::Win32::System::LibraryLoader::{
     GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GetModuleFileNameW, GetModuleHandleExW,
 };
+#[cfg(feature = "plugin")]
 use windows::core::PCWSTR;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -85,16 +99,6 @@ impl FromStr for YuvFormat {
     }
 }
 
-impl Into<rustavif::PixelFormat> for YuvFormat {
-    fn into(self) -> rustavif::PixelFormat {
-        match self {
-            YuvFormat::Yuv420 => rustavif::PixelFormat::Yuv420,
-            YuvFormat::Yuv422 => rustavif::PixelFormat::Yuv422,
-            YuvFormat::Yuv444 => rustavif::PixelFormat::Yuv444,
-        }
-    }
-}
-
 impl YuvFormat {
     fn to_index(&self) -> u32 {
         match self {
@@ -105,14 +109,124 @@ impl YuvFormat {
     }
 }
 
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum BitDepth {
+    #[default]
+    Eight,
+    Ten,
+    Twelve,
+}
+
+impl From<BitDepth> for &'static str {
+    fn from(depth: BitDepth) -> Self {
+        match depth {
+            BitDepth::Eight => "8bit",
+            BitDepth::Ten => "10bit",
+            BitDepth::Twelve => "12bit",
+        }
+    }
+}
+
+impl FromStr for BitDepth {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(BitDepth::Eight),
+            Ok(1) => Ok(BitDepth::Ten),
+            Ok(2) => Ok(BitDepth::Twelve),
+            _ => Err(()),
+        }
+    }
+}
+
+impl BitDepth {
+    fn to_index(self) -> u32 {
+        match self {
+            BitDepth::Eight => 0,
+            BitDepth::Ten => 1,
+            BitDepth::Twelve => 2,
+        }
+    }
+
+    /// 1サンプルあたりのビット数
+    pub fn bits(&self) -> u32 {
+        match self {
+            BitDepth::Eight => 8,
+            BitDepth::Ten => 10,
+            BitDepth::Twelve => 12,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum AlphaHandling {
+    #[default]
+    Straight,
+    Matte,
+}
+
+impl From<AlphaHandling> for &'static str {
+    fn from(handling: AlphaHandling) -> Self {
+        match handling {
+            AlphaHandling::Straight => "そのまま保持",
+            AlphaHandling::Matte => "背景色に合成",
+        }
+    }
+}
+
+impl FromStr for AlphaHandling {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(AlphaHandling::Straight),
+            Ok(1) => Ok(AlphaHandling::Matte),
+            _ => Err(()),
+        }
+    }
+}
+
+impl AlphaHandling {
+    fn to_index(self) -> u32 {
+        match self {
+            AlphaHandling::Straight => 0,
+            AlphaHandling::Matte => 1,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     pub repeat: u32,
     pub quality: u8,
     pub speed: u8,
     pub color_format: ColorFormat,
+    pub alpha_handling: AlphaHandling,
+    pub matte_color: [u8; 3],
     pub yuv_format: YuvFormat,
+    pub bit_depth: BitDepth,
     pub threads: usize,
+    /// 重複フレームをまとめる指定
+    pub dedup: Dedup,
+    /// 出力サイズの指定
+    pub resize: Resize,
+    /// 切り取りの指定
+    pub crop: Crop,
+    /// フレームレートの指定
+    pub decimate: Decimate,
+    /// 再生速度と再生順
+    pub playback: Playback,
+    /// ファイルサイズの上限
+    pub max_file_size: MaxFileSize,
+    /// 中断されたときの出力の扱い
+    pub on_abort: OnAbort,
+    /// 埋め込むメタデータの項目
+    pub metadata: MetadataSettings,
+    /// 書き込む色空間（RGBからYUVへの変換にも同じ変換行列を使う）
+    pub color_space: ColorSpace,
+    /// YUVをフルレンジで出力する（無効にするとリミテッドレンジ）
+    pub full_range: bool,
 }
 
 impl Default for Config {
@@ -122,13 +236,30 @@ impl Default for Config {
             quality: 75,
             speed: 10,
             color_format: ColorFormat::default(),
+            alpha_handling: AlphaHandling::default(),
+            matte_color: [0, 0, 0],
             yuv_format: YuvFormat::default(),
+            bit_depth: BitDepth::default(),
             threads: std::thread::available_parallelism().map_or(1, |p| p.get()),
+            dedup: Dedup::default(),
+            resize: Resize::default(),
+            crop: Crop::default(),
+            decimate: Decimate::default(),
+            playback: Playback::default(),
+            max_file_size: MaxFileSize::default(),
+            on_abort: OnAbort::default(),
+            metadata: MetadataSettings::default(),
+            color_space: ColorSpace::Srgb,
+            full_range: true,
         }
     }
 }
 
 impl Config {
+    /// 埋め込めるメタデータのファイル
+    pub const METADATA_FILES: MetadataFiles = MetadataFiles::GivenOrGenerated;
+
+    #[cfg(feature = "plugin")]
     fn config_file_path() -> Result<PathBuf, String> {
         let (buffer, len) = unsafe {
             let mut hmodule: HMODULE = HMODULE::default();
@@ -157,6 +288,66 @@ impl Config {
         }
     }
 
+    /// 透過付き出力時のαチャンネルの扱い
+    pub fn alpha_mode(&self) -> AlphaMode {
+        match self.alpha_handling {
+            AlphaHandling::Straight => AlphaMode::Straight,
+            AlphaHandling::Matte => AlphaMode::Matte(self.matte_color),
+        }
+    }
+
+    /// 出力ファイルに埋め込むメタデータ（XMP・EXIFはファイルから読み込む）
+    pub fn metadata(&self) -> Result<Metadata, String> {
+        self.metadata.to_metadata(concat!(
+            "AVIF出力プラグイン v",
+            env!("CARGO_PKG_VERSION"),
+            " by yu7400ki"
+        ))
+    }
+
+    /// ファイルサイズの上限と、収まるように調整する品質の範囲（上限なしの場合はNone）
+    pub fn size_limit(&self) -> Option<SizeLimit> {
+        self.max_file_size.limit("品質", 0, 100)
+    }
+
+    /// 出力ダイアログに表示する設定の概要
+    pub fn summary(&self) -> String {
+        let repeat = match self.repeat {
+            0 => "ループ∞".to_string(),
+            n => format!("ループ{}回", n),
+        };
+        let color = match (self.color_format, self.alpha_handling) {
+            (ColorFormat::Rgb24, _) => "透過無し".to_string(),
+            (ColorFormat::Rgba32, AlphaHandling::Straight) => "透過付き".to_string(),
+            (ColorFormat::Rgba32, AlphaHandling::Matte) => {
+                format!("透過付き(背景色{})", format_color(self.matte_color))
+            }
+        };
+        let yuv_format: &str = self.yuv_format.into();
+        let bit_depth: &str = self.bit_depth.into();
+        let mut summary = format!(
+            "AVIF 品質{} 速度{} {} {} {} {}",
+            self.quality, self.speed, yuv_format, bit_depth, repeat, color
+        );
+        summary.push_str(&self.dedup.summary());
+        summary.push_str(&self.resize.summary());
+        summary.push_str(&self.crop.summary());
+        summary.push_str(&self.decimate.summary());
+        summary.push_str(&self.playback.summary());
+        summary.push_str(&self.max_file_size.summary());
+        summary.push_str(&self.on_abort.summary());
+        summary.push_str(&self.metadata.summary(Self::METADATA_FILES));
+        if self.color_space != ColorSpace::Srgb {
+            summary.push(' ');
+            summary.push_str(self.color_space.into());
+        }
+        if !self.full_range {
+            summary.push_str(" リミテッドレンジ");
+        }
+        summary
+    }
+
+    #[cfg(feature = "plugin")]
     pub fn load() -> Self {
         let default = Self::default();
 
@@ -169,10 +360,15 @@ impl Config {
             return default;
         }
 
-        let ini = match Ini::load_from_file(&config_path) {
-            Ok(ini) => ini,
-            Err(_) => return default,
-        };
+        match Ini::load_from_file(&config_path) {
+            Ok(ini) => Self::from_ini(&ini),
+            Err(_) => default,
+        }
+    }
+
+    /// iniの`[Config]`セクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn from_ini(ini: &Ini) -> Self {
+        let default = Self::default();
 
         let section = ini.section(Some("Config"));
 
@@ -198,38 +394,100 @@ impl Config {
             .and_then(|s| s.parse::<ColorFormat>().ok())
             .unwrap_or_default();
 
+        let alpha_handling = section
+            .and_then(|s| s.get("alpha_handling"))
+            .and_then(|s| s.parse::<AlphaHandling>().ok())
+            .unwrap_or_default();
+
+        let matte_color = section
+            .and_then(|s| s.get("matte_color"))
+            .and_then(parse_color)
+            .unwrap_or(default.matte_color);
+
         let yuv_format = section
             .and_then(|s| s.get("yuv_format"))
             .and_then(|s| s.parse::<YuvFormat>().ok())
             .unwrap_or_default();
 
+        let bit_depth = section
+            .and_then(|s| s.get("bit_depth"))
+            .and_then(|s| s.parse::<BitDepth>().ok())
+            .unwrap_or_default();
+
         let threads = section
             .and_then(|s| s.get("threads"))
             .and_then(|s| s.parse::<usize>().ok())
             .unwrap_or(default.threads);
 
+        let color_space = section
+            .and_then(|s| s.get("color_space"))
+            .and_then(|s| s.parse::<ColorSpace>().ok())
+            .unwrap_or(default.color_space);
+
+        let full_range = section
+            .and_then(|s| s.get("full_range"))
+            .and_then(|s| s.parse::<u32>().ok())
+            .map(|v| v != 0)
+            .unwrap_or(default.full_range);
+
         Self {
             repeat,
             quality,
             speed,
             color_format,
+            alpha_handling,
+            matte_color,
             yuv_format,
+            bit_depth,
             threads,
+            dedup: Dedup::load_ini(section),
+            resize: Resize::load_ini(section),
+            crop: Crop::load_ini(section),
+            decimate: Decimate::load_ini(section),
+            playback: Playback::load_ini(section),
+            max_file_size: MaxFileSize::load_ini(section),
+            on_abort: OnAbort::load_ini(section),
+            metadata: MetadataSettings::load_ini(section),
+            color_space,
+            full_range,
         }
     }
 
+    #[cfg(feature = "plugin")]
     pub fn save(&self) -> Result<(), String> {
         let config_path = Self::config_file_path()?;
+        self.to_ini()
+            .write_to_file(&config_path)
+            .map_err(|e| e.to_string())
+    }
+
+    /// iniの`[Config]`セクションに書き出す
+    pub fn to_ini(&self) -> Ini {
         let mut ini = Ini::new();
 
-        ini.with_section(Some("Config"))
+        let mut section = ini.with_section(Some("Config"));
+        section
             .set("repeat", self.repeat.to_string())
             .set("quality", self.quality.to_string())
             .set("speed", self.speed.to_string())
             .set("color_format", self.color_format.to_index().to_string())
+            .set("alpha_handling", self.alpha_handling.to_index().to_string())
+            .set("matte_color", format_color(self.matte_color))
             .set("yuv_format", self.yuv_format.to_index().to_string())
+            .set("bit_depth", self.bit_depth.to_index().to_string())
             .set("threads", self.threads.to_string());
-
-        ini.write_to_file(&config_path).map_err(|e| e.to_string())
+        self.dedup.save_ini(&mut section);
+        self.resize.save_ini(&mut section);
+        self.crop.save_ini(&mut section);
+        self.decimate.save_ini(&mut section);
+        self.playback.save_ini(&mut section);
+        self.max_file_size.save_ini(&mut section);
+        self.on_abort.save_ini(&mut section);
+        self.metadata.save_ini(&mut section);
+        section
+            .set("color_space", self.color_space.to_index().to_string())
+            .set("full_range", (self.full_range as u32).to_string());
+
+        ini
     }
 }
@@ -1,10 +1,15 @@
-use crate::config::{ColorFormat, Config, YuvFormat};
+use crate::config::{AlphaHandling, BitDepth, ColorFormat, Config, YuvFormat};
+use aviutl::color::ColorSpace;
+use aviutl::dialog::{
+    CropSection, DecimateSection, DedupSection, MatteSection, MaxFileSizeSection, MetadataSection,
+    OnAbortSection, PlaybackSection, ResizeSection,
+};
 use std::sync::{Arc, Mutex};
 use win32_dialog::widget::ComboBox;
 use win32_dialog::{
     Dialog, MessageBox,
     layout::{FlexLayout, JustifyContent, SizeValue},
-    widget::{Button, ButtonEvent, Label, Number},
+    widget::{Button, ButtonEvent, CheckBox, Label, Number},
 };
 use windows::Win32::Foundation::*;
 
@@ -36,6 +41,16 @@ pub fn show_config_dialog(
         ColorFormat::Rgba32 => 1,
     });
 
+    let alpha_label = Label::new("半透明の扱い (透過付きのみ)");
+    let alpha_options = vec![AlphaHandling::Straight.into(), AlphaHandling::Matte.into()];
+    let alpha_combobox =
+        ComboBox::new(alpha_options).selected(match default_config.alpha_handling {
+            AlphaHandling::Straight => 0,
+            AlphaHandling::Matte => 1,
+        });
+
+    let matte_section = MatteSection::new(default_config.matte_color);
+
     let yuv_label = Label::new("YUVフォーマット");
     let yuv_options = vec![
         YuvFormat::Yuv420.into(),
@@ -48,6 +63,47 @@ pub fn show_config_dialog(
         YuvFormat::Yuv444 => 2,
     });
 
+    let bit_depth_label = Label::new("ビット深度");
+    let bit_depth_options = vec![
+        BitDepth::Eight.into(),
+        BitDepth::Ten.into(),
+        BitDepth::Twelve.into(),
+    ];
+    let bit_depth_combobox =
+        ComboBox::new(bit_depth_options).selected(match default_config.bit_depth {
+            BitDepth::Eight => 0,
+            BitDepth::Ten => 1,
+            BitDepth::Twelve => 2,
+        });
+
+    let dedup_section = DedupSection::new(&default_config.dedup);
+
+    let resize_section = ResizeSection::new(&default_config.resize);
+
+    let crop_section = CropSection::new(&default_config.crop);
+
+    let decimate_section = DecimateSection::new(&default_config.decimate);
+
+    let playback_section = PlaybackSection::new(&default_config.playback);
+
+    let max_file_size_section = MaxFileSizeSection::new(&default_config.max_file_size);
+
+    let on_abort_section = OnAbortSection::new(&default_config.on_abort);
+
+    let metadata_section = MetadataSection::new(&default_config.metadata, Config::METADATA_FILES);
+
+    let color_space_label = Label::new("色空間");
+    let color_space_options = vec![
+        ColorSpace::Srgb.into(),
+        ColorSpace::Bt709.into(),
+        ColorSpace::Bt601.into(),
+    ];
+    let color_space_combobox =
+        ComboBox::new(color_space_options).selected(default_config.color_space.to_index() as i32);
+
+    let full_range_checkbox =
+        CheckBox::new("フルレンジで出力する").checked(default_config.full_range);
+
     let mut dialog = Dialog::new("AVIF出力設定");
 
     let ok_button = Button::primary("OK").add_event_handler({
@@ -56,7 +112,20 @@ pub fn show_config_dialog(
         let quality_number = quality_number.clone();
         let speed_number = speed_number.clone();
         let color_combobox = color_combobox.clone();
+        let alpha_combobox = alpha_combobox.clone();
+        let matte_section = matte_section.clone();
         let yuv_combobox = yuv_combobox.clone();
+        let bit_depth_combobox = bit_depth_combobox.clone();
+        let dedup_section = dedup_section.clone();
+        let resize_section = resize_section.clone();
+        let crop_section = crop_section.clone();
+        let decimate_section = decimate_section.clone();
+        let playback_section = playback_section.clone();
+        let max_file_size_section = max_file_size_section.clone();
+        let on_abort_section = on_abort_section.clone();
+        let metadata_section = metadata_section.clone();
+        let color_space_combobox = color_space_combobox.clone();
+        let full_range_checkbox = full_range_checkbox.clone();
         let dialog = dialog.clone();
         move |_: ButtonEvent| {
             let repeat = match repeat_input.get_value::<u32>() {
@@ -101,6 +170,20 @@ pub fn show_config_dialog(
                 _ => Default::default(),
             };
 
+            let alpha_handling = match alpha_combobox.get_selected_index() {
+                0 => AlphaHandling::Straight,
+                1 => AlphaHandling::Matte,
+                _ => Default::default(),
+            };
+
+            let matte_color = match matte_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
             let yuv_format = match yuv_combobox.get_selected_index() {
                 0 => YuvFormat::Yuv420,
                 1 => YuvFormat::Yuv422,
@@ -108,14 +191,94 @@ pub fn show_config_dialog(
                 _ => Default::default(),
             };
 
+            let bit_depth = match bit_depth_combobox.get_selected_index() {
+                0 => BitDepth::Eight,
+                1 => BitDepth::Ten,
+                2 => BitDepth::Twelve,
+                _ => Default::default(),
+            };
+
+            let dedup = match dedup_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let resize = match resize_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let crop = match crop_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let decimate = match decimate_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let playback = match playback_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let max_file_size = match max_file_size_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let on_abort = on_abort_section.value();
+
+            let metadata = metadata_section.value();
+
+            let color_space = match color_space_combobox.get_selected_index() {
+                0 => ColorSpace::Srgb,
+                1 => ColorSpace::Bt709,
+                2 => ColorSpace::Bt601,
+                _ => Default::default(),
+            };
+            let full_range = full_range_checkbox.is_checked();
+
             if let Ok(mut guard) = result.lock() {
                 *guard = Some(Config {
                     repeat,
                     quality,
                     speed,
                     color_format,
+                    alpha_handling,
+                    matte_color,
                     yuv_format,
+                    bit_depth,
                     threads: Config::default().threads,
+                    dedup,
+                    resize,
+                    crop,
+                    decimate,
+                    playback,
+                    max_file_size,
+                    on_abort,
+                    metadata,
+                    color_space,
+                    full_range,
                 });
                 dialog.close();
             } else {
@@ -136,13 +299,16 @@ pub fn show_config_dialog(
     });
 
     // Create layout with sections
-    let mut layout = FlexLayout::column()
-        .with_width(SizeValue::Points(300.0))
-        .with_padding(15.0)
-        .with_gap(10.0);
+    // 1列では画面に収まらないので、基本・エンコード、フレーム・サイズ、メタデータ・中断の3列に分ける
+    let column = || {
+        FlexLayout::column()
+            .with_width(SizeValue::Points(300.0))
+            .with_gap(10.0)
+    };
+    let mut base_column = column();
 
     // Basic Settings Section
-    layout = layout
+    base_column = base_column
         .with_layout(
             FlexLayout::column()
                 .with_gap(5.0)
@@ -163,21 +329,76 @@ pub fn show_config_dialog(
         );
 
     // Color Format Section (only if RGBA feature is enabled)
-    layout = layout.with_layout(
+    base_column = base_column.with_layout(
         FlexLayout::column()
             .with_gap(5.0)
             .with_widget(color_label)
             .with_widget(color_combobox),
     );
 
+    // Alpha Section
+    base_column = base_column.with_layout(
+        FlexLayout::column()
+            .with_gap(5.0)
+            .with_widget(alpha_label)
+            .with_widget(alpha_combobox),
+    );
+    base_column = matte_section.add_to(base_column);
+
     // YUV Format Section
-    layout = layout.with_layout(
+    base_column = base_column.with_layout(
         FlexLayout::column()
             .with_gap(5.0)
             .with_widget(yuv_label)
             .with_widget(yuv_combobox),
     );
 
+    // Bit Depth Section
+    base_column = base_column.with_layout(
+        FlexLayout::column()
+            .with_gap(5.0)
+            .with_widget(bit_depth_label)
+            .with_widget(bit_depth_combobox),
+    );
+
+    // Color Space Section
+    base_column = base_column
+        .with_layout(
+            FlexLayout::column()
+                .with_gap(5.0)
+                .with_widget(color_space_label)
+                .with_widget(color_space_combobox),
+        )
+        .with_widget(full_range_checkbox);
+
+    let mut frame_column = column();
+
+    // Duplicate Frames Section
+    frame_column = dedup_section.add_to(frame_column);
+
+    // Frame Rate Section
+    frame_column = decimate_section.add_to(frame_column);
+
+    // Crop Section
+    frame_column = crop_section.add_to(frame_column);
+
+    // Resize Section
+    frame_column = resize_section.add_to(frame_column);
+
+    // Playback Section
+    frame_column = playback_section.add_to(frame_column);
+
+    // File Size Section
+    frame_column = max_file_size_section.add_to(frame_column);
+
+    let mut output_column = column();
+
+    // Metadata Section
+    output_column = metadata_section.add_to(output_column);
+
+    // Abort Section
+    output_column = on_abort_section.add_to(output_column);
+
     // Buttons Section
     let buttons_section = FlexLayout::row()
         .with_gap(10.0)
@@ -186,7 +407,17 @@ pub fn show_config_dialog(
         .with_widget(ok_button)
         .with_widget(cancel_button);
 
-    layout = layout.with_layout(buttons_section);
+    let layout = FlexLayout::column()
+        .with_padding(15.0)
+        .with_gap(10.0)
+        .with_layout(
+            FlexLayout::row()
+                .with_gap(15.0)
+                .with_layout(base_column)
+                .with_layout(frame_column)
+                .with_layout(output_column),
+        )
+        .with_layout(buttons_section);
 
     dialog = dialog.with_layout(layout);
 
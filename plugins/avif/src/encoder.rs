@@ -0,0 +1,171 @@
+//! libavifでのエンコード
+//!
+//! YUVに変換した画像にCICPとメタデータを設定するため、libavifのAPIを直接使う。
+//! 画像とエンコーダーはドロップ時に解放する。
+
+use crate::config::{ColorFormat, Config, YuvFormat};
+use libavif_sys::*;
+use std::ffi::CStr;
+
+/// libavifの関数の結果をエラーメッセージにする
+fn check(result: avifResult, message: &str) -> Result<(), String> {
+    if result as i32 == AVIF_RESULT_OK as i32 {
+        return Ok(());
+    }
+    let reason = unsafe { CStr::from_ptr(avifResultToString(result)) };
+    Err(format!("{}: {}", message, reason.to_string_lossy()))
+}
+
+/// libavifの画素形式
+fn pixel_format(format: YuvFormat) -> avifPixelFormat {
+    match format {
+        YuvFormat::Yuv420 => AVIF_PIXEL_FORMAT_YUV420,
+        YuvFormat::Yuv422 => AVIF_PIXEL_FORMAT_YUV422,
+        YuvFormat::Yuv444 => AVIF_PIXEL_FORMAT_YUV444,
+    }
+}
+
+/// YUVの画像
+pub struct YuvImage {
+    image: *mut avifImage,
+}
+
+impl YuvImage {
+    /// RGBの画素をYUVに変換する
+    ///
+    /// 変換に使う行列とレンジをCICPとして画像に設定するので、デコーダーは同じ行列でRGBに戻す
+    /// - `pixels`: 設定の色形式で並べた画素（8bit以外はネイティブエンディアンの16bit）
+    pub fn from_rgb(
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        config: &Config,
+    ) -> Result<Self, String> {
+        let depth = config.bit_depth.bits();
+        let (format, channels) = match config.color_format {
+            ColorFormat::Rgb24 => (AVIF_RGB_FORMAT_RGB, 3),
+            ColorFormat::Rgba32 => (AVIF_RGB_FORMAT_RGBA, 4),
+        };
+        let row_bytes = width * channels * if depth > 8 { 2 } else { 1 };
+        if pixels.len() < row_bytes as usize * height as usize {
+            return Err("YUV画像変換エラー: 画素のデータが足りません".into());
+        }
+
+        let image =
+            unsafe { avifImageCreate(width, height, depth, pixel_format(config.yuv_format)) };
+        if image.is_null() {
+            return Err("YUV画像変換エラー: 画像を作成できません".into());
+        }
+        // 以降のエラーではドロップで解放する
+        let yuv = Self { image };
+
+        let cicp = config.color_space.cicp();
+        unsafe {
+            (*image).colorPrimaries = cicp.primaries as _;
+            (*image).transferCharacteristics = cicp.transfer as _;
+            (*image).matrixCoefficients = cicp.matrix as _;
+            (*image).yuvRange = if config.full_range {
+                AVIF_RANGE_FULL
+            } else {
+                AVIF_RANGE_LIMITED
+            };
+
+            let mut rgb: avifRGBImage = std::mem::zeroed();
+            avifRGBImageSetDefaults(&mut rgb, image);
+            rgb.format = format;
+            // 変換では読み込むだけ
+            rgb.pixels = pixels.as_ptr() as *mut u8;
+            rgb.rowBytes = row_bytes;
+            check(avifImageRGBToYUV(image, &rgb), "YUV画像変換エラー")?;
+        }
+        Ok(yuv)
+    }
+
+    /// XMPとExifを付ける
+    ///
+    /// アニメーションでは最初のフレームの画像に付けるとXMP・Exifアイテムとして書き込まれる
+    pub fn set_metadata(&mut self, xmp: &[u8], exif: Option<&[u8]>) -> Result<(), String> {
+        unsafe {
+            check(
+                avifImageSetMetadataXMP(self.image, xmp.as_ptr(), xmp.len()),
+                "メタデータ設定エラー",
+            )?;
+            if let Some(exif) = exif {
+                check(
+                    avifImageSetMetadataExif(self.image, exif.as_ptr(), exif.len()),
+                    "メタデータ設定エラー",
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for YuvImage {
+    fn drop(&mut self) {
+        unsafe { avifImageDestroy(self.image) };
+    }
+}
+
+/// アニメーションのエンコーダー
+pub struct Encoder {
+    encoder: *mut avifEncoder,
+}
+
+impl Encoder {
+    /// 設定の品質・速度・ループ回数でエンコーダーを作る
+    /// - `timescale`: 1秒あたりの時間の単位数（フレームの表示時間はこの単位で指定する）
+    pub fn new(config: &Config, timescale: u64) -> Result<Self, String> {
+        let encoder = unsafe { avifEncoderCreate() };
+        if encoder.is_null() {
+            return Err("エンコーダー初期化エラー: エンコーダーを作成できません".into());
+        }
+        unsafe {
+            (*encoder).maxThreads = config.threads as _;
+            (*encoder).speed = config.speed as _;
+            (*encoder).quality = config.quality as _;
+            (*encoder).timescale = timescale;
+            // 設定の0は無限ループ、それ以外は最初の再生を含む回数
+            (*encoder).repetitionCount = match config.repeat {
+                0 => AVIF_REPETITION_COUNT_INFINITE,
+                n => (n - 1).min(i32::MAX as u32) as i32,
+            };
+        }
+        Ok(Self { encoder })
+    }
+
+    /// フレームを追加する
+    /// - `duration`: タイムスケール単位の表示時間
+    pub fn add_image(&mut self, image: &YuvImage, duration: u64) -> Result<(), String> {
+        let result = unsafe {
+            avifEncoderAddImage(
+                self.encoder,
+                image.image,
+                duration,
+                AVIF_ADD_IMAGE_FLAG_NONE as _,
+            )
+        };
+        check(result, "フレーム追加エラー")
+    }
+
+    /// エンコードを終え、AVIFのデータを返す
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        unsafe {
+            let mut output: avifRWData = std::mem::zeroed();
+            let result = avifEncoderFinish(self.encoder, &mut output);
+            let data = match result as i32 == AVIF_RESULT_OK as i32 {
+                true => std::slice::from_raw_parts(output.data, output.size).to_vec(),
+                false => Vec::new(),
+            };
+            avifRWDataFree(&mut output);
+            check(result, "エンコード完了エラー")?;
+            Ok(data)
+        }
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe { avifEncoderDestroy(self.encoder) };
+    }
+}
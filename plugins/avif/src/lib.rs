@@ -1,143 +1,174 @@
-mod config;
+pub mod config;
+#[cfg(feature = "plugin")]
 mod dialog;
+mod encoder;
+#[cfg(feature = "plugin")]
+mod plugin;
+
+use aviutl::abort;
+use aviutl::crop::CroppedSource;
+use aviutl::decimate::DecimatedSource;
+use aviutl::frame::FrameBuffer;
+use aviutl::metadata::Metadata;
+use aviutl::output_file::OutputFile;
+use aviutl::playback::PlaybackSource;
+use aviutl::resize::ResizedSource;
+use aviutl::size_limit::{SizeLimitReport, encode_within_size};
+use aviutl::source::FrameSource;
+use std::io::Write;
+use std::path::Path;
+
+use config::{BitDepth, ColorFormat, Config};
+use encoder::{Encoder, YuvImage};
+
+/// 16bitのサンプルを指定ビット数に丸めてネイティブエンディアンのバイト列にする
+fn to_ne_bytes<'a>(data: &[u16], bits: u32, bytes: &'a mut Vec<u8>) -> &'a mut [u8] {
+    let max = (1u32 << bits) - 1;
+    bytes.clear();
+    bytes.extend(
+        data.iter()
+            .flat_map(|&v| (((v as u32 * max + 32767) / 65535) as u16).to_ne_bytes()),
+    );
+    bytes
+}
 
-use aviutl::output2::{OutputInfo, OutputPluginTable};
-use rustavif::{BitDepth, Encoder, RgbFormat, RgbImage};
-use std::ffi::c_void;
-use widestring::{U16CStr, Utf16Str, utf16str};
-use win32_dialog::MessageBox;
-use windows::{Win32::Foundation::*, core::*};
-
-use config::{ColorFormat, Config};
-use dialog::show_config_dialog;
-
-fn create_avif_from_video(info: &OutputInfo, config: &Config) -> std::result::Result<(), String> {
-    let output_path = unsafe { U16CStr::from_ptr_str(info.savefile).to_string_lossy() };
-
-    let mut encoder = Encoder::new().map_err(|e| format!("エンコーダー初期化エラー: {}", e))?;
-    encoder.set_repetition_count(config.repeat);
-    encoder.set_timescale(info.rate as u64);
-    encoder.set_quality(config.quality);
-    encoder.set_speed(config.speed);
-    encoder.set_max_threads(config.threads as u32);
-
-    let width = info.w as u32;
-    let height = info.h as u32;
-    let num_frames = info.n as u32;
-
-    for frame in 0..num_frames {
-        if info.is_abort() {
-            return Err("処理が中断されました".into());
-        }
-
-        let image_data = match config.color_format {
-            ColorFormat::Rgb24 => info.get_video_rgb(frame as i32),
-            ColorFormat::Rgba32 => info.get_video_rgba(frame as i32),
-        };
-
-        if let Some(mut pixel_data) = image_data {
-            let rgb_pixels = RgbImage::from_pixels(
-                width,
-                height,
-                BitDepth::Eight,
-                RgbFormat::Rgba,
-                &mut pixel_data,
-            )
-            .map_err(|e| format!("RGBピクセル作成エラー: {}", e))?;
-
-            let image = rgb_pixels
-                .to_yuv_image(config.yuv_format.into())
-                .map_err(|e| format!("YUV画像変換エラー: {}", e))?;
-
-            encoder
-                .add_image(&image, info.scale as u64, Default::default())
-                .map_err(|e| format!("フレーム追加エラー: {}", e))?;
+/// 動画をAVIFとして出力する
+///
+/// ファイルサイズの上限が指定されている場合は、収まる品質を探して出力する
+/// - 戻り値: 上限が指定されている場合は、選んだ品質と出力したファイルサイズ
+pub fn create_avif_from_video(
+    source: &dyn FrameSource,
+    output_path: &Path,
+    config: &Config,
+) -> std::result::Result<Option<SizeLimitReport>, String> {
+    // 以降はフレームを間引き、切り取ってから出力サイズに拡大・縮小し、再生順を変えたフレームを読み込む
+    let decimated = DecimatedSource::new(source, config.decimate);
+    let cropped = CroppedSource::new(&decimated, config.crop)?;
+    let resized = ResizedSource::new(&cropped, config.resize);
+    let played = PlaybackSource::new(&resized, config.playback)?;
+    let metadata = config.metadata()?;
+
+    match config.size_limit() {
+        Some(limit) => {
+            let report = encode_within_size(
+                &played,
+                output_path,
+                &limit,
+                config.on_abort,
+                |source, path, value| {
+                    let config = Config {
+                        quality: value as u8,
+                        ..config.clone()
+                    };
+                    encode(source, path, &config, &metadata)
+                },
+            )?;
+            Ok(Some(report))
         }
-
-        info.rest_time_disp(frame as i32, num_frames as i32);
+        None => encode(&played, output_path, config, &metadata).map(|()| None),
     }
-
-    let data = encoder
-        .finish()
-        .map_err(|e| format!("エンコード完了エラー: {}", e))?;
-
-    std::fs::write(&output_path, data.as_slice())
-        .map_err(|e| format!("ファイル保存エラー: {}", e))?;
-
-    Ok(())
 }
 
-extern "C" fn output_func(oip: *mut OutputInfo) -> bool {
-    unsafe {
-        let info = match oip.as_ref() {
-            Some(info) => info,
-            None => return false,
-        };
-
-        let config = Config::load();
+/// 設定どおりにAVIFを出力する
+fn encode(
+    source: &dyn FrameSource,
+    output_path: &Path,
+    config: &Config,
+    metadata: &Metadata,
+) -> std::result::Result<(), String> {
+    let mut encoder = Encoder::new(config, source.rate() as u64)?;
+
+    let width = source.width() as u32;
+    let height = source.height() as u32;
+    let num_frames = source.frame_count() as u32;
+    let mut buffer = FrameBuffer::<u8>::new();
+    let mut buffer16 = FrameBuffer::<u16>::new();
+    let mut bytes = Vec::new();
+    // 8bitは出力する画素、それ以外は丸める前の16bitの画素で比較する
+    let mut detector = config.dedup.detector::<u8>();
+    let mut detector16 = config.dedup.detector::<u16>();
+    // 表示時間が決まるまで追加を待っているフレームと、まとめたフレーム数
+    let mut pending: Option<(_, u64)> = None;
+
+    let mut written = 0;
+    let mut aborted = false;
+
+    let mut add_image = |image: YuvImage, frames: u64| -> std::result::Result<(), String> {
+        encoder.add_image(&image, source.scale() as u64 * frames)?;
+        written += 1;
+        Ok(())
+    };
 
-        let result = match create_avif_from_video(info, &config) {
-            Ok(_) => true,
-            Err(e) => {
-                let error_msg = format!("AVIF出力エラー: {}", e);
-                MessageBox::error(None, &error_msg, "エラー");
-                false
+    for frame in 0..num_frames {
+        if source.is_abort() {
+            // 中断時も保存する場合は、それまでのフレームで出力を完成させる
+            if config.on_abort.finish {
+                aborted = true;
+                break;
             }
-        };
-
-        result
-    }
-}
+            return Err(abort::ABORTED.into());
+        }
 
-extern "C" fn config_func(hwnd: HWND, _dll_hinst: HINSTANCE) -> bool {
-    let default_config = Config::load();
+        let image_data = match (config.color_format, config.bit_depth) {
+            (ColorFormat::Rgb24, BitDepth::Eight) => source
+                .read_rgb(frame as i32, &mut buffer)
+                .is_some()
+                .then(|| buffer.as_mut_slice()),
+            (ColorFormat::Rgba32, BitDepth::Eight) => source
+                .read_rgba(frame as i32, config.alpha_mode(), &mut buffer)
+                .is_some()
+                .then(|| buffer.as_mut_slice()),
+            (ColorFormat::Rgb24, _) => source
+                .read_rgb16(frame as i32, &mut buffer16)
+                .map(|view| to_ne_bytes(view.data(), config.bit_depth.bits(), &mut bytes)),
+            (ColorFormat::Rgba32, _) => source
+                .read_rgba16(frame as i32, config.alpha_mode(), &mut buffer16)
+                .map(|view| to_ne_bytes(view.data(), config.bit_depth.bits(), &mut bytes)),
+        };
 
-    if let Ok(result) = show_config_dialog(hwnd, default_config) {
-        match result {
-            Some(config) => {
-                // 設定を保存
-                if let Err(e) = config.save() {
-                    let error_msg = format!("設定保存エラー: {}", e);
-                    MessageBox::warning(Some(hwnd), &error_msg, "警告");
+        if let Some(pixel_data) = image_data {
+            let is_duplicate = match config.bit_depth {
+                BitDepth::Eight => detector
+                    .as_mut()
+                    .is_some_and(|detector| detector.is_duplicate(pixel_data)),
+                _ => detector16
+                    .as_mut()
+                    .is_some_and(|detector| detector.is_duplicate(buffer16.as_slice())),
+            };
+
+            match &mut pending {
+                Some((_, frames)) if is_duplicate => *frames += 1,
+                _ => {
+                    // 書き込むCICPと同じ変換行列・レンジで変換する
+                    let mut image = YuvImage::from_rgb(width, height, pixel_data, config)?;
+
+                    // タイトルなどはXMPに書き込む
+                    if pending.is_none() {
+                        image.set_metadata(&metadata.xmp_packet(), metadata.exif.as_deref())?;
+                    }
+
+                    if let Some((previous, frames)) = pending.replace((image, 1)) {
+                        add_image(previous, frames)?;
+                    }
                 }
-                true
             }
-            None => false,
         }
-    } else {
-        MessageBox::error(Some(hwnd), "設定の取得に失敗しました。", "エラー");
-        false
-    }
-}
 
-const PLUGIN_NAME: &Utf16Str = utf16str!("AVIF出力プラグイン\0");
-const FILE_FILTER: &Utf16Str = utf16str!("AVIF Files (*.avif)\0*.avif\0All Files (*)\0*\0\0");
-const PLUGIN_INFO: &Utf16Str = utf16str!(concat!(
-    "AVIF出力プラグイン v",
-    env!("CARGO_PKG_VERSION"),
-    " by yu7400ki\0"
-));
-
-const fn init_plugin_table() -> OutputPluginTable {
-    OutputPluginTable {
-        flag: OutputPluginTable::FLAG_VIDEO,
-        name: PLUGIN_NAME.as_ptr(),
-        filefilter: FILE_FILTER.as_ptr(),
-        information: PLUGIN_INFO.as_ptr(),
-        func_output: Some(output_func),
-        func_config: Some(config_func),
-        func_get_config_text: None,
+        source.rest_time_disp(frame as i32, num_frames as i32);
     }
-}
 
-const OUTPUT_PLUGIN_TABLE: OutputPluginTable = init_plugin_table();
+    if let Some((image, frames)) = pending {
+        add_image(image, frames)?;
+    }
+    if aborted && written == 0 {
+        return Err(abort::ABORTED.into());
+    }
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn DllMain(_hinst: HINSTANCE, _reason: u32, _reserved: *mut c_void) -> BOOL {
-    TRUE
-}
+    let data = encoder.finish()?;
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn GetOutputPluginTable() -> *mut OutputPluginTable {
-    &OUTPUT_PLUGIN_TABLE as *const OutputPluginTable as *mut OutputPluginTable
+    let mut output_file = OutputFile::create(output_path)?;
+    output_file
+        .write_all(&data)
+        .map_err(|e| format!("ファイル保存エラー: {}", e))?;
+    output_file.commit()
 }
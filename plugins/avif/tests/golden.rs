@@ -0,0 +1,463 @@
+//! 合成アニメーションをAVIFに出力し、デコードした結果を入力と比較する
+
+use avif_output::config::{BitDepth, ColorFormat, Config, YuvFormat};
+use aviutl::abort::{self, OnAbort};
+use aviutl::color::ColorSpace;
+use aviutl::convert::AlphaMode;
+use aviutl::crop::{Crop, CropMode};
+use aviutl::decimate::Decimate;
+use aviutl::dedup::Dedup;
+use aviutl::metadata::MetadataSettings;
+use aviutl::playback::{Playback, PlaybackOrder};
+use aviutl::resize::{Resize, ResizeFilter};
+use aviutl::size_limit::MaxFileSize;
+use aviutl::source::MemoryFrameSource;
+use libavif_sys::*;
+use std::path::Path;
+use test_animations::TempPath;
+
+/// libavifで無限ループを表す繰り返し回数
+const REPETITION_COUNT_INFINITE: i32 = -1;
+
+struct Decoded {
+    width: u32,
+    height: u32,
+    /// 表示される画像（ストレートαのRGBA32）
+    frames: Vec<Vec<u8>>,
+    timescale: u64,
+    /// タイムスケール単位の表示時間
+    durations: Vec<u64>,
+    repetition_count: i32,
+}
+
+/// libavifのデコーダーでデコードし、8bitのRGBAに変換する
+fn decode(path: &Path) -> Decoded {
+    let data = std::fs::read(path).unwrap();
+
+    unsafe {
+        let decoder = avifDecoderCreate();
+        assert!(!decoder.is_null());
+        assert_eq!(
+            avifDecoderSetIOMemory(decoder, data.as_ptr(), data.len()) as i32,
+            0
+        );
+        assert_eq!(
+            avifDecoderParse(decoder) as i32,
+            0,
+            "AVIFの解析に失敗しました"
+        );
+
+        let mut width = 0;
+        let mut height = 0;
+        let mut frames = Vec::new();
+        let mut durations = Vec::new();
+        while avifDecoderNextImage(decoder) as i32 == 0 {
+            let image = (*decoder).image;
+            width = (*image).width;
+            height = (*image).height;
+
+            let mut rgb: avifRGBImage = std::mem::zeroed();
+            // 既定の出力はRGBA
+            avifRGBImageSetDefaults(&mut rgb, image);
+            rgb.depth = 8;
+            let _ = avifRGBImageAllocatePixels(&mut rgb);
+            assert_eq!(avifImageYUVToRGB(image, &mut rgb) as i32, 0);
+
+            let row_len = width as usize * 4;
+            let rgba: Vec<u8> = (0..height as usize)
+                .flat_map(|y| {
+                    let row = rgb.pixels.add(y * rgb.rowBytes as usize);
+                    std::slice::from_raw_parts(row, row_len).iter().copied()
+                })
+                .collect();
+            avifRGBImageFreePixels(&mut rgb);
+
+            frames.push(rgba);
+            durations.push((*decoder).imageTiming.durationInTimescales);
+        }
+
+        let decoded = Decoded {
+            width,
+            height,
+            frames,
+            timescale: (*decoder).timescale,
+            durations,
+            repetition_count: (*decoder).repetitionCount,
+        };
+        assert_eq!(decoded.frames.len(), (*decoder).imageCount as usize);
+        avifDecoderDestroy(decoder);
+        decoded
+    }
+}
+
+fn encode(source: &MemoryFrameSource, config: &Config, name: &str) -> Decoded {
+    let path = TempPath::new(name);
+    avif_output::create_avif_from_video(source, &path, config).unwrap();
+    decode(&path)
+}
+
+#[test]
+fn gradient_without_alpha() {
+    let source = test_animations::gradient();
+    let config = Config {
+        quality: 90,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-gradient.avif");
+
+    assert_eq!((decoded.width, decoded.height), (64, 48));
+    assert_eq!(decoded.repetition_count, REPETITION_COUNT_INFINITE);
+    // タイムスケールはrate、各フレームの長さはscale
+    assert_eq!(decoded.timescale, 30000);
+    assert_eq!(decoded.durations, [1001; 6]);
+    // 非可逆圧縮かつ色差を間引くので、グラデーションが折り返す急な境界では誤差が大きくなる
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decoded.frames,
+        96,
+        6.0,
+    );
+}
+
+#[test]
+fn moving_square_10bit_yuv444() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        quality: 90,
+        yuv_format: YuvFormat::Yuv444,
+        bit_depth: BitDepth::Ten,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square-10bit.avif");
+
+    assert_eq!(decoded.durations, [1001; 6]);
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decoded.frames,
+        64,
+        2.0,
+    );
+}
+
+#[test]
+fn alpha_fade_keeps_alpha() {
+    let source = test_animations::alpha_fade();
+    let config = Config {
+        quality: 90,
+        color_format: ColorFormat::Rgba32,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-fade.avif");
+
+    assert_eq!(decoded.durations, [1001; 6]);
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgba(&source, AlphaMode::Straight),
+        &decoded.frames,
+        64,
+        2.0,
+    );
+}
+
+#[test]
+fn slideshow_merges_duplicates() {
+    let source = test_animations::slideshow();
+    let config = Config {
+        quality: 90,
+        dedup: Dedup {
+            merge: true,
+            tolerance: 0,
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-slideshow.avif");
+
+    assert_eq!(decoded.durations, [3003, 2002, 1001]);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 3, 5]),
+        &decoded.frames,
+        64,
+        2.0,
+    );
+}
+
+#[test]
+fn moving_square_resized() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        quality: 90,
+        // 平均で半分にすると、正方形の端がそろっているので縮小した画像が計算で求まる
+        resize: Resize {
+            width: 32,
+            filter: ResizeFilter::Box,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square-resized.avif");
+
+    // 幅だけ指定すると縦横比を保って高さが決まる
+    assert_eq!((decoded.width, decoded.height), (32, 24));
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&test_animations::moving_square_halved()),
+        &decoded.frames,
+        64,
+        2.0,
+    );
+}
+
+#[test]
+fn moving_square_auto_trimmed() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        quality: 90,
+        crop: Crop {
+            mode: CropMode::Auto,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square-trimmed.avif");
+
+    // 全フレームで正方形が通った範囲だけが残る
+    let (_, _, width, height) = test_animations::SQUARE_BOUNDS;
+    assert_eq!(
+        (decoded.width as i32, decoded.height as i32),
+        (width, height)
+    );
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&test_animations::moving_square_trimmed()),
+        &decoded.frames,
+        64,
+        2.0,
+    );
+}
+
+#[test]
+fn gradient_decimated_to_10fps() {
+    let source = test_animations::gradient();
+    let config = Config {
+        quality: 90,
+        decimate: Decimate {
+            fps: 10,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-gradient-10fps.avif");
+
+    // 29.97fpsの0, 0.1, 0.2秒に表示されているフレーム
+    assert_eq!(decoded.timescale, 10);
+    assert_eq!(decoded.durations, [1; 3]);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 2, 5]),
+        &decoded.frames,
+        96,
+        6.0,
+    );
+}
+
+#[test]
+fn gradient_ping_pong() {
+    let source = test_animations::gradient();
+    let config = Config {
+        quality: 90,
+        playback: Playback {
+            order: PlaybackOrder::PingPong,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-gradient-pingpong.avif");
+
+    // 折り返しの両端のフレームは1回だけ表示する
+    assert_eq!(decoded.durations, [1001; 10]);
+    test_animations::assert_frames_close(
+        &test_animations::select(
+            &test_animations::expected_rgb(&source),
+            &[0, 1, 2, 3, 4, 5, 4, 3, 2, 1],
+        ),
+        &decoded.frames,
+        96,
+        6.0,
+    );
+}
+
+#[test]
+fn gradient_fits_in_max_file_size() {
+    let source = test_animations::gradient();
+    let full = TempPath::new("golden-size-full.avif");
+    avif_output::create_avif_from_video(&source, &full, &Config::default()).unwrap();
+    let full_size = std::fs::metadata(&*full).unwrap().len();
+
+    // 既定の設定で出力した大きさの半分を上限にする
+    let config = Config {
+        max_file_size: MaxFileSize {
+            kilobytes: (full_size / 2 / 1024).max(1) as u32,
+        },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-size-limit.avif");
+    let report = avif_output::create_avif_from_video(&source, &path, &config)
+        .unwrap()
+        .expect("上限を指定した場合は選んだ品質を返す");
+
+    let size = std::fs::metadata(&*path).unwrap().len();
+    assert_eq!(report.size, size);
+    assert!(size <= config.max_file_size.kilobytes as u64 * 1024);
+    assert!(report.encodes <= aviutl::size_limit::MAX_ENCODES);
+    // 探索に使った一時ファイルは残らない
+    let dir = path.parent().unwrap();
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(std::fs::read_dir(dir).unwrap().all(|entry| {
+        let entry = entry.unwrap().file_name();
+        let entry = entry.to_string_lossy();
+        entry == name || !entry.starts_with(name)
+    }));
+    assert_eq!(decode(&path).frames.len(), 6);
+}
+
+#[test]
+fn abort_keeps_the_previous_output() {
+    let source = test_animations::moving_square();
+    let path = TempPath::new("golden-abort.avif");
+    std::fs::write(&*path, b"previous").unwrap();
+
+    let aborting = test_animations::AbortingSource::new(&source, 3);
+    assert!(avif_output::create_avif_from_video(&aborting, &path, &Config::default()).is_err());
+    // 書きかけのファイルは残らず、前の出力も上書きされない
+    assert_eq!(std::fs::read(&*path).unwrap(), b"previous");
+    let dir = path.parent().unwrap();
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(std::fs::read_dir(dir).unwrap().all(|entry| {
+        let entry = entry.unwrap().file_name();
+        let entry = entry.to_string_lossy();
+        entry == name || !entry.starts_with(name)
+    }));
+}
+
+#[test]
+fn abort_finishes_with_encoded_frames() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        on_abort: OnAbort { finish: true },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-abort-finished.avif");
+
+    // 3フレーム出力したところで中断する
+    let aborting = test_animations::AbortingSource::new(&source, 3);
+    avif_output::create_avif_from_video(&aborting, &path, &config).unwrap();
+
+    let decoded = decode(&path);
+    assert_eq!(decoded.frames.len(), 3);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 1, 2]),
+        &decoded.frames,
+        64,
+        2.0,
+    );
+}
+
+#[test]
+fn abort_before_the_first_frame_keeps_the_previous_output() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        on_abort: OnAbort { finish: true },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-abort-empty.avif");
+    std::fs::write(&*path, b"previous").unwrap();
+
+    // 中断時も保存する指定でも、1フレームも出力しないうちに中断した場合は保存しない
+    let aborting = test_animations::AbortingSource::new(&source, 0);
+    let result = avif_output::create_avif_from_video(&aborting, &path, &config);
+    assert_eq!(result.unwrap_err(), abort::ABORTED);
+    assert_eq!(std::fs::read(&*path).unwrap(), b"previous");
+}
+
+#[test]
+fn metadata_in_exif_and_xmp_items() {
+    let source = test_animations::gradient();
+    let exif_path = TempPath::new("golden-metadata-avif.exif");
+    std::fs::write(&*exif_path, b"II*\0\x08\0\0\0\0\0").unwrap();
+    let config = Config {
+        metadata: MetadataSettings {
+            title: "Gradient".into(),
+            exif_path: exif_path.to_str().unwrap().into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-metadata.avif");
+    avif_output::create_avif_from_video(&source, &path, &config).unwrap();
+
+    // 解析したときの画像にExifとXMPのアイテムが読み込まれる
+    let data = std::fs::read(&*path).unwrap();
+    let (exif, xmp) = unsafe {
+        let decoder = avifDecoderCreate();
+        assert!(!decoder.is_null());
+        assert_eq!(
+            avifDecoderSetIOMemory(decoder, data.as_ptr(), data.len()) as i32,
+            0
+        );
+        assert_eq!(avifDecoderParse(decoder) as i32, 0);
+        let image = (*decoder).image;
+        let read = |data: avifRWData| std::slice::from_raw_parts(data.data, data.size).to_vec();
+        let metadata = (read((*image).exif), read((*image).xmp));
+        avifDecoderDestroy(decoder);
+        metadata
+    };
+    assert_eq!(exif, b"II*\0\x08\0\0\0\0\0");
+    let xmp = String::from_utf8(xmp).unwrap();
+    assert!(xmp.contains("<rdf:li xml:lang=\"x-default\">Gradient</rdf:li>"));
+    assert!(xmp.contains("<xmp:CreatorTool>AVIF出力プラグイン v"));
+
+    assert_eq!(decode(&path).frames.len(), 6);
+}
+
+#[test]
+fn bt709_limited_range_is_signalled() {
+    let source = test_animations::gradient();
+    let config = Config {
+        quality: 90,
+        yuv_format: YuvFormat::Yuv444,
+        color_space: ColorSpace::Bt709,
+        full_range: false,
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-color-bt709.avif");
+    avif_output::create_avif_from_video(&source, &path, &config).unwrap();
+
+    let data = std::fs::read(&*path).unwrap();
+    let cicp = unsafe {
+        let decoder = avifDecoderCreate();
+        assert!(!decoder.is_null());
+        assert_eq!(
+            avifDecoderSetIOMemory(decoder, data.as_ptr(), data.len()) as i32,
+            0
+        );
+        assert_eq!(avifDecoderParse(decoder) as i32, 0);
+        let image = (*decoder).image;
+        let cicp = (
+            (*image).colorPrimaries as u16,
+            (*image).transferCharacteristics as u16,
+            (*image).matrixCoefficients as u16,
+            (*image).yuvRange == AVIF_RANGE_LIMITED,
+        );
+        avifDecoderDestroy(decoder);
+        cicp
+    };
+    let expected = ColorSpace::Bt709.cicp();
+    assert_eq!(
+        cicp,
+        (expected.primaries, expected.transfer, expected.matrix, true)
+    );
+
+    // デコーダーは書き込んだ変換行列でRGBに戻すので、変換に同じ行列を使っていれば色がずれない
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decode(&path).frames,
+        96,
+        6.0,
+    );
+}
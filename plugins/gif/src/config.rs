@@ -1,10 +1,23 @@
+use aviutl::abort::OnAbort;
+use aviutl::convert::{AlphaMode, format_color, parse_color};
+use aviutl::crop::Crop;
+use aviutl::decimate::Decimate;
+use aviutl::dedup::Dedup;
+use aviutl::metadata::{Metadata, MetadataFiles, MetadataSettings};
+use aviutl::playback::Playback;
+use aviutl::resize::Resize;
+use aviutl::size_limit::{MaxFileSize, SizeLimit};
 use ini::Ini;
+#[cfg(feature = "plugin")]
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+#[cfg(feature = "plugin")]
 use windows::Win32::Foundation::{HMODULE, MAX_PATH};
+#[cfg(feature = "plugin")]
 use windows::Win32::System::LibraryLoader::{
     GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GetModuleFileNameW, GetModuleHandleExW,
 };
+#[cfg(feature = "plugin")]
 use windows::core::PCWSTR;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -49,11 +62,69 @@ impl ColorFormat {
     }
 }
 
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum AlphaHandling {
+    #[default]
+    Threshold,
+    Matte,
+}
+
+impl From<AlphaHandling> for &'static str {
+    fn from(handling: AlphaHandling) -> Self {
+        match handling {
+            AlphaHandling::Threshold => "しきい値で二値化",
+            AlphaHandling::Matte => "背景色に合成",
+        }
+    }
+}
+
+impl FromStr for AlphaHandling {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(AlphaHandling::Threshold),
+            Ok(1) => Ok(AlphaHandling::Matte),
+            _ => Err(()),
+        }
+    }
+}
+
+impl AlphaHandling {
+    fn to_index(self) -> u32 {
+        match self {
+            AlphaHandling::Threshold => 0,
+            AlphaHandling::Matte => 1,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     pub repeat: u16,
     pub color_format: ColorFormat,
+    pub alpha_handling: AlphaHandling,
+    pub alpha_threshold: u8,
+    pub matte_color: [u8; 3],
     pub speed: i32,
+    /// 重複フレームをまとめる指定
+    pub dedup: Dedup,
+    /// 出力サイズの指定
+    pub resize: Resize,
+    /// 切り取りの指定
+    pub crop: Crop,
+    /// フレームレートの指定
+    pub decimate: Decimate,
+    /// 再生速度と再生順
+    pub playback: Playback,
+    /// ファイルサイズの上限
+    pub max_file_size: MaxFileSize,
+    /// パレットの色数（透明色を含む、2-256）
+    pub colors: u16,
+    /// 中断されたときの出力の扱い
+    pub on_abort: OnAbort,
+    /// 埋め込むメタデータの項目
+    pub metadata: MetadataSettings,
 }
 
 impl Default for Config {
@@ -61,12 +132,28 @@ impl Default for Config {
         Self {
             repeat: 0,
             color_format: ColorFormat::default(),
+            alpha_handling: AlphaHandling::default(),
+            alpha_threshold: 128,
+            matte_color: [0, 0, 0],
             speed: 10,
+            dedup: Dedup::default(),
+            resize: Resize::default(),
+            crop: Crop::default(),
+            decimate: Decimate::default(),
+            playback: Playback::default(),
+            max_file_size: MaxFileSize::default(),
+            colors: 256,
+            on_abort: OnAbort::default(),
+            metadata: MetadataSettings::default(),
         }
     }
 }
 
 impl Config {
+    /// 埋め込めるメタデータのファイル
+    pub const METADATA_FILES: MetadataFiles = MetadataFiles::None;
+
+    #[cfg(feature = "plugin")]
     fn config_file_path() -> Result<PathBuf, String> {
         let (buffer, len) = unsafe {
             let mut hmodule: HMODULE = HMODULE::default();
@@ -95,6 +182,60 @@ impl Config {
         }
     }
 
+    /// 透過付き出力時のαチャンネルの扱い
+    pub fn alpha_mode(&self) -> AlphaMode {
+        match self.alpha_handling {
+            AlphaHandling::Threshold => AlphaMode::Threshold(self.alpha_threshold),
+            AlphaHandling::Matte => AlphaMode::Matte(self.matte_color),
+        }
+    }
+
+    /// 出力ファイルに埋め込むメタデータ
+    pub fn metadata(&self) -> Metadata {
+        // GIFにはコメント拡張だけを書き込む
+        self.metadata.to_text_metadata(concat!(
+            "GIF出力プラグイン v",
+            env!("CARGO_PKG_VERSION"),
+            " by yu7400ki"
+        ))
+    }
+
+    /// ファイルサイズの上限と、収まるように調整する色数の範囲（上限なしの場合はNone）
+    pub fn size_limit(&self) -> Option<SizeLimit> {
+        self.max_file_size.limit("色数", 2, 256)
+    }
+
+    /// 出力ダイアログに表示する設定の概要
+    pub fn summary(&self) -> String {
+        let repeat = match self.repeat {
+            0 => "ループ∞".to_string(),
+            n => format!("ループ{}回", n),
+        };
+        let color = match (self.color_format, self.alpha_handling) {
+            (ColorFormat::Rgb24, _) => "透過無し".to_string(),
+            (ColorFormat::Rgba32, AlphaHandling::Threshold) => {
+                format!("透過付き(しきい値{})", self.alpha_threshold)
+            }
+            (ColorFormat::Rgba32, AlphaHandling::Matte) => {
+                format!("透過付き(背景色{})", format_color(self.matte_color))
+            }
+        };
+        let mut summary = format!("GIF 速度{} {} {}", self.speed, repeat, color);
+        summary.push_str(&self.dedup.summary());
+        summary.push_str(&self.resize.summary());
+        summary.push_str(&self.crop.summary());
+        summary.push_str(&self.decimate.summary());
+        summary.push_str(&self.playback.summary());
+        summary.push_str(&self.max_file_size.summary());
+        if self.colors < 256 {
+            summary.push_str(&format!(" {}色", self.colors));
+        }
+        summary.push_str(&self.on_abort.summary());
+        summary.push_str(&self.metadata.summary(Self::METADATA_FILES));
+        summary
+    }
+
+    #[cfg(feature = "plugin")]
     pub fn load() -> Self {
         let default = Self::default();
 
@@ -107,10 +248,15 @@ impl Config {
             return default;
         }
 
-        let ini = match Ini::load_from_file(&config_path) {
-            Ok(ini) => ini,
-            Err(_) => return default,
-        };
+        match Ini::load_from_file(&config_path) {
+            Ok(ini) => Self::from_ini(&ini),
+            Err(_) => default,
+        }
+    }
+
+    /// iniの`[Config]`セクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn from_ini(ini: &Ini) -> Self {
+        let default = Self::default();
 
         let section = ini.section(Some("Config"));
 
@@ -124,27 +270,81 @@ impl Config {
             .and_then(|s| s.parse::<ColorFormat>().ok())
             .unwrap_or_default();
 
+        let alpha_handling = section
+            .and_then(|s| s.get("alpha_handling"))
+            .and_then(|s| s.parse::<AlphaHandling>().ok())
+            .unwrap_or_default();
+
+        let alpha_threshold = section
+            .and_then(|s| s.get("alpha_threshold"))
+            .and_then(|s| s.parse::<u8>().ok())
+            .unwrap_or(default.alpha_threshold);
+
+        let matte_color = section
+            .and_then(|s| s.get("matte_color"))
+            .and_then(parse_color)
+            .unwrap_or(default.matte_color);
+
         let speed = section
             .and_then(|s| s.get("speed"))
             .and_then(|s| s.parse::<i32>().ok())
             .unwrap_or(default.speed);
 
+        let colors = section
+            .and_then(|s| s.get("colors"))
+            .and_then(|s| s.parse::<u16>().ok())
+            .unwrap_or(default.colors)
+            .clamp(2, 256);
+
         Self {
             repeat,
             color_format,
+            alpha_handling,
+            alpha_threshold,
+            matte_color,
             speed,
+            dedup: Dedup::load_ini(section),
+            resize: Resize::load_ini(section),
+            crop: Crop::load_ini(section),
+            decimate: Decimate::load_ini(section),
+            playback: Playback::load_ini(section),
+            max_file_size: MaxFileSize::load_ini(section),
+            colors,
+            on_abort: OnAbort::load_ini(section),
+            metadata: MetadataSettings::load_ini(section),
         }
     }
 
+    #[cfg(feature = "plugin")]
     pub fn save(&self) -> Result<(), String> {
         let config_path = Self::config_file_path()?;
+        self.to_ini()
+            .write_to_file(&config_path)
+            .map_err(|e| e.to_string())
+    }
+
+    /// iniの`[Config]`セクションに書き出す
+    pub fn to_ini(&self) -> Ini {
         let mut ini = Ini::new();
 
-        ini.with_section(Some("Config"))
+        let mut section = ini.with_section(Some("Config"));
+        section
             .set("repeat", self.repeat.to_string())
             .set("color_format", self.color_format.to_index().to_string())
+            .set("alpha_handling", self.alpha_handling.to_index().to_string())
+            .set("alpha_threshold", self.alpha_threshold.to_string())
+            .set("matte_color", format_color(self.matte_color))
             .set("speed", self.speed.to_string());
+        self.dedup.save_ini(&mut section);
+        self.resize.save_ini(&mut section);
+        self.crop.save_ini(&mut section);
+        self.decimate.save_ini(&mut section);
+        self.playback.save_ini(&mut section);
+        self.max_file_size.save_ini(&mut section);
+        section.set("colors", self.colors.to_string());
+        self.on_abort.save_ini(&mut section);
+        self.metadata.save_ini(&mut section);
 
-        ini.write_to_file(&config_path).map_err(|e| e.to_string())
+        ini
     }
 }
@@ -1,4 +1,8 @@
-use crate::config::{ColorFormat, Config};
+use crate::config::{AlphaHandling, ColorFormat, Config};
+use aviutl::dialog::{
+    CropSection, DecimateSection, DedupSection, MatteSection, MaxFileSizeSection, MetadataSection,
+    OnAbortSection, PlaybackSection, ResizeSection,
+};
 use std::sync::{Arc, Mutex};
 use win32_dialog::widget::ComboBox;
 use win32_dialog::{
@@ -32,6 +36,42 @@ pub fn show_config_dialog(
         ColorFormat::Rgba32 => 1,
     });
 
+    let alpha_label = Label::new("半透明の扱い (透過付きのみ)");
+    let alpha_options = vec![AlphaHandling::Threshold.into(), AlphaHandling::Matte.into()];
+    let alpha_combobox =
+        ComboBox::new(alpha_options).selected(match default_config.alpha_handling {
+            AlphaHandling::Threshold => 0,
+            AlphaHandling::Matte => 1,
+        });
+
+    let threshold_label = Label::new("透過しきい値 (0-255)");
+    let threshold_input = Number::new()
+        .value(default_config.alpha_threshold as i32)
+        .range(0, 255);
+
+    let matte_section = MatteSection::new(default_config.matte_color);
+
+    let dedup_section = DedupSection::new(&default_config.dedup);
+
+    let resize_section = ResizeSection::new(&default_config.resize);
+
+    let crop_section = CropSection::new(&default_config.crop);
+
+    let decimate_section = DecimateSection::new(&default_config.decimate);
+
+    let playback_section = PlaybackSection::new(&default_config.playback);
+
+    let max_file_size_section = MaxFileSizeSection::new(&default_config.max_file_size);
+
+    let colors_label = Label::new("色数 (2-256)");
+    let colors_input = Number::new()
+        .value(default_config.colors as i32)
+        .range(2, 256);
+
+    let on_abort_section = OnAbortSection::new(&default_config.on_abort);
+
+    let metadata_section = MetadataSection::new(&default_config.metadata, Config::METADATA_FILES);
+
     let mut dialog = Dialog::new("GIF出力設定");
 
     let ok_button = Button::primary("OK").add_event_handler({
@@ -39,6 +79,18 @@ pub fn show_config_dialog(
         let repeat_input = repeat_input.clone();
         let speed_input = speed_input.clone();
         let color_combobox = color_combobox.clone();
+        let alpha_combobox = alpha_combobox.clone();
+        let threshold_input = threshold_input.clone();
+        let matte_section = matte_section.clone();
+        let dedup_section = dedup_section.clone();
+        let resize_section = resize_section.clone();
+        let crop_section = crop_section.clone();
+        let decimate_section = decimate_section.clone();
+        let playback_section = playback_section.clone();
+        let max_file_size_section = max_file_size_section.clone();
+        let colors_input = colors_input.clone();
+        let on_abort_section = on_abort_section.clone();
+        let metadata_section = metadata_section.clone();
         let dialog = dialog.clone();
         move |_: ButtonEvent| {
             let repeat = match repeat_input.get_value::<u16>() {
@@ -71,11 +123,113 @@ pub fn show_config_dialog(
                 _ => Default::default(),
             };
 
+            let alpha_handling = match alpha_combobox.get_selected_index() {
+                0 => AlphaHandling::Threshold,
+                1 => AlphaHandling::Matte,
+                _ => Default::default(),
+            };
+
+            let alpha_threshold = match threshold_input.get_value::<u8>() {
+                Ok(value) => value,
+                Err(_) => {
+                    MessageBox::error(
+                        Some(parent_hwnd),
+                        "透過しきい値の値が無効です。0-255の値を入力してください。",
+                        "エラー",
+                    );
+                    return;
+                }
+            };
+
+            let matte_color = match matte_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let dedup = match dedup_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let resize = match resize_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let crop = match crop_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let decimate = match decimate_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let playback = match playback_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let max_file_size = match max_file_size_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let colors = match colors_input.get_value::<u16>() {
+                Ok(value) if (2..=256).contains(&value) => value,
+                _ => {
+                    MessageBox::error(
+                        Some(parent_hwnd),
+                        "色数の値が無効です。2-256の値を入力してください。",
+                        "エラー",
+                    );
+                    return;
+                }
+            };
+
+            let on_abort = on_abort_section.value();
+
+            let metadata = metadata_section.value();
+
             if let Ok(mut guard) = result.lock() {
                 *guard = Some(Config {
                     repeat,
                     color_format,
+                    alpha_handling,
+                    alpha_threshold,
+                    matte_color,
                     speed,
+                    dedup,
+                    resize,
+                    crop,
+                    decimate,
+                    playback,
+                    max_file_size,
+                    colors,
+                    on_abort,
+                    metadata,
                 });
                 dialog.close();
             } else {
@@ -96,13 +250,16 @@ pub fn show_config_dialog(
     });
 
     // Create layout with sections
-    let mut layout = FlexLayout::column()
-        .with_width(SizeValue::Points(300.0))
-        .with_padding(15.0)
-        .with_gap(10.0);
+    // 1列では画面に収まらないので、基本・エンコード、フレーム・サイズ、メタデータ・中断の3列に分ける
+    let column = || {
+        FlexLayout::column()
+            .with_width(SizeValue::Points(300.0))
+            .with_gap(10.0)
+    };
+    let mut base_column = column();
 
     // Basic Settings Section
-    layout = layout
+    base_column = base_column
         .with_layout(
             FlexLayout::column()
                 .with_gap(5.0)
@@ -116,14 +273,66 @@ pub fn show_config_dialog(
                 .with_widget(speed_input),
         );
 
+    // Palette Section
+    base_column = base_column.with_layout(
+        FlexLayout::column()
+            .with_gap(5.0)
+            .with_widget(colors_label)
+            .with_widget(colors_input),
+    );
+
     // Color Format Section (only if RGBA feature is enabled)
-    layout = layout.with_layout(
+    base_column = base_column.with_layout(
         FlexLayout::column()
             .with_gap(5.0)
             .with_widget(color_label)
             .with_widget(color_combobox),
     );
 
+    // Alpha Section
+    base_column = base_column
+        .with_layout(
+            FlexLayout::column()
+                .with_gap(5.0)
+                .with_widget(alpha_label)
+                .with_widget(alpha_combobox),
+        )
+        .with_layout(
+            FlexLayout::column()
+                .with_gap(5.0)
+                .with_widget(threshold_label)
+                .with_widget(threshold_input),
+        );
+    base_column = matte_section.add_to(base_column);
+
+    let mut frame_column = column();
+
+    // Duplicate Frames Section
+    frame_column = dedup_section.add_to(frame_column);
+
+    // Frame Rate Section
+    frame_column = decimate_section.add_to(frame_column);
+
+    // Crop Section
+    frame_column = crop_section.add_to(frame_column);
+
+    // Resize Section
+    frame_column = resize_section.add_to(frame_column);
+
+    // Playback Section
+    frame_column = playback_section.add_to(frame_column);
+
+    // File Size Section
+    frame_column = max_file_size_section.add_to(frame_column);
+
+    let mut output_column = column();
+
+    // Metadata Section
+    output_column = metadata_section.add_to(output_column);
+
+    // Abort Section
+    output_column = on_abort_section.add_to(output_column);
+
     // Buttons Section
     let buttons_section = FlexLayout::row()
         .with_gap(10.0)
@@ -132,7 +341,17 @@ pub fn show_config_dialog(
         .with_widget(ok_button)
         .with_widget(cancel_button);
 
-    layout = layout.with_layout(buttons_section);
+    let layout = FlexLayout::column()
+        .with_padding(15.0)
+        .with_gap(10.0)
+        .with_layout(
+            FlexLayout::row()
+                .with_gap(15.0)
+                .with_layout(base_column)
+                .with_layout(frame_column)
+                .with_layout(output_column),
+        )
+        .with_layout(buttons_section);
 
     dialog = dialog.with_layout(layout);
 
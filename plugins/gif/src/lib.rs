@@ -1,23 +1,77 @@
-mod config;
+pub mod config;
+#[cfg(feature = "plugin")]
 mod dialog;
-
-use aviutl::output2::{OutputInfo, OutputPluginTable};
-use gif::{Encoder, Frame, Repeat};
-use std::ffi::c_void;
-use std::fs::File;
-use widestring::{U16CStr, Utf16Str, utf16str};
-use win32_dialog::MessageBox;
-use windows::{Win32::Foundation::*, core::*};
+mod optimize;
+#[cfg(feature = "plugin")]
+mod plugin;
+
+use aviutl::abort;
+use aviutl::crop::CroppedSource;
+use aviutl::decimate::DecimatedSource;
+use aviutl::frame::FrameBuffer;
+use aviutl::metadata::Metadata;
+use aviutl::output_file::OutputFile;
+use aviutl::playback::PlaybackSource;
+use aviutl::resize::ResizedSource;
+use aviutl::size_limit::{SizeLimitReport, encode_within_size};
+use aviutl::source::FrameSource;
+use aviutl::timing::FrameTiming;
+use gif::{Encoder, Extension, Repeat};
+use optimize::{FrameOptimizer, OptimizedFrame};
+use std::path::Path;
 
 use config::{ColorFormat, Config};
-use dialog::show_config_dialog;
 
-fn create_gif_from_video(info: &OutputInfo, config: &Config) -> std::result::Result<(), String> {
-    let output_path = unsafe { U16CStr::from_ptr_str(info.savefile).to_string_lossy() };
+/// 動画をGIFとして出力する
+///
+/// ファイルサイズの上限が指定されている場合は、収まる色数を探して出力する
+/// - 戻り値: 上限が指定されている場合は、選んだ色数と出力したファイルサイズ
+pub fn create_gif_from_video(
+    source: &dyn FrameSource,
+    output_path: &Path,
+    config: &Config,
+) -> std::result::Result<Option<SizeLimitReport>, String> {
+    // 以降はフレームを間引き、切り取ってから出力サイズに拡大・縮小し、再生順を変えたフレームを読み込む
+    let decimated = DecimatedSource::new(source, config.decimate);
+    let cropped = CroppedSource::new(&decimated, config.crop)?;
+    let resized = ResizedSource::new(&cropped, config.resize);
+    let played = PlaybackSource::new(&resized, config.playback)?;
+    let metadata = config.metadata();
+
+    match config.size_limit() {
+        Some(limit) => {
+            let report = encode_within_size(
+                &played,
+                output_path,
+                &limit,
+                config.on_abort,
+                |source, path, value| {
+                    let config = Config {
+                        colors: value as u16,
+                        ..config.clone()
+                    };
+                    encode(source, path, &config, &metadata)
+                },
+            )?;
+            Ok(Some(report))
+        }
+        None => encode(&played, output_path, config, &metadata).map(|()| None),
+    }
+}
 
-    let output_file =
-        File::create(&output_path).map_err(|e| format!("ファイル作成エラー: {}", e))?;
-    let mut encoder = Encoder::new(output_file, info.w as u16, info.h as u16, &[])
+/// 設定どおりにGIFを出力する
+fn encode(
+    source: &dyn FrameSource,
+    output_path: &Path,
+    config: &Config,
+    metadata: &Metadata,
+) -> std::result::Result<(), String> {
+    let width = source.width() as u16;
+    let height = source.height() as u16;
+    let num_frames = source.frame_count();
+
+    let mut output_file = OutputFile::create(output_path)?;
+    let mut encoder = Encoder::new(&mut output_file, width, height, &[])
         .map_err(|e| format!("エンコーダー初期化エラー: {}", e))?;
     // 設定を取得
     let repeat_setting = if config.repeat == 0 {
@@ -30,114 +84,82 @@ fn create_gif_from_video(info: &OutputInfo, config: &Config) -> std::result::Res
         .set_repeat(repeat_setting)
         .map_err(|e| format!("ループ設定エラー: {}", e))?;
 
-    for frame in 0..info.n {
-        if info.is_abort() {
-            return Err("処理が中断されました".into());
+    // メタデータはコメント拡張に1行ずつ書き込む
+    encoder
+        .write_raw_extension(
+            Extension::Comment.into(),
+            &[metadata.comment_text().as_bytes()],
+        )
+        .map_err(|e| format!("メタデータ書き込みエラー: {}", e))?;
+
+    let mut buffer = FrameBuffer::new();
+    // GIFの表示時間は1/100秒単位
+    let mut timing = FrameTiming::new(source.rate(), source.scale(), 100).with_min_delay(1);
+    let mut detector = config.dedup.detector();
+    let mut optimizer = FrameOptimizer::new(width as usize, height as usize);
+
+    let mut written = 0;
+    let mut aborted = false;
+    let mut write_frame = |optimized: OptimizedFrame| -> std::result::Result<(), String> {
+        let mut gif_frame = optimized.to_gif_frame(config.speed, config.colors as usize);
+        gif_frame.delay = timing.next_delay_for(optimized.frames).min(u16::MAX as u64) as u16;
+        encoder
+            .write_frame(&gif_frame)
+            .map_err(|e| format!("フレーム書き込みエラー: {}", e))?;
+        written += 1;
+        Ok(())
+    };
+
+    for frame in 0..num_frames {
+        if source.is_abort() {
+            // 中断時も保存する場合は、それまでのフレームで出力を完成させる
+            if config.on_abort.finish {
+                aborted = true;
+                break;
+            }
+            return Err(abort::ABORTED.into());
         }
 
         let image_data = match config.color_format {
-            ColorFormat::Rgb24 => info.get_video_rgb(frame),
-            ColorFormat::Rgba32 => info.get_video_rgba(frame),
+            ColorFormat::Rgb24 => source.read_rgb(frame, &mut buffer),
+            ColorFormat::Rgba32 => source.read_rgba(frame, config.alpha_mode(), &mut buffer),
         };
 
-        if let Some(image_data) = image_data {
-            let mut gif_frame = match config.color_format {
-                ColorFormat::Rgb24 => {
-                    Frame::from_rgb_speed(info.w as u16, info.h as u16, &image_data, config.speed)
+        if let Some(view) = image_data {
+            let is_duplicate = detector
+                .as_mut()
+                .is_some_and(|detector| detector.is_duplicate(view.data()));
+
+            if is_duplicate {
+                optimizer.extend_pending();
+            } else {
+                let rgba = match config.color_format {
+                    ColorFormat::Rgb24 => view
+                        .data()
+                        .chunks_exact(3)
+                        .flat_map(|p| [p[0], p[1], p[2], 255])
+                        .collect(),
+                    ColorFormat::Rgba32 => view.data().to_vec(),
+                };
+                // 直前のフレームは、このフレームとの差分で破棄方法が決まってから書き込む
+                if let Some(optimized) = optimizer.push(rgba) {
+                    write_frame(optimized)?;
                 }
-                ColorFormat::Rgba32 => Frame::from_rgba_speed(
-                    info.w as u16,
-                    info.h as u16,
-                    &mut image_data.clone(),
-                    config.speed,
-                ),
-            };
-
-            gif_frame.dispose = gif::DisposalMethod::Background;
-            let delay = (100.0 * info.scale as f64 / info.rate as f64).round() as u16;
-            gif_frame.delay = delay.max(1);
-
-            encoder
-                .write_frame(&gif_frame)
-                .map_err(|e| format!("フレーム書き込みエラー: {}", e))?;
-        }
-
-        info.rest_time_disp(frame, info.n);
-    }
-    Ok(())
-}
-
-extern "C" fn config_func(hwnd: HWND, _dll_hinst: HINSTANCE) -> bool {
-    let default_config = Config::load();
-
-    if let Ok(result) = show_config_dialog(hwnd, default_config) {
-        match result {
-            Some(config) => {
-                // 設定を保存
-                if let Err(e) = config.save() {
-                    let error_msg = format!("設定保存エラー: {}", e);
-                    MessageBox::warning(Some(hwnd), &error_msg, "警告");
-                }
-                true
             }
-            None => false,
         }
-    } else {
-        MessageBox::error(Some(hwnd), "設定の取得に失敗しました。", "エラー");
-        false
-    }
-}
-
-extern "C" fn output_func(oip: *mut OutputInfo) -> bool {
-    unsafe {
-        let info = match oip.as_ref() {
-            Some(info) => info,
-            None => return false,
-        };
-
-        let config = Config::load();
-
-        let result = match create_gif_from_video(info, &config) {
-            Ok(_) => true,
-            Err(e) => {
-                let error_msg = format!("GIF出力エラー: {}", e);
-                MessageBox::error(None, &error_msg, "エラー");
-                false
-            }
-        };
 
-        result
+        source.rest_time_disp(frame, num_frames);
     }
-}
 
-const PLUGIN_NAME: &Utf16Str = utf16str!("GIF出力プラグイン\0");
-const FILE_FILTER: &Utf16Str = utf16str!("GIF Files (*.gif)\0*.gif\0All Files (*)\0*\0\0");
-const PLUGIN_INFO: &Utf16Str = utf16str!(concat!(
-    "GIF出力プラグイン v",
-    env!("CARGO_PKG_VERSION"),
-    " by yu7400ki\0"
-));
-
-const fn init_plugin_table() -> OutputPluginTable {
-    OutputPluginTable {
-        flag: OutputPluginTable::FLAG_VIDEO,
-        name: PLUGIN_NAME.as_ptr(),
-        filefilter: FILE_FILTER.as_ptr(),
-        information: PLUGIN_INFO.as_ptr(),
-        func_output: Some(output_func),
-        func_config: Some(config_func),
-        func_get_config_text: None,
+    if let Some(optimized) = optimizer.finish() {
+        write_frame(optimized)?;
     }
-}
-
-const OUTPUT_PLUGIN_TABLE: OutputPluginTable = init_plugin_table();
-
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn DllMain(_hinst: HINSTANCE, _reason: u32, _reserved: *mut c_void) -> BOOL {
-    TRUE
-}
+    if aborted && written == 0 {
+        return Err(abort::ABORTED.into());
+    }
+    encoder
+        .into_inner()
+        .map_err(|e| format!("エンコーダー終了エラー: {}", e))?;
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn GetOutputPluginTable() -> *mut OutputPluginTable {
-    &OUTPUT_PLUGIN_TABLE as *const OutputPluginTable as *mut OutputPluginTable
+    output_file.commit()
 }
@@ -0,0 +1,356 @@
+//! 直前のフレームから変化した領域だけを書き込む最適化
+//!
+//! 各フレームを変化した範囲に切り詰め、直前と同じ画素は透明色にして下の画像をそのまま見せる。
+//! 同じ色が続くのでLZWで圧縮しやすくなる。
+
+use color_quant::NeuQuant;
+use gif::{DisposalMethod, Frame};
+use std::collections::{HashMap, HashSet};
+
+/// フレームを書き込む領域
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Rect {
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Rect {
+    fn union(self, other: Rect) -> Rect {
+        let left = self.left.min(other.left);
+        let top = self.top.min(other.top);
+        let right = (self.left + self.width).max(other.left + other.width);
+        let bottom = (self.top + self.height).max(other.top + other.height);
+        Rect {
+            left,
+            top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+}
+
+/// 書き込むフレーム
+pub struct OptimizedFrame {
+    left: u16,
+    top: u16,
+    width: u16,
+    height: u16,
+    /// 領域内の画素（RGBA32）。直前と同じ画素は完全透明
+    rgba: Vec<u8>,
+    dispose: DisposalMethod,
+    /// このフレームにまとめたフレーム数
+    pub frames: u64,
+}
+
+impl OptimizedFrame {
+    /// 減色してGIFのフレームにする
+    ///
+    /// 透明な画素がある場合は透明色のパレット番号を1つ確保し、不透明な画素だけで残りの色数に減色する
+    /// - colors: パレットの色数（透明色を含む）
+    pub fn to_gif_frame(&self, speed: i32, colors: usize) -> Frame<'static> {
+        let opaque: Vec<u8> = self
+            .rgba
+            .chunks_exact(4)
+            .filter(|pixel| pixel[3] != 0)
+            .flatten()
+            .copied()
+            .collect();
+        let has_transparent = opaque.len() < self.rgba.len();
+        let max_colors = colors
+            .min(256)
+            .saturating_sub(has_transparent as usize)
+            .max(1);
+
+        // 色数が収まる場合はそのままパレットにし、収まらない場合はNeuQuantで減色する
+        let mut colors = HashSet::new();
+        let fits = opaque.chunks_exact(4).all(|pixel| {
+            colors.insert([pixel[0], pixel[1], pixel[2]]);
+            colors.len() <= max_colors
+        });
+
+        let (mut palette, indices): (Vec<u8>, Vec<u8>) = if fits {
+            let mut colors: Vec<[u8; 3]> = colors.into_iter().collect();
+            colors.sort_unstable();
+            let lookup: HashMap<[u8; 3], u8> = colors.iter().copied().zip(0..=255).collect();
+            let indices = opaque
+                .chunks_exact(4)
+                .map(|pixel| lookup[&[pixel[0], pixel[1], pixel[2]]])
+                .collect();
+            (colors.concat(), indices)
+        } else {
+            let quantizer = NeuQuant::new(speed, max_colors, &opaque);
+            let indices = opaque
+                .chunks_exact(4)
+                .map(|pixel| quantizer.index_of(pixel) as u8)
+                .collect();
+            (quantizer.color_map_rgb(), indices)
+        };
+
+        let transparent = has_transparent.then(|| {
+            let index = (palette.len() / 3) as u8;
+            palette.extend_from_slice(&[0, 0, 0]);
+            index
+        });
+
+        let mut indices = indices.into_iter();
+        let buffer: Vec<u8> = self
+            .rgba
+            .chunks_exact(4)
+            .map(|pixel| match transparent {
+                Some(index) if pixel[3] == 0 => index,
+                _ => indices.next().unwrap(),
+            })
+            .collect();
+
+        let mut frame =
+            Frame::from_palette_pixels(self.width, self.height, buffer, palette, transparent);
+        frame.left = self.left;
+        frame.top = self.top;
+        frame.dispose = self.dispose;
+        frame
+    }
+}
+
+/// 書き込みを待っているフレーム
+struct Pending {
+    /// フレーム全体の画素
+    rgba: Vec<u8>,
+    rect: Rect,
+    frames: u64,
+}
+
+/// 各フレームの書き込む領域と破棄方法を決める
+pub struct FrameOptimizer {
+    width: usize,
+    height: usize,
+    /// 書き込みを待っているフレームを描く前のキャンバス
+    canvas: Vec<u8>,
+    pending: Option<Pending>,
+    /// 最初のフレームを受け取ったか
+    started: bool,
+}
+
+impl FrameOptimizer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            canvas: vec![0; width * height * 4],
+            pending: None,
+            started: false,
+        }
+    }
+
+    /// 次のフレーム（RGBA32、αは0か255として扱う）を追加する
+    ///
+    /// 直前のフレームの破棄方法はこのフレームで決まるので、確定した直前のフレームを返す
+    pub fn push(&mut self, mut rgba: Vec<u8>) -> Option<OptimizedFrame> {
+        // 完全透明の画素は色を揃え、それ以外は不透明にする
+        for pixel in rgba.chunks_exact_mut(4) {
+            if pixel[3] == 0 {
+                pixel.fill(0);
+            } else {
+                pixel[3] = 255;
+            }
+        }
+
+        let optimized = self
+            .pending
+            .take()
+            .map(|pending| self.flush(pending, Some(&rgba)));
+
+        // 最初のフレームは画像全体を書き込む
+        let rect = if self.started {
+            self.changed_rect(&rgba)
+        } else {
+            self.full_rect()
+        };
+        self.started = true;
+        self.pending = Some(Pending {
+            rgba,
+            rect,
+            frames: 1,
+        });
+        optimized
+    }
+
+    /// 書き込みを待っているフレームの表示時間を1フレーム延ばす
+    pub fn extend_pending(&mut self) {
+        if let Some(pending) = &mut self.pending {
+            pending.frames += 1;
+        }
+    }
+
+    /// 最後のフレームを返す
+    pub fn finish(&mut self) -> Option<OptimizedFrame> {
+        self.pending.take().map(|pending| self.flush(pending, None))
+    }
+
+    fn full_rect(&self) -> Rect {
+        Rect {
+            left: 0,
+            top: 0,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// 条件を満たす画素を囲む領域
+    fn bounding_rect(&self, mut matches: impl FnMut(usize) -> bool) -> Option<Rect> {
+        let (mut left, mut top, mut right, mut bottom) = (self.width, self.height, 0, 0);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if matches((y * self.width + x) * 4) {
+                    left = left.min(x);
+                    right = right.max(x + 1);
+                    top = top.min(y);
+                    bottom = bottom.max(y + 1);
+                }
+            }
+        }
+        (left < right).then(|| Rect {
+            left,
+            top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+
+    /// キャンバスから変化した領域（変化が無い場合も空のフレームは書き込めないので左上の1画素）
+    fn changed_rect(&self, rgba: &[u8]) -> Rect {
+        self.bounding_rect(|i| self.canvas[i..i + 4] != rgba[i..i + 4])
+            .unwrap_or(Rect {
+                left: 0,
+                top: 0,
+                width: 1,
+                height: 1,
+            })
+    }
+
+    /// 書き込みを待っているフレームの破棄方法を決めて、書き込む内容にする
+    /// - next: 次のフレームの画素
+    fn flush(&mut self, pending: Pending, next: Option<&[u8]>) -> OptimizedFrame {
+        let Pending {
+            rgba,
+            mut rect,
+            frames,
+        } = pending;
+
+        // 次のフレームで透明になる画素があれば、その範囲まで広げて書き込み、表示後に消す
+        let cleared =
+            next.and_then(|next| self.bounding_rect(|i| next[i + 3] == 0 && rgba[i + 3] != 0));
+        let dispose = match cleared {
+            Some(cleared) => {
+                rect = rect.union(cleared);
+                DisposalMethod::Background
+            }
+            None => DisposalMethod::Keep,
+        };
+
+        let mut data = Vec::with_capacity(rect.width * rect.height * 4);
+        for y in rect.top..rect.top + rect.height {
+            let start = (y * self.width + rect.left) * 4;
+            let end = start + rect.width * 4;
+            for (pixel, below) in rgba[start..end]
+                .chunks_exact(4)
+                .zip(self.canvas[start..end].chunks_exact(4))
+            {
+                if pixel == below {
+                    data.extend_from_slice(&[0; 4]);
+                } else {
+                    data.extend_from_slice(pixel);
+                }
+            }
+        }
+
+        self.canvas = rgba;
+        if dispose == DisposalMethod::Background {
+            for y in rect.top..rect.top + rect.height {
+                let start = (y * self.width + rect.left) * 4;
+                self.canvas[start..start + rect.width * 4].fill(0);
+            }
+        }
+
+        OptimizedFrame {
+            left: rect.left as u16,
+            top: rect.top as u16,
+            width: rect.width as u16,
+            height: rect.height as u16,
+            rgba: data,
+            dispose,
+            frames,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transparent_index_is_reserved_when_quantizing() {
+        // 256色を超える不透明な画素と透明な画素
+        let mut rgba: Vec<u8> = (0..300u32)
+            .flat_map(|i| [(i % 256) as u8, (i / 2) as u8, 0, 255])
+            .collect();
+        rgba.extend_from_slice(&[0; 4 * 20]);
+        let optimized = OptimizedFrame {
+            left: 0,
+            top: 0,
+            width: 320,
+            height: 1,
+            rgba,
+            dispose: DisposalMethod::Keep,
+            frames: 1,
+        };
+
+        let frame = optimized.to_gif_frame(10, 256);
+        let transparent = frame.transparent.unwrap();
+        assert_eq!(transparent, 255);
+        assert!(frame.buffer[..300].iter().all(|&i| i != transparent));
+        assert!(frame.buffer[300..].iter().all(|&i| i == transparent));
+    }
+
+    #[test]
+    fn palette_is_limited_to_the_color_count() {
+        let rgba: Vec<u8> = (0..64u8)
+            .flat_map(|i| [i * 4, 255 - i * 4, i, if i < 8 { 0 } else { 255 }])
+            .collect();
+        let optimized = OptimizedFrame {
+            left: 0,
+            top: 0,
+            width: 64,
+            height: 1,
+            rgba,
+            dispose: DisposalMethod::Keep,
+            frames: 1,
+        };
+
+        // 透明色を含めて16色
+        let frame = optimized.to_gif_frame(10, 16);
+        assert_eq!(frame.palette.as_ref().unwrap().len(), 16 * 3);
+        assert_eq!(frame.transparent, Some(15));
+        assert!(frame.buffer.iter().all(|&i| i < 16));
+    }
+
+    #[test]
+    fn clears_pixels_that_become_transparent() {
+        let mut optimizer = FrameOptimizer::new(2, 1);
+        assert!(
+            optimizer
+                .push(vec![255, 0, 0, 255, 0, 255, 0, 255])
+                .is_none()
+        );
+
+        let first = optimizer.push(vec![0, 0, 0, 0, 0, 255, 0, 255]).unwrap();
+        assert_eq!(first.dispose, DisposalMethod::Background);
+
+        // 消した後のキャンバスとの差分なので、変化の無い右の画素も書き込む
+        let second = optimizer.finish().unwrap();
+        assert_eq!(second.dispose, DisposalMethod::Keep);
+        assert_eq!((second.left, second.width), (1, 1));
+        assert_eq!(second.rgba, [0, 255, 0, 255]);
+    }
+}
@@ -0,0 +1,421 @@
+//! 合成アニメーションをGIFに出力し、デコードした結果を入力と比較する
+
+use aviutl::abort::{self, OnAbort};
+use aviutl::convert::AlphaMode;
+use aviutl::crop::{Crop, CropMode};
+use aviutl::decimate::Decimate;
+use aviutl::dedup::Dedup;
+use aviutl::metadata::MetadataSettings;
+use aviutl::playback::{Playback, PlaybackOrder};
+use aviutl::resize::{Resize, ResizeFilter};
+use aviutl::size_limit::MaxFileSize;
+use aviutl::source::MemoryFrameSource;
+use gif::{ColorOutput, DecodeOptions, DisposalMethod, Repeat};
+use gif_output::config::{AlphaHandling, ColorFormat, Config};
+use std::fs::File;
+use std::path::Path;
+use test_animations::{Blend, Canvas, TempPath};
+
+/// 29.97fpsを1/100秒単位にした表示時間
+const DELAYS: [u16; 6] = [3, 3, 4, 3, 3, 4];
+
+struct Decoded {
+    width: u16,
+    height: u16,
+    /// 表示される画像（ストレートαのRGBA32）
+    frames: Vec<Vec<u8>>,
+    delays: Vec<u16>,
+    /// (left, top, 幅, 高さ)
+    regions: Vec<(u16, u16, u16, u16)>,
+    repeat: Repeat,
+}
+
+/// GIFをデコードし、フレームを合成して表示される画像を求める
+fn decode(path: &Path) -> Decoded {
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::RGBA);
+    let mut decoder = options.read_info(File::open(path).unwrap()).unwrap();
+    let (width, height) = (decoder.width(), decoder.height());
+
+    let mut canvas = Canvas::new(width as usize, height as usize);
+    let mut frames = Vec::new();
+    let mut delays = Vec::new();
+    let mut regions = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        let area = (
+            frame.left as usize,
+            frame.top as usize,
+            frame.width as usize,
+            frame.height as usize,
+        );
+        let previous = canvas.clone();
+
+        // 透明色の画素はα=0になるので、重ねると下の画像が残る
+        canvas.draw(area.0, area.1, area.2, area.3, &frame.buffer, Blend::Over);
+        frames.push(canvas.pixels().to_vec());
+        delays.push(frame.delay);
+        regions.push((frame.left, frame.top, frame.width, frame.height));
+
+        match frame.dispose {
+            DisposalMethod::Background => canvas.clear(area.0, area.1, area.2, area.3),
+            DisposalMethod::Previous => canvas = previous,
+            DisposalMethod::Any | DisposalMethod::Keep => {}
+        }
+    }
+
+    Decoded {
+        width,
+        height,
+        frames,
+        delays,
+        regions,
+        repeat: decoder.repeat(),
+    }
+}
+
+fn encode(source: &MemoryFrameSource, config: &Config, name: &str) -> Decoded {
+    let path = TempPath::new(name);
+    gif_output::create_gif_from_video(source, &path, config).unwrap();
+    decode(&path)
+}
+
+#[test]
+fn gradient_without_alpha() {
+    let source = test_animations::gradient();
+    let decoded = encode(&source, &Config::default(), "golden-gradient.gif");
+
+    assert_eq!((decoded.width, decoded.height), (64, 48));
+    assert_eq!(decoded.delays, DELAYS);
+    assert_eq!(decoded.repeat, Repeat::Infinite);
+    // 256色に減色するので誤差は大きめに許容する
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decoded.frames,
+        96,
+        10.0,
+    );
+}
+
+#[test]
+fn moving_square_with_finite_repeat() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        repeat: 3,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square.gif");
+
+    assert_eq!(decoded.frames.len(), 6);
+    assert_eq!(decoded.delays, DELAYS);
+    // ループ回数は再生回数なので、繰り返しは1回少ない
+    assert_eq!(decoded.repeat, Repeat::Finite(2));
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decoded.frames,
+        2,
+        0.1,
+    );
+}
+
+#[test]
+fn moving_square_writes_changed_regions() {
+    let source = test_animations::moving_square();
+    let decoded = encode(&source, &Config::default(), "golden-square-optimized.gif");
+
+    // 最初のフレームは画像全体、以降は正方形が動いた範囲（移動前後を合わせた24x20）だけ
+    assert_eq!(decoded.regions[0], (0, 0, 64, 48));
+    for (frame, &region) in decoded.regions.iter().enumerate().skip(1) {
+        let (left, top) = (4 + (frame as u16 - 1) * 8, 4 + (frame as u16 - 1) * 4);
+        assert_eq!(region, (left, top, 24, 20));
+    }
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decoded.frames,
+        2,
+        0.1,
+    );
+}
+
+#[test]
+fn alpha_fade_with_threshold() {
+    let source = test_animations::alpha_fade();
+    let config = Config {
+        color_format: ColorFormat::Rgba32,
+        alpha_handling: AlphaHandling::Threshold,
+        alpha_threshold: 128,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-fade-threshold.gif");
+
+    assert_eq!(decoded.frames.len(), 6);
+    assert_eq!(decoded.delays, DELAYS);
+    // 透明かどうかが入力と違えば、誤差は上限を大きく超える
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgba(&source, AlphaMode::Threshold(128)),
+        &decoded.frames,
+        2,
+        0.1,
+    );
+}
+
+#[test]
+fn alpha_fade_with_matte() {
+    let source = test_animations::alpha_fade();
+    let config = Config {
+        color_format: ColorFormat::Rgba32,
+        alpha_handling: AlphaHandling::Matte,
+        matte_color: [255, 255, 255],
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-fade-matte.gif");
+
+    assert_eq!(decoded.frames.len(), 6);
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgba(&source, AlphaMode::Matte([255, 255, 255])),
+        &decoded.frames,
+        2,
+        0.1,
+    );
+}
+
+#[test]
+fn slideshow_merges_duplicates() {
+    let source = test_animations::slideshow();
+    let config = Config {
+        dedup: Dedup {
+            merge: true,
+            tolerance: 0,
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-slideshow.gif");
+
+    // 3, 2, 1フレーム分の表示時間（終了時刻10, 16, 20から求める）
+    assert_eq!(decoded.delays, [10, 6, 4]);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 3, 5]),
+        &decoded.frames,
+        2,
+        0.1,
+    );
+}
+
+#[test]
+fn moving_square_resized() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        // 平均で半分にすると、正方形の端がそろっているので縮小した画像が計算で求まる
+        resize: Resize {
+            width: 32,
+            filter: ResizeFilter::Box,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square-resized.gif");
+
+    // 幅だけ指定すると縦横比を保って高さが決まる
+    assert_eq!((decoded.width, decoded.height), (32, 24));
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&test_animations::moving_square_halved()),
+        &decoded.frames,
+        2,
+        0.1,
+    );
+}
+
+#[test]
+fn moving_square_auto_trimmed() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        crop: Crop {
+            mode: CropMode::Auto,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square-trimmed.gif");
+
+    // 全フレームで正方形が通った範囲だけが残る
+    let (_, _, width, height) = test_animations::SQUARE_BOUNDS;
+    assert_eq!(
+        (decoded.width as i32, decoded.height as i32),
+        (width, height)
+    );
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&test_animations::moving_square_trimmed()),
+        &decoded.frames,
+        2,
+        0.1,
+    );
+}
+
+#[test]
+fn gradient_decimated_to_10fps() {
+    let source = test_animations::gradient();
+    let config = Config {
+        decimate: Decimate {
+            fps: 10,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-gradient-10fps.gif");
+
+    // 29.97fpsの0, 0.1, 0.2秒に表示されているフレーム
+    assert_eq!(decoded.delays, [10; 3]);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 2, 5]),
+        &decoded.frames,
+        96,
+        10.0,
+    );
+}
+
+#[test]
+fn gradient_ping_pong() {
+    let source = test_animations::gradient();
+    let config = Config {
+        playback: Playback {
+            order: PlaybackOrder::PingPong,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-gradient-pingpong.gif");
+
+    // 折り返しの両端のフレームは1回だけ表示する
+    assert_eq!(decoded.frames.len(), 10);
+    test_animations::assert_frames_close(
+        &test_animations::select(
+            &test_animations::expected_rgb(&source),
+            &[0, 1, 2, 3, 4, 5, 4, 3, 2, 1],
+        ),
+        &decoded.frames,
+        96,
+        10.0,
+    );
+}
+
+#[test]
+fn gradient_fits_in_max_file_size() {
+    let source = test_animations::gradient();
+    let full = TempPath::new("golden-size-full.gif");
+    gif_output::create_gif_from_video(&source, &full, &Config::default()).unwrap();
+    let full_size = std::fs::metadata(&*full).unwrap().len();
+
+    // 既定の設定で出力した大きさの半分を上限にする
+    let config = Config {
+        max_file_size: MaxFileSize {
+            kilobytes: (full_size / 2 / 1024).max(1) as u32,
+        },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-size-limit.gif");
+    let report = gif_output::create_gif_from_video(&source, &path, &config)
+        .unwrap()
+        .expect("上限を指定した場合は選んだ色数を返す");
+
+    let size = std::fs::metadata(&*path).unwrap().len();
+    assert_eq!(report.size, size);
+    assert!(size <= config.max_file_size.kilobytes as u64 * 1024);
+    assert!(report.encodes <= aviutl::size_limit::MAX_ENCODES);
+    // 探索に使った一時ファイルは残らない
+    let dir = path.parent().unwrap();
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(std::fs::read_dir(dir).unwrap().all(|entry| {
+        let entry = entry.unwrap().file_name();
+        let entry = entry.to_string_lossy();
+        entry == name || !entry.starts_with(name)
+    }));
+    assert_eq!(decode(&path).frames.len(), 6);
+}
+
+#[test]
+fn abort_keeps_the_previous_output() {
+    let source = test_animations::moving_square();
+    let path = TempPath::new("golden-abort.gif");
+    std::fs::write(&*path, b"previous").unwrap();
+
+    let aborting = test_animations::AbortingSource::new(&source, 3);
+    assert!(gif_output::create_gif_from_video(&aborting, &path, &Config::default()).is_err());
+    // 書きかけのファイルは残らず、前の出力も上書きされない
+    assert_eq!(std::fs::read(&*path).unwrap(), b"previous");
+    let dir = path.parent().unwrap();
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(std::fs::read_dir(dir).unwrap().all(|entry| {
+        let entry = entry.unwrap().file_name();
+        let entry = entry.to_string_lossy();
+        entry == name || !entry.starts_with(name)
+    }));
+}
+
+#[test]
+fn abort_finishes_with_encoded_frames() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        on_abort: OnAbort { finish: true },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-abort-finished.gif");
+
+    // 3フレーム出力したところで中断する
+    let aborting = test_animations::AbortingSource::new(&source, 3);
+    gif_output::create_gif_from_video(&aborting, &path, &config).unwrap();
+
+    let decoded = decode(&path);
+    assert_eq!(decoded.frames.len(), 3);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 1, 2]),
+        &decoded.frames,
+        2,
+        0.1,
+    );
+}
+
+#[test]
+fn abort_before_the_first_frame_keeps_the_previous_output() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        on_abort: OnAbort { finish: true },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-abort-empty.gif");
+    std::fs::write(&*path, b"previous").unwrap();
+
+    // 中断時も保存する指定でも、1フレームも出力しないうちに中断した場合は保存しない
+    let aborting = test_animations::AbortingSource::new(&source, 0);
+    let result = gif_output::create_gif_from_video(&aborting, &path, &config);
+    assert_eq!(result.unwrap_err(), abort::ABORTED);
+    assert_eq!(std::fs::read(&*path).unwrap(), b"previous");
+}
+
+#[test]
+fn metadata_in_comment_extension() {
+    let source = test_animations::gradient();
+    let config = Config {
+        metadata: MetadataSettings {
+            title: "Gradient".into(),
+            author: "yu7400ki".into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-metadata.gif");
+    gif_output::create_gif_from_video(&source, &path, &config).unwrap();
+
+    // コメント拡張（0x21 0xFE）の最初のサブブロックに1行ずつ書き込まれる
+    let data = std::fs::read(&*path).unwrap();
+    let start = data
+        .windows(2)
+        .position(|w| w == [0x21, 0xFE])
+        .expect("コメント拡張がありません");
+    let length = data[start + 2] as usize;
+    let comment = String::from_utf8(data[start + 3..start + 3 + length].to_vec()).unwrap();
+    assert!(
+        comment.starts_with("Title: Gradient\nAuthor: yu7400ki\nSoftware: GIF出力プラグイン v")
+    );
+
+    // コメントがあってもデコードできる
+    assert_eq!(decode(&path).frames.len(), 6);
+}
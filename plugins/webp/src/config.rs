@@ -1,10 +1,24 @@
+use aviutl::abort::OnAbort;
+use aviutl::color::{self, ColorProfile};
+use aviutl::convert::{AlphaMode, format_color, parse_color};
+use aviutl::crop::{Crop, CropMode};
+use aviutl::decimate::Decimate;
+use aviutl::dedup::Dedup;
+use aviutl::metadata::{Metadata, MetadataFiles, MetadataSettings};
+use aviutl::playback::{Playback, PlaybackOrder};
+use aviutl::resize::{Resize, ResizeFilter, ResizeMode};
+use aviutl::size_limit::{MaxFileSize, SizeLimit};
 use ini::Ini;
+#[cfg(feature = "plugin")]
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+#[cfg(feature = "plugin")]
 use windows::Win32::Foundation::{HMODULE, MAX_PATH};
+#[cfg(feature = "plugin")]
 use windows::Win32::System::LibraryLoader::{
     GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GetModuleFileNameW, GetModuleHandleExW,
 };
+#[cfg(feature = "plugin")]
 use windows::core::PCWSTR;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -49,13 +63,72 @@ impl ColorFormat {
     }
 }
 
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum AlphaHandling {
+    #[default]
+    Straight,
+    Matte,
+}
+
+impl From<AlphaHandling> for &'static str {
+    fn from(handling: AlphaHandling) -> Self {
+        match handling {
+            AlphaHandling::Straight => "そのまま保持",
+            AlphaHandling::Matte => "背景色に合成",
+        }
+    }
+}
+
+impl FromStr for AlphaHandling {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(0) => Ok(AlphaHandling::Straight),
+            Ok(1) => Ok(AlphaHandling::Matte),
+            _ => Err(()),
+        }
+    }
+}
+
+impl AlphaHandling {
+    fn to_index(self) -> u32 {
+        match self {
+            AlphaHandling::Straight => 0,
+            AlphaHandling::Matte => 1,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     pub repeat: i32,
     pub color_format: ColorFormat,
+    pub alpha_handling: AlphaHandling,
+    pub matte_color: [u8; 3],
     pub lossless: bool,
     pub quality: f32,
     pub method: u8,
+    /// 重複フレームをまとめる指定
+    pub dedup: Dedup,
+    /// 出力サイズの指定
+    pub resize: Resize,
+    /// 切り取りの指定
+    pub crop: Crop,
+    /// フレームレートの指定
+    pub decimate: Decimate,
+    /// 再生速度と再生順
+    pub playback: Playback,
+    /// ファイルサイズの上限
+    pub max_file_size: MaxFileSize,
+    /// 中断されたときの出力の扱い
+    pub on_abort: OnAbort,
+    /// 埋め込むメタデータの項目
+    pub metadata: MetadataSettings,
+    /// 埋め込む色空間の情報
+    pub color_profile: ColorProfile,
+    /// 埋め込むICCプロファイルのパス（色空間の情報がICCプロファイルの場合）
+    pub icc_path: String,
 }
 
 impl Default for Config {
@@ -63,24 +136,80 @@ impl Default for Config {
         Self {
             repeat: 0,
             color_format: ColorFormat::default(),
+            alpha_handling: AlphaHandling::default(),
+            matte_color: [0, 0, 0],
             lossless: false,
             quality: 75.0,
             method: 4,
+            dedup: Dedup::default(),
+            resize: Resize::default(),
+            crop: Crop::default(),
+            decimate: Decimate::default(),
+            playback: Playback::default(),
+            max_file_size: MaxFileSize::default(),
+            on_abort: OnAbort::default(),
+            metadata: MetadataSettings::default(),
+            color_profile: ColorProfile::Srgb,
+            icc_path: String::new(),
         }
     }
 }
 
 impl Config {
+    /// 埋め込めるメタデータのファイル
+    pub const METADATA_FILES: MetadataFiles = MetadataFiles::GivenOrGenerated;
+
     pub const fn default() -> Self {
         Self {
             repeat: 0,
             color_format: ColorFormat::Rgb24,
+            alpha_handling: AlphaHandling::Straight,
+            matte_color: [0, 0, 0],
             lossless: false,
             quality: 75.0,
             method: 4,
+            dedup: Dedup {
+                merge: false,
+                tolerance: 0,
+            },
+            resize: Resize {
+                width: 0,
+                height: 0,
+                keep_aspect: true,
+                mode: ResizeMode::Fit,
+                filter: ResizeFilter::Lanczos3,
+            },
+            crop: Crop {
+                mode: CropMode::None,
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            },
+            decimate: Decimate {
+                fps: 0,
+                blend: false,
+            },
+            playback: Playback {
+                speed: 100,
+                order: PlaybackOrder::Forward,
+            },
+            max_file_size: MaxFileSize { kilobytes: 0 },
+            on_abort: OnAbort { finish: false },
+            metadata: MetadataSettings {
+                title: String::new(),
+                author: String::new(),
+                copyright: String::new(),
+                comment: String::new(),
+                xmp_path: String::new(),
+                exif_path: String::new(),
+            },
+            color_profile: ColorProfile::Srgb,
+            icc_path: String::new(),
         }
     }
 
+    #[cfg(feature = "plugin")]
     fn config_file_path() -> Result<PathBuf, String> {
         let (buffer, len) = unsafe {
             let mut hmodule: HMODULE = HMODULE::default();
@@ -109,6 +238,76 @@ impl Config {
         }
     }
 
+    /// 透過付き出力時のαチャンネルの扱い
+    pub fn alpha_mode(&self) -> AlphaMode {
+        match self.alpha_handling {
+            AlphaHandling::Straight => AlphaMode::Straight,
+            AlphaHandling::Matte => AlphaMode::Matte(self.matte_color),
+        }
+    }
+
+    /// 埋め込むICCプロファイル（sRGBは小さいsRGBのプロファイルを作る）
+    pub fn icc_profile(&self) -> Result<Option<Vec<u8>>, String> {
+        match self.color_profile {
+            ColorProfile::None => Ok(None),
+            ColorProfile::Srgb => Ok(Some(color::srgb_icc_profile())),
+            ColorProfile::Icc => color::load_icc(&self.icc_path).map(Some),
+        }
+    }
+
+    /// 出力ファイルに埋め込むメタデータ（XMP・EXIFはファイルから読み込む）
+    pub fn metadata(&self) -> Result<Metadata, String> {
+        self.metadata.to_metadata(concat!(
+            "WebP出力プラグイン v",
+            env!("CARGO_PKG_VERSION"),
+            " by yu7400ki"
+        ))
+    }
+
+    /// ファイルサイズの上限と、収まるように調整する品質の範囲（上限なしの場合はNone）
+    pub fn size_limit(&self) -> Option<SizeLimit> {
+        self.max_file_size.limit("品質", 0, 100)
+    }
+
+    /// 出力ダイアログに表示する設定の概要
+    pub fn summary(&self) -> String {
+        let compression = if self.lossless {
+            "可逆".to_string()
+        } else {
+            format!("非可逆 品質{}", self.quality)
+        };
+        let repeat = match self.repeat {
+            0 => "ループ∞".to_string(),
+            n => format!("ループ{}回", n),
+        };
+        let color = match (self.color_format, self.alpha_handling) {
+            (ColorFormat::Rgb24, _) => "透過無し".to_string(),
+            (ColorFormat::Rgba32, AlphaHandling::Straight) => "透過付き".to_string(),
+            (ColorFormat::Rgba32, AlphaHandling::Matte) => {
+                format!("透過付き(背景色{})", format_color(self.matte_color))
+            }
+        };
+        let mut summary = format!(
+            "WebP {} メソッド{} {} {}",
+            compression, self.method, repeat, color
+        );
+        summary.push_str(&self.dedup.summary());
+        summary.push_str(&self.resize.summary());
+        summary.push_str(&self.crop.summary());
+        summary.push_str(&self.decimate.summary());
+        summary.push_str(&self.playback.summary());
+        summary.push_str(&self.max_file_size.summary());
+        summary.push_str(&self.on_abort.summary());
+        summary.push_str(&self.metadata.summary(Self::METADATA_FILES));
+        match self.color_profile {
+            ColorProfile::Srgb => {}
+            ColorProfile::None => summary.push_str(" 色空間なし"),
+            ColorProfile::Icc => summary.push_str(" ICC"),
+        }
+        summary
+    }
+
+    #[cfg(feature = "plugin")]
     pub fn load() -> Self {
         let default = Self::default();
 
@@ -121,10 +320,15 @@ impl Config {
             return default;
         }
 
-        let ini = match Ini::load_from_file(&config_path) {
-            Ok(ini) => ini,
-            Err(_) => return default,
-        };
+        match Ini::load_from_file(&config_path) {
+            Ok(ini) => Self::from_ini(&ini),
+            Err(_) => default,
+        }
+    }
+
+    /// iniの`[Config]`セクションから読み込む（無い項目や不正な値は既定値にする）
+    pub fn from_ini(ini: &Ini) -> Self {
+        let default = Self::default();
 
         let section = ini.section(Some("Config"));
 
@@ -138,6 +342,16 @@ impl Config {
             .and_then(|s| s.parse::<ColorFormat>().ok())
             .unwrap_or_default();
 
+        let alpha_handling = section
+            .and_then(|s| s.get("alpha_handling"))
+            .and_then(|s| s.parse::<AlphaHandling>().ok())
+            .unwrap_or_default();
+
+        let matte_color = section
+            .and_then(|s| s.get("matte_color"))
+            .and_then(parse_color)
+            .unwrap_or(default.matte_color);
+
         let lossless = section
             .and_then(|s| s.get("lossless"))
             .and_then(|s| s.parse::<bool>().ok())
@@ -155,26 +369,70 @@ impl Config {
             .unwrap_or(default.method)
             .clamp(0, 6);
 
+        let color_profile = section
+            .and_then(|s| s.get("color_profile"))
+            .and_then(|s| s.parse::<ColorProfile>().ok())
+            .unwrap_or(default.color_profile);
+
+        let icc_path = section
+            .and_then(|s| s.get("icc_path"))
+            .map(|s| s.to_string())
+            .unwrap_or(default.icc_path);
+
         Self {
             repeat,
             color_format,
+            alpha_handling,
+            matte_color,
             lossless,
             quality,
             method,
+            dedup: Dedup::load_ini(section),
+            resize: Resize::load_ini(section),
+            crop: Crop::load_ini(section),
+            decimate: Decimate::load_ini(section),
+            playback: Playback::load_ini(section),
+            max_file_size: MaxFileSize::load_ini(section),
+            on_abort: OnAbort::load_ini(section),
+            metadata: MetadataSettings::load_ini(section),
+            color_profile,
+            icc_path,
         }
     }
 
+    #[cfg(feature = "plugin")]
     pub fn save(&self) -> Result<(), String> {
         let config_path = Self::config_file_path()?;
+        self.to_ini()
+            .write_to_file(&config_path)
+            .map_err(|e| e.to_string())
+    }
+
+    /// iniの`[Config]`セクションに書き出す
+    pub fn to_ini(&self) -> Ini {
         let mut ini = Ini::new();
 
-        ini.with_section(Some("Config"))
+        let mut section = ini.with_section(Some("Config"));
+        section
             .set("repeat", self.repeat.to_string())
             .set("color_format", self.color_format.to_index().to_string())
+            .set("alpha_handling", self.alpha_handling.to_index().to_string())
+            .set("matte_color", format_color(self.matte_color))
             .set("lossless", self.lossless.to_string())
             .set("quality", self.quality.to_string())
             .set("method", self.method.to_string());
+        self.dedup.save_ini(&mut section);
+        self.resize.save_ini(&mut section);
+        self.crop.save_ini(&mut section);
+        self.decimate.save_ini(&mut section);
+        self.playback.save_ini(&mut section);
+        self.max_file_size.save_ini(&mut section);
+        self.on_abort.save_ini(&mut section);
+        self.metadata.save_ini(&mut section);
+        section
+            .set("color_profile", self.color_profile.to_index().to_string())
+            .set("icc_path", self.icc_path.as_str());
 
-        ini.write_to_file(&config_path).map_err(|e| e.to_string())
+        ini
     }
 }
@@ -1,10 +1,15 @@
-use crate::config::{ColorFormat, Config};
+use crate::config::{AlphaHandling, ColorFormat, Config};
+use aviutl::color::ColorProfile;
+use aviutl::dialog::{
+    CropSection, DecimateSection, DedupSection, MatteSection, MaxFileSizeSection, MetadataSection,
+    OnAbortSection, PlaybackSection, ResizeSection,
+};
 use std::sync::{Arc, Mutex};
 use win32_dialog::widget::ComboBox;
 use win32_dialog::{
     Dialog, MessageBox,
     layout::{FlexLayout, JustifyContent, SizeValue},
-    widget::{Button, ButtonEvent, CheckBox, Label, Number},
+    widget::{Button, ButtonEvent, CheckBox, Label, Number, TextBox},
 };
 use windows::Win32::Foundation::*;
 
@@ -27,6 +32,16 @@ pub fn show_config_dialog(
         ColorFormat::Rgba32 => 1,
     });
 
+    let alpha_label = Label::new("半透明の扱い (透過付きのみ)");
+    let alpha_options = vec![AlphaHandling::Straight.into(), AlphaHandling::Matte.into()];
+    let alpha_combobox =
+        ComboBox::new(alpha_options).selected(match default_config.alpha_handling {
+            AlphaHandling::Straight => 0,
+            AlphaHandling::Matte => 1,
+        });
+
+    let matte_section = MatteSection::new(default_config.matte_color);
+
     let lossless_checkbox = CheckBox::new("ロスレス圧縮").checked(default_config.lossless);
 
     let quality_label = Label::new("品質 (0-100)");
@@ -39,15 +54,55 @@ pub fn show_config_dialog(
         .value(default_config.method as i32)
         .range(0, 6);
 
+    let dedup_section = DedupSection::new(&default_config.dedup);
+
+    let resize_section = ResizeSection::new(&default_config.resize);
+
+    let crop_section = CropSection::new(&default_config.crop);
+
+    let decimate_section = DecimateSection::new(&default_config.decimate);
+
+    let playback_section = PlaybackSection::new(&default_config.playback);
+
+    let max_file_size_section = MaxFileSizeSection::new(&default_config.max_file_size);
+
+    let on_abort_section = OnAbortSection::new(&default_config.on_abort);
+
+    let metadata_section = MetadataSection::new(&default_config.metadata, Config::METADATA_FILES);
+
+    let color_profile_label = Label::new("色空間の情報");
+    let color_profile_options = vec![
+        ColorProfile::None.into(),
+        ColorProfile::Srgb.into(),
+        ColorProfile::Icc.into(),
+    ];
+    let color_profile_combobox = ComboBox::new(color_profile_options)
+        .selected(default_config.color_profile.to_index() as i32);
+
+    let icc_path_label = Label::new("ICCプロファイルのファイル");
+    let icc_path_input = TextBox::new().text(&default_config.icc_path);
+
     let mut dialog = Dialog::new("WebP出力設定");
 
     let ok_button = Button::primary("OK").add_event_handler({
         let result = Arc::clone(&result);
         let repeat_input = repeat_input.clone();
         let color_combobox = color_combobox.clone();
+        let alpha_combobox = alpha_combobox.clone();
+        let matte_section = matte_section.clone();
         let lossless_checkbox = lossless_checkbox.clone();
         let quality_input = quality_input.clone();
         let method_input = method_input.clone();
+        let dedup_section = dedup_section.clone();
+        let resize_section = resize_section.clone();
+        let crop_section = crop_section.clone();
+        let decimate_section = decimate_section.clone();
+        let playback_section = playback_section.clone();
+        let max_file_size_section = max_file_size_section.clone();
+        let on_abort_section = on_abort_section.clone();
+        let metadata_section = metadata_section.clone();
+        let color_profile_combobox = color_profile_combobox.clone();
+        let icc_path_input = icc_path_input.clone();
         let dialog = dialog.clone();
         move |_: ButtonEvent| {
             let repeat = match repeat_input.get_value::<i32>() {
@@ -68,6 +123,20 @@ pub fn show_config_dialog(
                 _ => Default::default(),
             };
 
+            let alpha_handling = match alpha_combobox.get_selected_index() {
+                0 => AlphaHandling::Straight,
+                1 => AlphaHandling::Matte,
+                _ => Default::default(),
+            };
+
+            let matte_color = match matte_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
             let lossless = lossless_checkbox.is_checked();
 
             let quality = if lossless {
@@ -102,13 +171,85 @@ pub fn show_config_dialog(
                 }
             };
 
+            let dedup = match dedup_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let resize = match resize_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let crop = match crop_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let decimate = match decimate_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let playback = match playback_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let max_file_size = match max_file_size_section.value() {
+                Ok(value) => value,
+                Err(message) => {
+                    MessageBox::error(Some(parent_hwnd), &message, "エラー");
+                    return;
+                }
+            };
+
+            let on_abort = on_abort_section.value();
+
+            let metadata = metadata_section.value();
+
+            let color_profile = match color_profile_combobox.get_selected_index() {
+                0 => ColorProfile::None,
+                1 => ColorProfile::Srgb,
+                2 => ColorProfile::Icc,
+                _ => Default::default(),
+            };
+            let icc_path = icc_path_input.get_text();
+
             if let Ok(mut guard) = result.lock() {
                 *guard = Some(Config {
                     repeat,
                     color_format,
+                    alpha_handling,
+                    matte_color,
                     lossless,
                     quality,
                     method,
+                    dedup,
+                    resize,
+                    crop,
+                    decimate,
+                    playback,
+                    max_file_size,
+                    on_abort,
+                    metadata,
+                    color_profile,
+                    icc_path,
                 });
                 dialog.close();
             } else {
@@ -129,13 +270,16 @@ pub fn show_config_dialog(
     });
 
     // Create layout with sections
-    let mut layout = FlexLayout::column()
-        .with_width(SizeValue::Points(300.0))
-        .with_padding(15.0)
-        .with_gap(10.0);
+    // 1列では画面に収まらないので、基本・エンコード、フレーム・サイズ、メタデータ・中断の3列に分ける
+    let column = || {
+        FlexLayout::column()
+            .with_width(SizeValue::Points(300.0))
+            .with_gap(10.0)
+    };
+    let mut base_column = column();
 
     // Basic Settings Section
-    layout = layout.with_layout(
+    base_column = base_column.with_layout(
         FlexLayout::column()
             .with_gap(5.0)
             .with_widget(repeat_label)
@@ -143,15 +287,24 @@ pub fn show_config_dialog(
     );
 
     // Color Format Section (only if RGBA feature is enabled)
-    layout = layout.with_layout(
+    base_column = base_column.with_layout(
         FlexLayout::column()
             .with_gap(5.0)
             .with_widget(color_label)
             .with_widget(color_combobox),
     );
 
+    // Alpha Section
+    base_column = base_column.with_layout(
+        FlexLayout::column()
+            .with_gap(5.0)
+            .with_widget(alpha_label)
+            .with_widget(alpha_combobox),
+    );
+    base_column = matte_section.add_to(base_column);
+
     // Compression Settings Section
-    layout = layout
+    base_column = base_column
         .with_widget(lossless_checkbox)
         .with_layout(
             FlexLayout::column()
@@ -166,6 +319,49 @@ pub fn show_config_dialog(
                 .with_widget(method_input),
         );
 
+    // Color Profile Section
+    base_column = base_column
+        .with_layout(
+            FlexLayout::column()
+                .with_gap(5.0)
+                .with_widget(color_profile_label)
+                .with_widget(color_profile_combobox),
+        )
+        .with_layout(
+            FlexLayout::column()
+                .with_gap(5.0)
+                .with_widget(icc_path_label)
+                .with_widget(icc_path_input),
+        );
+
+    let mut frame_column = column();
+
+    // Duplicate Frames Section
+    frame_column = dedup_section.add_to(frame_column);
+
+    // Frame Rate Section
+    frame_column = decimate_section.add_to(frame_column);
+
+    // Crop Section
+    frame_column = crop_section.add_to(frame_column);
+
+    // Resize Section
+    frame_column = resize_section.add_to(frame_column);
+
+    // Playback Section
+    frame_column = playback_section.add_to(frame_column);
+
+    // File Size Section
+    frame_column = max_file_size_section.add_to(frame_column);
+
+    let mut output_column = column();
+
+    // Metadata Section
+    output_column = metadata_section.add_to(output_column);
+
+    // Abort Section
+    output_column = on_abort_section.add_to(output_column);
+
     // Buttons Section
     let buttons_section = FlexLayout::row()
         .with_gap(10.0)
@@ -174,7 +370,17 @@ pub fn show_config_dialog(
         .with_widget(ok_button)
         .with_widget(cancel_button);
 
-    layout = layout.with_layout(buttons_section);
+    let layout = FlexLayout::column()
+        .with_padding(15.0)
+        .with_gap(10.0)
+        .with_layout(
+            FlexLayout::row()
+                .with_gap(15.0)
+                .with_layout(base_column)
+                .with_layout(frame_column)
+                .with_layout(output_column),
+        )
+        .with_layout(buttons_section);
 
     dialog = dialog.with_layout(layout);
 
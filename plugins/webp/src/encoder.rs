@@ -23,10 +23,10 @@ SOFTWARE.
  */
 #![allow(dead_code)]
 
+use aviutl::frame::FrameView;
 use libwebp_sys::*;
 use std::ffi::CString;
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::Write;
 
 /// Pixel layout describing the order of color channels
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -48,38 +48,71 @@ impl PixelLayout {
     }
 }
 
-/// Animation frame data
-pub struct AnimFrame {
-    image: Vec<u8>,
+/// Animation frame data borrowing the caller's pixel buffer
+pub struct AnimFrame<'a> {
+    image: &'a [u8],
     layout: PixelLayout,
     width: u32,
     height: u32,
+    stride: u32,
     timestamp: i32,
 }
 
-impl AnimFrame {
-    pub fn new(image: &[u8], layout: PixelLayout, width: u32, height: u32, timestamp: i32) -> Self {
+impl<'a> AnimFrame<'a> {
+    /// Create a frame from image data whose rows are `stride` bytes apart
+    pub fn new(
+        image: &'a [u8],
+        layout: PixelLayout,
+        width: u32,
+        height: u32,
+        stride: u32,
+        timestamp: i32,
+    ) -> Self {
         Self {
-            image: image.to_vec(),
+            image,
             layout,
             width,
             height,
+            stride,
             timestamp,
         }
     }
 
-    /// Create a frame from RGB image data
-    pub fn from_rgb(image: &[u8], width: u32, height: u32, timestamp: i32) -> Self {
-        Self::new(image, PixelLayout::Rgb, width, height, timestamp)
+    /// Create a frame from packed RGB image data
+    pub fn from_rgb(image: &'a [u8], width: u32, height: u32, timestamp: i32) -> Self {
+        Self::new(image, PixelLayout::Rgb, width, height, width * 3, timestamp)
     }
 
-    /// Create a frame from RGBA image data
-    pub fn from_rgba(image: &[u8], width: u32, height: u32, timestamp: i32) -> Self {
-        Self::new(image, PixelLayout::Rgba, width, height, timestamp)
+    /// Create a frame from packed RGBA image data
+    pub fn from_rgba(image: &'a [u8], width: u32, height: u32, timestamp: i32) -> Self {
+        Self::new(
+            image,
+            PixelLayout::Rgba,
+            width,
+            height,
+            width * 4,
+            timestamp,
+        )
+    }
+
+    /// Create a frame from a borrowed frame view without copying
+    pub fn from_view(view: FrameView<'a, u8>, timestamp: i32) -> Self {
+        let layout = match view.layout() {
+            aviutl::frame::PixelLayout::Rgb => PixelLayout::Rgb,
+            aviutl::frame::PixelLayout::Rgba => PixelLayout::Rgba,
+        };
+        Self::new(
+            view.data(),
+            layout,
+            view.width() as u32,
+            view.height() as u32,
+            view.stride() as u32,
+            timestamp,
+        )
     }
 
     pub fn get_image(&self) -> &[u8] {
-        &self.image
+        self.image
     }
 
     pub fn get_layout(&self) -> PixelLayout {
@@ -97,16 +130,23 @@ impl AnimFrame {
     pub fn height(&self) -> u32 {
         self.height
     }
+
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
 }
 
-/// WebP animation encoder that accumulates frames and writes to file on finalization
-pub struct AnimEncoder<'a> {
+/// WebP animation encoder that accumulates frames and writes them to `W` on finalization
+pub struct AnimEncoder<'a, W: Write> {
     encoder: *mut WebPAnimEncoder,
     config: &'a WebPConfig,
     width: u32,
     height: u32,
-    writer: BufWriter<File>,
+    writer: W,
     muxparams: WebPMuxAnimParams,
+    icc_profile: Option<Vec<u8>>,
+    exif: Option<Vec<u8>>,
+    xmp: Option<Vec<u8>>,
     finalized: bool,
 }
 
@@ -125,6 +165,8 @@ pub enum StreamingAnimEncodeError {
     EncoderNotInitialized,
     /// Encoder already finalized
     AlreadyFinalized,
+    /// Frame data is smaller than its size and stride require
+    InvalidFrameData,
 }
 
 impl std::fmt::Display for StreamingAnimEncodeError {
@@ -140,6 +182,7 @@ impl std::fmt::Display for StreamingAnimEncodeError {
             StreamingAnimEncodeError::IoError(e) => write!(f, "IO error: {}", e),
             StreamingAnimEncodeError::EncoderNotInitialized => write!(f, "Encoder not initialized"),
             StreamingAnimEncodeError::AlreadyFinalized => write!(f, "Encoder already finalized"),
+            StreamingAnimEncodeError::InvalidFrameData => write!(f, "Invalid frame data"),
         }
     }
 }
@@ -159,16 +202,14 @@ impl From<std::io::Error> for StreamingAnimEncodeError {
     }
 }
 
-impl<'a> AnimEncoder<'a> {
+impl<'a, W: Write> AnimEncoder<'a, W> {
     /// Create a new streaming animation encoder
     pub fn new(
         width: u32,
         height: u32,
         config: &'a WebPConfig,
-        file: File,
+        writer: W,
     ) -> Result<Self, StreamingAnimEncodeError> {
-        let writer = BufWriter::new(file);
-
         unsafe {
             let mut uninit = std::mem::MaybeUninit::<WebPAnimEncoderOptions>::uninit();
             let mux_abi_version = WebPGetMuxABIVersion();
@@ -195,6 +236,9 @@ impl<'a> AnimEncoder<'a> {
                     bgcolor: 0,
                     loop_count: 0,
                 },
+                icc_profile: None,
+                exif: None,
+                xmp: None,
                 finalized: false,
             })
         }
@@ -214,8 +258,23 @@ impl<'a> AnimEncoder<'a> {
         self.muxparams.loop_count = loop_count;
     }
 
+    /// Set an ICC profile to store in the `ICCP` chunk
+    pub fn set_icc_profile(&mut self, icc_profile: Vec<u8>) {
+        self.icc_profile = Some(icc_profile);
+    }
+
+    /// Set EXIF data (starting with the TIFF header) to store in the `EXIF` chunk
+    pub fn set_exif(&mut self, exif: Vec<u8>) {
+        self.exif = Some(exif);
+    }
+
+    /// Set an XMP packet to store in the `XMP ` chunk
+    pub fn set_xmp(&mut self, xmp: Vec<u8>) {
+        self.xmp = Some(xmp);
+    }
+
     /// Add a frame to the animation
-    pub fn add_frame(&mut self, frame: AnimFrame) -> Result<(), StreamingAnimEncodeError> {
+    pub fn add_frame(&mut self, frame: AnimFrame<'_>) -> Result<(), StreamingAnimEncodeError> {
         if self.finalized {
             return Err(StreamingAnimEncodeError::AlreadyFinalized);
         }
@@ -226,6 +285,7 @@ impl<'a> AnimEncoder<'a> {
                 frame.get_layout(),
                 self.width,
                 self.height,
+                frame.stride(),
             )?;
 
             let ok = WebPAnimEncoderAdd(
@@ -246,14 +306,21 @@ impl<'a> AnimEncoder<'a> {
     }
 
     /// Finalize the animation and write to file
-    pub fn finalize(mut self) -> Result<(), StreamingAnimEncodeError> {
+    ///
+    /// `end_timestamp` is the time at which the last frame ends and determines its duration
+    pub fn finalize(mut self, end_timestamp: i32) -> Result<(), StreamingAnimEncodeError> {
         if self.finalized {
             return Err(StreamingAnimEncodeError::AlreadyFinalized);
         }
 
         unsafe {
             // Add the final null frame to signal end of animation
-            WebPAnimEncoderAdd(self.encoder, std::ptr::null_mut(), 0, std::ptr::null());
+            WebPAnimEncoderAdd(
+                self.encoder,
+                std::ptr::null_mut(),
+                end_timestamp as std::os::raw::c_int,
+                std::ptr::null(),
+            );
 
             let mut webp_data = std::mem::MaybeUninit::<WebPData>::uninit();
             let ok = WebPAnimEncoderAssemble(self.encoder, webp_data.as_mut_ptr());
@@ -273,6 +340,25 @@ impl<'a> AnimEncoder<'a> {
                 return Err(StreamingAnimEncodeError::WebPMuxError(mux_error));
             }
 
+            // The mux copies the metadata, so the buffers only need to outlive these calls
+            let chunks = [
+                (c"ICCP", &self.icc_profile),
+                (c"EXIF", &self.exif),
+                (c"XMP ", &self.xmp),
+            ];
+            for (fourcc, data) in chunks {
+                let Some(data) = data else { continue };
+                let chunk = WebPData {
+                    bytes: data.as_ptr(),
+                    size: data.len(),
+                };
+                let mux_error = WebPMuxSetChunk(mux, fourcc.as_ptr(), &chunk, 1);
+                if mux_error != WebPMuxError::WEBP_MUX_OK {
+                    WebPMuxDelete(mux);
+                    return Err(StreamingAnimEncodeError::WebPMuxError(mux_error));
+                }
+            }
+
             let mut final_data = std::mem::MaybeUninit::<WebPData>::uninit();
             WebPMuxAssemble(mux, final_data.as_mut_ptr());
             WebPMuxDelete(mux);
@@ -292,7 +378,7 @@ impl<'a> AnimEncoder<'a> {
     }
 }
 
-impl<'a> Drop for AnimEncoder<'a> {
+impl<'a, W: Write> Drop for AnimEncoder<'a, W> {
     fn drop(&mut self) {
         unsafe {
             if !self.encoder.is_null() {
@@ -303,11 +389,14 @@ impl<'a> Drop for AnimEncoder<'a> {
 }
 
 /// Helper function to create WebPPicture from raw image data
+///
+/// The rows are read directly from `image` using `stride`, so padded buffers need no repacking
 unsafe fn new_picture(
     image: &[u8],
     layout: PixelLayout,
     width: u32,
     height: u32,
+    stride: u32,
 ) -> Result<WebPPicture, StreamingAnimEncodeError> {
     let mut pic = match WebPPicture::new() {
         Ok(pic) => pic,
@@ -316,8 +405,17 @@ unsafe fn new_picture(
 
     pic.width = width as i32;
     pic.height = height as i32;
-
-    let stride = width as i32 * layout.bytes_per_pixel() as i32;
+    // Import as ARGB: the animation encoder works on ARGB anyway, and a YUV import
+    // would make lossless output lossy
+    pic.use_argb = 1;
+
+    let row_len = width as usize * layout.bytes_per_pixel();
+    if (stride as usize) < row_len
+        || (height > 0 && image.len() < (height as usize - 1) * stride as usize + row_len)
+    {
+        return Err(StreamingAnimEncodeError::InvalidFrameData);
+    }
+    let stride = stride as i32;
 
     match layout {
         PixelLayout::Rgb => {
@@ -1,21 +1,84 @@
-mod config;
+pub mod config;
+#[cfg(feature = "plugin")]
 mod dialog;
 mod encoder;
+#[cfg(feature = "plugin")]
+mod plugin;
 
 use crate::encoder::{AnimEncoder, AnimFrame, WebPConfig};
-use aviutl::output2::{OutputInfo, OutputPluginTable};
+use aviutl::abort;
+use aviutl::crop::CroppedSource;
+use aviutl::decimate::DecimatedSource;
+use aviutl::frame::FrameBuffer;
+use aviutl::metadata::Metadata;
+use aviutl::output_file::OutputFile;
+use aviutl::playback::PlaybackSource;
+use aviutl::resize::ResizedSource;
+use aviutl::size_limit::{SizeLimitReport, encode_within_size};
+use aviutl::source::FrameSource;
+use aviutl::timing::FrameTiming;
 use config::{ColorFormat, Config};
-use dialog::show_config_dialog;
-use std::ffi::c_void;
-use widestring::{U16CStr, Utf16Str, utf16str};
-use win32_dialog::MessageBox;
-use windows::{Win32::Foundation::*, core::*};
+use std::path::Path;
+
+/// 動画をWebPとして出力する
+///
+/// ファイルサイズの上限が指定されている場合は、収まる品質を探して出力する
+/// - 戻り値: 上限が指定されている場合は、選んだ品質と出力したファイルサイズ
+pub fn create_webp_from_video(
+    source: &dyn FrameSource,
+    output_path: &Path,
+    config: &Config,
+) -> std::result::Result<Option<SizeLimitReport>, String> {
+    // 以降はフレームを間引き、切り取ってから出力サイズに拡大・縮小し、再生順を変えたフレームを読み込む
+    let decimated = DecimatedSource::new(source, config.decimate);
+    let cropped = CroppedSource::new(&decimated, config.crop)?;
+    let resized = ResizedSource::new(&cropped, config.resize);
+    let played = PlaybackSource::new(&resized, config.playback)?;
+    let metadata = config.metadata()?;
+    let icc_profile = config.icc_profile()?;
+
+    match config.size_limit() {
+        Some(limit) => {
+            let report = encode_within_size(
+                &played,
+                output_path,
+                &limit,
+                config.on_abort,
+                |source, path, value| {
+                    let config = Config {
+                        quality: value as f32,
+                        lossless: false,
+                        ..config.clone()
+                    };
+                    encode(source, path, &config, &metadata, icc_profile.as_deref())
+                },
+            )?;
+            Ok(Some(report))
+        }
+        None => encode(
+            &played,
+            output_path,
+            config,
+            &metadata,
+            icc_profile.as_deref(),
+        )
+        .map(|()| None),
+    }
+}
 
-fn create_webp_from_video(info: &OutputInfo, config: &Config) -> std::result::Result<(), String> {
-    let output_path = unsafe { U16CStr::from_ptr_str(info.savefile).to_string_lossy() };
+/// 設定どおりにWebPを出力する
+fn encode(
+    source: &dyn FrameSource,
+    output_path: &Path,
+    config: &Config,
+    metadata: &Metadata,
+    icc_profile: Option<&[u8]>,
+) -> std::result::Result<(), String> {
+    let width = source.width() as u32;
+    let height = source.height() as u32;
+    let num_frames = source.frame_count();
 
-    let output_file =
-        std::fs::File::create(&output_path).map_err(|e| format!("ファイル作成エラー: {}", e))?;
+    let mut output_file = OutputFile::create(output_path)?;
 
     let mut webp_config = WebPConfig::new().map_err(|_| "WebPConfig初期化エラー")?;
 
@@ -25,121 +88,70 @@ fn create_webp_from_video(info: &OutputInfo, config: &Config) -> std::result::Re
     webp_config.alpha_compression = 1;
     webp_config.thread_level = 1;
 
-    let mut encoder = AnimEncoder::new(info.w as u32, info.h as u32, &webp_config, output_file)
+    let mut encoder = AnimEncoder::new(width, height, &webp_config, &mut output_file)
         .map_err(|e| format!("エンコーダー初期化エラー: {}", e))?;
 
     encoder.set_loop_count(config.repeat);
+    // タイトルなどはXMPに書き込む
+    encoder.set_xmp(metadata.xmp_packet());
+    if let Some(exif) = &metadata.exif {
+        encoder.set_exif(exif.clone());
+    }
+    if let Some(icc_profile) = icc_profile {
+        encoder.set_icc_profile(icc_profile.to_vec());
+    }
+
+    // タイムスタンプは単調増加である必要があるので最低1msずつ進める
+    let mut timing = FrameTiming::new(source.rate(), source.scale(), 1000).with_min_delay(1);
 
-    let duration_ms = (1000.0 * info.scale as f64 / info.rate as f64).max(1.0) as i32;
-    let mut timestamp = 0;
+    let mut buffer = FrameBuffer::new();
+    let mut detector = config.dedup.detector();
+    let mut written = 0;
+    let mut aborted = false;
 
-    for frame in 0..info.n {
-        if info.is_abort() {
-            return Err("処理が中断されました".into());
+    for frame in 0..num_frames {
+        if source.is_abort() {
+            // 中断時も保存する場合は、それまでのフレームで出力を完成させる
+            if config.on_abort.finish {
+                aborted = true;
+                break;
+            }
+            return Err(abort::ABORTED.into());
         }
 
         let image_data = match config.color_format {
-            ColorFormat::Rgb24 => info.get_video_rgb(frame),
-            ColorFormat::Rgba32 => info.get_video_rgba(frame),
+            ColorFormat::Rgb24 => source.read_rgb(frame, &mut buffer),
+            ColorFormat::Rgba32 => source.read_rgba(frame, config.alpha_mode(), &mut buffer),
         };
 
-        if let Some(pixel_data) = image_data {
-            let anim_frame = match config.color_format {
-                ColorFormat::Rgb24 => {
-                    AnimFrame::from_rgb(&pixel_data, info.w as u32, info.h as u32, timestamp)
-                }
-                ColorFormat::Rgba32 => {
-                    AnimFrame::from_rgba(&pixel_data, info.w as u32, info.h as u32, timestamp)
-                }
-            };
+        let timestamp = timing.elapsed() as i32;
+        timing.next_delay();
+
+        // 直前と同じフレームは追加せず、前のフレームをこのフレームの終わりまで表示する
+        let is_duplicate = image_data.is_some_and(|view| {
+            detector
+                .as_mut()
+                .is_some_and(|detector| detector.is_duplicate(view.data()))
+        });
 
+        if let Some(view) = image_data.filter(|_| !is_duplicate) {
+            // 行間隔ごとlibwebpに渡すので並べ直しのコピーは不要
             encoder
-                .add_frame(anim_frame)
+                .add_frame(AnimFrame::from_view(view, timestamp))
                 .map_err(|e| format!("フレーム追加エラー: {}", e))?;
+            written += 1;
         }
 
-        timestamp += duration_ms;
-        info.rest_time_disp(frame, info.n);
-    }
-
-    encoder
-        .finalize()
-        .map_err(|e| format!("エンコード完了エラー: {}", e))?;
-
-    Ok(())
-}
-
-extern "C" fn output_func(oip: *mut OutputInfo) -> bool {
-    unsafe {
-        let info = match oip.as_ref() {
-            Some(info) => info,
-            None => return false,
-        };
-
-        let config = Config::load();
-
-        let result = match create_webp_from_video(info, &config) {
-            Ok(_) => true,
-            Err(e) => {
-                let error_msg = format!("WebP出力エラー: {}", e);
-                MessageBox::error(None, &error_msg, "エラー");
-                false
-            }
-        };
-
-        result
-    }
-}
-
-extern "C" fn config_func(hwnd: HWND, _dll_hinst: HINSTANCE) -> bool {
-    let default_config = Config::load();
-
-    if let Ok(result) = show_config_dialog(hwnd, default_config) {
-        match result {
-            Some(config) => {
-                // 設定を保存
-                if let Err(e) = config.save() {
-                    let error_msg = format!("設定保存エラー: {}", e);
-                    MessageBox::warning(Some(hwnd), &error_msg, "警告");
-                }
-                true
-            }
-            None => false,
-        }
-    } else {
-        MessageBox::error(Some(hwnd), "設定の取得に失敗しました。", "エラー");
-        false
+        source.rest_time_disp(frame, num_frames);
     }
-}
 
-const PLUGIN_NAME: &Utf16Str = utf16str!("WebP出力プラグイン\0");
-const FILE_FILTER: &Utf16Str = utf16str!("WebP Files (*.webp)\0*.webp\0All Files (*)\0*\0\0");
-const PLUGIN_INFO: &Utf16Str = utf16str!(concat!(
-    "WebP出力プラグイン v",
-    env!("CARGO_PKG_VERSION"),
-    " by yu7400ki\0"
-));
-
-const fn init_plugin_table() -> OutputPluginTable {
-    OutputPluginTable {
-        flag: OutputPluginTable::FLAG_VIDEO,
-        name: PLUGIN_NAME.as_ptr(),
-        filefilter: FILE_FILTER.as_ptr(),
-        information: PLUGIN_INFO.as_ptr(),
-        func_output: Some(output_func),
-        func_config: Some(config_func),
-        func_get_config_text: None,
+    if aborted && written == 0 {
+        return Err(abort::ABORTED.into());
     }
-}
 
-const OUTPUT_PLUGIN_TABLE: OutputPluginTable = init_plugin_table();
-
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn DllMain(_hinst: HINSTANCE, _reason: u32, _reserved: *mut c_void) -> BOOL {
-    TRUE
-}
+    encoder
+        .finalize(timing.elapsed() as i32)
+        .map_err(|e| format!("エンコード完了エラー: {}", e))?;
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn GetOutputPluginTable() -> *mut OutputPluginTable {
-    &OUTPUT_PLUGIN_TABLE as *const OutputPluginTable as *mut OutputPluginTable
+    output_file.commit()
 }
@@ -0,0 +1,460 @@
+//! 合成アニメーションをWebPに出力し、デコードした結果を入力と比較する
+
+use aviutl::abort::{self, OnAbort};
+use aviutl::color::ColorProfile;
+use aviutl::convert::AlphaMode;
+use aviutl::crop::{Crop, CropMode};
+use aviutl::decimate::Decimate;
+use aviutl::dedup::Dedup;
+use aviutl::metadata::MetadataSettings;
+use aviutl::playback::{Playback, PlaybackOrder};
+use aviutl::resize::{Resize, ResizeFilter};
+use aviutl::size_limit::MaxFileSize;
+use aviutl::source::MemoryFrameSource;
+use libwebp_sys::*;
+use std::path::Path;
+use test_animations::TempPath;
+use webp_output::config::{AlphaHandling, ColorFormat, Config};
+
+/// 29.97fpsをミリ秒単位にした表示時間
+const DELAYS: [i32; 6] = [33, 33, 34, 33, 33, 34];
+
+struct Decoded {
+    width: u32,
+    height: u32,
+    /// 表示される画像（ストレートαのRGBA32）
+    frames: Vec<Vec<u8>>,
+    delays: Vec<i32>,
+    loop_count: u32,
+}
+
+/// libwebpのWebPAnimDecoderでデコードする
+fn decode(path: &Path) -> Decoded {
+    let data = std::fs::read(path).unwrap();
+    let webp_data = WebPData {
+        bytes: data.as_ptr(),
+        size: data.len(),
+    };
+
+    unsafe {
+        let mut options = std::mem::MaybeUninit::<WebPAnimDecoderOptions>::uninit();
+        assert_ne!(WebPAnimDecoderOptionsInit(options.as_mut_ptr()), 0);
+        let mut options = options.assume_init();
+        options.color_mode = WEBP_CSP_MODE::MODE_RGBA;
+
+        let decoder = WebPAnimDecoderNew(&webp_data, &options);
+        assert!(!decoder.is_null(), "WebPのデコードに失敗しました");
+
+        let mut info = WebPAnimInfo::default();
+        assert_ne!(WebPAnimDecoderGetInfo(decoder, &mut info), 0);
+        let frame_len = (info.canvas_width * info.canvas_height * 4) as usize;
+
+        let mut frames = Vec::new();
+        let mut delays = Vec::new();
+        // タイムスタンプは各フレームの終了時刻
+        let mut previous = 0;
+        while WebPAnimDecoderHasMoreFrames(decoder) != 0 {
+            let mut pixels = std::ptr::null_mut();
+            let mut timestamp = 0;
+            assert_ne!(
+                WebPAnimDecoderGetNext(decoder, &mut pixels, &mut timestamp),
+                0
+            );
+            frames.push(std::slice::from_raw_parts(pixels, frame_len).to_vec());
+            delays.push(timestamp - previous);
+            previous = timestamp;
+        }
+        WebPAnimDecoderDelete(decoder);
+
+        Decoded {
+            width: info.canvas_width,
+            height: info.canvas_height,
+            frames,
+            delays,
+            loop_count: info.loop_count,
+        }
+    }
+}
+
+fn encode(source: &MemoryFrameSource, config: &Config, name: &str) -> Decoded {
+    let path = TempPath::new(name);
+    webp_output::create_webp_from_video(source, &path, config).unwrap();
+    decode(&path)
+}
+
+#[test]
+fn gradient_lossy() {
+    let source = test_animations::gradient();
+    let config = Config {
+        quality: 90.0,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-gradient.webp");
+
+    assert_eq!((decoded.width, decoded.height), (64, 48));
+    assert_eq!(decoded.delays, DELAYS);
+    assert_eq!(decoded.loop_count, 0);
+    // 非可逆圧縮なので、グラデーションが折り返す急な境界では誤差が大きくなる
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decoded.frames,
+        80,
+        4.0,
+    );
+}
+
+#[test]
+fn moving_square_lossless_with_finite_repeat() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        repeat: 3,
+        lossless: true,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square.webp");
+
+    assert_eq!(decoded.loop_count, 3);
+    assert_eq!(decoded.delays, DELAYS);
+    // 可逆圧縮なので入力と完全に一致する
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&source),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn alpha_fade_lossless_keeps_alpha() {
+    let source = test_animations::alpha_fade();
+    let config = Config {
+        color_format: ColorFormat::Rgba32,
+        alpha_handling: AlphaHandling::Straight,
+        lossless: true,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-fade-lossless.webp");
+
+    assert_eq!(decoded.delays, DELAYS);
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgba(&source, AlphaMode::Straight),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn alpha_fade_lossy_keeps_alpha() {
+    let source = test_animations::alpha_fade();
+    let config = Config {
+        color_format: ColorFormat::Rgba32,
+        alpha_handling: AlphaHandling::Straight,
+        quality: 90.0,
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-fade-lossy.webp");
+
+    assert_eq!(decoded.delays, DELAYS);
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgba(&source, AlphaMode::Straight),
+        &decoded.frames,
+        32,
+        1.0,
+    );
+}
+
+#[test]
+fn slideshow_merges_duplicates() {
+    let source = test_animations::slideshow();
+    let config = Config {
+        lossless: true,
+        dedup: Dedup {
+            merge: true,
+            tolerance: 0,
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-slideshow.webp");
+
+    // 3, 2, 1フレーム分の表示時間（終了時刻100, 166, 200から求める）
+    assert_eq!(decoded.delays, [100, 66, 34]);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 3, 5]),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn moving_square_resized() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        lossless: true,
+        // 平均で半分にすると、正方形の端がそろっているので縮小した画像が計算で求まる
+        resize: Resize {
+            width: 32,
+            filter: ResizeFilter::Box,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square-resized.webp");
+
+    // 幅だけ指定すると縦横比を保って高さが決まる
+    assert_eq!((decoded.width, decoded.height), (32, 24));
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&test_animations::moving_square_halved()),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn moving_square_auto_trimmed() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        lossless: true,
+        crop: Crop {
+            mode: CropMode::Auto,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-square-trimmed.webp");
+
+    // 全フレームで正方形が通った範囲だけが残る
+    let (_, _, width, height) = test_animations::SQUARE_BOUNDS;
+    assert_eq!(
+        (decoded.width as i32, decoded.height as i32),
+        (width, height)
+    );
+    test_animations::assert_frames_close(
+        &test_animations::expected_rgb(&test_animations::moving_square_trimmed()),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn gradient_decimated_to_10fps() {
+    let source = test_animations::gradient();
+    let config = Config {
+        lossless: true,
+        decimate: Decimate {
+            fps: 10,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-gradient-10fps.webp");
+
+    // 29.97fpsの0, 0.1, 0.2秒に表示されているフレーム
+    assert_eq!(decoded.delays, [100; 3]);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 2, 5]),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn gradient_ping_pong() {
+    let source = test_animations::gradient();
+    let config = Config {
+        lossless: true,
+        playback: Playback {
+            order: PlaybackOrder::PingPong,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let decoded = encode(&source, &config, "golden-gradient-pingpong.webp");
+
+    // 折り返しの両端のフレームは1回だけ表示する
+    assert_eq!(decoded.frames.len(), 10);
+    test_animations::assert_frames_close(
+        &test_animations::select(
+            &test_animations::expected_rgb(&source),
+            &[0, 1, 2, 3, 4, 5, 4, 3, 2, 1],
+        ),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+#[test]
+fn gradient_fits_in_max_file_size() {
+    let source = test_animations::gradient();
+    // 小さい画像ではICCプロファイルの大きさが上限に対して大きすぎるので埋め込まない
+    let base = Config {
+        color_profile: ColorProfile::None,
+        ..Default::default()
+    };
+    let full = TempPath::new("golden-size-full.webp");
+    webp_output::create_webp_from_video(&source, &full, &base).unwrap();
+    let full_size = std::fs::metadata(&*full).unwrap().len();
+
+    // 埋め込まずに出力した大きさの半分を上限にする
+    let config = Config {
+        max_file_size: MaxFileSize {
+            kilobytes: (full_size / 2 / 1024).max(1) as u32,
+        },
+        ..base
+    };
+    let path = TempPath::new("golden-size-limit.webp");
+    let report = webp_output::create_webp_from_video(&source, &path, &config)
+        .unwrap()
+        .expect("上限を指定した場合は選んだ品質を返す");
+
+    let size = std::fs::metadata(&*path).unwrap().len();
+    assert_eq!(report.size, size);
+    assert!(size <= config.max_file_size.kilobytes as u64 * 1024);
+    assert!(report.encodes <= aviutl::size_limit::MAX_ENCODES);
+    // 探索に使った一時ファイルは残らない
+    let dir = path.parent().unwrap();
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(std::fs::read_dir(dir).unwrap().all(|entry| {
+        let entry = entry.unwrap().file_name();
+        let entry = entry.to_string_lossy();
+        entry == name || !entry.starts_with(name)
+    }));
+    assert_eq!(decode(&path).frames.len(), 6);
+}
+
+#[test]
+fn abort_keeps_the_previous_output() {
+    let source = test_animations::moving_square();
+    let path = TempPath::new("golden-abort.webp");
+    std::fs::write(&*path, b"previous").unwrap();
+
+    let aborting = test_animations::AbortingSource::new(&source, 3);
+    assert!(webp_output::create_webp_from_video(&aborting, &path, &Config::default()).is_err());
+    // 書きかけのファイルは残らず、前の出力も上書きされない
+    assert_eq!(std::fs::read(&*path).unwrap(), b"previous");
+    let dir = path.parent().unwrap();
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(std::fs::read_dir(dir).unwrap().all(|entry| {
+        let entry = entry.unwrap().file_name();
+        let entry = entry.to_string_lossy();
+        entry == name || !entry.starts_with(name)
+    }));
+}
+
+#[test]
+fn abort_finishes_with_encoded_frames() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        lossless: true,
+        on_abort: OnAbort { finish: true },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-abort-finished.webp");
+
+    // 3フレーム出力したところで中断する
+    let aborting = test_animations::AbortingSource::new(&source, 3);
+    webp_output::create_webp_from_video(&aborting, &path, &config).unwrap();
+
+    let decoded = decode(&path);
+    assert_eq!(decoded.frames.len(), 3);
+    test_animations::assert_frames_close(
+        &test_animations::select(&test_animations::expected_rgb(&source), &[0, 1, 2]),
+        &decoded.frames,
+        0,
+        0.0,
+    );
+}
+
+/// WebPMuxでチャンクを取り出す
+fn read_chunk(path: &Path, fourcc: &std::ffi::CStr) -> Option<Vec<u8>> {
+    let data = std::fs::read(path).unwrap();
+    let webp_data = WebPData {
+        bytes: data.as_ptr(),
+        size: data.len(),
+    };
+
+    unsafe {
+        let mux = WebPMuxCreateInternal(&webp_data, 0, WebPGetMuxABIVersion());
+        assert!(!mux.is_null(), "WebPの解析に失敗しました");
+        let mut chunk = WebPData::default();
+        let chunk_data = match WebPMuxGetChunk(mux, fourcc.as_ptr(), &mut chunk) {
+            WebPMuxError::WEBP_MUX_OK => {
+                Some(std::slice::from_raw_parts(chunk.bytes, chunk.size).to_vec())
+            }
+            _ => None,
+        };
+        WebPMuxDelete(mux);
+        chunk_data
+    }
+}
+
+#[test]
+fn abort_before_the_first_frame_keeps_the_previous_output() {
+    let source = test_animations::moving_square();
+    let config = Config {
+        on_abort: OnAbort { finish: true },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-abort-empty.webp");
+    std::fs::write(&*path, b"previous").unwrap();
+
+    // 中断時も保存する指定でも、1フレームも出力しないうちに中断した場合は保存しない
+    let aborting = test_animations::AbortingSource::new(&source, 0);
+    let result = webp_output::create_webp_from_video(&aborting, &path, &config);
+    assert_eq!(result.unwrap_err(), abort::ABORTED);
+    assert_eq!(std::fs::read(&*path).unwrap(), b"previous");
+}
+
+#[test]
+fn metadata_in_exif_and_xmp_chunks() {
+    let source = test_animations::gradient();
+    let exif_path = TempPath::new("golden-metadata-webp.exif");
+    std::fs::write(&*exif_path, b"II*\0\x08\0\0\0\0\0").unwrap();
+    let config = Config {
+        metadata: MetadataSettings {
+            title: "Gradient".into(),
+            exif_path: exif_path.to_str().unwrap().into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-metadata.webp");
+    webp_output::create_webp_from_video(&source, &path, &config).unwrap();
+
+    assert_eq!(
+        read_chunk(&path, c"EXIF").as_deref(),
+        Some(&b"II*\0\x08\0\0\0\0\0"[..])
+    );
+    let xmp = String::from_utf8(read_chunk(&path, c"XMP ").unwrap()).unwrap();
+    assert!(xmp.contains("<rdf:li xml:lang=\"x-default\">Gradient</rdf:li>"));
+    assert!(xmp.contains("<xmp:CreatorTool>WebP出力プラグイン v"));
+
+    // メタデータがあってもアニメーションとしてデコードできる
+    assert_eq!(decode(&path).frames.len(), 6);
+}
+
+#[test]
+fn srgb_icc_profile_in_iccp_chunk() {
+    let source = test_animations::gradient();
+    let path = TempPath::new("golden-color-srgb.webp");
+    webp_output::create_webp_from_video(&source, &path, &Config::default()).unwrap();
+    assert_eq!(
+        read_chunk(&path, c"ICCP"),
+        Some(aviutl::color::srgb_icc_profile())
+    );
+
+    // 指定しない場合は書き込まない
+    let config = Config {
+        color_profile: ColorProfile::None,
+        ..Default::default()
+    };
+    let path = TempPath::new("golden-color-none.webp");
+    webp_output::create_webp_from_video(&source, &path, &config).unwrap();
+    assert_eq!(read_chunk(&path, c"ICCP"), None);
+    assert_eq!(decode(&path).frames.len(), 6);
+}